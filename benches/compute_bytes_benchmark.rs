@@ -1,5 +1,11 @@
-use arklib::resource::{ResourceId, ResourceIdTrait};
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use arklib::resource::{
+    ResourceId, ResourceIdBlake3, ResourceIdSha256, ResourceIdTrait,
+    ResourceIdXxh3,
+};
+use criterion::measurement::WallTime;
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion,
+};
 use rand::prelude::*;
 use std::fs;
 
@@ -10,6 +16,27 @@ fn generate_random_data(size: usize) -> Vec<u8> {
     (0..size).map(|_| rng.gen()).collect()
 }
 
+fn bench_algorithm<Id: for<'de> ResourceIdTrait<'de>>(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    data: &[u8],
+) {
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            Id::compute_bytes(black_box(data))
+                .expect("compute_bytes returned an error")
+        });
+    });
+}
+
+/// Compares all ID algorithms on the same data
+fn bench_algorithms(group: &mut BenchmarkGroup<WallTime>, data: &[u8]) {
+    bench_algorithm::<ResourceId>(group, "compute_bytes", data);
+    bench_algorithm::<ResourceIdBlake3>(group, "compute_bytes_blake3", data);
+    bench_algorithm::<ResourceIdXxh3>(group, "compute_bytes_xxh3", data);
+    bench_algorithm::<ResourceIdSha256>(group, "compute_bytes_sha256", data);
+}
+
 fn compute_bytes_on_raw_data(c: &mut Criterion) {
     let inputs = [
         ("compute_bytes_small", 1024),
@@ -22,12 +49,7 @@ fn compute_bytes_on_raw_data(c: &mut Criterion) {
         let mut group = c.benchmark_group(name.to_string());
         group.measurement_time(std::time::Duration::from_secs(5)); // Set the measurement time here
 
-        group.bench_function("compute_bytes", move |b| {
-            b.iter(|| {
-                ResourceId::compute_bytes(black_box(&input_data))
-                    .expect("compute_bytes returned an error")
-            });
-        });
+        bench_algorithms(&mut group, &input_data);
 
         group.finish();
     }
@@ -48,12 +70,7 @@ fn compute_bytes_on_files_benchmark(c: &mut Criterion) {
         let mut group = c.benchmark_group(file_path.to_string());
         group.measurement_time(std::time::Duration::from_secs(10)); // Set the measurement time here
 
-        group.bench_function("compute_bytes", move |b| {
-            b.iter(|| {
-                ResourceId::compute_bytes(black_box(&raw_bytes))
-                    .expect("compute_bytes returned an error")
-            });
-        });
+        bench_algorithms(&mut group, &raw_bytes);
 
         group.finish();
     }
@@ -0,0 +1,89 @@
+//! Synthetic corpora of resources shared by the benchmarks
+//!
+//! The amount of files and their size are taken from `ARK_BENCH_FILES`
+//! and `ARK_BENCH_FILE_SIZE`, so that the same benchmarks can be run
+//! against 10k, 100k or 1M files:
+//!
+//! ```sh
+//! ARK_BENCH_FILES=100000 cargo bench --bench index_scale_benchmark
+//! ```
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use tempdir::TempDir;
+
+pub const DEFAULT_FILES: usize = 10_000;
+pub const DEFAULT_FILE_SIZE: usize = 1024;
+const FILES_PER_FOLDER: usize = 100;
+const FOLDERS_PER_FOLDER: usize = 10;
+
+pub struct Corpus {
+    dir: TempDir,
+    pub files: Vec<PathBuf>,
+}
+
+impl Corpus {
+    /// Generates a corpus sized by the environment variables
+    pub fn from_env() -> Self {
+        Self::generate(
+            env_or("ARK_BENCH_FILES", DEFAULT_FILES),
+            env_or("ARK_BENCH_FILE_SIZE", DEFAULT_FILE_SIZE),
+        )
+    }
+
+    /// Generates `files` files of `file_size` random bytes, spread over
+    /// a tree of folders like a real library. The same arguments always
+    /// produce the same content.
+    pub fn generate(files: usize, file_size: usize) -> Self {
+        let dir = TempDir::new("arklib_bench").unwrap();
+        let mut rng = StdRng::seed_from_u64(files as u64);
+        let mut content = vec![0u8; file_size];
+        let mut paths = Vec::with_capacity(files);
+        for file in 0..files {
+            let folder = folder_of(dir.path(), file / FILES_PER_FOLDER);
+            if file % FILES_PER_FOLDER == 0 {
+                fs::create_dir_all(&folder).unwrap();
+            }
+            let path = folder.join(format!("resource_{}.bin", file));
+            rng.fill_bytes(&mut content);
+            fs::write(&path, &content).unwrap();
+            paths.push(path);
+        }
+        Corpus { dir, files: paths }
+    }
+
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Modifies every `nth` file, so that updates have work to do
+    pub fn touch(&self, nth: usize, round: u64) {
+        for path in self.files.iter().step_by(nth) {
+            let mut content = fs::read(path).unwrap();
+            content[0] = content[0].wrapping_add(round as u8 | 1);
+            fs::write(path, content).unwrap();
+        }
+    }
+}
+
+fn folder_of(root: &Path, folder: usize) -> PathBuf {
+    let mut path = root.to_path_buf();
+    let mut rest = folder;
+    loop {
+        path.push(format!("folder_{}", rest % FOLDERS_PER_FOLDER));
+        rest /= FOLDERS_PER_FOLDER;
+        if rest == 0 {
+            return path;
+        }
+    }
+}
+
+fn env_or(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,69 @@
+use arklib::index::ResourceIndex;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::fs;
+use tempdir::TempDir;
+
+const FOLDERS: usize = 100;
+const FILES_PER_FOLDER: usize = 100; // Set the amount of generated resources here
+
+fn index_memory_benchmark(c: &mut Criterion) {
+    let dir = TempDir::new("arklib_bench").unwrap();
+    for folder in 0..FOLDERS {
+        let folder_path = dir
+            .path()
+            .join("some/deeply/nested/library")
+            .join(format!("folder_{}", folder));
+        fs::create_dir_all(&folder_path).unwrap();
+        for file in 0..FILES_PER_FOLDER {
+            fs::write(
+                folder_path.join(format!("resource_{}.txt", file)),
+                format!("{}-{}", folder, file),
+            )
+            .unwrap();
+        }
+    }
+
+    let index = ResourceIndex::build(dir.path());
+    let compact = index.compact();
+    println!(
+        "Heap size of {} entries: {} bytes in the index, {} bytes compacted",
+        index.count_files(),
+        index.heap_size(),
+        compact.heap_size()
+    );
+
+    let mut group = c.benchmark_group("index_memory");
+    let paths: Vec<_> = index
+        .paths()
+        .map(|(path, id)| (path.to_path_buf(), *id))
+        .collect();
+
+    group.bench_function("compact", |b| {
+        b.iter(|| black_box(&index).compact());
+    });
+    group.bench_function("index_lookup_by_id", |b| {
+        b.iter(|| {
+            for (_, id) in &paths {
+                black_box(index.get_path(id));
+            }
+        });
+    });
+    group.bench_function("compact_lookup_by_id", |b| {
+        b.iter(|| {
+            for (_, id) in &paths {
+                black_box(compact.find_id(id));
+            }
+        });
+    });
+    group.bench_function("compact_lookup_by_path", |b| {
+        b.iter(|| {
+            for (path, _) in &paths {
+                black_box(compact.find_path(path));
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, index_memory_benchmark);
+criterion_main!(benches);
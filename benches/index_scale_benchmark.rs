@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use arklib::index::{RelativePath, ResourceIndex, SortKey};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+mod corpus;
+use corpus::Corpus;
+
+fn index_scale_benchmark(c: &mut Criterion) {
+    let corpus = Corpus::from_env();
+    let files = corpus.files.len();
+    let mut index = ResourceIndex::build(corpus.root());
+    index.store().unwrap();
+
+    let mut group = c.benchmark_group(format!("index_scale_{}", files));
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+    // fluctuations below 5% are not reported as regressions
+    group.noise_threshold(0.05);
+
+    group.bench_function("build", |b| {
+        b.iter(|| ResourceIndex::build(black_box(corpus.root())));
+    });
+    group.bench_function("store", |b| {
+        b.iter(|| black_box(&index).store().unwrap());
+    });
+    group.bench_function("load", |b| {
+        b.iter(|| ResourceIndex::load(black_box(corpus.root())).unwrap());
+    });
+    group.bench_function("update_all_unchanged", |b| {
+        b.iter(|| index.update_all().unwrap());
+    });
+    let mut round = 0;
+    group.bench_function("update_all_1%_modified", |b| {
+        b.iter_batched(
+            || {
+                round += 1;
+                corpus.touch(100, round);
+            },
+            |_| index.update_all().unwrap(),
+            criterion::BatchSize::PerIteration,
+        );
+    });
+
+    let ids: Vec<_> = index.ids().copied().collect();
+    group.bench_function("query_by_id", |b| {
+        b.iter(|| {
+            for id in &ids {
+                black_box(index.get_path(id));
+            }
+        });
+    });
+    group.bench_function("query_by_path", |b| {
+        b.iter(|| {
+            for path in &corpus.files {
+                black_box(index.get_entry(path));
+            }
+        });
+    });
+    let folder = RelativePath::parse("folder_0").unwrap();
+    group.bench_function("query_by_prefix", |b| {
+        b.iter(|| black_box(index.entries_with_prefix(&folder)));
+    });
+    group.bench_function("sort_by_modified", |b| {
+        b.iter(|| black_box(index.iter_sorted_by(SortKey::Modified).len()));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, index_scale_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+use arklib::resource::{ResourceId, ResourceIdBlake3, ResourceIdTrait};
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkId, Criterion,
+    Throughput,
+};
+use rand::prelude::*;
+use std::fs;
+use tempdir::TempDir;
+
+const FILE_SIZES: [usize; 3] = [1 << 20, 16 << 20, 128 << 20]; // Add file sizes to benchmark here
+
+fn generate_random_data(size: usize) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    (0..size).map(|_| rng.gen()).collect()
+}
+
+fn parallel_hashing_benchmark(c: &mut Criterion) {
+    let dir = TempDir::new("arklib_bench").unwrap();
+
+    let mut group = c.benchmark_group("parallel_hashing");
+    group.sample_size(10);
+    group.measurement_time(std::time::Duration::from_secs(10)); // Set the measurement time here
+
+    for size in FILE_SIZES.iter() {
+        let path = dir.path().join(size.to_string());
+        fs::write(&path, generate_random_data(*size)).unwrap();
+        let data_size = *size as u64;
+        group.throughput(Throughput::Bytes(data_size));
+
+        group.bench_with_input(
+            BenchmarkId::new("crc32", size),
+            &path,
+            |b, path| {
+                b.iter(|| {
+                    ResourceId::compute(data_size, black_box(path))
+                        .expect("compute returned an error")
+                });
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("blake3", size),
+            &path,
+            |b, path| {
+                b.iter(|| {
+                    ResourceIdBlake3::compute(data_size, black_box(path))
+                        .expect("compute returned an error")
+                });
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("blake3_parallel", size),
+            &path,
+            |b, path| {
+                b.iter(|| {
+                    ResourceIdBlake3::compute_parallel(
+                        data_size,
+                        black_box(path),
+                    )
+                    .expect("compute_parallel returned an error")
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parallel_hashing_benchmark);
+criterion_main!(benches);
@@ -1,4 +1,7 @@
-use std::{collections::HashSet, env, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashSet, env, ffi::OsString, fs::File, io::Read,
+    path::PathBuf, str::FromStr,
+};
 
 use flate2::read::GzDecoder;
 use fs_extra::file::CopyOptions;
@@ -6,22 +9,21 @@ use fs_extra::file::CopyOptions;
 use tar::Archive;
 use target_lexicon::{Architecture, Environment, OperatingSystem, Triple};
 const PDFIUM_VERSION: &str = "5104";
+// Local archive of pdfium from bblanchon/pdfium-binaries, used instead
+// of downloading it, e.g. for offline builds
+const PDFIUM_PATH_VAR: &str = "ARK_PDFIUM_PATH";
 
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed={}", PDFIUM_PATH_VAR);
+    if env::var_os("CARGO_FEATURE_PDFIUM").is_none() {
+        return;
+    }
     let t = env::var("TARGET").unwrap();
     let target = Triple::from_str(t.as_str()).unwrap();
     let out_dir = env::var_os("OUT_DIR").unwrap();
 
     println!("{}", target.operating_system);
-    // Avoid duplicate download
-    if !fs_extra::dir::ls(&out_dir, &HashSet::new())
-        .unwrap()
-        .items
-        .is_empty()
-    {
-        return;
-    }
-
     let mut name = vec!["pdfium"];
     match target.environment {
         Environment::Android | Environment::Androideabi => {
@@ -32,6 +34,33 @@ fn main() {
         }
         _ => {}
     }
+    if target.operating_system == OperatingSystem::Ios {
+        // Apps can't load libraries from arbitrary paths on iOS, so pdfium
+        // has to be embedded into the app bundle as a framework, the same
+        // way as into the APK on Android.
+        return;
+    }
+
+    if let Some(path) = env::var_os(PDFIUM_PATH_VAR) {
+        let archive = File::open(&path).unwrap_or_else(|e| {
+            panic!(
+                "Couldn't open {} from {}: {}",
+                PDFIUM_PATH_VAR,
+                path.to_string_lossy(),
+                e
+            )
+        });
+        unpack(archive, &out_dir, &target);
+        return;
+    }
+    // Avoid duplicate download
+    if !fs_extra::dir::ls(&out_dir, &HashSet::new())
+        .unwrap()
+        .items
+        .is_empty()
+    {
+        return;
+    }
 
     match target.operating_system {
         OperatingSystem::Windows => name.push("win"),
@@ -40,7 +69,6 @@ fn main() {
                 name.push("linux")
             }
         }
-        OperatingSystem::Ios => name.push("ios"),
         OperatingSystem::MacOSX {
             major: _,
             minor: _,
@@ -67,42 +95,50 @@ fn main() {
 
     let request = ureq::get(url.as_str())
         .call()
-        .unwrap()
+        .unwrap_or_else(|e| {
+            panic!(
+                "Couldn't download pdfium from {}: {}\n\
+                Set {} to a local archive of pdfium, \
+                or disable the `pdfium` feature",
+                url, e, PDFIUM_PATH_VAR
+            )
+        })
         .into_reader();
-    let ar = GzDecoder::new(request);
+    unpack(request, &out_dir, &target);
+}
+
+/// Unpacks the .tgz archive of pdfium and moves the library
+/// into the root of `OUT_DIR`, where `pdf.rs` looks for it
+fn unpack<R: Read>(archive: R, out_dir: &OsString, target: &Triple) {
+    let ar = GzDecoder::new(archive);
     let mut ar = Archive::new(ar);
-    ar.unpack(&out_dir).unwrap();
+    ar.unpack(out_dir).unwrap();
+    let mut options = CopyOptions::new();
+    options.overwrite = true;
+    let out_dir = PathBuf::from(out_dir);
     match target.operating_system {
         OperatingSystem::Windows => fs_extra::file::move_file(
-            PathBuf::from(&out_dir)
-                .join("bin")
-                .join("pdfium.dll"),
-            PathBuf::from(&out_dir).join("pdfium.dll"),
-            &CopyOptions::new(),
+            out_dir.join("bin").join("pdfium.dll"),
+            out_dir.join("pdfium.dll"),
+            &options,
         )
         .unwrap(),
-        OperatingSystem::Ios
-        | OperatingSystem::MacOSX {
+        OperatingSystem::MacOSX {
             major: _,
             minor: _,
             patch: _,
         }
         | OperatingSystem::Darwin => fs_extra::file::move_file(
-            PathBuf::from(&out_dir)
-                .join("bin")
-                .join("libpdfium.dylib"),
-            PathBuf::from(&out_dir).join("libpdfium.dylib"),
-            &CopyOptions::new(),
+            out_dir.join("bin").join("libpdfium.dylib"),
+            out_dir.join("libpdfium.dylib"),
+            &options,
         )
         .unwrap(),
         _ => fs_extra::file::move_file(
-            PathBuf::from(&out_dir)
-                .join("lib")
-                .join("libpdfium.so"),
-            PathBuf::from(&out_dir).join("libpdfium.so"),
-            &CopyOptions::new(),
+            out_dir.join("lib").join("libpdfium.so"),
+            out_dir.join("libpdfium.so"),
+            &options,
         )
         .unwrap(),
     };
-    println!("cargo:rerun-if-changed=build.rs");
 }
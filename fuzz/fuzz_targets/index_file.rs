@@ -0,0 +1,30 @@
+//! Feeds arbitrary bytes as the index file of a root, loading must fail
+//! gracefully and never index anything outside of the root
+//!
+//! ```sh
+//! cargo +nightly fuzz run index_file
+//! ```
+#![no_main]
+
+use std::fs;
+
+use arklib::index::ResourceIndex;
+use arklib::{ARK_FOLDER, INDEX_PATH};
+use libfuzzer_sys::fuzz_target;
+use tempdir::TempDir;
+
+fuzz_target!(|data: &[u8]| {
+    let root = TempDir::new("arklib_fuzz").unwrap();
+    fs::write(root.path().join("resource.txt"), "resource").unwrap();
+    fs::create_dir(root.path().join("folder")).unwrap();
+    let ark = root.path().join(ARK_FOLDER);
+    fs::create_dir(&ark).unwrap();
+    fs::write(ark.join(INDEX_PATH), data).unwrap();
+
+    if let Ok(index) = ResourceIndex::load(root.path()) {
+        for (path, _) in index.paths() {
+            assert!(path.starts_with(index.root()));
+            assert!(path.is_file());
+        }
+    }
+});
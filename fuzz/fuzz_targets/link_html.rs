@@ -0,0 +1,13 @@
+//! Feeds malformed web pages into the OpenGraph selectors
+//!
+//! ```sh
+//! cargo +nightly fuzz run link_html
+//! ```
+#![no_main]
+
+use arklib::link::OpenGraph;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    OpenGraph::parse(&String::from_utf8_lossy(data));
+});
@@ -0,0 +1,18 @@
+//! Feeds arbitrary bytes as a PDF document, rendering must fail
+//! gracefully instead of panicking
+//!
+//! Pdfium must be available as a system library:
+//!
+//! ```sh
+//! cargo +nightly fuzz run pdf_preview
+//! ```
+#![no_main]
+
+use std::io::Cursor;
+
+use arklib::pdf::{render_preview_page, PDFQuality};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = render_preview_page(Cursor::new(data.to_vec()), PDFQuality::Low);
+});
@@ -0,0 +1,19 @@
+//! Feeds arbitrary strings into the parsers of IDs, any accepted string
+//! must be the canonical form of the parsed ID
+//!
+//! ```sh
+//! cargo +nightly fuzz run resource_id
+//! ```
+#![no_main]
+
+use arklib::resource::{ResourceId, ResourceIdBlake3};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    if let Ok(id) = data.parse::<ResourceId>() {
+        assert_eq!(id.to_string(), data);
+    }
+    if let Ok(id) = data.parse::<ResourceIdBlake3>() {
+        assert_eq!(id.to_string(), data);
+    }
+});
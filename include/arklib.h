@@ -0,0 +1,22 @@
+#ifndef ARKLIB_H
+#define ARKLIB_H
+
+#include <stdint.h>
+
+/* Functions returning a status return 0 on success and -1 on failure,
+ * described by ark_last_error(). */
+
+int32_t ark_initialize(const char *app_id_dir);
+
+int32_t ark_provide_index(const char *root);
+
+/* Returns -1 on failure */
+int64_t ark_count_resources(const char *root);
+
+/* Returns NULL if there was no failure on this thread since the last call,
+ * the message must be released with ark_string_free() */
+char *ark_last_error(void);
+
+void ark_string_free(char *string);
+
+#endif
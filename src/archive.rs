@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::resource::{ResourceId, ResourceIdTrait};
+use crate::{ArklibError, Result};
+
+/// Memory reserved upfront for an extracted entry, sizes in headers of
+/// archives are not trusted beyond it
+const RESERVE_LIMIT: u64 = 1024 * 1024;
+
+/// Archive formats which content can be listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Detects the format by the file name, comic book archives
+    /// (`.cbz`, `.cbt`) are recognized as well
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let name = path
+            .as_ref()
+            .file_name()?
+            .to_string_lossy()
+            .to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") || name.ends_with(".cbt") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") || name.ends_with(".cbz") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file stored inside of an archive
+///
+/// Entries are virtual child resources of the archive: their ids are
+/// computed from the entry content, so an entry has the same id as the
+/// same file extracted into the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    /// Path of the entry inside of the archive
+    pub name: String,
+    pub id: ResourceId,
+}
+
+/// Lists all non-empty files stored in the archive
+pub fn list_entries<P: AsRef<Path>>(
+    path: P,
+    format: ArchiveFormat,
+) -> Result<Vec<ArchiveEntry>> {
+    log::debug!("Listing entries of archive {}", path.as_ref().display());
+
+    let mut entries = vec![];
+    visit_entries(path, format, |name, size, reader| {
+        if size > 0 {
+            let mut reader = BufReader::new(reader);
+            let id = ResourceId::compute_reader(size, &mut reader)?;
+            entries.push(ArchiveEntry { name, id });
+        }
+        Ok(true)
+    })?;
+    Ok(entries)
+}
+
+/// Extracts content of a single entry without unpacking the whole archive
+///
+/// The entry is read up to the size in its header, so that an entry
+/// inflating beyond it can't exhaust memory.
+pub fn extract_entry<P: AsRef<Path>>(
+    path: P,
+    format: ArchiveFormat,
+    entry_name: &str,
+) -> Result<Vec<u8>> {
+    let mut content = None;
+    visit_entries(path, format, |name, size, reader| {
+        if name != entry_name {
+            return Ok(true);
+        }
+        let mut buf = Vec::with_capacity(size.min(RESERVE_LIMIT) as usize);
+        reader.take(size).read_to_end(&mut buf)?;
+        content = Some(buf);
+        Ok(false)
+    })?;
+    content.ok_or_else(|| {
+        ArklibError::Path(format!("Entry {} not found in archive", entry_name))
+    })
+}
+
+/// Calls `visitor` with name, size and content of every file entry,
+/// stopping as soon as it returns `false`
+fn visit_entries<P, F>(
+    path: P,
+    format: ArchiveFormat,
+    mut visitor: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(String, u64, &mut dyn Read) -> Result<bool>,
+{
+    let file = File::open(path)?;
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = ZipArchive::new(BufReader::new(file))?;
+            for i in 0..archive.len() {
+                let mut entry = archive.by_index(i)?;
+                if entry.is_dir() {
+                    continue;
+                }
+                let name = entry.name().to_string();
+                let size = entry.size();
+                if !visitor(name, size, &mut entry)? {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        ArchiveFormat::Tar => visit_tar(BufReader::new(file), visitor),
+        ArchiveFormat::TarGz => {
+            visit_tar(GzDecoder::new(BufReader::new(file)), visitor)
+        }
+    }
+}
+
+fn visit_tar<R, F>(reader: R, mut visitor: F) -> Result<()>
+where
+    R: Read,
+    F: FnMut(String, u64, &mut dyn Read) -> Result<bool>,
+{
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        let size = entry.size();
+        if !visitor(name, size, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    const CONTENT_1: &[u8] = b"first entry";
+    const CONTENT_2: &[u8] = b"second entry";
+
+    #[test]
+    fn list_and_extract_zip() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let path = dir.path().join("comic.cbz");
+
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.add_directory("pages", FileOptions::default())
+            .unwrap();
+        zip.start_file("pages/1.txt", FileOptions::default())
+            .unwrap();
+        zip.write_all(CONTENT_1).unwrap();
+        zip.start_file("pages/2.txt", FileOptions::default())
+            .unwrap();
+        zip.write_all(CONTENT_2).unwrap();
+        zip.finish().unwrap();
+
+        let format = ArchiveFormat::from_path(&path).unwrap();
+        assert_eq!(format, ArchiveFormat::Zip);
+
+        let entries = list_entries(&path, format).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ArchiveEntry {
+                    name: "pages/1.txt".to_string(),
+                    id: ResourceId::compute_bytes(CONTENT_1).unwrap(),
+                },
+                ArchiveEntry {
+                    name: "pages/2.txt".to_string(),
+                    id: ResourceId::compute_bytes(CONTENT_2).unwrap(),
+                },
+            ]
+        );
+
+        let extracted = extract_entry(&path, format, "pages/2.txt").unwrap();
+        assert_eq!(extracted, CONTENT_2);
+        assert!(extract_entry(&path, format, "missing").is_err());
+    }
+
+    #[test]
+    fn list_and_extract_tar() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let path = dir.path().join("backup.tar");
+
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for (name, content) in [("a.txt", CONTENT_1), ("b/c.txt", CONTENT_2)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content)
+                .unwrap();
+        }
+        builder.finish().unwrap();
+
+        let format = ArchiveFormat::from_path(&path).unwrap();
+        let entries = list_entries(&path, format).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].name, "b/c.txt");
+        assert_eq!(
+            entries[1].id,
+            ResourceId::compute_bytes(CONTENT_2).unwrap()
+        );
+
+        let extracted = extract_entry(&path, format, "a.txt").unwrap();
+        assert_eq!(extracted, CONTENT_1);
+    }
+
+    #[test]
+    fn extract_entry_with_forged_size() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let path = dir.path().join("forged.tar");
+
+        let mut header = tar::Header::new_gnu();
+        header.set_path("huge.txt").unwrap();
+        header.set_size(u64::MAX / 2);
+        header.set_cksum();
+        let mut data = header.as_bytes().to_vec();
+        data.extend_from_slice(CONTENT_1);
+        std::fs::write(&path, data).unwrap();
+
+        // only the data actually stored is read
+        let extracted =
+            extract_entry(&path, ArchiveFormat::Tar, "huge.txt").unwrap();
+        assert_eq!(extracted, CONTENT_1);
+    }
+}
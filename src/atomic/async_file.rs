@@ -0,0 +1,191 @@
+//! Async variants of operations of [`AtomicFile`]
+//!
+//! The files are the same as written by the blocking operations, so both
+//! can be used on the same [`AtomicFile`] at once. I/O is done by
+//! `tokio::fs`, which moves it off the executor threads.
+use std::io::{ErrorKind, Result};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::file::{
+    check_link, latest_files, rename_version, Durability, SwapStrategy, TmpFile,
+};
+use super::notify::notify;
+use super::{AtomicFile, ReadOnlyFile};
+
+impl TmpFile {
+    pub async fn create_in_async(
+        temp_dir: impl AsRef<std::path::Path>,
+    ) -> Result<Self> {
+        let filename: String = std::iter::repeat_with(fastrand::alphanumeric)
+            .take(10)
+            .collect();
+        let path = temp_dir.as_ref().join(filename);
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Self::new(file.into_std().await, path))
+    }
+
+    /// Replaces the content of the temporary file
+    pub async fn write_async(&self, data: &[u8]) -> Result<()> {
+        tokio::fs::write(&self.path, data).await
+    }
+}
+
+impl ReadOnlyFile {
+    /// Async variant of [`ReadOnlyFile::read_content`]
+    pub async fn read_content_async(&self) -> Result<Vec<u8>> {
+        if self.version == 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                "File not found",
+            ));
+        }
+        tokio::fs::read(&self.path).await
+    }
+}
+
+impl AtomicFile {
+    /// Async variant of [`AtomicFile::latest_version`]
+    pub async fn latest_version_async(
+        &self,
+    ) -> Result<(usize, Vec<ReadOnlyFile>)> {
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        let mut paths = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            paths.push(entry.path());
+        }
+        Ok(latest_files(paths.into_iter()))
+    }
+
+    /// Async variant of [`AtomicFile::load`]
+    pub async fn load_async(&self) -> Result<ReadOnlyFile> {
+        let (version, files) = self.latest_version_async().await?;
+        self.choose_file(version, files)
+    }
+
+    /// Async variant of [`AtomicFile::make_temp`]
+    pub async fn make_temp_async(&self) -> Result<TmpFile> {
+        TmpFile::create_in_async(&self.directory).await
+    }
+
+    /// Async variant of [`AtomicFile::compare_and_swap`]
+    pub async fn compare_and_swap_async(
+        &self,
+        current: &ReadOnlyFile,
+        mut new: TmpFile,
+    ) -> Result<()> {
+        let new_path = self.path(current.version + 1);
+        if self.durability == Durability::Always {
+            tokio::fs::File::from_std(new.file.try_clone()?)
+                .sync_data()
+                .await?;
+        } else {
+            new.sync(self.durability)?;
+        }
+        let (latest_version, _) = self.latest_version_async().await?;
+        if latest_version > current.version {
+            return Err(std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                "the `current` file is not the latest version",
+            ));
+        }
+        match self.swap {
+            SwapStrategy::HardLink => {
+                let res = tokio::fs::hard_link(&new.path, new_path).await;
+                if let Err(err) = res {
+                    check_link(&new, err)?;
+                }
+            }
+            SwapStrategy::Rename => {
+                let tmp = new.path.clone();
+                tokio::task::spawn_blocking(move || {
+                    rename_version(&tmp, &new_path)
+                })
+                .await
+                .map_err(std::io::Error::other)??;
+            }
+        }
+
+        let file = self.clone();
+        let number_of_removed = tokio::task::spawn_blocking(move || {
+            file.prune_old_versions(latest_version)
+        })
+        .await
+        .unwrap_or_default();
+        log::debug!("pruned {} old files", number_of_removed);
+        notify(self, current.version + 1);
+        Ok(())
+    }
+}
+
+/// Async variant of [`super::modify_json`]
+pub async fn modify_json_async<T: Serialize + DeserializeOwned>(
+    atomic_file: &AtomicFile,
+    mut operator: impl FnMut(&mut Option<T>),
+) -> Result<()> {
+    loop {
+        let latest = atomic_file.load_async().await?;
+        let mut val = None;
+        if latest.version != 0 {
+            let content = latest.read_content_async().await?;
+            val = Some(serde_json::from_slice(&content)?);
+        }
+        operator(&mut val);
+        let data = serde_json::to_vec(&val)?;
+        let tmp = atomic_file.make_temp_async().await?;
+        tmp.write_async(&data).await?;
+        match atomic_file
+            .compare_and_swap_async(&latest, tmp)
+            .await
+        {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atomic::modify_json;
+    use crate::initialize;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn async_writes_are_read_by_blocking_ones() {
+        initialize();
+
+        let dir = TempDir::new("async_writes").unwrap();
+        let file = AtomicFile::new(dir.path().join("numbers")).unwrap();
+        let tasks: Vec<_> = (0..5)
+            .map(|i| {
+                let file = file.clone();
+                tokio::spawn(async move {
+                    modify_json_async(
+                        &file,
+                        |numbers: &mut Option<Vec<u32>>| {
+                            numbers.get_or_insert_with(Vec::new).push(i)
+                        },
+                    )
+                    .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        modify_json(&file, |numbers: &mut Option<Vec<u32>>| {
+            numbers.get_or_insert_with(Vec::new).push(5)
+        })
+        .unwrap();
+
+        let latest = file.load_async().await.unwrap();
+        assert_eq!(latest.version, 6);
+        let mut numbers: Vec<u32> =
+            serde_json::from_slice(&latest.read_content_async().await.unwrap())
+                .unwrap();
+        numbers.sort();
+        assert_eq!(numbers, (0..6).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,162 @@
+//! Versions of an [`AtomicFile`] written by several machines at once
+//!
+//! File synchronization can bring files of the same version from
+//! different devices. [`AtomicFile::load`] prefers the local one, while
+//! sync layers list the conflicting files with [`AtomicFile::conflicts`]
+//! to present them to users, and write the chosen or merged content as
+//! the next version with [`AtomicFile::resolve`].
+use std::fs;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use super::{AtomicFile, ReadOnlyFile};
+
+/// File of the latest version written by one of the machines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictingVersion {
+    pub version: usize,
+    /// ID of the app which wrote the file, taken from the file name
+    pub writer: String,
+    pub path: PathBuf,
+    pub modified: Option<SystemTime>,
+    /// Whether the file was written by this app
+    pub local: bool,
+}
+
+impl ConflictingVersion {
+    pub fn read_content(&self) -> Result<Vec<u8>> {
+        fs::read(&self.path)
+    }
+}
+
+/// Content of the version resolving a conflict
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Keeps the content of one of the conflicting files
+    Pick(PathBuf),
+    /// Content merged by the caller
+    Merge(Vec<u8>),
+}
+
+impl AtomicFile {
+    /// Files of the latest version if there are several of them, the local
+    /// one first and the other ones from the newest
+    pub fn conflicts(&self) -> Result<Vec<ConflictingVersion>> {
+        let (version, files) = self.latest_version()?;
+        if files.len() < 2 {
+            return Ok(vec![]);
+        }
+        let name = self
+            .directory
+            .file_name()
+            .map(|name| format!("{}_", name.to_string_lossy()))
+            .unwrap_or_default();
+        let mut conflicts: Vec<ConflictingVersion> = files
+            .into_iter()
+            .map(|file| {
+                let filename = file
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let writer = filename
+                    .rsplit_once('.')
+                    .map(|(stem, _)| stem)
+                    .unwrap_or(&filename);
+                let writer = writer.strip_prefix(&name).unwrap_or(writer);
+                ConflictingVersion {
+                    version,
+                    writer: writer.to_string(),
+                    modified: fs::metadata(&file.path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok(),
+                    local: filename.starts_with(&self.prefix),
+                    path: file.path,
+                }
+            })
+            .collect();
+        conflicts.sort_by(|a, b| {
+            b.local
+                .cmp(&a.local)
+                .then(b.modified.cmp(&a.modified))
+        });
+        Ok(conflicts)
+    }
+
+    /// Writes the resolution as the next version, which supersedes all
+    /// the conflicting files
+    ///
+    /// Fails with `AlreadyExists` if another version has been written
+    /// since the conflicts were listed, and with `InvalidInput` if the
+    /// picked file is not of the latest version.
+    pub fn resolve(&self, resolution: Resolution) -> Result<()> {
+        let (version, files) = self.latest_version()?;
+        let content = match resolution {
+            Resolution::Pick(path) => {
+                if !files.iter().any(|file| file.path == path) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "the picked file is not of the latest version",
+                    ));
+                }
+                fs::read(path)?
+            }
+            Resolution::Merge(content) => content,
+        };
+        let temp = self.make_temp()?;
+        (&temp).write_all(&content)?;
+        let current = ReadOnlyFile {
+            version,
+            path: self.path(version),
+        };
+        self.compare_and_swap(&current, temp)?;
+        log::info!(
+            "Resolved conflict of version {} of {}",
+            version,
+            self.directory.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initialize;
+    use tempdir::TempDir;
+
+    #[test]
+    fn conflicts_are_listed_and_resolved() {
+        initialize();
+
+        let dir = TempDir::new("conflicts").unwrap();
+        let file = AtomicFile::new(dir.path().join("data")).unwrap();
+        let temp = file.make_temp().unwrap();
+        (&temp).write_all(b"local").unwrap();
+        file.compare_and_swap(&file.load().unwrap(), temp)
+            .unwrap();
+        assert!(file.conflicts().unwrap().is_empty());
+
+        let remote = file.directory.join("data_phone.1");
+        fs::write(&remote, "remote").unwrap();
+        let conflicts = file.conflicts().unwrap();
+        assert_eq!(conflicts.len(), 2);
+        assert!(conflicts[0].local);
+        assert_eq!(conflicts[1].writer, "phone");
+        assert_eq!(conflicts[1].read_content().unwrap(), b"remote");
+
+        let stale = file.path(1);
+        file.resolve(Resolution::Pick(remote)).unwrap();
+        assert!(file.conflicts().unwrap().is_empty());
+        let latest = file.load().unwrap();
+        assert_eq!(latest.version, 2);
+        assert_eq!(latest.read_to_string().unwrap(), "remote");
+
+        let err = file.resolve(Resolution::Pick(stale)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        file.resolve(Resolution::Merge(b"merged".to_vec()))
+            .unwrap();
+        assert_eq!(file.load().unwrap().read_to_string().unwrap(), "merged");
+    }
+}
@@ -1,16 +1,51 @@
 use std::fs::{self, File};
 use std::io::{Error, ErrorKind, Read, Result};
-#[cfg(target_os = "unix")]
-use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
+use super::notify::notify;
 use crate::app_id;
+use crate::platform::{link_count, retry_shared};
 
 const MAX_VERSION_FILES: usize = 10;
+/// Coarsest granularity of modification times of directories, e.g. FAT
+/// of SD cards, below which a modification can leave the time unchanged
+const MTIME_GRANULARITY: Duration = Duration::from_secs(2);
+/// Age after which a claim of a version is considered left behind by
+/// a crashed writer
+const STALE_CLAIM: Duration = Duration::from_secs(30);
+
+/// When new versions are flushed to the storage device
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Durability {
+    /// Before the new version becomes visible, so that a crash never
+    /// leaves an incomplete latest version
+    #[default]
+    Always,
+    /// When the temporary file is closed after the swap, which doesn't
+    /// stretch the window for concurrent writers, but a crash right after
+    /// the swap can leave the latest version incomplete
+    OnClose,
+    /// Never, leaving it to the OS
+    None,
+}
+
+/// How new versions are made visible under their names
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum SwapStrategy {
+    /// Hard linking the temporary file, which fails if the version exists
+    #[default]
+    HardLink,
+    /// Renaming the temporary file after claiming the version with
+    /// a file next to it, for filesystems without hard links
+    /// like FAT32 and exFAT
+    Rename,
+}
 
 pub struct TmpFile {
-    file: File,
-    path: PathBuf,
+    pub(super) file: File,
+    pub(super) path: PathBuf,
+    sync_on_close: bool,
 }
 
 impl TmpFile {
@@ -20,7 +55,27 @@ impl TmpFile {
             .collect();
         let path = temp_dir.as_ref().join(filename);
         let file = std::fs::File::create(&path)?;
-        Ok(Self { file, path })
+        Ok(Self::new(file, path))
+    }
+
+    pub(super) fn new(file: File, path: PathBuf) -> Self {
+        Self {
+            file,
+            path,
+            sync_on_close: false,
+        }
+    }
+
+    /// Flushes the content according to the policy, before it is swapped
+    pub(super) fn sync(&mut self, durability: Durability) -> Result<()> {
+        match durability {
+            Durability::Always => self.file.sync_data(),
+            Durability::OnClose => {
+                self.sync_on_close = true;
+                Ok(())
+            }
+            Durability::None => Ok(()),
+        }
     }
 }
 
@@ -42,7 +97,13 @@ impl std::io::Write for &TmpFile {
 
 impl Drop for TmpFile {
     fn drop(&mut self) {
-        let _ = std::fs::remove_file(&self.path);
+        // the new version shares the data with the temporary file
+        if self.sync_on_close {
+            if let Err(e) = self.file.sync_data() {
+                log::warn!("Couldn't sync {}: {}", self.path.display(), e);
+            }
+        }
+        let _ = retry_shared(|| std::fs::remove_file(&self.path));
     }
 }
 
@@ -60,7 +121,7 @@ impl ReadOnlyFile {
     /// of the`AtomicFile` has been created yet.
     pub fn open(&self) -> Result<Option<File>> {
         if self.version != 0 {
-            Ok(Some(File::open(&self.path)?))
+            Ok(Some(retry_shared(|| File::open(&self.path))?))
         } else {
             Ok(None)
         }
@@ -95,6 +156,19 @@ impl ReadOnlyFile {
 pub struct AtomicFile {
     pub directory: PathBuf,
     pub prefix: String,
+    pub durability: Durability,
+    pub swap: SwapStrategy,
+}
+
+/// Files of the directory of an [`AtomicFile`], which `modify` and
+/// `modify_json` reuse from loading till swapping
+pub(super) struct Listing {
+    pub(super) version: usize,
+    pub(super) files: Vec<ReadOnlyFile>,
+    paths: Vec<PathBuf>,
+    /// Modification time of the directory before listing, if old enough
+    /// to tell later modifications apart
+    modified: Option<SystemTime>,
 }
 
 fn parse_version(filename: Option<&str>) -> Option<usize> {
@@ -102,6 +176,150 @@ fn parse_version(filename: Option<&str>) -> Option<usize> {
     version.parse().ok()
 }
 
+/// Finds the latest version among the files of the directory together
+/// with all files of this version
+pub(super) fn latest_files(
+    paths: impl Iterator<Item = PathBuf>,
+) -> (usize, Vec<ReadOnlyFile>) {
+    let (files, version) = paths.fold(
+        (vec![], 0),
+        |(mut files, mut current_max_version), path| {
+            let filename = path.file_name().and_then(|name| name.to_str());
+            if let Some(version) = parse_version(filename) {
+                // It's possible to have same version for two files coming
+                // from different devices or even different local apps.
+                // Add such files to the result
+                if version >= current_max_version {
+                    files.push(ReadOnlyFile { version, path });
+                    current_max_version = version;
+                }
+            }
+            (files, current_max_version)
+        },
+    );
+    let files = files
+        .into_iter()
+        .filter(|file| file.version == version)
+        .collect();
+    (version, files)
+}
+
+/// Deletes versions too old to be kept, returning the number of files
+/// deleted
+fn prune<'a>(
+    paths: impl Iterator<Item = &'a PathBuf>,
+    version: usize,
+) -> usize {
+    let mut deleted = 0;
+    for path in paths {
+        let filename = path.file_name().and_then(|name| name.to_str());
+        if let Some(file_version) = parse_version(filename) {
+            if file_version + MAX_VERSION_FILES - 1 <= version
+                && retry_shared(|| fs::remove_file(path)).is_ok()
+            {
+                deleted += 1;
+            }
+        }
+    }
+    deleted
+}
+
+/// Decides whether the failed link of the new version failed indeed
+pub(super) fn check_link(new: &TmpFile, err: Error) -> Result<()> {
+    // From open(2) manual page:
+    //
+    // "[...] create a unique file on the same filesystem (e.g.,
+    // incorporating hostname and PID), and use link(2) to make a link
+    // to the lockfile. If link(2) returns 0, the lock is successful.
+    // Otherwise, use stat(2) on the unique file to check if its link
+    // count has increased to 2, in which case the lock is also
+    // succesful."
+    match link_count(&new.path.metadata()?) {
+        Some(2) => Ok(()),
+        _ => Err(err),
+    }
+}
+
+/// File next to a version being written, named uniquely by every writer
+///
+/// A writer holds the claim only if no other fresh claim of the version
+/// exists after creating its own. Of writers claiming concurrently, the
+/// later ones see the earlier claims and give up, at worst all of them do.
+/// Claims are never replaced, so a writer only ever removes its own claim
+/// and claims older than [`STALE_CLAIM`], which no writer holds anymore.
+struct Claim(PathBuf);
+
+impl Claim {
+    fn acquire(version: &Path) -> Result<Self> {
+        let (Some(directory), Some(name)) =
+            (version.parent(), version.file_name())
+        else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the version has no file name",
+            ));
+        };
+        let prefix = format!("{}.claim.", name.to_string_lossy());
+        let token: String = std::iter::repeat_with(fastrand::alphanumeric)
+            .take(10)
+            .collect();
+        let path = directory.join(format!("{}{}", prefix, token));
+        File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let claim = Self(path);
+
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            if entry.path() == claim.0
+                || !entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(&prefix)
+            {
+                continue;
+            }
+            let stale = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    modified
+                        .elapsed()
+                        .is_ok_and(|age| age > STALE_CLAIM)
+                });
+            if !stale {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    "the version is claimed by another writer",
+                ));
+            }
+            log::warn!("Removing stale claim {}", entry.path().display());
+            let _ = fs::remove_file(entry.path());
+        }
+        Ok(claim)
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Renames the temporary file to the new version unless the version
+/// exists or is being written, since renaming replaces existing files
+pub(super) fn rename_version(tmp: &Path, new_path: &Path) -> Result<()> {
+    let _claim = Claim::acquire(new_path)?;
+    if new_path.exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            "the new version has been written already",
+        ));
+    }
+    retry_shared(|| fs::rename(tmp, new_path))
+}
+
 impl AtomicFile {
     pub fn new(path: impl Into<PathBuf>) -> crate::Result<Self> {
         let directory = path.into();
@@ -112,14 +330,30 @@ impl AtomicFile {
 
         std::fs::create_dir_all(&directory)?;
         let filename: &str = match directory.file_name() {
-            Some(name) => name.to_str().unwrap(),
+            Some(name) => name.to_str().ok_or(crate::ArklibError::Parse)?,
             None => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "`path` must specify a directory name",
             ))?,
         };
         let prefix = format!("{}_{}.", filename, app_id);
-        Ok(Self { directory, prefix })
+        let swap = crate::probe::swap_strategy(&directory);
+        Ok(Self {
+            directory,
+            prefix,
+            durability: Durability::default(),
+            swap,
+        })
+    }
+
+    pub fn with_durability(self, durability: Durability) -> Self {
+        Self { durability, ..self }
+    }
+
+    /// Overrides the strategy chosen from the probe of the root, see
+    /// [`crate::probe::FsCapabilities::hard_links`]
+    pub fn with_swap(self, swap: SwapStrategy) -> Self {
+        Self { swap, ..self }
     }
 
     /// Return the latest version together with vector of the
@@ -127,39 +361,35 @@ impl AtomicFile {
     /// can appear due to usage of file syncronization. Different devices
     /// can create same version simultaneously.
     pub fn latest_version(&self) -> Result<(usize, Vec<ReadOnlyFile>)> {
-        let files_iterator = fs::read_dir(&self.directory)?.flatten();
-        let (files, version) = files_iterator.into_iter().fold(
-            (vec![], 0),
-            |(mut files, mut current_max_version), entry| {
-                let filename = entry.file_name();
-                if let Some(version) = parse_version(filename.to_str()) {
-                    // It's possible to have same version for two files coming
-                    // from different devices or even different local apps.
-                    // Add such files to the result
-                    if version >= current_max_version {
-                        let read_only = ReadOnlyFile {
-                            version,
-                            path: entry.path(),
-                        };
-                        files.push(read_only);
-                        current_max_version = version;
-                    }
-                }
-                (files, current_max_version)
-            },
-        );
-        let files = files
-            .into_iter()
-            .filter_map(|file| {
-                let file_version = parse_version(file.path.to_str())?;
-                if file_version == version {
-                    Some(file)
-                } else {
-                    None
-                }
-            })
+        let entries = fs::read_dir(&self.directory)?
+            .flatten()
+            .map(|entry| entry.path());
+        Ok(latest_files(entries))
+    }
+
+    pub(super) fn list(&self) -> Result<Listing> {
+        let modified = self.directory_modified().filter(|modified| {
+            SystemTime::now()
+                .duration_since(*modified)
+                .is_ok_and(|age| age >= MTIME_GRANULARITY)
+        });
+        let paths: Vec<PathBuf> = fs::read_dir(&self.directory)?
+            .flatten()
+            .map(|entry| entry.path())
             .collect();
-        Ok((version, files))
+        let (version, files) = latest_files(paths.iter().cloned());
+        Ok(Listing {
+            version,
+            files,
+            paths,
+            modified,
+        })
+    }
+
+    fn directory_modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.directory)
+            .and_then(|metadata| metadata.modified())
+            .ok()
     }
 
     pub fn path(&self, version: usize) -> PathBuf {
@@ -168,7 +398,16 @@ impl AtomicFile {
     }
 
     pub fn load(&self) -> Result<ReadOnlyFile> {
-        let (version, mut files) = self.latest_version()?;
+        let (version, files) = self.latest_version()?;
+        self.choose_file(version, files)
+    }
+
+    /// Chooses the file of this app among files of the latest version
+    pub(super) fn choose_file(
+        &self,
+        version: usize,
+        mut files: Vec<ReadOnlyFile>,
+    ) -> Result<ReadOnlyFile> {
         let file = match files.len() {
             0 => ReadOnlyFile {
                 version,
@@ -215,58 +454,78 @@ impl AtomicFile {
         &self,
         current: &ReadOnlyFile,
         new: TmpFile,
+    ) -> Result<()> {
+        self.swap(current, new, None)
+    }
+
+    /// Same as [`AtomicFile::compare_and_swap`], but the directory isn't
+    /// listed again if it hasn't been modified since `listing`
+    pub(super) fn compare_and_swap_listed(
+        &self,
+        current: &ReadOnlyFile,
+        new: TmpFile,
+        listing: &Listing,
+    ) -> Result<()> {
+        self.swap(current, new, Some(listing))
+    }
+
+    fn swap(
+        &self,
+        current: &ReadOnlyFile,
+        mut new: TmpFile,
+        listing: Option<&Listing>,
     ) -> Result<()> {
         let new_path = self.path(current.version + 1);
-        (new.file).sync_data()?;
+        new.sync(self.durability)?;
         // Just to check if current.version is still the latest_version
-        let (latest_version, _) = self.latest_version()?;
+        let listing = listing.filter(|listing| {
+            listing.modified.is_some()
+                && listing.modified == self.directory_modified()
+        });
+        let latest_version = match listing {
+            Some(listing) => listing.version,
+            None => self.latest_version()?.0,
+        };
         if latest_version > current.version {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::AlreadyExists,
                 "the `current` file is not the latest version",
             ));
         }
-        // May return `EEXIST`.
-        let res = std::fs::hard_link(&new.path, new_path);
-        if let Err(err) = res {
-            #[cfg(target_os = "unix")]
-            // From open(2) manual page:
-            //
-            // "[...] create a unique file on the same filesystem (e.g.,
-            // incorporating hostname and PID), and use link(2) to make a link
-            // to the lockfile. If link(2) returns 0, the lock is successful.
-            // Otherwise, use stat(2) on the unique file to check if its link
-            // count has increased to 2, in which case the lock is also
-            // succesful."
-            if new.path.metadata()?.nlink() != 2 {
-                Err(err)?;
+        match self.swap {
+            SwapStrategy::HardLink => {
+                // May return `EEXIST`.
+                let res =
+                    retry_shared(|| std::fs::hard_link(&new.path, &new_path));
+                if let Err(err) = res {
+                    check_link(&new, err)?;
+                }
             }
-            #[cfg(not(target_os = "unix"))]
-            Err(err)?;
+            SwapStrategy::Rename => rename_version(&new.path, &new_path)?,
         }
 
-        let number_of_removed = self.prune_old_versions(latest_version);
+        let number_of_removed = match listing {
+            Some(listing) => prune(listing.paths.iter(), latest_version),
+            None => self.prune_old_versions(latest_version),
+        };
         log::debug!("pruned {} old files", number_of_removed);
+        notify(self, current.version + 1);
         Ok(())
     }
 
     /// Return the number of files deleted
-    fn prune_old_versions(&self, version: usize) -> usize {
-        let mut deleted = 0;
-        if let Ok(iterator) = fs::read_dir(&self.directory) {
-            for entry in iterator.flatten() {
-                if let Some(file_version) =
-                    parse_version(entry.file_name().to_str())
-                {
-                    if file_version + MAX_VERSION_FILES - 1 <= version
-                        && fs::remove_file(entry.path()).is_ok()
-                    {
-                        deleted += 1;
-                    }
-                }
-            }
+    pub(super) fn prune_old_versions(&self, version: usize) -> usize {
+        match fs::read_dir(&self.directory) {
+            Ok(iterator) => prune(
+                iterator
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .collect::<Vec<_>>()
+                    .iter(),
+                version,
+            ),
+            Err(_) => 0,
         }
-        deleted
     }
 }
 
@@ -300,6 +559,65 @@ mod tests {
         assert_eq!(version_files, MAX_VERSION_FILES);
     }
 
+    #[test]
+    fn rename_swaps_detect_conflicts() {
+        initialize();
+        let dir = TempDir::new("rename_swaps").unwrap();
+        let file = AtomicFile::new(dir.path().join("numbers"))
+            .unwrap()
+            .with_swap(SwapStrategy::Rename);
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let file = file.clone();
+                std::thread::spawn(move || {
+                    crate::atomic::modify_json(
+                        &file,
+                        |numbers: &mut Option<Vec<u32>>| {
+                            numbers.get_or_insert_with(Vec::new).push(i)
+                        },
+                    )
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+        let latest = file.load().unwrap();
+        assert_eq!(latest.version, 8);
+        let mut numbers: Vec<u32> =
+            serde_json::from_slice(&latest.read_content().unwrap()).unwrap();
+        numbers.sort();
+        assert_eq!(numbers, (0..8).collect::<Vec<_>>());
+
+        // a swap is refused while the version is claimed, unless the claim
+        // was left long ago
+        let claim =
+            PathBuf::from(format!("{}.claim.crashed", file.path(9).display()));
+        let claimed = File::create(&claim).unwrap();
+        let temp = file.make_temp().unwrap();
+        let err = file.compare_and_swap(&latest, temp).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        claimed
+            .set_modified(SystemTime::now() - STALE_CLAIM * 2)
+            .unwrap();
+        let temp = file.make_temp().unwrap();
+        file.compare_and_swap(&latest, temp).unwrap();
+        assert!(!claim.exists());
+        assert_eq!(file.load().unwrap().version, 9);
+        let claims = fs::read_dir(&file.directory)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .contains(".claim.")
+            })
+            .count();
+        assert_eq!(claims, 0);
+    }
+
     #[test]
     fn multiple_version_files() {
         initialize();
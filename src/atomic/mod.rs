@@ -1,9 +1,19 @@
+#[cfg(feature = "async")]
+mod async_file;
+mod conflict;
 mod file;
+mod notify;
 
 use serde::{de::DeserializeOwned, Serialize};
 use std::io::{Read, Result, Write};
 
-pub use file::AtomicFile;
+pub use conflict::{ConflictingVersion, Resolution};
+pub(crate) use file::ReadOnlyFile;
+pub use file::{AtomicFile, Durability, SwapStrategy};
+pub use notify::{subscribe, FileChange};
+
+#[cfg(feature = "async")]
+pub use async_file::modify_json_async;
 
 pub fn modify(
     atomic_file: &AtomicFile,
@@ -11,16 +21,20 @@ pub fn modify(
 ) -> Result<()> {
     let mut buf = vec![];
     loop {
-        let latest = atomic_file.load()?;
+        // the temporary file is created before listing, so that it
+        // doesn't invalidate the listing
+        let tmp = atomic_file.make_temp()?;
+        let listing = atomic_file.list()?;
+        let latest =
+            atomic_file.choose_file(listing.version, listing.files.clone())?;
         buf.clear();
         if let Some(mut file) = latest.open()? {
             file.read_to_end(&mut buf)?;
         }
         let data = operator(&buf);
-        let tmp = atomic_file.make_temp()?;
         (&tmp).write_all(&data)?;
         (&tmp).flush()?;
-        match atomic_file.compare_and_swap(&latest, tmp) {
+        match atomic_file.compare_and_swap_listed(&latest, tmp, &listing) {
             Ok(()) => return Ok(()),
             Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
                 continue
@@ -35,18 +49,20 @@ pub fn modify_json<T: Serialize + DeserializeOwned>(
     mut operator: impl FnMut(&mut Option<T>),
 ) -> Result<()> {
     loop {
-        let latest = atomic_file.load()?;
+        let tmp = atomic_file.make_temp()?;
+        let listing = atomic_file.list()?;
+        let latest =
+            atomic_file.choose_file(listing.version, listing.files.clone())?;
         let mut val = None;
         if let Some(file) = latest.open()? {
             val = Some(serde_json::from_reader(std::io::BufReader::new(file))?);
         }
         operator(&mut val);
-        let tmp = atomic_file.make_temp()?;
         let mut writer = std::io::BufWriter::new(&tmp);
         serde_json::to_writer(&mut writer, &val)?;
         writer.flush()?;
         drop(writer);
-        match atomic_file.compare_and_swap(&latest, tmp) {
+        match atomic_file.compare_and_swap_listed(&latest, tmp, &listing) {
             Ok(()) => return Ok(()),
             Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
                 continue
@@ -135,4 +151,39 @@ mod tests {
             assert!(last_content.contains(&as_byte));
         }
     }
+
+    #[test]
+    fn listing_is_reused_until_modified() {
+        initialize();
+
+        let dir = TempDir::new("listing").unwrap();
+        let file = AtomicFile::new(dir.path().join("data"))
+            .unwrap()
+            .with_durability(Durability::OnClose);
+        modify_json(&file, |value: &mut Option<u32>| *value = Some(1)).unwrap();
+
+        let tmp = file.make_temp().unwrap();
+        let past =
+            std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::open(&file.directory)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        let listing = file.list().unwrap();
+        let latest = file
+            .choose_file(listing.version, listing.files.clone())
+            .unwrap();
+        assert_eq!(latest.version, 1);
+        // another device writes the next version in the meantime
+        std::fs::write(file.directory.join("data_other.2"), "2").unwrap();
+        let err = file
+            .compare_and_swap_listed(&latest, tmp, &listing)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+
+        let file = file.with_durability(Durability::None);
+        modify_json(&file, |value: &mut Option<u32>| *value = Some(3)).unwrap();
+        assert_eq!(file.load().unwrap().version, 3);
+        assert_eq!(file.load().unwrap().read_to_string().unwrap(), "3");
+    }
 }
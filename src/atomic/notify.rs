@@ -0,0 +1,94 @@
+//! Notifications about new versions of atomic files
+//!
+//! Every successful swap of any [`AtomicFile`] of the process is sent to
+//! all subscribers, so that caches of tags, search indexes or UI can be
+//! invalidated without polling the directories. Versions brought by file
+//! synchronization from other devices are not reported.
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+use super::AtomicFile;
+
+static SUBSCRIBERS: Lazy<Mutex<Vec<Sender<FileChange>>>> =
+    Lazy::new(|| Mutex::new(vec![]));
+
+/// New version of an [`AtomicFile`] written by this process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    /// Directory of the [`AtomicFile`]
+    pub directory: PathBuf,
+    /// File of the new version
+    pub path: PathBuf,
+    pub version: usize,
+    /// ID of the app which wrote the version
+    pub writer: String,
+}
+
+/// Returns a channel receiving changes of all atomic files, until
+/// the receiver is dropped
+pub fn subscribe() -> Receiver<FileChange> {
+    let (sender, receiver) = mpsc::channel();
+    SUBSCRIBERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(sender);
+    receiver
+}
+
+pub(super) fn notify(file: &AtomicFile, version: usize) {
+    let mut subscribers = SUBSCRIBERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if subscribers.is_empty() {
+        return;
+    }
+    let name = file
+        .directory
+        .file_name()
+        .map(|name| format!("{}_", name.to_string_lossy()))
+        .unwrap_or_default();
+    let writer = file
+        .prefix
+        .strip_prefix(&name)
+        .unwrap_or(&file.prefix)
+        .trim_end_matches('.');
+    let change = FileChange {
+        directory: file.directory.clone(),
+        path: file.path(version),
+        version,
+        writer: writer.to_string(),
+    };
+    subscribers.retain(|subscriber| subscriber.send(change.clone()).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atomic::modify_json;
+    use crate::{app_id, initialize};
+    use tempdir::TempDir;
+
+    #[test]
+    fn swaps_are_notified() {
+        initialize();
+
+        let dir = TempDir::new("notify").unwrap();
+        let file = AtomicFile::new(dir.path().join("data")).unwrap();
+        let changes = subscribe();
+        modify_json(&file, |value: &mut Option<u32>| *value = Some(1)).unwrap();
+        modify_json(&file, |value: &mut Option<u32>| *value = Some(2)).unwrap();
+
+        // other tests write atomic files at the same time
+        let changes: Vec<FileChange> = changes
+            .try_iter()
+            .filter(|change| change.directory == file.directory)
+            .collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].version, 2);
+        assert_eq!(changes[1].path, file.load().unwrap().path);
+        assert_eq!(changes[1].writer, app_id::read().unwrap());
+    }
+}
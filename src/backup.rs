@@ -0,0 +1,447 @@
+//! Integrity-checked backups of a whole root
+//!
+//! A backup is a folder holding copies of all resources under `content`,
+//! copies of the `.ark` folder without generated data under `ark`, and
+//! `manifest.json` listing every copied file together with its checksum
+//! and, for resources, its ID. The manifest is written last, so a backup
+//! interrupted midway has no manifest and is never mistaken for a
+//! complete one.
+//!
+//! IDs are checked with the namespace of the backed up root, which is
+//! recorded in the manifest.
+use std::fs::{self, File};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+use crate::index::{IndexEntry, IndexOptions};
+use crate::resource::{Namespace, ResourceId};
+use crate::util::fs::{join_relative, relative_path, write_file};
+use crate::{
+    provide_index, ArklibError, Result, ARK_FOLDER, INDEX_DB_FILE,
+    INDEX_LOCK_FILE, INDEX_PATH, WRITER_LOCK_FILE,
+};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+const CONTENT_FOLDER: &str = "content";
+const ARK_COPY_FOLDER: &str = "ark";
+/// Generated data is not backed up, it is regenerated after restore
+const GENERATED_FOLDER: &str = "cache";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupEntry {
+    /// Path relative to the root, with `/` as separator
+    pub path: String,
+    /// Blake3 hash of the content
+    pub checksum: String,
+    /// ID of the resource, absent for files of the `.ark` folder and for
+    /// placeholders, which IDs don't depend on the content
+    pub id: Option<ResourceId>,
+    /// Whether the ID is computed from samples of the content
+    #[serde(default)]
+    pub sampled: bool,
+}
+
+/// Snapshot of the index of the root together with its user data
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// UUID of the namespace of the root, see [`crate::resource::Namespace`]
+    #[serde(default)]
+    pub namespace: Option<String>,
+    pub resources: Vec<BackupEntry>,
+    /// Files of the `.ark` folder, paths are relative to it
+    pub user_data: Vec<BackupEntry>,
+}
+
+/// Problems found in a backup during verification or restore
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    /// Number of files which passed verification
+    pub verified: usize,
+    /// Files listed in the manifest but absent from the backup
+    pub missing: Vec<String>,
+    /// Files which content doesn't match the manifest,
+    /// these are never restored
+    pub corrupted: Vec<String>,
+}
+
+impl RestoreReport {
+    pub fn is_intact(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+/// Copies all resources and user data of the root into `dest`,
+/// which must not contain another backup
+pub fn backup<P: AsRef<Path>, Q: AsRef<Path>>(
+    root: P,
+    dest: Q,
+) -> Result<BackupManifest> {
+    let dest = dest.as_ref();
+    if dest.join(MANIFEST_FILE).exists() {
+        return Err(ArklibError::Collision(format!(
+            "{} already contains a backup",
+            dest.display()
+        )));
+    }
+
+    let index = provide_index(&root)?;
+    let index = index.read().unwrap_or_else(|e| e.into_inner());
+    let root = index.root();
+    log::info!("Backing up {} into {}", root.display(), dest.display());
+
+    let mut manifest = BackupManifest {
+        namespace: index
+            .options()
+            .namespace
+            .map(|namespace| namespace.uuid().to_string()),
+        ..Default::default()
+    };
+    let mut resources: Vec<(&Path, &IndexEntry)> = index.entries().collect();
+    resources.sort_by_key(|(path, _)| *path);
+    for (path, entry) in resources {
+        let relative = relative_path(root, path)?;
+        let copy = join_relative(&dest.join(CONTENT_FOLDER), &relative)?;
+        manifest.resources.push(BackupEntry {
+            checksum: copy_file(path, &copy)?,
+            path: relative,
+            id: (!entry.remote).then_some(entry.id),
+            sampled: entry.sampled,
+        });
+    }
+
+    let ark = root.join(ARK_FOLDER);
+    for entry in WalkDir::new(&ark)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() != 1
+                || (entry.file_name() != GENERATED_FOLDER
+                    && entry.file_name() != INDEX_PATH
+                    && !entry
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with(INDEX_DB_FILE)
+                    && entry.file_name() != INDEX_LOCK_FILE
+                    && entry.file_name() != WRITER_LOCK_FILE)
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let relative = relative_path(&ark, entry.path())?;
+        let copy = join_relative(&dest.join(ARK_COPY_FOLDER), &relative)?;
+        manifest.user_data.push(BackupEntry {
+            checksum: copy_file(entry.path(), &copy)?,
+            path: relative,
+            id: None,
+            sampled: false,
+        });
+    }
+
+    write_file(
+        &dest.join(MANIFEST_FILE),
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    log::info!(
+        "Backed up {} resources and {} user data files",
+        manifest.resources.len(),
+        manifest.user_data.len()
+    );
+    Ok(manifest)
+}
+
+/// Checks every file of the backup against the manifest
+/// without restoring anything
+pub fn verify<P: AsRef<Path>>(backup: P) -> Result<RestoreReport> {
+    let backup = backup.as_ref();
+    let manifest = load_manifest(backup)?;
+    let options = id_options(&manifest)?;
+
+    let mut report = RestoreReport::default();
+    for (folder, entry) in entries(&manifest) {
+        check(&backup.join(folder), entry, &options, &mut report)?;
+    }
+    Ok(report)
+}
+
+/// Restores the backup into `dest_root`, verifying every file against
+/// the manifest and resources against their IDs
+///
+/// Files which fail verification are skipped and listed in the report.
+/// Existing files of `dest_root` are never overwritten.
+pub fn restore<P: AsRef<Path>, Q: AsRef<Path>>(
+    backup: P,
+    dest_root: Q,
+) -> Result<RestoreReport> {
+    let backup = backup.as_ref();
+    let dest_root = dest_root.as_ref();
+    let manifest = load_manifest(backup)?;
+    let options = id_options(&manifest)?;
+    log::info!(
+        "Restoring {} into {}",
+        backup.display(),
+        dest_root.display()
+    );
+
+    let mut report = RestoreReport::default();
+    for (folder, entry) in entries(&manifest) {
+        let source = join_relative(&backup.join(folder), &entry.path)?;
+        let destination = match folder {
+            CONTENT_FOLDER => join_relative(dest_root, &entry.path)?,
+            _ => join_relative(&dest_root.join(ARK_FOLDER), &entry.path)?,
+        };
+        if destination.exists() {
+            return Err(ArklibError::Collision(format!(
+                "{} already exists",
+                destination.display()
+            )));
+        }
+        if check(&backup.join(folder), entry, &options, &mut report)? {
+            copy_file(&source, &destination)?;
+        }
+    }
+
+    if !report.is_intact() {
+        log::warn!(
+            "Backup is damaged: {} files missing, {} files corrupted",
+            report.missing.len(),
+            report.corrupted.len()
+        );
+    }
+    Ok(report)
+}
+
+fn load_manifest(backup: &Path) -> Result<BackupManifest> {
+    let path = backup.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Err(ArklibError::Path(format!(
+            "{} has no manifest, the backup is incomplete",
+            backup.display()
+        )));
+    }
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Options computing IDs the same way as the index of the backed up root
+fn id_options(manifest: &BackupManifest) -> Result<IndexOptions> {
+    let namespace = match &manifest.namespace {
+        Some(uuid) => Some(Namespace::new(
+            Uuid::parse_str(uuid).map_err(|_| ArklibError::Parse)?,
+        )),
+        None => None,
+    };
+    Ok(IndexOptions {
+        namespace,
+        ..Default::default()
+    })
+}
+
+fn entries(
+    manifest: &BackupManifest,
+) -> impl Iterator<Item = (&'static str, &BackupEntry)> {
+    manifest
+        .resources
+        .iter()
+        .map(|entry| (CONTENT_FOLDER, entry))
+        .chain(
+            manifest
+                .user_data
+                .iter()
+                .map(|entry| (ARK_COPY_FOLDER, entry)),
+        )
+}
+
+/// Verifies a single file of the backup, recording the outcome
+fn check(
+    folder: &Path,
+    entry: &BackupEntry,
+    options: &IndexOptions,
+    report: &mut RestoreReport,
+) -> Result<bool> {
+    let path = join_relative(folder, &entry.path)?;
+    if !path.is_file() {
+        log::warn!("{} is missing from the backup", entry.path);
+        report.missing.push(entry.path.clone());
+        return Ok(false);
+    }
+
+    let mut intact = checksum(&path)? == entry.checksum;
+    if let Some(id) = entry.id {
+        intact = intact && options.content_id(&path, entry.sampled)? == id;
+    }
+    if intact {
+        report.verified += 1;
+    } else {
+        log::warn!("{} is corrupted in the backup", entry.path);
+        report.corrupted.push(entry.path.clone());
+    }
+    Ok(intact)
+}
+
+/// Copies the file, returning checksum of the copied content
+fn copy_file(source: &Path, destination: &Path) -> Result<String> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(source, destination)?;
+    checksum(destination)
+}
+
+fn checksum(path: &Path) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use tempdir::TempDir;
+
+    fn create_root() -> TempDir {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let root = TempDir::new("arklib_test").unwrap();
+        fs::write(root.path().join("a.txt"), "first").unwrap();
+        fs::create_dir(root.path().join("sub")).unwrap();
+        fs::write(root.path().join("sub/b.txt"), "second").unwrap();
+
+        let tags = root.path().join(ARK_FOLDER).join("user/tags");
+        fs::create_dir_all(&tags).unwrap();
+        fs::write(tags.join("tags_device.1"), "vacation").unwrap();
+        let previews = root
+            .path()
+            .join(ARK_FOLDER)
+            .join("cache/previews");
+        fs::create_dir_all(&previews).unwrap();
+        fs::write(previews.join("preview"), "generated").unwrap();
+        root
+    }
+
+    #[test]
+    fn backup_and_restore_roundtrip() {
+        let root = create_root();
+        let dest = TempDir::new("arklib_test").unwrap();
+        let restored = TempDir::new("arklib_test").unwrap();
+
+        let manifest = backup(root.path(), dest.path()).unwrap();
+        assert_eq!(manifest.resources.len(), 2);
+        // the root config is written while opening the root
+        let user_data: Vec<&str> = manifest
+            .user_data
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+        assert!(user_data.contains(&"user/tags/tags_device.1"));
+        assert!(!user_data
+            .iter()
+            .any(|path| path.starts_with("cache")));
+        assert!(verify(dest.path()).unwrap().is_intact());
+        assert!(backup(root.path(), dest.path()).is_err());
+
+        let report = restore(dest.path(), restored.path()).unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.verified, 2 + user_data.len());
+        assert_eq!(
+            fs::read_to_string(restored.path().join("sub/b.txt")).unwrap(),
+            "second"
+        );
+        assert!(restored
+            .path()
+            .join(ARK_FOLDER)
+            .join("user/tags/tags_device.1")
+            .exists());
+        assert!(!restored
+            .path()
+            .join(ARK_FOLDER)
+            .join("cache")
+            .exists());
+
+        // restoring twice would overwrite the restored files
+        assert!(restore(dest.path(), restored.path()).is_err());
+    }
+
+    #[test]
+    fn damaged_backup_is_reported() {
+        let root = create_root();
+        let dest = TempDir::new("arklib_test").unwrap();
+        let restored = TempDir::new("arklib_test").unwrap();
+        let manifest = backup(root.path(), dest.path()).unwrap();
+
+        let content = dest.path().join(CONTENT_FOLDER);
+        fs::write(content.join("a.txt"), "tampered").unwrap();
+        fs::remove_file(content.join("sub/b.txt")).unwrap();
+
+        let report = verify(dest.path()).unwrap();
+        assert_eq!(report.corrupted, vec!["a.txt".to_string()]);
+        assert_eq!(report.missing, vec!["sub/b.txt".to_string()]);
+
+        let report = restore(dest.path(), restored.path()).unwrap();
+        assert!(!report.is_intact());
+        assert_eq!(report.verified, manifest.user_data.len());
+        assert!(!restored.path().join("a.txt").exists());
+    }
+
+    #[test]
+    fn namespaced_ids_are_verified() {
+        let root = create_root();
+        let dest = TempDir::new("arklib_test").unwrap();
+        let restored = TempDir::new("arklib_test").unwrap();
+        let namespace = crate::resource::set_namespaced(root.path(), true)
+            .unwrap()
+            .unwrap();
+
+        let manifest = backup(root.path(), dest.path()).unwrap();
+        assert_eq!(manifest.namespace, Some(namespace.uuid().to_string()));
+        let plain = ResourceId::compute_bytes(b"first").unwrap();
+        assert_eq!(manifest.resources[0].id, Some(namespace.apply(plain)));
+
+        assert!(verify(dest.path()).unwrap().is_intact());
+        let report = restore(dest.path(), restored.path()).unwrap();
+        assert!(report.is_intact());
+        assert_eq!(
+            report.verified,
+            manifest.resources.len() + manifest.user_data.len()
+        );
+    }
+
+    #[test]
+    fn sampled_ids_are_verified() {
+        let root = create_root();
+        let dest = TempDir::new("arklib_test").unwrap();
+        let mut manifest = backup(root.path(), dest.path()).unwrap();
+
+        // recorded from an index with sampled IDs
+        let copy = dest.path().join(CONTENT_FOLDER).join("a.txt");
+        let entry = &mut manifest.resources[0];
+        entry.id = Some(ResourceId::compute_sampled(5, &copy).unwrap());
+        entry.sampled = true;
+        fs::write(
+            dest.path().join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        assert!(verify(dest.path()).unwrap().is_intact());
+
+        manifest.resources[0].sampled = false;
+        fs::write(
+            dest.path().join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        let report = verify(dest.path()).unwrap();
+        assert_eq!(report.corrupted, vec!["a.txt".to_string()]);
+    }
+
+    #[test]
+    fn incomplete_backup_is_rejected() {
+        let root = create_root();
+        let dest = TempDir::new("arklib_test").unwrap();
+        backup(root.path(), dest.path()).unwrap();
+        fs::remove_file(dest.path().join(MANIFEST_FILE)).unwrap();
+
+        assert!(verify(dest.path()).is_err());
+    }
+}
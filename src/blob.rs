@@ -0,0 +1,285 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use fastcdc::v2020::StreamCDC;
+use serde::{Deserialize, Serialize};
+
+use crate::resource::ResourceId;
+use crate::util::fs::write_file;
+use crate::{ArklibError, Result, ARK_FOLDER, BLOBS_STORAGE_FOLDER};
+
+/// Chunks smaller than this are only produced at the end of the content
+pub const MIN_CHUNK_SIZE: u32 = 16 * 1024;
+pub const AVG_CHUNK_SIZE: u32 = 64 * 1024;
+pub const MAX_CHUNK_SIZE: u32 = 256 * 1024;
+
+const CHUNKS_FOLDER: &str = "chunks";
+const MANIFESTS_FOLDER: &str = "manifests";
+
+/// A content-defined chunk of a blob, addressed by its blake3 hash
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Chunk {
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Ordered list of chunks which content of a resource consists of
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobManifest {
+    pub id: ResourceId,
+    pub chunks: Vec<Chunk>,
+}
+
+impl BlobManifest {
+    pub fn size(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.size).sum()
+    }
+}
+
+/// Content-addressed store of resource contents under `.ark/cache/blobs`
+///
+/// Contents are split into chunks using FastCDC, so boundaries of chunks
+/// depend on the content itself. A slightly modified version of a large
+/// file shares most of its chunks with the original version, and every
+/// chunk is stored only once no matter how many blobs refer to it.
+pub struct BlobStore {
+    directory: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let directory = root
+            .as_ref()
+            .join(ARK_FOLDER)
+            .join(BLOBS_STORAGE_FOLDER);
+        fs::create_dir_all(directory.join(CHUNKS_FOLDER))?;
+        fs::create_dir_all(directory.join(MANIFESTS_FOLDER))?;
+        Ok(Self { directory })
+    }
+
+    /// Splits content of the resource into chunks, storing only the chunks
+    /// which are not present in the store yet
+    pub fn put<R: Read>(
+        &self,
+        id: ResourceId,
+        reader: R,
+    ) -> Result<BlobManifest> {
+        log::debug!("Storing blob of {}", id);
+
+        let mut chunks = vec![];
+        let chunker = StreamCDC::new(
+            reader,
+            MIN_CHUNK_SIZE,
+            AVG_CHUNK_SIZE,
+            MAX_CHUNK_SIZE,
+        );
+        for chunk in chunker {
+            let chunk = chunk.map_err(std::io::Error::from)?;
+            let hash = blake3::hash(&chunk.data).to_hex().to_string();
+            let path = self.chunk_path(&hash);
+            if !path.exists() {
+                write_file(&path, &chunk.data)?;
+            } else {
+                log::trace!("[blob] chunk {} is already stored", hash);
+            }
+            chunks.push(Chunk {
+                hash,
+                size: chunk.length as u64,
+            });
+        }
+
+        let manifest = BlobManifest { id, chunks };
+        write_file(&self.manifest_path(id), &serde_json::to_vec(&manifest)?)?;
+        Ok(manifest)
+    }
+
+    pub fn put_file<P: AsRef<Path>>(
+        &self,
+        id: ResourceId,
+        path: P,
+    ) -> Result<BlobManifest> {
+        let file = File::open(path)?;
+        self.put(id, BufReader::new(file))
+    }
+
+    pub fn contains(&self, id: ResourceId) -> bool {
+        self.manifest_path(id).exists()
+    }
+
+    pub fn manifest(&self, id: ResourceId) -> Result<Option<BlobManifest>> {
+        let path = self.manifest_path(id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(path)?;
+        Ok(Some(serde_json::from_reader(BufReader::new(file))?))
+    }
+
+    /// Returns the chunks of the manifest which are absent from the store,
+    /// so only these need to be transferred from another device
+    pub fn missing_chunks(&self, manifest: &BlobManifest) -> Vec<Chunk> {
+        let mut seen = HashSet::new();
+        manifest
+            .chunks
+            .iter()
+            .filter(|chunk| {
+                seen.insert(&chunk.hash)
+                    && !self.chunk_path(&chunk.hash).exists()
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Stores a single chunk received from elsewhere, verifying its hash
+    pub fn put_chunk(&self, chunk: &Chunk, data: &[u8]) -> Result<()> {
+        verify_chunk(chunk, data)?;
+        let path = self.chunk_path(&chunk.hash);
+        if !path.exists() {
+            write_file(&path, data)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_chunk(&self, chunk: &Chunk) -> Result<Vec<u8>> {
+        let data = fs::read(self.chunk_path(&chunk.hash))?;
+        verify_chunk(chunk, &data)?;
+        Ok(data)
+    }
+
+    /// Reassembles content of the resource, verifying every chunk
+    pub fn get<W: Write>(&self, id: ResourceId, mut writer: W) -> Result<()> {
+        let manifest = self.manifest(id)?.ok_or_else(|| {
+            ArklibError::Path(format!("Blob {} is not stored", id))
+        })?;
+        for chunk in manifest.chunks.iter() {
+            writer.write_all(&self.read_chunk(chunk)?)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Forgets the blob of the resource, its chunks are removed
+    /// by [`BlobStore::collect_garbage`]
+    pub fn remove(&self, id: ResourceId) -> Result<()> {
+        let path = self.manifest_path(id);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Removes chunks not referenced by any manifest,
+    /// returning the number of removed chunks
+    pub fn collect_garbage(&self) -> Result<usize> {
+        let mut referenced = HashSet::new();
+        for entry in fs::read_dir(self.directory.join(MANIFESTS_FOLDER))? {
+            let file = File::open(entry?.path())?;
+            let manifest: BlobManifest =
+                serde_json::from_reader(BufReader::new(file))?;
+            referenced.extend(
+                manifest
+                    .chunks
+                    .into_iter()
+                    .map(|chunk| chunk.hash),
+            );
+        }
+
+        let mut removed = 0;
+        for prefix in fs::read_dir(self.directory.join(CHUNKS_FOLDER))? {
+            for entry in fs::read_dir(prefix?.path())? {
+                let entry = entry?;
+                let hash = entry.file_name().to_string_lossy().to_string();
+                if !referenced.contains(&hash) {
+                    fs::remove_file(entry.path())?;
+                    removed += 1;
+                }
+            }
+        }
+        log::debug!("Removed {} unreferenced chunks", removed);
+        Ok(removed)
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.directory
+            .join(CHUNKS_FOLDER)
+            .join(hash.get(..2).unwrap_or_default())
+            .join(hash)
+    }
+
+    fn manifest_path(&self, id: ResourceId) -> PathBuf {
+        self.directory
+            .join(MANIFESTS_FOLDER)
+            .join(id.to_string())
+    }
+}
+
+fn verify_chunk(chunk: &Chunk, data: &[u8]) -> Result<()> {
+    let hash = blake3::hash(data).to_hex();
+    if hash.as_str() != chunk.hash || data.len() as u64 != chunk.size {
+        return Err(ArklibError::Other(anyhow!(
+            "Chunk {} is corrupted",
+            chunk.hash
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use tempdir::TempDir;
+
+    fn random_bytes(size: usize) -> Vec<u8> {
+        let mut rng = fastrand::Rng::with_seed(42);
+        (0..size).map(|_| rng.u8(..)).collect()
+    }
+
+    #[test]
+    fn put_and_get_roundtrip() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let store = BlobStore::new(dir.path()).unwrap();
+
+        let data = random_bytes(1024 * 1024);
+        let id = ResourceId::compute_bytes(&data).unwrap();
+        let manifest = store.put(id, data.as_slice()).unwrap();
+        assert!(manifest.chunks.len() > 1);
+        assert_eq!(manifest.size(), data.len() as u64);
+        assert!(store.contains(id));
+
+        let mut restored = vec![];
+        store.get(id, &mut restored).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn modified_content_shares_chunks() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let store = BlobStore::new(dir.path()).unwrap();
+
+        let original = random_bytes(1024 * 1024);
+        let mut modified = original.clone();
+        modified.splice(500_000..500_000, b"inserted".iter().cloned());
+
+        let id1 = ResourceId::compute_bytes(&original).unwrap();
+        let id2 = ResourceId::compute_bytes(&modified).unwrap();
+        let manifest1 = store.put(id1, original.as_slice()).unwrap();
+        let manifest2 = store.put(id2, modified.as_slice()).unwrap();
+
+        let shared = manifest2
+            .chunks
+            .iter()
+            .filter(|chunk| manifest1.chunks.contains(chunk))
+            .count();
+        assert!(shared >= manifest2.chunks.len() - 2);
+
+        store.remove(id1).unwrap();
+        let removed = store.collect_garbage().unwrap();
+        assert!(removed <= 2);
+        assert_eq!(store.missing_chunks(&manifest2), vec![]);
+        assert!(!store.missing_chunks(&manifest1).is_empty());
+    }
+}
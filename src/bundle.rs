@@ -0,0 +1,324 @@
+//! Bundles sharing a resource together with its user data
+//!
+//! A bundle is a zip archive holding `bundle.json` with the tags, the
+//! score and the properties of the resource, and the resource itself
+//! under `content`. Importing a bundle into another root adds the
+//! resource unless the root has the same content already, and merges
+//! the user data into the user data of the root.
+//!
+//! arklib has no storage of annotations of its own, annotations made by
+//! apps are kept in properties of resources and travel with them.
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::resource::ResourceId;
+use crate::scores::{score_of, set_score, Score};
+use crate::storage::prop::{load_raw_properties, store_properties};
+use crate::tags::{add_tags, tags_of, Tag, Tags};
+use crate::util::fs::unique_path;
+use crate::{
+    provide_index, ArklibError, Result, ARK_FOLDER, PROPERTIES_STORAGE_FOLDER,
+};
+
+pub const BUNDLE_VERSION: u32 = 1;
+const MANIFEST_ENTRY: &str = "bundle.json";
+const CONTENT_FOLDER: &str = "content";
+
+/// Description of the resource of a bundle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub version: u32,
+    /// ID of the resource in the root it was exported from
+    pub id: ResourceId,
+    pub file_name: String,
+    pub tags: Tags,
+    pub score: Score,
+    pub properties: Option<Value>,
+}
+
+/// Writes the resource indexed in the root together with its user data
+/// as a bundle
+pub fn export_resource_bundle<P: AsRef<Path>, W: Write + Seek>(
+    root: P,
+    id: ResourceId,
+    writer: W,
+) -> Result<BundleManifest> {
+    let path = {
+        let index = provide_index(&root)?;
+        let index = index.read().unwrap_or_else(|e| e.into_inner());
+        index
+            .get_path(&id)
+            .map(Path::to_path_buf)
+            .ok_or_else(|| {
+                ArklibError::Path(format!("Resource {} is not indexed", id))
+            })?
+    };
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| ArklibError::Path(path.display().to_string()))?;
+    let manifest = BundleManifest {
+        version: BUNDLE_VERSION,
+        id,
+        file_name,
+        tags: tags_of(&root, id)?,
+        score: score_of(&root, id)?,
+        properties: load_properties(&root, id)?,
+    };
+
+    let mut zip = ZipWriter::new(writer);
+    zip.start_file(MANIFEST_ENTRY, FileOptions::default())?;
+    zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+    zip.start_file(
+        format!("{}/{}", CONTENT_FOLDER, manifest.file_name),
+        FileOptions::default(),
+    )?;
+    io::copy(&mut File::open(&path)?, &mut zip)?;
+    zip.finish()?;
+    log::info!("Exported {} into a bundle", path.display());
+    Ok(manifest)
+}
+
+/// Adds the resource of the bundle to the root and merges its user data,
+/// returning the path and the ID of the resource in the root
+///
+/// A resource which content is in the root already is not copied again.
+/// Tags are added to the tags of the resource, the score is taken only if
+/// the resource has none and properties are merged.
+pub fn import_resource_bundle<P: AsRef<Path>, R: Read + Seek>(
+    root: P,
+    reader: R,
+) -> Result<(PathBuf, ResourceId)> {
+    let mut zip = ZipArchive::new(reader)?;
+    let manifest: BundleManifest =
+        serde_json::from_reader(zip.by_name(MANIFEST_ENTRY)?)?;
+    if manifest.version > BUNDLE_VERSION {
+        return Err(ArklibError::Other(anyhow!(
+            "Bundle version {} is not supported",
+            manifest.version
+        )));
+    }
+    if Path::new(&manifest.file_name).file_name()
+        != Some(manifest.file_name.as_ref())
+    {
+        return Err(ArklibError::Path(format!(
+            "Illegal file name: {}",
+            manifest.file_name
+        )));
+    }
+    let index = provide_index(&root)?;
+    let (root_path, options) = {
+        let index = index.read().unwrap_or_else(|e| e.into_inner());
+        (index.root().to_path_buf(), index.options().clone())
+    };
+
+    // the content is received into a hidden file of the root, and a single
+    // byte more than announced is enough to refuse it, however much
+    // it inflates
+    let size = manifest.id.data_size;
+    let tmp = root_path.join(format!(".{}.part", manifest.id));
+    let received = receive_content(&mut zip, &manifest.file_name, size, &tmp)
+        .and_then(|()| options.content_id(&tmp, options.samples(size)));
+    let id = match received {
+        Ok(id) => id,
+        Err(e) => {
+            remove_partial(&tmp);
+            return Err(e);
+        }
+    };
+
+    let mut index = index.write().unwrap_or_else(|e| e.into_inner());
+    let (path, id) = match index.get_path(&id) {
+        Some(path) => {
+            remove_partial(&tmp);
+            (path.to_path_buf(), id)
+        }
+        None => {
+            let path = unique_path(index.root(), &manifest.file_name);
+            fs::rename(&tmp, &path)?;
+            // the ID is taken from the index, which user data is keyed by
+            match index.index_new(&path)?.added.into_values().next() {
+                Some(id) => (path, id),
+                None => {
+                    return Err(ArklibError::Path(format!(
+                        "{} wasn't indexed",
+                        path.display()
+                    )))
+                }
+            }
+        }
+    };
+    drop(index);
+
+    if !manifest.tags.is_empty() {
+        let tags: Vec<Tag> = manifest.tags.into_iter().collect();
+        add_tags(&root, id, &tags)?;
+    }
+    if manifest.score != 0 && score_of(&root, id)? == 0 {
+        set_score(&root, id, manifest.score)?;
+    }
+    if let Some(properties) = &manifest.properties {
+        store_properties(&root, id, properties)?;
+    }
+    log::info!("Imported a bundle as {}", path.display());
+    Ok((path, id))
+}
+
+/// Copies the content of the bundle into the file, refusing content
+/// of another size than announced
+fn receive_content<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    file_name: &str,
+    size: u64,
+    tmp: &Path,
+) -> Result<()> {
+    let mut content =
+        zip.by_name(&format!("{}/{}", CONTENT_FOLDER, file_name))?;
+    let mut file = File::create(tmp)?;
+    let copied = io::copy(
+        &mut content.by_ref().take(size.saturating_add(1)),
+        &mut file,
+    )?;
+    if copied != size {
+        return Err(ArklibError::SizeMismatch {
+            expected: size,
+            actual: copied,
+        });
+    }
+    Ok(())
+}
+
+fn remove_partial(tmp: &Path) {
+    if let Err(e) = fs::remove_file(tmp) {
+        if e.kind() != io::ErrorKind::NotFound {
+            log::warn!("Couldn't remove {}: {}", tmp.display(), e);
+        }
+    }
+}
+
+fn load_properties<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<Value>> {
+    let folder = root
+        .as_ref()
+        .join(ARK_FOLDER)
+        .join(PROPERTIES_STORAGE_FOLDER)
+        .join(id.to_string());
+    if !folder.exists() {
+        return Ok(None);
+    }
+    match load_raw_properties(root, id) {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        Err(ArklibError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use serde_json::json;
+    use std::io::Cursor;
+    use tempdir::TempDir;
+
+    #[test]
+    fn bundles_carry_user_data() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let source = TempDir::new("arklib_test").unwrap();
+        let target = TempDir::new("arklib_test").unwrap();
+        fs::write(source.path().join("paper.txt"), "annotated").unwrap();
+        let id = ResourceId::compute_bytes(b"annotated").unwrap();
+        add_tags(source.path(), id, &["science".into()]).unwrap();
+        set_score(source.path(), id, 3).unwrap();
+        store_properties(source.path(), id, &json!({"note": "read"})).unwrap();
+
+        let mut bundle = Cursor::new(vec![]);
+        let manifest =
+            export_resource_bundle(source.path(), id, &mut bundle).unwrap();
+        assert_eq!(manifest.file_name, "paper.txt");
+
+        add_tags(target.path(), id, &["to read".into()]).unwrap();
+        bundle.set_position(0);
+        let (path, imported) =
+            import_resource_bundle(target.path(), &mut bundle).unwrap();
+        assert_eq!(imported, id);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "annotated");
+        assert_eq!(tags_of(target.path(), id).unwrap().len(), 2);
+        assert_eq!(score_of(target.path(), id).unwrap(), 3);
+        assert_eq!(
+            load_properties(target.path(), id).unwrap(),
+            Some(json!({"note": "read"}))
+        );
+
+        // content is not duplicated by importing again
+        bundle.set_position(0);
+        let (again, _) =
+            import_resource_bundle(target.path(), &mut bundle).unwrap();
+        assert_eq!(again, path);
+        let files = fs::read_dir(target.path())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().is_file())
+            .count();
+        assert_eq!(files, 1);
+
+        // content larger than announced is refused without reading it all
+        let mut forged = ZipWriter::new(Cursor::new(vec![]));
+        forged
+            .start_file(MANIFEST_ENTRY, FileOptions::default())
+            .unwrap();
+        forged
+            .write_all(&serde_json::to_vec(&manifest).unwrap())
+            .unwrap();
+        forged
+            .start_file("content/paper.txt", FileOptions::default())
+            .unwrap();
+        forged.write_all(&[0; 1024 * 1024]).unwrap();
+        let mut forged = forged.finish().unwrap();
+        forged.set_position(0);
+        match import_resource_bundle(target.path(), forged) {
+            Err(ArklibError::SizeMismatch { expected, actual }) => {
+                assert_eq!(expected, manifest.id.data_size);
+                assert_eq!(actual, manifest.id.data_size + 1);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        let files = fs::read_dir(target.path())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().is_file())
+            .count();
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn imported_user_data_uses_ids_of_the_root() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let source = TempDir::new("arklib_test").unwrap();
+        let target = TempDir::new("arklib_test").unwrap();
+        fs::write(source.path().join("paper.txt"), "annotated").unwrap();
+        let id = ResourceId::compute_bytes(b"annotated").unwrap();
+        add_tags(source.path(), id, &["science".into()]).unwrap();
+        let mut bundle = Cursor::new(vec![]);
+        export_resource_bundle(source.path(), id, &mut bundle).unwrap();
+
+        let namespace = crate::resource::set_namespaced(target.path(), true)
+            .unwrap()
+            .unwrap();
+        bundle.set_position(0);
+        let (_, imported) =
+            import_resource_bundle(target.path(), &mut bundle).unwrap();
+        assert_eq!(imported, namespace.apply(id));
+        assert_eq!(tags_of(target.path(), imported).unwrap().len(), 1);
+        assert!(tags_of(target.path(), id).unwrap().is_empty());
+    }
+}
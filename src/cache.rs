@@ -0,0 +1,379 @@
+//! Provenance and invalidation of generated data cached per resource
+//!
+//! Metadata, previews and thumbnails are cached in one entry per ID.
+//! Every entry records the ID it was generated from and when, in a stamp
+//! next to the versions of the entry. When the content by a path changes,
+//! the resource gets a new ID and the entries of the previous ID become
+//! stale, [`invalidate_stale_caches`] removes them on index updates.
+//!
+//! The total size of the caches can be limited by a budget kept in the
+//! `cache` section of `.ark/config`. [`enforce_budget`], run by the
+//! [`crate::index::UpdateScheduler`] after every update, evicts least
+//! recently used entries beyond the budget, except entries of resources
+//! pinned by [`crate::pins::pin`].
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::index::{IndexUpdate, ResourceIndex};
+use crate::pins::load_pins;
+use crate::resource::ResourceId;
+use crate::storage::config::{load_section, store_section};
+use crate::util::fs::write_file;
+use crate::{
+    Result, ARK_FOLDER, METADATA_STORAGE_FOLDER, PREVIEWS_STORAGE_FOLDER,
+    THUMBNAILS_STORAGE_FOLDER,
+};
+
+/// Name of the stamp inside of cache entries, it is never taken
+/// for a version of the entry
+const STAMP_FILE: &str = "stamp.json";
+const CONFIG_SECTION: &str = "cache";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheBudget {
+    max_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CacheKind {
+    Metadata,
+    Previews,
+    Thumbnails,
+}
+
+impl CacheKind {
+    pub const ALL: [CacheKind; 3] = [
+        CacheKind::Metadata,
+        CacheKind::Previews,
+        CacheKind::Thumbnails,
+    ];
+
+    pub(crate) fn folder(&self) -> &'static str {
+        match self {
+            CacheKind::Metadata => METADATA_STORAGE_FOLDER,
+            CacheKind::Previews => PREVIEWS_STORAGE_FOLDER,
+            CacheKind::Thumbnails => THUMBNAILS_STORAGE_FOLDER,
+        }
+    }
+
+    fn entry_path<P: AsRef<Path>>(&self, root: P, id: ResourceId) -> PathBuf {
+        root.as_ref()
+            .join(ARK_FOLDER)
+            .join(self.folder())
+            .join(id.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStamp {
+    /// ID of the resource the entry was generated from
+    pub source: ResourceId,
+    /// Milliseconds since the epoch
+    pub generated: u64,
+}
+
+/// Loads the stamp of the cache entry, `None` if the entry doesn't exist
+/// or was generated before stamps were recorded
+pub fn load_cache_stamp<P: AsRef<Path>>(
+    root: P,
+    kind: CacheKind,
+    id: ResourceId,
+) -> Result<Option<CacheStamp>> {
+    let path = kind.entry_path(root, id).join(STAMP_FILE);
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Measures bytes taken by cached data of every kind on disk, so apps
+/// can suggest cleaning the cache up
+pub fn cache_usage<P: AsRef<Path>>(root: P) -> BTreeMap<CacheKind, u64> {
+    let mut usage = BTreeMap::new();
+    for kind in CacheKind::ALL {
+        let folder = root.as_ref().join(ARK_FOLDER).join(kind.folder());
+        let bytes = WalkDir::new(folder)
+            .into_iter()
+            .filter_map(|entry| entry.ok()?.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .sum();
+        usage.insert(kind, bytes);
+    }
+    usage
+}
+
+/// Removes all cached data of the resource, returning the number of
+/// removed entries
+pub fn invalidate_caches<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<usize> {
+    let mut removed = 0;
+    for kind in CacheKind::ALL {
+        let path = kind.entry_path(&root, id);
+        match fs::remove_dir_all(&path) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes cached data of resources deleted or replaced by the update,
+/// returning the number of removed entries
+///
+/// Resources reported as both deleted and added keep their caches.
+/// Consumers of the update reading caches of previous IDs, like
+/// [`crate::wiki::sync_wiki_links`], must be run before.
+pub fn invalidate_stale_caches<P: AsRef<Path>>(
+    root: P,
+    update: &IndexUpdate,
+) -> Result<usize> {
+    let current: HashSet<ResourceId> = update
+        .added
+        .values()
+        .chain(update.modified.values().map(|(_, new_id)| new_id))
+        .copied()
+        .collect();
+    let stale: HashSet<ResourceId> = update
+        .deleted
+        .iter()
+        .chain(update.modified.values().map(|(old_id, _)| old_id))
+        .filter(|id| !current.contains(id))
+        .copied()
+        .collect();
+
+    let mut removed = 0;
+    for id in stale {
+        removed += invalidate_caches(&root, id)?;
+    }
+    log::debug!("Removed {} stale cache entries", removed);
+    Ok(removed)
+}
+
+/// Removes cache entries of resources which are not indexed anymore or
+/// which stamps name another resource, e.g. after updates were missed
+pub fn sweep_caches(index: &ResourceIndex) -> Result<usize> {
+    let mut removed = 0;
+    for kind in CacheKind::ALL {
+        let folder = index.root().join(ARK_FOLDER).join(kind.folder());
+        if !folder.exists() {
+            continue;
+        }
+        for entry in fs::read_dir(folder)? {
+            let entry = entry?;
+            let Ok(id) = entry.file_name().to_string_lossy().parse() else {
+                continue;
+            };
+            let misplaced = load_cache_stamp(index.root(), kind, id)?
+                .is_some_and(|stamp| stamp.source != id);
+            if misplaced || !index.contains(&id) {
+                fs::remove_dir_all(entry.path())?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Loads the limit of bytes taken by all caches of the root,
+/// `None` if unlimited
+pub fn load_cache_budget<P: AsRef<Path>>(root: P) -> Result<Option<u64>> {
+    let budget: Option<CacheBudget> = load_section(root, CONFIG_SECTION)?;
+    Ok(budget.map(|budget| budget.max_bytes))
+}
+
+pub fn set_cache_budget<P: AsRef<Path>>(root: P, max_bytes: u64) -> Result<()> {
+    store_section(root, CONFIG_SECTION, &CacheBudget { max_bytes })
+}
+
+/// Evicts least recently used cache entries of all kinds until the caches
+/// fit into the budget of the root, returning the number of evicted entries
+///
+/// Entries of pinned resources are kept even if the caches exceed
+/// the budget.
+///
+/// Entries are ordered by the latest access or modification of their
+/// files. Access times are updated at most once a day on most systems,
+/// which is precise enough for eviction.
+pub fn enforce_budget<P: AsRef<Path>>(root: P) -> Result<usize> {
+    let Some(max_bytes) = load_cache_budget(&root)? else {
+        return Ok(0);
+    };
+    let pins = load_pins(&root)?;
+    let mut entries = vec![];
+    for kind in CacheKind::ALL {
+        let folder = root.as_ref().join(ARK_FOLDER).join(kind.folder());
+        if !folder.exists() {
+            continue;
+        }
+        for entry in fs::read_dir(folder)? {
+            let entry = entry?;
+            let (bytes, used) = entry_usage(&entry.path());
+            let pinned = entry
+                .file_name()
+                .to_string_lossy()
+                .parse()
+                .is_ok_and(|id| pins.contains(&id));
+            entries.push((pinned, used, bytes, entry.path()));
+        }
+    }
+    let mut total: u64 = entries.iter().map(|(_, _, bytes, _)| bytes).sum();
+    if total <= max_bytes {
+        return Ok(0);
+    }
+
+    entries.sort();
+    let mut evicted = 0;
+    for (pinned, _, bytes, path) in entries {
+        if total <= max_bytes || pinned {
+            break;
+        }
+        let removed = match path.is_dir() {
+            true => fs::remove_dir_all(&path),
+            false => fs::remove_file(&path),
+        };
+        match removed {
+            Ok(()) => {
+                total -= bytes;
+                evicted += 1;
+            }
+            Err(e) => log::warn!("Couldn't evict {}: {}", path.display(), e),
+        }
+    }
+    log::debug!("Evicted {} cache entries, {} bytes left", evicted, total);
+    Ok(evicted)
+}
+
+/// Bytes taken by the files of the entry and the time they were used last
+fn entry_usage(path: &Path) -> (u64, SystemTime) {
+    let mut bytes = 0;
+    let mut used = UNIX_EPOCH;
+    for metadata in WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+    {
+        bytes += metadata.len();
+        for time in [metadata.accessed(), metadata.modified()]
+            .into_iter()
+            .flatten()
+        {
+            used = used.max(time);
+        }
+    }
+    (bytes, used)
+}
+
+/// Records that the cache entry at `path` has just been generated
+pub(crate) fn stamp_entry(path: &Path, source: ResourceId) -> Result<()> {
+    let stamp = CacheStamp {
+        source,
+        generated: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default(),
+    };
+    write_file(&path.join(STAMP_FILE), &serde_json::to_vec(&stamp)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use crate::storage::metadata;
+    use crate::storage::preview::{load_preview, store_preview};
+    use tempdir::TempDir;
+
+    #[test]
+    fn caches_of_replaced_resources_are_invalidated() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "first").unwrap();
+        fs::write(root.join("b.txt"), "second").unwrap();
+        let mut index = ResourceIndex::build(&root);
+        let a = index.get_entry(root.join("a.txt")).unwrap().id;
+        let b = index.get_entry(root.join("b.txt")).unwrap().id;
+        for id in [a, b] {
+            metadata::store_field(&root, id, "lines", &1).unwrap();
+            store_preview(&root, id, b"preview").unwrap();
+        }
+        let stamp = load_cache_stamp(&root, CacheKind::Previews, a)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stamp.source, a);
+        assert!(stamp.generated > 0);
+        assert!(cache_usage(&root)[&CacheKind::Previews] > 0);
+
+        fs::write(root.join("a.txt"), "changed").unwrap();
+        let update = index.update_one(&root.join("a.txt"), a).unwrap();
+        assert_eq!(invalidate_stale_caches(&root, &update).unwrap(), 2);
+        assert_eq!(load_preview(&root, a).unwrap(), None);
+        assert_eq!(
+            metadata::load::<serde_json::Value, _>(&root, a).unwrap(),
+            None
+        );
+        assert!(load_preview(&root, b).unwrap().is_some());
+
+        fs::remove_file(root.join("b.txt")).unwrap();
+        let mut index = ResourceIndex::build(&root);
+        assert_eq!(sweep_caches(&index).unwrap(), 2);
+        assert_eq!(index.update_all().unwrap(), IndexUpdate::default());
+        assert_eq!(
+            load_cache_stamp(&root, CacheKind::Metadata, b).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let ids: Vec<ResourceId> = (0..3)
+            .map(|i| ResourceId::compute_bytes(&[i]).unwrap())
+            .collect();
+        let now = SystemTime::now();
+        for (age, id) in ids.iter().rev().enumerate() {
+            store_preview(root, *id, &[0; 1000]).unwrap();
+            let used = now - std::time::Duration::from_secs(age as u64 * 60);
+            let times = fs::FileTimes::new()
+                .set_accessed(used)
+                .set_modified(used);
+            let entry = CacheKind::Previews.entry_path(root, *id);
+            for file in WalkDir::new(entry).into_iter().flatten() {
+                if file.file_type().is_file() {
+                    fs::File::options()
+                        .write(true)
+                        .open(file.path())
+                        .unwrap()
+                        .set_times(times)
+                        .unwrap();
+                }
+            }
+        }
+        assert_eq!(enforce_budget(root).unwrap(), 0);
+
+        let usage = cache_usage(root)[&CacheKind::Previews];
+        set_cache_budget(root, usage / 2).unwrap();
+        assert_eq!(load_cache_budget(root).unwrap(), Some(usage / 2));
+        assert_eq!(enforce_budget(root).unwrap(), 2);
+        assert_eq!(load_preview(root, ids[0]).unwrap(), None);
+        assert_eq!(load_preview(root, ids[1]).unwrap(), None);
+        assert!(load_preview(root, ids[2]).unwrap().is_some());
+
+        crate::pins::pin(root, ids[2]).unwrap();
+        set_cache_budget(root, 0).unwrap();
+        assert_eq!(enforce_budget(root).unwrap(), 0);
+        assert!(load_preview(root, ids[2]).unwrap().is_some());
+    }
+}
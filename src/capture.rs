@@ -0,0 +1,369 @@
+//! Single entry point for content shared into ARK from other apps
+//!
+//! Captured content is written into the intake folder, which is
+//! configured in the `capture` section of `.ark/config`, and indexed
+//! right away. Files without a known extension get one by sniffing their
+//! content. Previews of images, PDFs, books and office documents are
+//! generated immediately, failing to generate them doesn't fail the
+//! capture. Content which is indexed already is handled according to
+//! [`DuplicatePolicy`]. URLs are saved as [`Link`] files without fetching
+//! the pages, [`Link::save`] fetches their previews.
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::index::RelativePath;
+use crate::link::Link;
+use crate::notes::find_id;
+use crate::office::OfficeFormat;
+#[cfg(feature = "pdfium")]
+use crate::pdf::{render_preview_page, PDFQuality};
+use crate::query::Kind;
+use crate::resource::ResourceId;
+use crate::storage::config::{load_section, store_section};
+use crate::util::fs::{safe_file_name, unique_path, write_file};
+use crate::{epub, images, office, provide_index, text, Result};
+
+const CONFIG_SECTION: &str = "capture";
+
+pub const DEFAULT_INTAKE_FOLDER: &str = "Inbox";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// Folder receiving captured resources
+    pub intake_folder: RelativePath,
+    /// Handling of content which is indexed already
+    pub duplicates: DuplicatePolicy,
+}
+
+/// What to do with captured content which is indexed already
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum DuplicatePolicy {
+    /// Nothing is written, the existing resource is returned
+    #[default]
+    Skip,
+    /// A hard link to the existing file is created, so the resource
+    /// gets one more path without taking more space. Falls back to
+    /// [`DuplicatePolicy::KeepBoth`] if linking is not supported.
+    LinkAsAlias,
+    /// A copy of the content is written
+    KeepBoth,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            intake_folder: RelativePath::parse(DEFAULT_INTAKE_FOLDER)
+                .expect("Default intake folder is a valid path"),
+            duplicates: DuplicatePolicy::default(),
+        }
+    }
+}
+
+impl CaptureConfig {
+    /// Loads the config of the root, with the default intake folder
+    /// if never stored
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Self> {
+        Ok(load_section(root, CONFIG_SECTION)?.unwrap_or_default())
+    }
+
+    pub fn store<P: AsRef<Path>>(&self, root: P) -> Result<()> {
+        store_section(root, CONFIG_SECTION, self)
+    }
+}
+
+/// Path of the intake folder of the root
+pub fn intake_folder<P: AsRef<Path>>(root: P) -> Result<PathBuf> {
+    let config = CaptureConfig::load(&root)?;
+    Ok(config.intake_folder.to_path(root))
+}
+
+/// Content shared into ARK
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Captured {
+    Bytes(Vec<u8>),
+    Url(Url),
+}
+
+/// Outcome of capturing content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    pub id: ResourceId,
+    /// Path of the captured resource, or of the existing one if the
+    /// capture was skipped
+    pub path: PathBuf,
+    /// Policy applied if the content was indexed before
+    pub duplicate: Option<DuplicatePolicy>,
+}
+
+/// Writes the content into the intake folder of the root and indexes it,
+/// handling content which is indexed already by the configured policy
+///
+/// The suggested name is made safe for the file system and files with
+/// the same name are kept, the new one is renamed instead. Links are
+/// named by their IDs and titled by the suggested name.
+pub fn capture<P: AsRef<Path>>(
+    root: P,
+    content: Captured,
+    suggested_name: &str,
+) -> Result<Capture> {
+    let policy = CaptureConfig::load(&root)?.duplicates;
+    capture_with(root, content, suggested_name, policy)
+}
+
+/// Captures the content like [`capture`], handling duplicates by the
+/// given policy
+pub fn capture_with<P: AsRef<Path>>(
+    root: P,
+    content: Captured,
+    suggested_name: &str,
+    policy: DuplicatePolicy,
+) -> Result<Capture> {
+    let config = CaptureConfig::load(&root)?;
+    let index = provide_index(&root)?;
+    let mut index = index.write().unwrap_or_else(|e| e.into_inner());
+    let folder = index.absolute_path(&config.intake_folder);
+    std::fs::create_dir_all(&folder)?;
+
+    let (path, data) = match content {
+        Captured::Bytes(data) => {
+            let path = unique_path(&folder, &file_name(suggested_name, &data));
+            write_file(&path, &data)?;
+            (path, Some(data))
+        }
+        Captured::Url(url) => {
+            let link = Link::new(url, suggested_name.trim().to_string(), None);
+            (link.write_into(&root, &folder)?, None)
+        }
+    };
+
+    let id = index.compute_id(&path)?;
+    let duplicate = match index.get_path(&id).map(Path::to_path_buf) {
+        // links to the same page are written into the same file
+        Some(existing) if existing == path => {
+            return Ok(Capture {
+                id,
+                path,
+                duplicate: Some(DuplicatePolicy::Skip),
+            });
+        }
+        Some(existing) => match policy {
+            DuplicatePolicy::Skip => {
+                std::fs::remove_file(&path)?;
+                return Ok(Capture {
+                    id,
+                    path: existing,
+                    duplicate: Some(policy),
+                });
+            }
+            DuplicatePolicy::LinkAsAlias => {
+                std::fs::remove_file(&path)?;
+                match std::fs::hard_link(&existing, &path) {
+                    Ok(()) => Some(policy),
+                    Err(e) => {
+                        log::warn!(
+                            "Couldn't link {} to {}: {}",
+                            path.display(),
+                            existing.display(),
+                            e
+                        );
+                        std::fs::copy(&existing, &path)?;
+                        Some(DuplicatePolicy::KeepBoth)
+                    }
+                }
+            }
+            DuplicatePolicy::KeepBoth => Some(policy),
+        },
+        None => None,
+    };
+    let update = index.index_new(&path)?;
+    let id = find_id(&update, &path)?;
+    drop(index);
+
+    // previews of duplicates have been generated already
+    if let (Some(data), None) = (data, duplicate) {
+        if let Err(e) = generate_previews(&root, id, &path, data) {
+            log::warn!("Couldn't generate previews of {}: {}", id, e);
+        }
+    }
+    Ok(Capture {
+        id,
+        path,
+        duplicate,
+    })
+}
+
+/// Names the file after the suggested name, adding an extension
+/// detected from the content if the name has no known one
+fn file_name(suggested_name: &str, data: &[u8]) -> String {
+    let name = safe_file_name(suggested_name);
+    if Kind::of(&name) != Kind::Other {
+        return name;
+    }
+    match sniff_extension(data) {
+        Some(extension) => format!("{}.{}", name, extension),
+        None => name,
+    }
+}
+
+/// Recognizes common formats by their signatures, or plain text
+fn sniff_extension(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\xFF\xD8\xFF", "jpg"),
+        (b"\x89PNG\r\n\x1A\n", "png"),
+        (b"GIF8", "gif"),
+        (b"%PDF-", "pdf"),
+        (b"PK\x03\x04", "zip"),
+        (b"\x1F\x8B", "gz"),
+        (b"ID3", "mp3"),
+        (b"OggS", "ogg"),
+        (b"fLaC", "flac"),
+    ];
+    if let Some((_, extension)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+    {
+        return Some(extension);
+    }
+    match (data.get(..4), data.get(8..12)) {
+        (Some(b"RIFF"), Some(b"WEBP")) => return Some("webp"),
+        (Some(b"RIFF"), Some(b"WAVE")) => return Some("wav"),
+        _ => {}
+    }
+    if data.get(4..8) == Some(b"ftyp") {
+        return Some("mp4");
+    }
+    match std::str::from_utf8(data) {
+        Ok(text) if !text.contains('\0') => Some("txt"),
+        _ => None,
+    }
+}
+
+fn generate_previews<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    path: &Path,
+    data: Vec<u8>,
+) -> Result<()> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if images::is_supported(&extension) {
+        return images::generate(root, id, Cursor::new(data));
+    }
+    if let Some(format) = OfficeFormat::from_extension(&extension) {
+        return office::generate(root, id, Cursor::new(data), format)
+            .map(|_| ());
+    }
+    match extension.as_str() {
+        #[cfg(feature = "pdfium")]
+        "pdf" => {
+            let page =
+                render_preview_page(Cursor::new(data), PDFQuality::Medium)?;
+            images::store_previews(root, id, &page)
+        }
+        "epub" => epub::generate(root, id, Cursor::new(data)).map(|_| ()),
+        _ if Kind::of(path) == Kind::Text => {
+            text::generate(root, id, data.as_slice(), Some(&extension))
+                .map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::preview::load_thumbnail;
+    use tempdir::TempDir;
+
+    #[test]
+    fn kinds_are_sniffed() {
+        assert_eq!(file_name("photo", b"\xFF\xD8\xFF\xE0"), "photo.jpg");
+        assert_eq!(file_name("notes: day 1", b"hello"), "notes_ day 1.txt");
+        assert_eq!(file_name("report.pdf", b"hello"), "report.pdf");
+        assert_eq!(file_name("blob", &[0, 1, 2]), "blob");
+    }
+
+    #[test]
+    fn content_is_captured_into_intake_folder() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let photo = std::fs::read("tests/lena.jpg").unwrap();
+
+        let captured = capture(&root, Captured::Bytes(photo), "lena").unwrap();
+        assert_eq!(captured.path, root.join("Inbox/lena.jpg"));
+        assert_eq!(captured.duplicate, None);
+        let index = provide_index(&root).unwrap();
+        assert_eq!(
+            index.read().unwrap().get_path(&captured.id),
+            Some(captured.path.as_path())
+        );
+        assert!(load_thumbnail(&root, captured.id)
+            .unwrap()
+            .is_some());
+
+        let config = CaptureConfig {
+            intake_folder: RelativePath::parse("shared/links").unwrap(),
+            ..CaptureConfig::default()
+        };
+        config.store(&root).unwrap();
+        assert_eq!(
+            intake_folder(&root).unwrap(),
+            root.join("shared").join("links")
+        );
+        let url = Url::parse("https://ark-builders.dev/").unwrap();
+        let link = capture(&root, Captured::Url(url.clone()), "ARK").unwrap();
+        assert_eq!(
+            link.path,
+            root.join("shared/links")
+                .join(link.id.to_string())
+        );
+        assert_eq!(Kind::of(&link.path), Kind::Link);
+        let again = capture(&root, Captured::Url(url), "ARK").unwrap();
+        assert_eq!(again.path, link.path);
+        assert_eq!(again.duplicate, Some(DuplicatePolicy::Skip));
+    }
+
+    #[test]
+    fn duplicates_follow_policies() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let content = || Captured::Bytes(b"shared twice".to_vec());
+        let first = capture(&root, content(), "note.txt").unwrap();
+
+        let skipped = capture(&root, content(), "copy.txt").unwrap();
+        assert_eq!(skipped.duplicate, Some(DuplicatePolicy::Skip));
+        assert_eq!(skipped.path, first.path);
+        assert!(!root.join("Inbox/copy.txt").exists());
+
+        let kept = capture_with(
+            &root,
+            content(),
+            "note.txt",
+            DuplicatePolicy::KeepBoth,
+        )
+        .unwrap();
+        assert_eq!(kept.duplicate, Some(DuplicatePolicy::KeepBoth));
+        assert_eq!(kept.path, root.join("Inbox/note (2).txt"));
+
+        let alias = capture_with(
+            &root,
+            content(),
+            "alias.txt",
+            DuplicatePolicy::LinkAsAlias,
+        )
+        .unwrap();
+        assert_eq!(alias.duplicate, Some(DuplicatePolicy::LinkAsAlias));
+        assert_eq!(alias.id, first.id);
+        assert_eq!(std::fs::read(&alias.path).unwrap(), b"shared twice");
+    }
+}
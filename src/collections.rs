@@ -0,0 +1,211 @@
+//! Smart collections, i.e. named queries saved by the user
+//!
+//! Collections store only their [`Query`], membership is evaluated against
+//! the current index. [`CollectionWatcher`] remembers the last membership
+//! of every collection and reports which resources entered or left
+//! collections since the previous refresh.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::index::ResourceIndex;
+use crate::query::{query, Query};
+use crate::resource::ResourceId;
+use crate::{ArklibError, Result, ARK_FOLDER, COLLECTIONS_STORAGE_FILE};
+
+/// Loads all collections of the root by their names
+pub fn load_collections<P: AsRef<Path>>(
+    root: P,
+) -> Result<BTreeMap<String, Query>> {
+    let path = storage_path(root);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    match AtomicFile::new(path)?.load()?.open()? {
+        Some(file) => {
+            Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+        }
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Creates the collection or replaces the query of an existing one
+pub fn save_collection<P: AsRef<Path>>(
+    root: P,
+    name: &str,
+    query: &Query,
+) -> Result<()> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ArklibError::Other(anyhow::anyhow!(
+            "Collection name is empty"
+        )));
+    }
+    modify(root, |collections| {
+        collections.insert(name.to_string(), query.clone());
+    })
+}
+
+/// Deletes the collection, returning whether it existed
+pub fn delete_collection<P: AsRef<Path>>(root: P, name: &str) -> Result<bool> {
+    let mut deleted = false;
+    modify(root, |collections| {
+        deleted = collections.remove(name).is_some();
+    })?;
+    Ok(deleted)
+}
+
+/// Finds current members of the collection
+pub fn evaluate(
+    index: &ResourceIndex,
+    name: &str,
+) -> Result<BTreeSet<ResourceId>> {
+    let collections = load_collections(index.root())?;
+    let collection = collections.get(name).ok_or_else(|| {
+        ArklibError::Other(anyhow::anyhow!("No collection named {}", name))
+    })?;
+    query(index, collection)
+}
+
+/// Change of membership of a collection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionChange {
+    pub name: String,
+    pub added: BTreeSet<ResourceId>,
+    pub removed: BTreeSet<ResourceId>,
+}
+
+/// Tracks membership of all collections of the root
+#[derive(Default)]
+pub struct CollectionWatcher {
+    members: HashMap<String, BTreeSet<ResourceId>>,
+    subscribers: Vec<Sender<CollectionChange>>,
+}
+
+impl CollectionWatcher {
+    pub fn new() -> Self {
+        CollectionWatcher::default()
+    }
+
+    /// Returns a channel receiving every change reported by
+    /// [`CollectionWatcher::refresh`]
+    pub fn subscribe(&mut self) -> Receiver<CollectionChange> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Evaluates all collections and reports changes of their membership
+    ///
+    /// The first refresh reports all members as added. Deleted collections
+    /// are reported with all their members removed.
+    pub fn refresh(
+        &mut self,
+        index: &ResourceIndex,
+    ) -> Result<Vec<CollectionChange>> {
+        let mut current = HashMap::new();
+        for (name, collection) in load_collections(index.root())? {
+            current.insert(name, query(index, &collection)?);
+        }
+
+        let mut changes = vec![];
+        let empty = BTreeSet::new();
+        let names: BTreeSet<&String> = current
+            .keys()
+            .chain(self.members.keys())
+            .collect();
+        for name in names {
+            let now = current.get(name).unwrap_or(&empty);
+            let before = self.members.get(name).unwrap_or(&empty);
+            let change = CollectionChange {
+                name: name.clone(),
+                added: now.difference(before).copied().collect(),
+                removed: before.difference(now).copied().collect(),
+            };
+            if !change.added.is_empty() || !change.removed.is_empty() {
+                changes.push(change);
+            }
+        }
+        self.members = current;
+
+        self.subscribers.retain(|subscriber| {
+            changes
+                .iter()
+                .all(|change| subscriber.send(change.clone()).is_ok())
+        });
+        Ok(changes)
+    }
+}
+
+fn modify<P: AsRef<Path>>(
+    root: P,
+    mut operator: impl FnMut(&mut BTreeMap<String, Query>),
+) -> Result<()> {
+    let file = AtomicFile::new(storage_path(root))?;
+    modify_json(
+        &file,
+        |collections: &mut Option<BTreeMap<String, Query>>| {
+            operator(collections.get_or_insert_with(BTreeMap::new));
+        },
+    )?;
+    Ok(())
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(COLLECTIONS_STORAGE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Kind;
+    use crate::tags::{add_tags, remove_tags};
+    use tempdir::TempDir;
+
+    #[test]
+    fn membership_changes_are_reported() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        std::fs::write(root.join("a.jpg"), "a").unwrap();
+        std::fs::write(root.join("b.jpg"), "b").unwrap();
+        let index = ResourceIndex::build(&root);
+        let a = index.get_entry(root.join("a.jpg")).unwrap().id;
+        let b = index.get_entry(root.join("b.jpg")).unwrap().id;
+        add_tags(&root, a, &["best".into()]).unwrap();
+
+        let best = Query {
+            all_tags: vec!["best".into()],
+            kinds: vec![Kind::Image],
+            ..Query::default()
+        };
+        save_collection(&root, "Best photos", &best).unwrap();
+        assert!(save_collection(&root, " ", &best).is_err());
+        assert_eq!(evaluate(&index, "Best photos").unwrap(), [a].into());
+
+        let mut watcher = CollectionWatcher::new();
+        let receiver = watcher.subscribe();
+        assert_eq!(watcher.refresh(&index).unwrap().len(), 1);
+        assert!(watcher.refresh(&index).unwrap().is_empty());
+
+        add_tags(&root, b, &["best".into()]).unwrap();
+        remove_tags(&root, a, &["best".into()]).unwrap();
+        let changes = watcher.refresh(&index).unwrap();
+        assert_eq!(
+            changes,
+            [CollectionChange {
+                name: "Best photos".into(),
+                added: [b].into(),
+                removed: [a].into(),
+            }]
+        );
+        assert_eq!(receiver.try_iter().count(), 2);
+
+        assert!(delete_collection(&root, "Best photos").unwrap());
+        let changes = watcher.refresh(&index).unwrap();
+        assert_eq!(changes[0].removed, [b].into());
+    }
+}
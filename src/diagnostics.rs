@@ -0,0 +1,45 @@
+//! Diagnostics of slow operations for apps shipping them with bug reports
+//!
+//! Building, loading, updating and storing the index are traced as spans
+//! carrying the amounts of processed files and `elapsed_ms`. Once enabled,
+//! every span is written as a JSON line when it closes:
+//!
+//! ```json
+//! {"timestamp":"...","level":"INFO","fields":{"message":"close"},
+//!  "span":{"root":"/storage/emulated/0/Photos","files":20713,
+//!  "elapsed_ms":5121,"name":"index_build"}}
+//! ```
+use std::io;
+
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+use crate::{ArklibError, Result};
+
+/// Default filter, reporting spans of the index without its debug events
+pub const DEFAULT_FILTER: &str = "arklib=info";
+
+/// Writes JSON diagnostics into the writer, e.g. a file in the app cache
+///
+/// The filter uses the `RUST_LOG` syntax, [`DEFAULT_FILTER`] is used if
+/// `None`. Fails if a global subscriber has been installed already.
+pub fn init_json_diagnostics<W>(writer: W, filter: Option<&str>) -> Result<()>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_new(filter.unwrap_or(DEFAULT_FILTER))
+        .map_err(|_| ArklibError::Parse)?;
+    tracing_subscriber::fmt()
+        .json()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .try_init()
+        .map_err(|e| ArklibError::Other(anyhow::anyhow!(e)))
+}
+
+/// Writes JSON diagnostics into the standard error
+pub fn init_stderr_diagnostics() -> Result<()> {
+    init_json_diagnostics(io::stderr, None)
+}
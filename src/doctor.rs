@@ -0,0 +1,444 @@
+//! Health check and repair of the `.ark` folder of a root
+//!
+//! A single corrupt line of the index or a version of user data written
+//! simultaneously on two devices used to make apps rebuild or drop data
+//! of the whole root. [`doctor`] finds such problems without modifying
+//! anything, and [`repair`] applies fixes which never lose user data:
+//! corrupt lines are dropped from the index, generated data of unknown
+//! resources is removed, and duplicated versions are resolved by writing
+//! a new version.
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use walkdir::WalkDir;
+
+use crate::atomic::{AtomicFile, Durability, Resolution, SwapStrategy};
+use crate::cache::CacheKind;
+use crate::index::{
+    parse_header, parse_line, IndexLock, INDEX_FORMAT_VERSION, INDEX_HEADER,
+    INDEX_LOCK_TIMEOUT,
+};
+use crate::platform::{self, grant_access};
+use crate::registrar::WriterInfo;
+use crate::resource::ResourceId;
+use crate::util::fs::write_file;
+use crate::{
+    ArklibError, Result, ARK_FOLDER, INDEX_PATH, PROPERTIES_STORAGE_FOLDER,
+    WRITER_LOCK_FILE,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// Line of the index file which can't be parsed, numbered from 1
+    CorruptIndexLine { line: usize, content: String },
+    /// Generated data of a resource which isn't indexed
+    OrphanedCache { kind: CacheKind, id: ResourceId },
+    /// Properties of a resource which isn't indexed, the resource might
+    /// be just temporarily absent, so they are never removed
+    OrphanedProperties { id: ResourceId },
+    /// Writer lock left by a process which stopped refreshing its
+    /// heartbeat, `held` if the lock is still taken
+    StaleWriterLock { info: WriterInfo, held: bool },
+    /// Several devices wrote the latest version of the file at once
+    DuplicatedVersions {
+        directory: PathBuf,
+        version: usize,
+        files: Vec<PathBuf>,
+    },
+    /// Entry of the `.ark` folder which can't be read or written
+    PermissionDenied { path: PathBuf },
+}
+
+impl Issue {
+    /// Whether [`repair`] can fix the issue
+    pub fn is_repairable(&self) -> bool {
+        match self {
+            Issue::OrphanedProperties { .. } => false,
+            Issue::StaleWriterLock { held, .. } => !held,
+            _ => true,
+        }
+    }
+}
+
+/// Checks the `.ark` folder of the root, without modifying it
+pub fn doctor<P: AsRef<Path>>(root: P) -> Result<Vec<Issue>> {
+    let ark = root.as_ref().join(ARK_FOLDER);
+    let mut issues = vec![];
+    if !ark.is_dir() {
+        return Ok(issues);
+    }
+
+    // user data is permanent, so permission problems
+    // are checked before anything is read
+    for entry in WalkDir::new(&ark) {
+        let path = match entry {
+            Ok(entry) => entry.into_path(),
+            Err(e) => match e.path() {
+                Some(path) => path.to_path_buf(),
+                None => continue,
+            },
+        };
+        if !is_accessible(&path) {
+            issues.push(Issue::PermissionDenied { path });
+        }
+    }
+
+    if let Some(ids) = check_index(&ark, &mut issues)? {
+        check_orphans(&ark, &ids, &mut issues)?;
+    }
+    check_writer_lock(&ark, &mut issues)?;
+    for entry in WalkDir::new(&ark).into_iter().flatten() {
+        if entry.file_type().is_dir() {
+            check_versions(entry.path(), &mut issues);
+        }
+    }
+    Ok(issues)
+}
+
+/// Applies safe fixes to the issues found by [`doctor`], returning
+/// the issues which weren't fixed
+pub fn repair<P: AsRef<Path>>(root: P, issues: &[Issue]) -> Result<Vec<Issue>> {
+    let root = root.as_ref();
+    let ark = root.join(ARK_FOLDER);
+    let mut remaining = vec![];
+
+    let corrupt: Vec<(usize, &str)> = issues
+        .iter()
+        .filter_map(|issue| match issue {
+            Issue::CorruptIndexLine { line, content } => {
+                Some((*line, content.as_str()))
+            }
+            _ => None,
+        })
+        .collect();
+    if !corrupt.is_empty() {
+        repair_index(root, &corrupt)?;
+    }
+
+    for issue in issues {
+        match issue {
+            Issue::CorruptIndexLine { .. } => {}
+            Issue::OrphanedCache { kind, id } => {
+                let path = ark.join(kind.folder()).join(id.to_string());
+                match fs::remove_dir_all(path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(e.into())
+                    }
+                    _ => {}
+                }
+            }
+            Issue::StaleWriterLock { held: false, .. } => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .open(ark.join(WRITER_LOCK_FILE))?;
+                // a new writer might have appeared meanwhile
+                match platform::try_lock(&file, true)? {
+                    true => {
+                        file.set_len(0)?;
+                        FileExt::unlock(&file)?;
+                    }
+                    false => remaining.push(issue.clone()),
+                }
+            }
+            Issue::DuplicatedVersions {
+                directory,
+                version,
+                files,
+            } => repair_versions(directory, *version, files)?,
+            Issue::PermissionDenied { path } => {
+                if let Err(e) = grant_access(path) {
+                    log::warn!("Couldn't fix {}: {}", path.display(), e);
+                    remaining.push(issue.clone());
+                }
+            }
+            _ => remaining.push(issue.clone()),
+        }
+    }
+    Ok(remaining)
+}
+
+fn is_accessible(path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    let readable = match metadata.is_dir() {
+        true => fs::read_dir(path).is_ok(),
+        false => File::open(path).is_ok(),
+    };
+    readable && !metadata.permissions().readonly()
+}
+
+/// Collects corrupt lines of the index, returning IDs of the index if
+/// all of its lines are valid
+fn check_index(
+    ark: &Path,
+    issues: &mut Vec<Issue>,
+) -> Result<Option<HashSet<ResourceId>>> {
+    let path = ark.join(INDEX_PATH);
+    // a legacy index is a folder
+    if path.is_dir() {
+        return Ok(None);
+    }
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut version = 1;
+    let mut ids = HashSet::new();
+    let mut valid = true;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if number == 0 {
+            match parse_header(&line) {
+                Ok(Some(header)) => {
+                    version = header;
+                    continue;
+                }
+                Ok(None) => {}
+                Err(ArklibError::Parse) => {
+                    version = INDEX_FORMAT_VERSION;
+                    valid = false;
+                    issues.push(Issue::CorruptIndexLine {
+                        line: 1,
+                        content: line,
+                    });
+                    continue;
+                }
+                // indexes of newer versions are left to newer apps
+                Err(_) => return Ok(None),
+            }
+        }
+        match parse_line(&line, version) {
+            Ok((entry, _)) => {
+                ids.insert(entry.id);
+            }
+            Err(_) => {
+                valid = false;
+                issues.push(Issue::CorruptIndexLine {
+                    line: number + 1,
+                    content: line,
+                });
+            }
+        }
+    }
+    Ok(valid.then_some(ids))
+}
+
+fn check_orphans(
+    ark: &Path,
+    ids: &HashSet<ResourceId>,
+    issues: &mut Vec<Issue>,
+) -> Result<()> {
+    let orphans = |folder: PathBuf| -> Result<Vec<ResourceId>> {
+        if !folder.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut orphans = vec![];
+        for entry in fs::read_dir(folder)? {
+            let name = entry?.file_name();
+            match name.to_string_lossy().parse() {
+                Ok(id) if !ids.contains(&id) => orphans.push(id),
+                _ => {}
+            }
+        }
+        orphans.sort();
+        Ok(orphans)
+    };
+
+    for kind in CacheKind::ALL {
+        for id in orphans(ark.join(kind.folder()))? {
+            issues.push(Issue::OrphanedCache { kind, id });
+        }
+    }
+    for id in orphans(ark.join(PROPERTIES_STORAGE_FOLDER))? {
+        issues.push(Issue::OrphanedProperties { id });
+    }
+    Ok(())
+}
+
+fn check_writer_lock(ark: &Path, issues: &mut Vec<Issue>) -> Result<()> {
+    let path = ark.join(WRITER_LOCK_FILE);
+    let Ok(mut file) = File::open(&path) else {
+        return Ok(());
+    };
+    let mut bytes = vec![];
+    match file.read_to_end(&mut bytes) {
+        // locks are mandatory on Windows, so a live writer blocks reading
+        Err(e) if platform::is_lock_contended(&e) => return Ok(()),
+        result => result?,
+    };
+    // the lock is cleared by writers closing the root
+    let Ok(info) = serde_json::from_slice::<WriterInfo>(&bytes) else {
+        return Ok(());
+    };
+    if info.is_alive() {
+        return Ok(());
+    }
+    let held = !platform::try_lock(&file, false)?;
+    if !held {
+        FileExt::unlock(&file)?;
+    }
+    issues.push(Issue::StaleWriterLock { info, held });
+    Ok(())
+}
+
+/// Finds versions of an [`AtomicFile`] in the folder written by
+/// several devices
+fn check_versions(directory: &Path, issues: &mut Vec<Issue>) {
+    let Some(name) = directory.file_name() else {
+        return;
+    };
+    let prefix = format!("{}_", name.to_string_lossy());
+    let file = AtomicFile {
+        directory: directory.to_path_buf(),
+        prefix: prefix.clone(),
+        durability: Durability::default(),
+        swap: SwapStrategy::default(),
+    };
+    let Ok((version, files)) = file.latest_version() else {
+        return;
+    };
+    let mut files: Vec<PathBuf> = files
+        .into_iter()
+        .map(|file| file.path)
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .collect();
+    if files.len() > 1 {
+        files.sort();
+        issues.push(Issue::DuplicatedVersions {
+            directory: directory.to_path_buf(),
+            version,
+            files,
+        });
+    }
+}
+
+/// Drops the corrupt lines from the index file, if they haven't
+/// changed since they were found
+fn repair_index(root: &Path, corrupt: &[(usize, &str)]) -> Result<()> {
+    let _lock = IndexLock::exclusive(root, INDEX_LOCK_TIMEOUT)?;
+    let path = root.join(ARK_FOLDER).join(INDEX_PATH);
+    let content = fs::read_to_string(&path)?;
+    let mut repaired = String::with_capacity(content.len());
+    for (number, line) in content.lines().enumerate() {
+        let number = number + 1;
+        if !corrupt.contains(&(number, line)) {
+            repaired.push_str(line);
+        } else if number == 1 && line.starts_with(INDEX_HEADER) {
+            repaired.push_str(&format!(
+                "{} {}",
+                INDEX_HEADER, INDEX_FORMAT_VERSION
+            ));
+        } else {
+            log::warn!("Dropping line {} of the index: {}", number, line);
+            continue;
+        }
+        repaired.push('\n');
+    }
+    write_file(&path, repaired.as_bytes())
+}
+
+/// Writes a new version with the content of this device, or of the most
+/// recently written duplicate, so that every device loads the same one
+fn repair_versions(
+    directory: &Path,
+    version: usize,
+    files: &[PathBuf],
+) -> Result<()> {
+    let file = AtomicFile::new(directory)?;
+    // the local file first, otherwise the newest one
+    let chosen = file.conflicts()?.into_iter().find(|conflict| {
+        conflict.version == version && files.contains(&conflict.path)
+    });
+    let Some(chosen) = chosen else {
+        // another version was written meanwhile
+        return Ok(());
+    };
+    file.resolve(Resolution::Pick(chosen.path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::invalidate_caches;
+    use crate::index::ResourceIndex;
+    use crate::storage::metadata;
+    use crate::tags::{set_tags, tags_of};
+    use tempdir::TempDir;
+
+    #[test]
+    fn issues_are_found_and_repaired() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "first").unwrap();
+        let index = ResourceIndex::build(&root);
+        index.store().unwrap();
+        let id = index.get_entry(root.join("a.txt")).unwrap().id;
+        set_tags(&root, id, &["tag".to_string()]).unwrap();
+        assert_eq!(doctor(&root).unwrap(), vec![]);
+
+        let orphan = ResourceId {
+            data_size: 1,
+            hash: 1,
+        };
+        metadata::store_field(&root, orphan, "kind", &"text").unwrap();
+        let index_path = root.join(ARK_FOLDER).join(INDEX_PATH);
+        let mut content = fs::read_to_string(&index_path).unwrap();
+        content.push_str("garbage\n");
+        fs::write(&index_path, content).unwrap();
+
+        // the same version of tags written on another device
+        let tags =
+            AtomicFile::new(root.join(ARK_FOLDER).join("user/tags")).unwrap();
+        let (version, files) = tags.latest_version().unwrap();
+        let foreign = tags
+            .directory
+            .join(format!("tags_otherdevice.{}", version));
+        fs::write(&foreign, "{}").unwrap();
+        let mut duplicates = vec![foreign, files[0].path.clone()];
+        duplicates.sort();
+
+        let issues = doctor(&root).unwrap();
+        assert_eq!(
+            issues,
+            vec![
+                Issue::CorruptIndexLine {
+                    line: 3,
+                    content: "garbage".to_string()
+                },
+                Issue::DuplicatedVersions {
+                    directory: tags.directory.clone(),
+                    version,
+                    files: duplicates,
+                },
+            ]
+        );
+
+        assert_eq!(repair(&root, &issues).unwrap(), vec![]);
+        let issues = doctor(&root).unwrap();
+        assert_eq!(
+            issues,
+            vec![Issue::OrphanedCache {
+                kind: CacheKind::Metadata,
+                id: orphan
+            }]
+        );
+        assert_eq!(repair(&root, &issues).unwrap(), vec![]);
+        assert_eq!(doctor(&root).unwrap(), vec![]);
+        assert_eq!(invalidate_caches(&root, orphan).unwrap(), 0);
+
+        // local content is kept
+        assert_eq!(tags_of(&root, id).unwrap().len(), 1);
+        assert_eq!(ResourceIndex::load(&root).unwrap(), index);
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::resource::ResourceId;
+use crate::storage::metadata;
+use crate::storage::preview::store_preview;
+use crate::util::xml::{attribute, read_zip_entry};
+use crate::{ArklibError, Result};
+
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// Metadata extracted from the package document of an EPUB file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpubMetadata {
+    /// Represents the `dc:title` element of the package
+    pub title: Option<String>,
+    /// Represents the first `dc:creator` element of the package
+    pub author: Option<String>,
+}
+
+/// An opened EPUB book
+///
+/// Only the package document is parsed on opening, the cover image
+/// is read lazily with [`Epub::cover`].
+pub struct Epub<R: Read + Seek> {
+    archive: ZipArchive<R>,
+    pub metadata: EpubMetadata,
+    cover_path: Option<String>,
+}
+
+/// A manifest item of the package document
+struct ManifestItem {
+    id: String,
+    href: String,
+    media_type: String,
+    properties: String,
+}
+
+impl<R: Read + Seek> Epub<R> {
+    pub fn open(data: R) -> Result<Self> {
+        let mut archive = ZipArchive::new(data)?;
+
+        let container = read_zip_entry(&mut archive, CONTAINER_PATH)?;
+        let package_path = find_package_path(&container)?;
+        let package = read_zip_entry(&mut archive, &package_path)?;
+        let (metadata, cover_href) = parse_package(&package)?;
+
+        // hrefs of the manifest are relative to the package document
+        let cover_path =
+            cover_href.map(|href| match package_path.rsplit_once('/') {
+                Some((dir, _)) => format!("{dir}/{href}"),
+                None => href,
+            });
+
+        Ok(Self {
+            archive,
+            metadata,
+            cover_path,
+        })
+    }
+
+    /// Returns raw bytes of the cover image, if the book declares one
+    pub fn cover(&mut self) -> Result<Option<Vec<u8>>> {
+        match &self.cover_path {
+            Some(path) => {
+                let path = path.clone();
+                Ok(Some(read_zip_entry(&mut self.archive, &path)?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Extracts title and author of the book into the metadata cache and its
+/// cover image into the previews cache
+pub fn generate<R: Read + Seek, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    data: R,
+) -> Result<EpubMetadata> {
+    let mut epub = Epub::open(data)?;
+    metadata::store(&root, id, &epub.metadata)?;
+    if let Some(cover) = epub.cover()? {
+        store_preview(&root, id, &cover)?;
+    }
+    Ok(epub.metadata)
+}
+
+fn find_package_path(container: &[u8]) -> Result<String> {
+    let mut reader = Reader::from_reader(container);
+    let mut buf = vec![];
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e)
+                if e.local_name().as_ref() == b"rootfile" =>
+            {
+                return attribute(&e, b"full-path").ok_or(ArklibError::Parse)
+            }
+            Event::Eof => return Err(ArklibError::Parse),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Parses the package document, returning the metadata together with
+/// the manifest href of the cover image
+fn parse_package(package: &[u8]) -> Result<(EpubMetadata, Option<String>)> {
+    let mut reader = Reader::from_reader(package);
+    let mut buf = vec![];
+
+    let mut metadata = EpubMetadata::default();
+    let mut cover_id: Option<String> = None;
+    let mut items: Vec<ManifestItem> = vec![];
+    // Local name of the `dc:*` element we are currently inside of
+    let mut current: Option<Vec<u8>> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                if name == b"title" || name == b"creator" {
+                    current = Some(name);
+                } else {
+                    handle_element(&e, &mut cover_id, &mut items);
+                }
+            }
+            Event::Empty(e) => handle_element(&e, &mut cover_id, &mut items),
+            Event::Text(text) => {
+                let text = text.unescape()?.trim().to_string();
+                match current.as_deref() {
+                    Some(b"title") if metadata.title.is_none() => {
+                        metadata.title = Some(text)
+                    }
+                    Some(b"creator") if metadata.author.is_none() => {
+                        metadata.author = Some(text)
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(_) => current = None,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    // EPUB 3 marks the cover with a property, EPUB 2 uses `<meta>`
+    let cover = items
+        .iter()
+        .find(|item| {
+            item.properties
+                .split_whitespace()
+                .any(|p| p == "cover-image")
+        })
+        .or_else(|| {
+            cover_id.and_then(|cover_id| {
+                items.iter().find(|item| item.id == cover_id)
+            })
+        })
+        .or_else(|| {
+            items.iter().find(|item| {
+                item.media_type.starts_with("image/")
+                    && item.id.to_lowercase().contains("cover")
+            })
+        })
+        .map(|item| item.href.clone());
+
+    Ok((metadata, cover))
+}
+
+fn handle_element(
+    element: &BytesStart,
+    cover_id: &mut Option<String>,
+    items: &mut Vec<ManifestItem>,
+) {
+    match element.local_name().as_ref() {
+        b"meta" if attribute(element, b"name").as_deref() == Some("cover") => {
+            *cover_id = attribute(element, b"content");
+        }
+        b"item" => {
+            if let (Some(id), Some(href)) =
+                (attribute(element, b"id"), attribute(element, b"href"))
+            {
+                items.push(ManifestItem {
+                    id,
+                    href,
+                    media_type: attribute(element, b"media-type")
+                        .unwrap_or_default(),
+                    properties: attribute(element, b"properties")
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+    const PACKAGE: &str = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>Alice&apos;s Adventures</dc:title>
+    <dc:creator>Lewis Carroll</dc:creator>
+    <meta name="cover" content="cover-img"/>
+  </metadata>
+  <manifest>
+    <item id="chapter" href="chapter.xhtml" media-type="application/xhtml+xml"/>
+    <item id="cover-img" href="images/lena.jpg" media-type="image/jpeg"/>
+  </manifest>
+</package>"#;
+
+    fn build_epub(cover: &[u8]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        let options = FileOptions::default();
+        zip.start_file("mimetype", options).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        zip.start_file(CONTAINER_PATH, options).unwrap();
+        zip.write_all(CONTAINER.as_bytes()).unwrap();
+        zip.start_file("OEBPS/content.opf", options)
+            .unwrap();
+        zip.write_all(PACKAGE.as_bytes()).unwrap();
+        zip.start_file("OEBPS/images/lena.jpg", options)
+            .unwrap();
+        zip.write_all(cover).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn epub_metadata_and_cover() {
+        let cover = std::fs::read("tests/lena.jpg").unwrap();
+        let data = build_epub(&cover);
+
+        let mut epub = Epub::open(Cursor::new(data)).unwrap();
+        assert_eq!(
+            epub.metadata,
+            EpubMetadata {
+                title: Some("Alice's Adventures".to_string()),
+                author: Some("Lewis Carroll".to_string()),
+            }
+        );
+        assert_eq!(epub.cover().unwrap(), Some(cover));
+    }
+
+    #[test]
+    fn epub_without_container_is_rejected() {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file("mimetype", FileOptions::default())
+            .unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        let data = zip.finish().unwrap().into_inner();
+
+        assert!(Epub::open(Cursor::new(data)).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::str::Utf8Error;
 
 use thiserror::Error;
@@ -16,6 +17,19 @@ pub enum ArklibError {
     Parse,
     #[error("Networking error")]
     Network,
+    #[error("Expected {expected} bytes but {actual} bytes were read")]
+    SizeMismatch { expected: u64, actual: u64 },
+    #[error("Operation was cancelled")]
+    Cancelled,
+    #[error("Resource is busy: {0}")]
+    Busy(String),
+    #[error(
+        "Root {} is inside of root {}, open the outer root instead \
+         or allow nesting explicitly with `set_nesting_allowed`",
+        .root.display(),
+        .outer.display()
+    )]
+    NestedRoot { root: PathBuf, outer: PathBuf },
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -44,8 +58,94 @@ impl From<url::ParseError> for ArklibError {
     }
 }
 
+impl From<zip::result::ZipError> for ArklibError {
+    fn from(_: zip::result::ZipError) -> Self {
+        Self::Parse
+    }
+}
+
+impl From<quick_xml::Error> for ArklibError {
+    fn from(_: quick_xml::Error) -> Self {
+        Self::Parse
+    }
+}
+
+#[cfg(feature = "pdfium")]
+impl From<pdfium_render::prelude::PdfiumError> for ArklibError {
+    fn from(e: pdfium_render::prelude::PdfiumError) -> Self {
+        Self::Other(anyhow::anyhow!("PDF error: {:?}", e))
+    }
+}
+
+impl From<image::ImageError> for ArklibError {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e) => Self::Io(e),
+            _ => Self::Parse,
+        }
+    }
+}
+
+impl From<globset::Error> for ArklibError {
+    fn from(_: globset::Error) -> Self {
+        Self::Parse
+    }
+}
+
+#[cfg(feature = "net")]
+impl From<bincode::Error> for ArklibError {
+    fn from(_: bincode::Error) -> Self {
+        Self::Parse
+    }
+}
+
+#[cfg(feature = "net")]
+impl From<mdns_sd::Error> for ArklibError {
+    fn from(_: mdns_sd::Error) -> Self {
+        Self::Network
+    }
+}
+
+#[cfg(feature = "sqlite-index")]
+impl From<rusqlite::Error> for ArklibError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Other(anyhow::anyhow!("SQLite error: {}", e))
+    }
+}
+
 impl From<Box<dyn std::error::Error>> for ArklibError {
     fn from(e: Box<dyn std::error::Error>) -> Self {
         Self::Other(anyhow::anyhow!(e.to_string()))
     }
 }
+
+/// Runs the operation, turning a panic into an error instead of unwinding
+/// into the caller, which aborts the process when the caller is foreign
+/// code like JNI bindings
+pub fn catch_panic<T, F>(operation: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + std::panic::UnwindSafe,
+{
+    std::panic::catch_unwind(operation).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        log::error!("Recovered from a panic: {}", message);
+        Err(ArklibError::Other(anyhow::anyhow!("Panic: {}", message)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_become_errors() {
+        assert_eq!(catch_panic(|| Ok(1)).unwrap(), 1);
+        let error = catch_panic::<(), _>(|| panic!("boom")).unwrap_err();
+        assert_eq!(error.to_string(), "Panic: boom");
+        assert!(catch_panic::<(), _>(|| Err(ArklibError::Parse)).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! C interface for apps which can't use the Rust API, e.g. iOS apps
+//!
+//! The declarations are in `include/arklib.h`, which is exported to Swift
+//! by `include/module.modulemap` when the static library is packaged into
+//! an XCFramework. Functions returning a status return 0 on success and -1
+//! on failure, in which case [`ark_last_error`] describes the failure.
+//! Panics never cross the boundary.
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+
+use crate::{catch_panic, provide_index, ArklibError, Result};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Initializes the library, loading the app id from the folder
+/// or generating it there
+///
+/// # Safety
+///
+/// `app_id_dir` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ark_initialize(app_id_dir: *const c_char) -> i32 {
+    status(catch_panic(|| {
+        crate::app_id::load(path(app_id_dir)?)?;
+        Ok(())
+    }))
+}
+
+/// Provides the index of the root, building it on the first call
+///
+/// # Safety
+///
+/// `root` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ark_provide_index(root: *const c_char) -> i32 {
+    status(catch_panic(|| {
+        provide_index(path(root)?)?;
+        Ok(())
+    }))
+}
+
+/// Returns the number of resources in the index of the root,
+/// or -1 on failure
+///
+/// # Safety
+///
+/// `root` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ark_count_resources(root: *const c_char) -> i64 {
+    let count = catch_panic(|| {
+        let index = provide_index(path(root)?)?;
+        let count = index
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .count_resources();
+        Ok(count as i64)
+    });
+    count.unwrap_or_else(|e| {
+        set_last_error(e);
+        -1
+    })
+}
+
+/// Returns the message of the last failure on this thread, or null;
+/// the message must be released with [`ark_string_free`]
+#[no_mangle]
+pub extern "C" fn ark_last_error() -> *mut c_char {
+    LAST_ERROR
+        .with(|last| last.borrow_mut().take())
+        .and_then(|message| CString::new(message).ok())
+        .map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+/// Releases a string returned by the library
+///
+/// # Safety
+///
+/// `string` must be null or returned by the library and not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ark_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+unsafe fn path(string: *const c_char) -> Result<PathBuf> {
+    if string.is_null() {
+        return Err(ArklibError::Path("Null path".into()));
+    }
+    let string = CStr::from_ptr(string)
+        .to_str()
+        .map_err(|e| ArklibError::Other(anyhow!(e)))?;
+    Ok(PathBuf::from(string))
+}
+
+fn status(result: Result<()>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+fn set_last_error(error: ArklibError) {
+    log::error!("{}", error);
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(error.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn failures_are_reported() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let root = CString::new(dir.path().to_str().unwrap()).unwrap();
+        let temp =
+            CString::new(std::env::temp_dir().to_str().unwrap()).unwrap();
+        unsafe {
+            assert_eq!(ark_initialize(temp.as_ptr()), 0);
+            assert_eq!(ark_count_resources(root.as_ptr()), 1);
+
+            assert_eq!(ark_provide_index(std::ptr::null()), -1);
+            let error = ark_last_error();
+            assert_eq!(
+                CStr::from_ptr(error).to_str().unwrap(),
+                "Path error: Null path"
+            );
+            ark_string_free(error);
+            assert!(ark_last_error().is_null());
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Properties of folders, like titles and cover images
+//!
+//! Folders have no IDs, so their properties are keyed by
+//! [`RelativePath::storage_key`] and stored under `user/folders`, one
+//! [`AtomicFile`] per folder. The relative path is stored as well, so that
+//! all folders with properties can be listed.
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::index::{RelativePath, ResourceIndex};
+use crate::query::Kind;
+use crate::resource::ResourceId;
+use crate::{Result, ARK_FOLDER, FOLDER_PROPERTIES_STORAGE_FOLDER};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FolderProperties {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Resource shown as the cover of the folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover: Option<ResourceId>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProperties {
+    path: RelativePath,
+    #[serde(flatten)]
+    properties: FolderProperties,
+}
+
+/// Loads properties of the folder, if any were stored
+pub fn load_folder_properties<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+) -> Result<Option<FolderProperties>> {
+    Ok(load_stored(storage_path(root).join(folder.storage_key()))?
+        .map(|stored| stored.properties))
+}
+
+/// Replaces all properties of the folder
+pub fn store_folder_properties<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+    properties: &FolderProperties,
+) -> Result<()> {
+    modify(root, folder, |current| *current = properties.clone())
+}
+
+/// Sets or clears the cover of the folder, keeping other properties
+pub fn set_cover<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+    cover: Option<ResourceId>,
+) -> Result<()> {
+    modify(root, folder, |current| current.cover = cover)
+}
+
+/// Lists all folders with properties
+pub fn list_folder_properties<P: AsRef<Path>>(
+    root: P,
+) -> Result<Vec<(RelativePath, FolderProperties)>> {
+    let storage = storage_path(root);
+    if !storage.exists() {
+        return Ok(vec![]);
+    }
+    let mut folders = vec![];
+    for entry in std::fs::read_dir(storage)? {
+        if let Some(stored) = load_stored(entry?.path())? {
+            folders.push((stored.path, stored.properties));
+        }
+    }
+    folders.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(folders)
+}
+
+/// Returns the cover of the folder if it is still indexed, otherwise
+/// the first image nested into the folder
+pub fn resolve_cover(
+    index: &ResourceIndex,
+    folder: &RelativePath,
+) -> Result<Option<ResourceId>> {
+    let cover = load_folder_properties(index.root(), folder)?
+        .and_then(|properties| properties.cover)
+        .filter(|cover| index.contains(cover));
+    if cover.is_some() {
+        return Ok(cover);
+    }
+    Ok(index
+        .entries_with_prefix(folder)
+        .into_iter()
+        .find(|(path, _)| Kind::of(path.as_str()) == Kind::Image)
+        .map(|(_, entry)| entry.id))
+}
+
+fn load_stored(path: PathBuf) -> Result<Option<StoredProperties>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    match AtomicFile::new(path)?.load()?.open()? {
+        Some(file) => Ok(Some(serde_json::from_reader(BufReader::new(file))?)),
+        None => Ok(None),
+    }
+}
+
+fn modify<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+    mut operator: impl FnMut(&mut FolderProperties),
+) -> Result<()> {
+    let file = AtomicFile::new(storage_path(root).join(folder.storage_key()))?;
+    modify_json(&file, |stored: &mut Option<StoredProperties>| {
+        let stored = stored.get_or_insert_with(|| StoredProperties {
+            path: folder.clone(),
+            properties: FolderProperties::default(),
+        });
+        operator(&mut stored.properties);
+    })?;
+    Ok(())
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(FOLDER_PROPERTIES_STORAGE_FOLDER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn folders_have_covers() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        std::fs::create_dir_all(root.join("trip/day 1")).unwrap();
+        std::fs::write(root.join("trip/notes.txt"), "notes").unwrap();
+        std::fs::write(root.join("trip/day 1/b.jpg"), "b").unwrap();
+        std::fs::write(root.join("trip/day 1/c.jpg"), "c").unwrap();
+        let index = ResourceIndex::build(&root);
+        let c = index
+            .get_entry(root.join("trip/day 1/c.jpg"))
+            .unwrap()
+            .id;
+        let b = index
+            .get_entry(root.join("trip/day 1/b.jpg"))
+            .unwrap()
+            .id;
+
+        let trip = RelativePath::parse("trip").unwrap();
+        assert_eq!(load_folder_properties(&root, &trip).unwrap(), None);
+        assert_eq!(resolve_cover(&index, &trip).unwrap(), Some(b));
+
+        let properties = FolderProperties {
+            title: Some("Summer trip".into()),
+            ..FolderProperties::default()
+        };
+        store_folder_properties(&root, &trip, &properties).unwrap();
+        set_cover(&root, &trip, Some(c)).unwrap();
+        assert_eq!(resolve_cover(&index, &trip).unwrap(), Some(c));
+        let listed = list_folder_properties(&root).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0, trip);
+        assert_eq!(listed[0].1.title.as_deref(), Some("Summer trip"));
+        assert_eq!(listed[0].1.cover, Some(c));
+
+        // covers deleted from the root are not shown
+        let missing = ResourceId {
+            hash: 1,
+            data_size: 1,
+        };
+        set_cover(&root, &trip, Some(missing)).unwrap();
+        assert_eq!(resolve_cover(&index, &trip).unwrap(), Some(b));
+    }
+}
@@ -0,0 +1,425 @@
+//! Spatial index of photo locations
+//!
+//! GPS coordinates are read from EXIF attributes of photos and kept in a
+//! single cache file sorted by latitude, so that map views can query a
+//! region without reading metadata of every resource. The index is small
+//! enough to be loaded into memory entirely, it has to be refreshed with
+//! [`update_geo_index`] after every update of the resource index.
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek};
+use std::path::{Path, PathBuf};
+
+use exif::{In, Tag, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::index::IndexUpdate;
+use crate::resource::ResourceId;
+use crate::{images, Result, ARK_FOLDER, GEO_STORAGE_FILE};
+
+/// Mean radius of the Earth, in meters
+const EARTH_RADIUS: f64 = 6_371_000.0;
+/// Length of one degree of latitude, in meters
+const DEGREE_LENGTH: f64 = EARTH_RADIUS * std::f64::consts::PI / 180.0;
+
+/// Coordinates in degrees, south and west are negative
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    /// Great-circle distance to the other point, in meters
+    pub fn distance(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+}
+
+/// Rectangle of coordinates, `west` is greater than `east` for
+/// rectangles crossing the antimeridian
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+impl Bounds {
+    pub fn contains(&self, point: &GeoPoint) -> bool {
+        let lon = match self.west <= self.east {
+            true => self.west <= point.lon && point.lon <= self.east,
+            false => self.west <= point.lon || point.lon <= self.east,
+        };
+        self.south <= point.lat && point.lat <= self.north && lon
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct GeoEntry {
+    id: ResourceId,
+    #[serde(flatten)]
+    point: GeoPoint,
+}
+
+/// Locations of resources sorted by latitude
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeoIndex {
+    entries: Vec<GeoEntry>,
+}
+
+impl GeoIndex {
+    /// Loads the index of the root, empty if it was never built
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let path = storage_path(root);
+        if !path.exists() {
+            return Ok(GeoIndex::default());
+        }
+        match AtomicFile::new(path)?.load()?.open()? {
+            Some(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            None => Ok(GeoIndex::default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, id: ResourceId) -> Option<GeoPoint> {
+        self.entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.point)
+    }
+
+    /// Sets the location of the resource, replacing the previous one
+    pub fn insert(&mut self, id: ResourceId, point: GeoPoint) {
+        self.remove(id);
+        let position = self
+            .entries
+            .partition_point(|entry| entry.point.lat < point.lat);
+        self.entries
+            .insert(position, GeoEntry { id, point });
+    }
+
+    pub fn remove(&mut self, id: ResourceId) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.id != id);
+        self.entries.len() != len
+    }
+
+    /// Finds resources located within the bounds
+    pub fn find_in_bbox(&self, bounds: &Bounds) -> Vec<(ResourceId, GeoPoint)> {
+        self.latitudes(bounds.south, bounds.north)
+            .iter()
+            .filter(|entry| bounds.contains(&entry.point))
+            .map(|entry| (entry.id, entry.point))
+            .collect()
+    }
+
+    /// Finds resources located within `radius` meters from the point,
+    /// the nearest ones first
+    pub fn find_near(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius: f64,
+    ) -> Vec<(ResourceId, f64)> {
+        let center = GeoPoint { lat, lon };
+        let span = radius / DEGREE_LENGTH;
+        let mut found: Vec<(ResourceId, f64)> = self
+            .latitudes(lat - span, lat + span)
+            .iter()
+            .map(|entry| (entry.id, center.distance(&entry.point)))
+            .filter(|(_, distance)| *distance <= radius)
+            .collect();
+        found.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        found
+    }
+
+    /// Entries with latitudes in the range, found by binary search
+    fn latitudes(&self, south: f64, north: f64) -> &[GeoEntry] {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.point.lat < south);
+        let end = self
+            .entries
+            .partition_point(|entry| entry.point.lat <= north);
+        &self.entries[start..end.max(start)]
+    }
+}
+
+/// Reads GPS coordinates from EXIF attributes of the photo
+pub fn read_location<R: BufRead + Seek>(data: R) -> Result<Option<GeoPoint>> {
+    let exif = match images::read_exif(data)? {
+        Some(exif) => exif,
+        None => return Ok(None),
+    };
+    let coordinate = |tag, reference, negative: u8| {
+        let degrees = match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(parts) if !parts.is_empty() => parts
+                .iter()
+                .zip([1.0, 60.0, 3600.0])
+                .map(|(part, divisor)| part.to_f64() / divisor)
+                .sum::<f64>(),
+            _ => return None,
+        };
+        let sign = match &exif.get_field(reference, In::PRIMARY)?.value {
+            Value::Ascii(values)
+                if values.first()?.first() == Some(&negative) =>
+            {
+                -1.0
+            }
+            _ => 1.0,
+        };
+        Some(sign * degrees)
+    };
+    let lat = coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S');
+    let lon = coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W');
+    Ok(match (lat, lon) {
+        (Some(lat), Some(lon))
+            if lat.is_finite()
+                && lon.is_finite()
+                && lat.abs() <= 90.0
+                && lon.abs() <= 180.0 =>
+        {
+            Some(GeoPoint { lat, lon })
+        }
+        _ => None,
+    })
+}
+
+/// Adds locations of photos added or modified by the update and removes
+/// locations of deleted resources
+///
+/// Returns the number of located photos.
+pub fn update_geo_index<P: AsRef<Path>>(
+    root: P,
+    update: &IndexUpdate,
+) -> Result<usize> {
+    let mut located = vec![];
+    let added = update.added.iter().map(|(path, id)| (path, *id));
+    let modified = update
+        .modified
+        .iter()
+        .map(|(path, (_, id))| (path, *id));
+    for (path, id) in added.chain(modified) {
+        let supported = path.extension().is_some_and(|extension| {
+            images::is_supported(&extension.to_string_lossy())
+        });
+        if !supported {
+            continue;
+        }
+        let location = File::open(path)
+            .map_err(Into::into)
+            .and_then(|file| read_location(BufReader::new(file)));
+        match location {
+            Ok(Some(point)) => located.push((id, point)),
+            Ok(None) => {}
+            Err(e) => {
+                log::warn!("Couldn't read EXIF of {}: {}", path.display(), e)
+            }
+        }
+    }
+
+    let outdated: Vec<ResourceId> = update
+        .deleted
+        .iter()
+        .copied()
+        .chain(
+            update
+                .modified
+                .values()
+                .map(|(old_id, _)| *old_id),
+        )
+        .collect();
+    if located.is_empty() && outdated.is_empty() {
+        return Ok(0);
+    }
+    let file = AtomicFile::new(storage_path(&root))?;
+    modify_json(&file, |index: &mut Option<GeoIndex>| {
+        let index = index.get_or_insert_with(GeoIndex::default);
+        for id in &outdated {
+            index.remove(*id);
+        }
+        for (id, point) in &located {
+            index.insert(*id, *point);
+        }
+    })?;
+    Ok(located.len())
+}
+
+/// Finds resources of the root located within the bounds
+pub fn find_in_bbox<P: AsRef<Path>>(
+    root: P,
+    bounds: &Bounds,
+) -> Result<Vec<(ResourceId, GeoPoint)>> {
+    Ok(GeoIndex::load(root)?.find_in_bbox(bounds))
+}
+
+/// Finds resources of the root located within `radius` meters
+/// from the point, the nearest ones first
+pub fn find_near<P: AsRef<Path>>(
+    root: P,
+    lat: f64,
+    lon: f64,
+    radius: f64,
+) -> Result<Vec<(ResourceId, f64)>> {
+    Ok(GeoIndex::load(root)?.find_near(lat, lon, radius))
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(GEO_STORAGE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use image::{DynamicImage, RgbImage};
+    use std::io::Cursor;
+    use tempdir::TempDir;
+
+    fn id(hash: u32) -> ResourceId {
+        ResourceId { hash, data_size: 1 }
+    }
+
+    fn point(lat: f64, lon: f64) -> GeoPoint {
+        GeoPoint { lat, lon }
+    }
+
+    /// JPEG with an APP1 segment holding the GPS IFD
+    fn located_jpeg(
+        lat: (u32, u32, u32),
+        south: bool,
+        lon: (u32, u32, u32),
+        west: bool,
+    ) -> Vec<u8> {
+        let rationals = |(d, m, s): (u32, u32, u32)| {
+            [d, 1, m, 1, s, 1]
+                .iter()
+                .flat_map(|n| n.to_be_bytes())
+                .collect::<Vec<u8>>()
+        };
+        let reference =
+            |tag: u8, value: u8| [0, tag, 0, 2, 0, 0, 0, 2, value, 0, 0, 0];
+        let offset =
+            |tag: u8, offset: u8| [0, tag, 0, 5, 0, 0, 0, 3, 0, 0, 0, offset];
+        let mut tiff = vec![b'M', b'M', 0, 42, 0, 0, 0, 8];
+        // IFD0 pointing to the GPS IFD at 26
+        tiff.extend([0, 1, 0x88, 0x25, 0, 4, 0, 0, 0, 1, 0, 0, 0, 26]);
+        tiff.extend([0, 0, 0, 0]);
+        // GPS IFD with coordinates at 80 and 104
+        tiff.extend([0, 4]);
+        tiff.extend(reference(
+            1,
+            if south {
+                b'S'
+            } else {
+                b'N'
+            },
+        ));
+        tiff.extend(offset(2, 80));
+        tiff.extend(reference(3, if west { b'W' } else { b'E' }));
+        tiff.extend(offset(4, 104));
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(rationals(lat));
+        tiff.extend(rationals(lon));
+
+        let image = DynamicImage::ImageRgb8(RgbImage::new(8, 8));
+        let jpeg = images::encode(&image).unwrap();
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend([0xFF, 0xE1]);
+        bytes.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        bytes.extend(b"Exif\0\0");
+        bytes.extend(tiff);
+        bytes.extend(&jpeg[2..]);
+        bytes
+    }
+
+    #[test]
+    fn gps_coordinates_are_read() {
+        let jpeg = located_jpeg((48, 51, 36), false, (2, 17, 24), true);
+        let location = read_location(Cursor::new(jpeg)).unwrap().unwrap();
+        assert!((location.lat - 48.86).abs() < 1e-9);
+        assert!((location.lon + 2.29).abs() < 1e-9);
+
+        let plain = images::encode(&DynamicImage::new_rgb8(8, 8)).unwrap();
+        assert_eq!(read_location(Cursor::new(plain)).unwrap(), None);
+    }
+
+    #[test]
+    fn regions_are_queried() {
+        let mut index = GeoIndex::default();
+        index.insert(id(1), point(48.8566, 2.3522)); // Paris
+        index.insert(id(2), point(48.8049, 2.1204)); // Versailles
+        index.insert(id(3), point(51.5074, -0.1278)); // London
+        index.insert(id(4), point(-17.7134, 178.0650)); // Fiji
+        index.insert(id(3), point(51.5072, -0.1276));
+        assert_eq!(index.len(), 4);
+
+        let near = index.find_near(48.8566, 2.3522, 30_000.0);
+        let ids: Vec<ResourceId> = near.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [id(1), id(2)]);
+        assert!((near[1].1 - 17_000.0).abs() < 1_000.0);
+
+        let europe = Bounds {
+            south: 40.0,
+            west: -10.0,
+            north: 60.0,
+            east: 2.2,
+        };
+        let found: Vec<ResourceId> = index
+            .find_in_bbox(&europe)
+            .iter()
+            .map(|(id, _)| *id)
+            .collect();
+        assert_eq!(found, [id(2), id(3)]);
+        let pacific = Bounds {
+            south: -30.0,
+            west: 170.0,
+            north: 0.0,
+            east: -170.0,
+        };
+        assert_eq!(index.find_in_bbox(&pacific).len(), 1);
+    }
+
+    #[test]
+    fn index_follows_updates() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let path = root.join("photo.jpg");
+        let jpeg = located_jpeg((48, 51, 36), false, (2, 17, 24), false);
+        std::fs::write(&path, &jpeg).unwrap();
+        let photo = ResourceId::compute_bytes(&jpeg).unwrap();
+
+        let update = IndexUpdate {
+            added: [(path, photo)].into(),
+            ..IndexUpdate::default()
+        };
+        assert_eq!(update_geo_index(root, &update).unwrap(), 1);
+        let near = find_near(root, 48.86, 2.29, 100.0).unwrap();
+        assert_eq!(near.len(), 1);
+        assert_eq!(near[0].0, photo);
+
+        let update = IndexUpdate {
+            deleted: [photo].into(),
+            ..IndexUpdate::default()
+        };
+        update_geo_index(root, &update).unwrap();
+        assert!(GeoIndex::load(root).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+//! gRPC service of a root, following the schema in `proto/arklib.proto`
+//!
+//! Daemons written in other languages generate clients from the schema,
+//! while [`ArklibServer`] routes their calls to the index and the user
+//! data of the root.
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+
+use prost::Message;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{empty_body, http, Body, BoxFuture, Service, StdError};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::Status;
+
+use crate::storage::prop::{load_raw_properties, store_properties};
+use crate::tags::{set_tags, tags_of};
+use crate::{ArklibError, ResourceIndexLock, Result};
+
+pub mod proto;
+
+use proto::{required, Empty, IndexEntries, IndexUpdate, Properties, Tags};
+
+const SERVICE_NAME: &str = "arklib.v1.Arklib";
+
+/// Answers calls until the listener fails
+pub async fn serve(
+    listener: TcpListener,
+    index: ResourceIndexLock,
+) -> Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(ArklibServer::new(index))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+        .map_err(|e| ArklibError::Other(e.into()))
+}
+
+#[derive(Clone)]
+pub struct ArklibServer {
+    index: ResourceIndexLock,
+}
+
+impl ArklibServer {
+    pub fn new(index: ResourceIndexLock) -> Self {
+        ArklibServer { index }
+    }
+
+    fn list_entries(&self, _: Empty) -> Result<IndexEntries> {
+        let index = self
+            .index
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        let entries = index
+            .entries()
+            .map(|(path, entry)| {
+                proto::IndexEntry::new(index.root(), path, entry)
+            })
+            .collect::<Result<_>>()?;
+        Ok(IndexEntries { entries })
+    }
+
+    fn update(&self, _: Empty) -> Result<IndexUpdate> {
+        let mut index = self
+            .index
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        let update = index.update_all()?;
+        IndexUpdate::new(index.root(), &update)
+    }
+
+    fn get_tags(&self, id: proto::ResourceId) -> Result<Tags> {
+        let tags = tags_of(self.root(), id.into())?;
+        Ok(Tags {
+            id: Some(id),
+            tags: tags.into_iter().collect(),
+        })
+    }
+
+    fn set_tags(&self, tags: Tags) -> Result<Tags> {
+        let id = required(tags.id)?;
+        set_tags(self.root(), id, &tags.tags)?;
+        self.get_tags(id.into())
+    }
+
+    fn get_properties(&self, id: proto::ResourceId) -> Result<Properties> {
+        let json = match load_raw_properties(self.root(), id.into()) {
+            Ok(json) => {
+                String::from_utf8(json).map_err(|_| ArklibError::Parse)?
+            }
+            Err(ArklibError::Io(e))
+                if e.kind() == std::io::ErrorKind::NotFound =>
+            {
+                "{}".to_string()
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Properties { id: Some(id), json })
+    }
+
+    fn set_properties(&self, properties: Properties) -> Result<Properties> {
+        let id = required(properties.id)?;
+        let value: serde_json::Value = serde_json::from_str(&properties.json)?;
+        if !value.is_object() {
+            return Err(ArklibError::Parse);
+        }
+        store_properties(self.root(), id, &value)?;
+        self.get_properties(id.into())
+    }
+
+    fn root(&self) -> std::path::PathBuf {
+        self.index
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .root()
+            .to_path_buf()
+    }
+}
+
+impl NamedService for ArklibServer {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for ArklibServer
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        _: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let server = self.clone();
+        let method = request
+            .uri()
+            .path()
+            .strip_prefix(&format!("/{}/", SERVICE_NAME))
+            .unwrap_or_default()
+            .to_string();
+        match method.as_str() {
+            "ListEntries" => unary(request, move |r| server.list_entries(r)),
+            "Update" => unary(request, move |r| server.update(r)),
+            "GetTags" => unary(request, move |r| server.get_tags(r)),
+            "SetTags" => unary(request, move |r| server.set_tags(r)),
+            "GetProperties" => {
+                unary(request, move |r| server.get_properties(r))
+            }
+            "SetProperties" => {
+                unary(request, move |r| server.set_properties(r))
+            }
+            _ => Box::pin(async move {
+                let response = http::Response::builder()
+                    .header("grpc-status", tonic::Code::Unimplemented as i32)
+                    .header(http::header::CONTENT_TYPE, "application/grpc")
+                    .body(empty_body())
+                    .unwrap_or_default();
+                Ok(response)
+            }),
+        }
+    }
+}
+
+/// Handler of a unary method, as generated by tonic-build for every method
+#[derive(Clone)]
+struct Unary<F>(F);
+
+impl<Req, Resp, F> UnaryService<Req> for Unary<F>
+where
+    F: Fn(Req) -> Result<Resp> + Clone + Send + 'static,
+    Req: Send + 'static,
+    Resp: Send + 'static,
+{
+    type Response = Resp;
+    type Future = BoxFuture<tonic::Response<Resp>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let handler = self.0.clone();
+        Box::pin(async move {
+            handler(request.into_inner())
+                .map(tonic::Response::new)
+                .map_err(status)
+        })
+    }
+}
+
+fn unary<B, Req, Resp, F>(
+    request: http::Request<B>,
+    handler: F,
+) -> BoxFuture<http::Response<BoxBody>, Infallible>
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    Req: Message + Default + Send + 'static,
+    Resp: Message + Send + 'static,
+    F: Fn(Req) -> Result<Resp> + Clone + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = Grpc::new(ProstCodec::<Resp, Req>::default());
+        Ok(grpc.unary(Unary(handler), request).await)
+    })
+}
+
+fn status(error: ArklibError) -> Status {
+    match error {
+        ArklibError::Path(_) => Status::not_found(error.to_string()),
+        ArklibError::Parse => Status::invalid_argument(error.to_string()),
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResourceIndex;
+    use http::uri::PathAndQuery;
+    use std::fs;
+    use std::sync::{Arc, RwLock};
+    use tempdir::TempDir;
+    use tonic::transport::Channel;
+
+    async fn call<Req, Resp>(
+        channel: &Channel,
+        method: &'static str,
+        request: Req,
+    ) -> std::result::Result<Resp, Status>
+    where
+        Req: Message + Send + Sync + 'static,
+        Resp: Message + Default + Send + Sync + 'static,
+    {
+        let mut client = tonic::client::Grpc::new(channel.clone());
+        client
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        client
+            .unary(
+                tonic::Request::new(request),
+                PathAndQuery::from_static(method),
+                ProstCodec::<Req, Resp>::default(),
+            )
+            .await
+            .map(|response| response.into_inner())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn calls_are_served() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "hello").unwrap();
+        let index = Arc::new(RwLock::new(ResourceIndex::build(&root)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, index));
+        let channel = Channel::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+
+        let entries: IndexEntries =
+            call(&channel, "/arklib.v1.Arklib/ListEntries", Empty {})
+                .await
+                .unwrap();
+        assert_eq!(entries.entries.len(), 1);
+        assert_eq!(entries.entries[0].path, "a.txt");
+        let id = entries.entries[0].id;
+
+        let tags = Tags {
+            id,
+            tags: vec!["greeting".to_string()],
+        };
+        let tags: Tags = call(&channel, "/arklib.v1.Arklib/SetTags", tags)
+            .await
+            .unwrap();
+        assert_eq!(tags.tags, vec!["greeting".to_string()]);
+
+        fs::write(root.join("b.txt"), "world").unwrap();
+        let update: IndexUpdate =
+            call(&channel, "/arklib.v1.Arklib/Update", Empty {})
+                .await
+                .unwrap();
+        assert_eq!(update.added[0].path, "b.txt");
+        let update = update.into_update(&root).unwrap();
+        assert!(update.added.contains_key(&root.join("b.txt")));
+
+        let missing = Tags::default();
+        let error =
+            call::<_, Tags>(&channel, "/arklib.v1.Arklib/SetTags", missing)
+                .await
+                .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    }
+}
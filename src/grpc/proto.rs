@@ -0,0 +1,358 @@
+//! Messages of `proto/arklib.proto` and their conversions
+//!
+//! The messages are written out the way prost generates them, so that
+//! building doesn't require `protoc`. Tests check that names, types and
+//! tags of their fields and the methods of the service match the schema.
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::index::IndexUpdate as Update;
+use crate::resource::ResourceId as Id;
+use crate::util::fs::relative_path;
+use crate::{ArklibError, Result};
+
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct Empty {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, prost::Message)]
+pub struct ResourceId {
+    #[prost(uint64, tag = "1")]
+    pub data_size: u64,
+    #[prost(uint32, tag = "2")]
+    pub hash: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IndexEntry {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(message, optional, tag = "2")]
+    pub id: Option<ResourceId>,
+    #[prost(int64, tag = "3")]
+    pub modified: i64,
+    #[prost(bool, tag = "4")]
+    pub sampled: bool,
+    #[prost(bool, tag = "5")]
+    pub remote: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IndexEntries {
+    #[prost(message, repeated, tag = "1")]
+    pub entries: Vec<IndexEntry>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Added {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(message, optional, tag = "2")]
+    pub id: Option<ResourceId>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Modified {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(message, optional, tag = "2")]
+    pub old_id: Option<ResourceId>,
+    #[prost(message, optional, tag = "3")]
+    pub new_id: Option<ResourceId>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Moved {
+    #[prost(message, optional, tag = "1")]
+    pub id: Option<ResourceId>,
+    #[prost(string, tag = "2")]
+    pub from: String,
+    #[prost(string, tag = "3")]
+    pub to: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IndexUpdate {
+    #[prost(message, repeated, tag = "1")]
+    pub deleted: Vec<ResourceId>,
+    #[prost(message, repeated, tag = "2")]
+    pub added: Vec<Added>,
+    #[prost(message, repeated, tag = "3")]
+    pub modified: Vec<Modified>,
+    #[prost(message, repeated, tag = "4")]
+    pub moved: Vec<Moved>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Tags {
+    #[prost(message, optional, tag = "1")]
+    pub id: Option<ResourceId>,
+    #[prost(string, repeated, tag = "2")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Properties {
+    #[prost(message, optional, tag = "1")]
+    pub id: Option<ResourceId>,
+    #[prost(string, tag = "2")]
+    pub json: String,
+}
+
+impl From<Id> for ResourceId {
+    fn from(id: Id) -> Self {
+        ResourceId {
+            data_size: id.data_size,
+            hash: id.hash,
+        }
+    }
+}
+
+impl From<ResourceId> for Id {
+    fn from(id: ResourceId) -> Self {
+        Id {
+            data_size: id.data_size,
+            hash: id.hash,
+        }
+    }
+}
+
+/// Takes the ID out of a message field, which is optional in proto3
+pub fn required(id: Option<ResourceId>) -> Result<Id> {
+    id.map(Id::from).ok_or(ArklibError::Parse)
+}
+
+impl IndexEntry {
+    pub fn new(
+        root: &Path,
+        path: &Path,
+        entry: &crate::index::IndexEntry,
+    ) -> Result<Self> {
+        let modified = entry
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(IndexEntry {
+            path: relative_path(root, path)?,
+            id: Some(entry.id.into()),
+            modified: modified.as_millis() as i64,
+            sampled: entry.sampled,
+            remote: entry.remote,
+        })
+    }
+
+    pub fn into_entry(self) -> Result<crate::index::IndexEntry> {
+        Ok(crate::index::IndexEntry {
+            modified: UNIX_EPOCH
+                + Duration::from_millis(self.modified.max(0) as u64),
+            id: required(self.id)?,
+            sampled: self.sampled,
+            remote: self.remote,
+        })
+    }
+}
+
+impl IndexUpdate {
+    /// Converts the update, making its paths relative to the root
+    pub fn new(root: &Path, update: &Update) -> Result<Self> {
+        let mut message = IndexUpdate {
+            deleted: update
+                .deleted
+                .iter()
+                .map(|id| (*id).into())
+                .collect(),
+            ..Default::default()
+        };
+        for (path, id) in &update.added {
+            message.added.push(Added {
+                path: relative_path(root, path)?,
+                id: Some((*id).into()),
+            });
+        }
+        for (path, (old_id, new_id)) in &update.modified {
+            message.modified.push(Modified {
+                path: relative_path(root, path)?,
+                old_id: Some((*old_id).into()),
+                new_id: Some((*new_id).into()),
+            });
+        }
+        for (id, (from, to)) in &update.moved {
+            message.moved.push(Moved {
+                id: Some((*id).into()),
+                from: relative_path(root, from)?,
+                to: relative_path(root, to)?,
+            });
+        }
+        Ok(message)
+    }
+
+    /// Converts the update back, resolving its paths against the root
+    pub fn into_update(self, root: &Path) -> Result<Update> {
+        let mut update = Update {
+            deleted: self.deleted.into_iter().map(Id::from).collect(),
+            ..Default::default()
+        };
+        for added in self.added {
+            update
+                .added
+                .insert(root.join(added.path), required(added.id)?);
+        }
+        for modified in self.modified {
+            update.modified.insert(
+                root.join(modified.path),
+                (required(modified.old_id)?, required(modified.new_id)?),
+            );
+        }
+        for moved in self.moved {
+            update.moved.insert(
+                required(moved.id)?,
+                (root.join(moved.from), root.join(moved.to)),
+            );
+        }
+        Ok(update)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    const SCHEMA: &str = include_str!("../../proto/arklib.proto");
+    const SCALARS: [&str; 5] = ["uint64", "uint32", "int64", "bool", "string"];
+
+    /// Name, kind (a scalar type or `message`), type name,
+    /// whether it's repeated and tag of a field
+    type Field = (String, String, String, bool, u32);
+
+    fn schema_messages() -> BTreeMap<String, Vec<Field>> {
+        let mut messages = BTreeMap::new();
+        let mut current: Option<(String, Vec<Field>)> = None;
+        for line in SCHEMA.lines().map(str::trim) {
+            if let Some(rest) = line.strip_prefix("message ") {
+                let name = rest.trim_end_matches(['{', '}', ' ']);
+                match rest.ends_with("{}") {
+                    true => {
+                        messages.insert(name.to_string(), vec![]);
+                    }
+                    false => current = Some((name.to_string(), vec![])),
+                }
+            } else if line == "}" {
+                if let Some((name, fields)) = current.take() {
+                    messages.insert(name, fields);
+                }
+            } else if let Some((_, fields)) = current.as_mut() {
+                let Some(line) = line.strip_suffix(';') else {
+                    continue;
+                };
+                let (declaration, tag) = line.split_once(" = ").unwrap();
+                let mut words: Vec<&str> =
+                    declaration.split_whitespace().collect();
+                let repeated = words[0] == "repeated";
+                if repeated {
+                    words.remove(0);
+                }
+                let (type_name, name) = (words[0], words[1]);
+                let kind = match SCALARS.contains(&type_name) {
+                    true => type_name,
+                    false => "message",
+                };
+                fields.push((
+                    name.to_string(),
+                    kind.to_string(),
+                    type_name.to_string(),
+                    repeated,
+                    tag.parse().unwrap(),
+                ));
+            }
+        }
+        messages
+    }
+
+    fn source_messages() -> BTreeMap<String, Vec<Field>> {
+        let source = include_str!("proto.rs");
+        let source = &source[..source.find("#[cfg(test)]").unwrap()];
+        let mut messages = BTreeMap::new();
+        let mut current: Option<(String, Vec<Field>)> = None;
+        let mut attribute: Option<(String, bool, u32)> = None;
+        for line in source.lines().map(str::trim) {
+            if let Some(rest) = line.strip_prefix("pub struct ") {
+                let name = rest.trim_end_matches(['{', '}', ' ']);
+                match rest.ends_with("{}") {
+                    true => {
+                        messages.insert(name.to_string(), vec![]);
+                    }
+                    false => current = Some((name.to_string(), vec![])),
+                }
+            } else if line == "}" {
+                if let Some((name, fields)) = current.take() {
+                    messages.insert(name, fields);
+                }
+            } else if let Some(rest) = line.strip_prefix("#[prost(") {
+                let parts: Vec<&str> =
+                    rest.trim_end_matches(")]").split(", ").collect();
+                let tag = parts
+                    .iter()
+                    .find_map(|part| part.strip_prefix("tag = "))
+                    .unwrap()
+                    .trim_matches('"');
+                attribute = Some((
+                    parts[0].to_string(),
+                    parts.contains(&"repeated"),
+                    tag.parse().unwrap(),
+                ));
+            } else if let Some(rest) = line.strip_prefix("pub ") {
+                let (Some((kind, repeated, tag)), Some((_, fields))) =
+                    (attribute.take(), current.as_mut())
+                else {
+                    continue;
+                };
+                let (name, rust_type) = rest.split_once(": ").unwrap();
+                let type_name = rust_type
+                    .trim_end_matches(',')
+                    .trim_start_matches("Option<")
+                    .trim_start_matches("Vec<")
+                    .trim_end_matches('>');
+                let type_name = match kind == "message" {
+                    true => type_name.to_string(),
+                    false => kind.clone(),
+                };
+                fields.push((name.to_string(), kind, type_name, repeated, tag));
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn messages_and_methods_match_the_schema() {
+        assert_eq!(source_messages(), schema_messages());
+
+        let package = SCHEMA
+            .lines()
+            .find_map(|line| line.strip_prefix("package "))
+            .unwrap()
+            .trim_end_matches(';');
+        let service = SCHEMA
+            .lines()
+            .find_map(|line| line.strip_prefix("service "))
+            .unwrap()
+            .trim_end_matches(" {");
+        assert_eq!(
+            super::super::SERVICE_NAME,
+            format!("{}.{}", package, service)
+        );
+
+        let server = include_str!("mod.rs");
+        let methods: Vec<&str> = SCHEMA
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("rpc "))
+            .map(|rpc| rpc.split('(').next().unwrap())
+            .collect();
+        assert_eq!(methods.len(), 6);
+        for method in methods {
+            assert!(
+                server.contains(&format!("\"{}\" =>", method)),
+                "{} is not served",
+                method
+            );
+        }
+    }
+}
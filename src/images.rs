@@ -0,0 +1,404 @@
+//! Previews and thumbnails of images
+//!
+//! Images are decoded once, turned upright according to their EXIF
+//! orientation and downscaled to both sizes, so that all apps show the
+//! same cached previews instead of decoding full-size photos themselves.
+//! HEIC/HEIF images are decoded with libheif if the `heic` feature is
+//! enabled.
+//!
+//! Apps loading many previews at once can ask for a format and a limit
+//! of bytes with [`PreviewOptions`]. The format of stored previews is
+//! recorded in the metadata of the resource.
+use std::io::{BufRead, Cursor, Read, Seek};
+use std::path::Path;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+
+use crate::resource::ResourceId;
+use crate::storage::metadata;
+use crate::storage::preview::{store_preview, store_thumbnail};
+use crate::{ArklibError, Result};
+
+// cached previews of all kinds of resources are read the same way
+pub use crate::storage::preview::{load_preview, load_thumbnail};
+
+/// Longest side of previews, in pixels
+pub const PREVIEW_SIZE: u32 = 1024;
+/// Longest side of thumbnails, in pixels
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+/// Metadata field with the format of the stored previews
+pub const PREVIEW_FORMAT_METADATA_FIELD: &str = "preview_format";
+
+const JPEG_QUALITY: u8 = 85;
+/// Lowest quality tried to fit JPEG previews into the limit of bytes
+/// before downscaling them
+const MIN_JPEG_QUALITY: u8 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    Jpeg,
+    Png,
+    /// Lossless, so mostly useful for images with transparency
+    WebP,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreviewOptions {
+    /// JPEG, or PNG for images with transparency, if not set
+    pub format: Option<PreviewFormat>,
+    /// Limit of bytes of every encoded preview and thumbnail, met by
+    /// lowering the quality of JPEG and then by downscaling
+    pub max_bytes: Option<usize>,
+}
+
+/// Whether previews of files with the extension can be generated
+pub fn is_supported(extension: &str) -> bool {
+    let extension = extension.to_lowercase();
+    matches!(
+        extension.as_str(),
+        "jpg" | "jpeg" | "png" | "webp" | "gif" | "bmp" | "tif" | "tiff"
+    ) || (cfg!(feature = "heic")
+        && matches!(extension.as_str(), "heic" | "heif"))
+}
+
+/// Decodes the image, rotating and flipping it according to its
+/// EXIF orientation
+pub fn decode<R: BufRead + Seek>(mut data: R) -> Result<DynamicImage> {
+    if is_heif(&mut data)? {
+        return decode_heif(data);
+    }
+    let mut decoder = ImageReader::new(data)
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Scales the image down to fit into a square with the side of `size`,
+/// keeping the aspect ratio. Smaller images are left as is.
+pub fn downscale(image: &DynamicImage, size: u32) -> DynamicImage {
+    if image.width() <= size && image.height() <= size {
+        return image.clone();
+    }
+    // Lanczos is slow, but keeps fine details of photos
+    image.resize(size, size, FilterType::Lanczos3)
+}
+
+/// Encodes the image as JPEG, or as PNG if it has transparency
+pub fn encode(image: &DynamicImage) -> Result<Vec<u8>> {
+    encode_as(image, default_format(image), JPEG_QUALITY)
+}
+
+/// Encodes the image in the format, lowering the quality and then
+/// the size of the image until it takes at most `max_bytes`
+pub fn encode_within(
+    image: &DynamicImage,
+    format: PreviewFormat,
+    max_bytes: Option<usize>,
+) -> Result<Vec<u8>> {
+    let Some(max_bytes) = max_bytes else {
+        return encode_as(image, format, JPEG_QUALITY);
+    };
+    let mut image = image.clone();
+    loop {
+        let mut quality = JPEG_QUALITY;
+        loop {
+            let bytes = encode_as(&image, format, quality)?;
+            if bytes.len() <= max_bytes {
+                return Ok(bytes);
+            }
+            if format != PreviewFormat::Jpeg || quality <= MIN_JPEG_QUALITY {
+                break;
+            }
+            quality = quality.saturating_sub(15).max(MIN_JPEG_QUALITY);
+        }
+        let size = image.width().max(image.height());
+        if size <= 1 {
+            return Err(ArklibError::Other(anyhow::anyhow!(
+                "Preview doesn't fit into {} bytes",
+                max_bytes
+            )));
+        }
+        image = downscale(&image, size * 3 / 4);
+    }
+}
+
+fn default_format(image: &DynamicImage) -> PreviewFormat {
+    match image.color().has_alpha() {
+        true => PreviewFormat::Png,
+        false => PreviewFormat::Jpeg,
+    }
+}
+
+fn encode_as(
+    image: &DynamicImage,
+    format: PreviewFormat,
+    quality: u8,
+) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    match format {
+        PreviewFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut bytes, quality);
+            image.to_rgb8().write_with_encoder(encoder)?;
+        }
+        PreviewFormat::Png => {
+            image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?
+        }
+        // the encoder supports 8-bit images only
+        PreviewFormat::WebP => image
+            .to_rgba8()
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::WebP)?,
+    }
+    Ok(bytes)
+}
+
+/// Generates the preview and the thumbnail of the image
+/// and stores them into the caches
+pub fn generate<R: BufRead + Seek, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    data: R,
+) -> Result<()> {
+    store_previews(root, id, &decode(data)?)
+}
+
+/// Generates the preview and the thumbnail of the image like [`generate`]
+/// in the requested format and size
+pub fn generate_with<R: BufRead + Seek, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    data: R,
+    options: PreviewOptions,
+) -> Result<PreviewFormat> {
+    store_previews_with(root, id, &decode(data)?, options)
+}
+
+/// Downscales the image rendered from a resource of any kind into the
+/// preview and the thumbnail of the resource and stores them
+pub fn store_previews<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    image: &DynamicImage,
+) -> Result<()> {
+    store_previews_with(root, id, image, PreviewOptions::default()).map(|_| ())
+}
+
+/// Stores the preview and the thumbnail like [`store_previews`]
+/// in the requested format and size, returning the chosen format
+pub fn store_previews_with<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    image: &DynamicImage,
+    options: PreviewOptions,
+) -> Result<PreviewFormat> {
+    let format = options
+        .format
+        .unwrap_or_else(|| default_format(image));
+    let preview = downscale(image, PREVIEW_SIZE);
+    store_preview(
+        &root,
+        id,
+        &encode_within(&preview, format, options.max_bytes)?,
+    )?;
+    let thumbnail = downscale(&preview, THUMBNAIL_SIZE);
+    store_thumbnail(
+        &root,
+        id,
+        &encode_within(&thumbnail, format, options.max_bytes)?,
+    )?;
+    metadata::store_field(root, id, PREVIEW_FORMAT_METADATA_FIELD, &format)?;
+    Ok(format)
+}
+
+/// Loads the format of the stored previews of the resource, `None` if
+/// they were stored before formats were recorded
+pub fn load_preview_format<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<PreviewFormat>> {
+    let metadata: Option<serde_json::Value> = metadata::load(root, id)?;
+    match metadata
+        .as_ref()
+        .and_then(|metadata| metadata.get(PREVIEW_FORMAT_METADATA_FIELD))
+    {
+        Some(format) => Ok(Some(serde_json::from_value(format.clone())?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads EXIF attributes of the image, if it has any
+pub(crate) fn read_exif<R: BufRead + Seek>(
+    mut data: R,
+) -> Result<Option<exif::Exif>> {
+    match exif::Reader::new().read_from_container(&mut data) {
+        Ok(exif) => Ok(Some(exif)),
+        Err(exif::Error::NotFound(_)) => Ok(None),
+        Err(exif::Error::Io(e)) => Err(e.into()),
+        Err(_) => Err(crate::ArklibError::Parse),
+    }
+}
+
+/// Recognizes HEIF containers by the brand of their `ftyp` box
+fn is_heif<R: Read + Seek>(data: &mut R) -> Result<bool> {
+    let mut header = [0; 12];
+    let start = data.stream_position()?;
+    let read = data.read(&mut header)?;
+    data.seek(std::io::SeekFrom::Start(start))?;
+    Ok(read == header.len()
+        && &header[4..8] == b"ftyp"
+        && matches!(
+            &header[8..12],
+            b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"mif1" | b"msf1"
+        ))
+}
+
+/// Decodes the primary image of the HEIF container, libheif applies
+/// the rotation and mirroring stored in the container itself
+#[cfg(feature = "heic")]
+fn decode_heif<R: Read>(mut data: R) -> Result<DynamicImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let mut bytes = vec![];
+    data.read_to_end(&mut bytes)?;
+    let heif_error = |e: libheif_rs::HeifError| {
+        crate::ArklibError::Other(anyhow::anyhow!("HEIF error: {}", e))
+    };
+    let context = HeifContext::read_from_bytes(&bytes).map_err(heif_error)?;
+    let handle = context
+        .primary_image_handle()
+        .map_err(heif_error)?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(heif_error)?;
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or(crate::ArklibError::Parse)?;
+
+    // rows of the plane may be padded
+    let row = plane.width as usize * 4;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|line| &line[..row])
+        .copied()
+        .collect();
+    let buffer = image::RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .ok_or(crate::ArklibError::Parse)?;
+    Ok(DynamicImage::ImageRgba8(buffer))
+}
+
+#[cfg(not(feature = "heic"))]
+fn decode_heif<R: Read>(_data: R) -> Result<DynamicImage> {
+    Err(crate::ArklibError::Other(anyhow::anyhow!(
+        "HEIC images are supported with the `heic` feature only"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use image::RgbImage;
+    use tempdir::TempDir;
+
+    /// JPEG with an APP1 segment holding EXIF orientation 6,
+    /// i.e. rotated by 90 degrees clockwise
+    fn rotated_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(width, height));
+        let jpeg = encode(&image).unwrap();
+        let exif: &[u8] = &[
+            b'E', b'x', b'i', b'f', 0, 0, // EXIF header
+            b'M', b'M', 0, 42, 0, 0, 0, 8, // big-endian TIFF header
+            0, 1, // one entry
+            0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, // orientation
+            0, 0, 0, 0, // no more entries
+        ];
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend([0xFF, 0xE1]);
+        bytes.extend(((exif.len() + 2) as u16).to_be_bytes());
+        bytes.extend(exif);
+        bytes.extend(&jpeg[2..]);
+        bytes
+    }
+
+    #[test]
+    fn exif_orientation_is_applied() {
+        let image = decode(Cursor::new(rotated_jpeg(40, 20))).unwrap();
+        assert_eq!((image.width(), image.height()), (20, 40));
+    }
+
+    #[test]
+    fn images_are_downscaled() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(2048, 1024));
+        let preview = downscale(&image, PREVIEW_SIZE);
+        assert_eq!((preview.width(), preview.height()), (1024, 512));
+        let small = DynamicImage::ImageRgb8(RgbImage::new(100, 50));
+        assert_eq!(downscale(&small, THUMBNAIL_SIZE).width(), 100);
+    }
+
+    #[test]
+    fn previews_are_cached() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let data = rotated_jpeg(1200, 600);
+        let id = ResourceId::compute_bytes(&data).unwrap();
+        assert_eq!(load_preview(dir.path(), id).unwrap(), None);
+
+        generate(dir.path(), id, Cursor::new(data)).unwrap();
+        let preview = load_preview(dir.path(), id).unwrap().unwrap();
+        let preview = decode(Cursor::new(preview)).unwrap();
+        assert_eq!((preview.width(), preview.height()), (512, 1024));
+        let thumbnail = load_thumbnail(dir.path(), id).unwrap().unwrap();
+        let thumbnail = decode(Cursor::new(thumbnail)).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (128, 256));
+
+        assert!(generate(dir.path(), id, Cursor::new(b"not an image")).is_err());
+        assert!(!is_heif(&mut Cursor::new(b"short")).unwrap());
+    }
+
+    #[test]
+    fn previews_are_transcoded_within_budget() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let noise = RgbImage::from_fn(800, 800, |x, y| {
+            let value = (x * 7919 + y * 104729) % 251;
+            image::Rgb([value as u8, (value * 3) as u8, (value * 5) as u8])
+        });
+        let image = DynamicImage::ImageRgb8(noise);
+        let id = ResourceId::compute_bytes(b"noise").unwrap();
+        store_previews(dir.path(), id, &image).unwrap();
+        assert_eq!(
+            load_preview_format(dir.path(), id).unwrap(),
+            Some(PreviewFormat::Jpeg)
+        );
+
+        let options = PreviewOptions {
+            format: Some(PreviewFormat::WebP),
+            max_bytes: Some(20_000),
+        };
+        let format = store_previews_with(dir.path(), id, &image, options);
+        assert_eq!(format.unwrap(), PreviewFormat::WebP);
+        let preview = load_preview(dir.path(), id).unwrap().unwrap();
+        assert!(preview.len() <= 20_000);
+        assert_eq!(
+            ImageReader::new(Cursor::new(preview))
+                .with_guessed_format()
+                .unwrap()
+                .format(),
+            Some(ImageFormat::WebP)
+        );
+        assert_eq!(
+            load_preview_format(dir.path(), id).unwrap(),
+            Some(PreviewFormat::WebP)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Rules organizing files which appear in the root, like downloads
+//!
+//! Rules are stored in the `import` section of `.ark/config` and are
+//! applied to files added by an update of the index, whether it comes
+//! from a watcher or from [`ResourceIndex::update_all`]. The first rule
+//! matching a file moves it into the target folder and tags it.
+use std::path::Path;
+
+use globset::{Glob, GlobMatcher};
+use serde::{Deserialize, Serialize};
+
+use crate::index::{IndexUpdate, RelativePath, ResourceIndex};
+use crate::query::Kind;
+use crate::storage::config::{load_section, store_section};
+use crate::tags::{add_tags, Tag};
+use crate::util::fs::unique_path;
+use crate::Result;
+
+const CONFIG_SECTION: &str = "import";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportRule {
+    /// Glob matched against paths relative to the root
+    pub glob: String,
+    /// Files are matched only if they are of one of the kinds,
+    /// files of all kinds are matched if empty
+    pub kinds: Vec<Kind>,
+    /// Folder receiving matched files, files stay in place if not set
+    pub target: Option<RelativePath>,
+    pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportRules {
+    /// Rules in the order of precedence
+    pub rules: Vec<ImportRule>,
+}
+
+impl ImportRules {
+    /// Loads rules of the root, which are empty if never stored
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Self> {
+        Ok(load_section(root, CONFIG_SECTION)?.unwrap_or_default())
+    }
+
+    pub fn store<P: AsRef<Path>>(&self, root: P) -> Result<()> {
+        store_section(root, CONFIG_SECTION, self)
+    }
+
+    /// Compiles the globs, failing if any of them is malformed
+    pub fn matcher(&self) -> Result<ImportMatcher> {
+        let rules = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let glob = Glob::new(&rule.glob)?.compile_matcher();
+                Ok((glob, rule.clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ImportMatcher { rules })
+    }
+}
+
+/// Compiled [`ImportRules`]
+pub struct ImportMatcher {
+    rules: Vec<(GlobMatcher, ImportRule)>,
+}
+
+impl ImportMatcher {
+    /// Finds the first rule matching the file
+    pub fn find(&self, path: &RelativePath) -> Option<&ImportRule> {
+        let kind = Kind::of(path.as_str());
+        self.rules
+            .iter()
+            .find(|(glob, rule)| {
+                glob.is_match(path.as_str())
+                    && (rule.kinds.is_empty() || rule.kinds.contains(&kind))
+            })
+            .map(|(_, rule)| rule)
+    }
+}
+
+/// Applies import rules of the root to files added by the update
+///
+/// Files already inside of the target folder of their rule are only
+/// tagged. Returns the update of the index with the moved files, files
+/// which couldn't be moved are logged and left in place.
+pub fn apply_import_rules(
+    index: &mut ResourceIndex,
+    update: &IndexUpdate,
+) -> Result<IndexUpdate> {
+    let rules = ImportRules::load(index.root())?;
+    let mut result = IndexUpdate::default();
+    if rules.rules.is_empty() {
+        return Ok(result);
+    }
+    let matcher = rules.matcher()?;
+
+    let mut added: Vec<_> = update.added.iter().collect();
+    added.sort();
+    for (path, id) in added {
+        let Ok(relative) = index.relative_path(path) else {
+            continue;
+        };
+        let Some(rule) = matcher.find(&relative) else {
+            continue;
+        };
+        if !rule.tags.is_empty() {
+            add_tags(index.root(), *id, &rule.tags)?;
+        }
+        let Some(target) = &rule.target else {
+            continue;
+        };
+        if relative.parent().as_ref() == Some(target) {
+            continue;
+        }
+        let name = relative.file_name().unwrap_or_default();
+        let destination = unique_path(&index.absolute_path(target), name);
+        match index.move_file(path, &destination) {
+            Ok(moved) => result.moved.extend(moved.moved),
+            Err(e) => log::warn!(
+                "Couldn't move {} into {}: {}",
+                path.display(),
+                target,
+                e
+            ),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::tags_of;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn new_files_are_organized() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let rules = ImportRules {
+            rules: vec![
+                ImportRule {
+                    glob: "Download/**".into(),
+                    kinds: vec![Kind::Image],
+                    target: Some(RelativePath::parse("Photos").unwrap()),
+                    tags: vec!["downloaded".into()],
+                },
+                ImportRule {
+                    glob: "**/*.pdf".into(),
+                    target: Some(RelativePath::parse("Documents").unwrap()),
+                    ..ImportRule::default()
+                },
+            ],
+        };
+        rules.store(&root).unwrap();
+        assert_eq!(ImportRules::load(&root).unwrap(), rules);
+
+        let mut index = ResourceIndex::build(&root);
+        fs::create_dir_all(root.join("Download")).unwrap();
+        fs::create_dir_all(root.join("Photos")).unwrap();
+        fs::write(root.join("Download/cat.jpg"), "cat").unwrap();
+        fs::write(root.join("Photos/cat.jpg"), "older cat").unwrap();
+        fs::write(root.join("Download/song.mp3"), "song").unwrap();
+        fs::write(root.join("Download/paper.pdf"), "paper").unwrap();
+        let update = index.update_all().unwrap();
+
+        let moved = apply_import_rules(&mut index, &update).unwrap();
+        assert_eq!(moved.moved.len(), 2);
+        let cat = index
+            .get_entry(root.join("Photos/cat (2).jpg"))
+            .unwrap()
+            .id;
+        assert_eq!(tags_of(&root, cat).unwrap(), ["downloaded".into()].into());
+        assert!(index
+            .get_entry(root.join("Documents/paper.pdf"))
+            .is_some());
+        assert!(root.join("Download/song.mp3").exists());
+        assert!(!root.join("Download/cat.jpg").exists());
+        // the index knows the new paths without rescanning
+        assert!(index.update_all().unwrap().added.is_empty());
+    }
+}
@@ -7,20 +7,93 @@ use std::fs::{self, File, Metadata};
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
-use std::ops::Add;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::UNIX_EPOCH;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::field::Empty;
 use walkdir::{DirEntry, WalkDir};
 
 use crate::{
     resource::ResourceId, ArklibError, Result, ARK_FOLDER, INDEX_PATH,
 };
 
+mod case;
+mod clock;
+mod compact;
+mod events;
+mod folders;
+mod id_cache;
+mod id_xattr;
+pub(crate) mod json;
+mod lazy;
+mod links;
+mod lock;
+mod manifest;
+#[cfg(feature = "mmap-index")]
+mod mapped;
+mod metrics;
+mod nesting;
+mod placeholder;
+mod plan;
+mod profile;
+mod recovery;
+mod relative;
+mod scheduler;
+mod sorted;
+#[cfg(feature = "sqlite-index")]
+mod sqlite;
+mod stream;
+pub use case::{is_case_insensitive, CaseSensitivity};
+pub use clock::{Clock, ManualClock, SharedClock, SystemClock};
+pub use compact::{CompactIndex, EntryHandle};
+pub use events::FsEvent;
+pub use folders::FolderSummary;
+pub use id_xattr::ID_ATTRIBUTE;
+pub use json::JSON_SCHEMA_VERSION;
+pub use lazy::LazyIndex;
+pub use links::OutsideLinks;
+pub use manifest::{ManifestFormat, ManifestReport, ARK_MANIFEST_HEADER};
+#[cfg(feature = "mmap-index")]
+pub use mapped::MappedIndex;
+pub use metrics::{Durations, IndexMetrics};
+pub use nesting::{find_outer_root, is_nesting_allowed, set_nesting_allowed};
+pub use placeholder::is_placeholder;
+pub use plan::{plan_update, UpdatePlan};
+pub use profile::{load_profile, set_profile, IndexingProfile};
+pub use recovery::{LoadReport, SkippedLine};
+pub use scheduler::{SchedulerOptions, UpdateScheduler};
+#[cfg(feature = "sqlite-index")]
+pub use sqlite::SqliteIndex;
+
+pub(crate) use lock::IndexLock;
+pub use relative::RelativePath;
+pub use sorted::{SortKey, SortedEntries};
+pub use stream::{open_resource, open_resource_range, ResourceReader};
+
 pub const RESOURCE_UPDATED_THRESHOLD: Duration = Duration::from_millis(1);
+/// Default time to wait for other processes to release the index file
+pub const INDEX_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Files of at least this size are hashed using multiple threads by default
+pub const PARALLEL_HASHING_THRESHOLD: u64 = 16 * 1024 * 1024;
+/// Default of [`IndexOptions::hashing_buffer_size`]
+pub const HASHING_BUFFER_SIZE: usize = 512 * 1024;
+/// First line of the index file, followed by the format version
+///
+/// Paths in the index file are relative to the root and use `/`
+/// as separator on all platforms. Since version 3, backslashes and
+/// line breaks in paths are escaped.
+pub(crate) const INDEX_HEADER: &str = "# ark-index";
+pub(crate) const INDEX_FORMAT_VERSION: u32 = 3;
+/// Marks sampled IDs in the index file
+const SAMPLED_ID_PREFIX: &str = "~";
+/// Marks IDs of placeholders in the index file
+const REMOTE_ID_PREFIX: &str = "?";
 pub type Paths = HashSet<PathBuf>;
-use crate::resource::ResourceIdTrait;
+use crate::resource::{Namespace, ResourceIdTrait};
+use crate::util::fs::{locate_relative, write_file};
+use id_cache::IdCache;
+use metrics::Operation;
 
 /// IndexEntry represents a [`ResourceId`] and the time it was last modified
 #[derive(
@@ -31,6 +104,119 @@ pub struct IndexEntry {
     pub modified: SystemTime,
     /// The resource's ID
     pub id: ResourceId,
+    /// Whether the ID was computed from samples of the content only,
+    /// see [`ResourceIdTrait::compute_sampled`]
+    #[serde(default)]
+    pub sampled: bool,
+    /// Whether the file is a placeholder of a file kept in the cloud,
+    /// which ID is derived from its path instead of the content
+    #[serde(default)]
+    pub remote: bool,
+}
+
+/// Options affecting how the index computes IDs of resources
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct IndexOptions {
+    /// Files of at least this size are hashed using multiple threads,
+    /// see [`ResourceIdTrait::compute_parallel`]. `None` disables
+    /// parallel hashing completely.
+    pub parallel_hashing_threshold: Option<u64>,
+    /// Files of at least this size get sampled IDs, which can be upgraded
+    /// to full ones later using [`ResourceIndex::upgrade_id`]. `None`,
+    /// the default, hashes all files completely.
+    pub sampled_hashing_threshold: Option<u64>,
+    /// Whether IDs of unchanged files are taken from `.ark/cache/ids`
+    /// instead of hashing the files again. The cache is written together
+    /// with the index.
+    pub use_id_cache: bool,
+    /// Whether computed IDs are mirrored into the `user.ark.id` extended
+    /// attribute of files and taken from it when the file is unchanged
+    pub mirror_ids_to_xattr: bool,
+    /// How long to wait for other processes to release the index file
+    /// before failing with [`ArklibError::Busy`]
+    pub lock_timeout: Duration,
+    /// Whether paths differing only in case denote the same file,
+    /// detected per root by default
+    pub case_sensitivity: CaseSensitivity,
+    /// Number of files hashed at once while building and updating
+    pub scan_threads: usize,
+    /// Size of chunks files are read in while hashing them completely
+    pub hashing_buffer_size: usize,
+    /// Namespace mixed into IDs of the root, see [`set_namespaced`]
+    ///
+    /// [`set_namespaced`]: crate::resource::set_namespaced
+    pub namespace: Option<Namespace>,
+    /// Whether the root may be indexed inside of another root, see
+    /// [`set_nesting_allowed`]
+    pub allow_nested: bool,
+    /// Whether links to folders are followed while discovering files,
+    /// which matters only for folders outside of the root
+    pub follow_symlinks: bool,
+    /// Whether links leading outside of the root are indexed
+    pub outside_links: OutsideLinks,
+    /// Whether placeholders of files kept in the cloud are hashed, which
+    /// downloads them, see [`is_placeholder`]
+    pub hash_placeholders: bool,
+    /// Source of current and modification times, see [`Clock`]
+    pub clock: SharedClock,
+}
+
+impl IndexOptions {
+    /// Mixes the namespace, if any, into the ID computed from the content
+    pub fn namespaced(&self, id: ResourceId) -> ResourceId {
+        match &self.namespace {
+            Some(namespace) => namespace.apply(id),
+            None => id,
+        }
+    }
+
+    /// Whether files of this size get sampled IDs
+    pub fn samples(&self, size: u64) -> bool {
+        matches!(
+            self.sampled_hashing_threshold,
+            Some(threshold) if size >= threshold
+        )
+    }
+
+    /// Computes the ID of the file from its content the way the index
+    /// does, sampled or completely, never taking it from the `user.ark.id`
+    /// attribute
+    ///
+    /// Meant for verifying content received from elsewhere against IDs
+    /// of the same kind.
+    pub fn content_id(&self, path: &Path, sampled: bool) -> Result<ResourceId> {
+        let size = fs::metadata(path)?.len();
+        Ok(self.namespaced(hash_content(path, size, sampled, self)?))
+    }
+
+    fn id_cache(&self, root: &Path) -> IdCache {
+        if self.use_id_cache {
+            IdCache::load(root)
+        } else {
+            IdCache::default()
+        }
+    }
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            parallel_hashing_threshold: Some(PARALLEL_HASHING_THRESHOLD),
+            sampled_hashing_threshold: None,
+            use_id_cache: true,
+            mirror_ids_to_xattr: false,
+            lock_timeout: INDEX_LOCK_TIMEOUT,
+            case_sensitivity: CaseSensitivity::Detect,
+            scan_threads: 1,
+            hashing_buffer_size: HASHING_BUFFER_SIZE,
+            namespace: None,
+            allow_nested: false,
+            follow_symlinks: false,
+            outside_links: OutsideLinks::Skip,
+            hash_placeholders: false,
+            clock: SharedClock::default(),
+        }
+    }
 }
 
 /// Represents an index of resources stored as files
@@ -55,22 +241,50 @@ pub struct ResourceIndex {
     pub collisions: HashMap<ResourceId, usize>,
     /// The root path of the index
     root: PathBuf,
+    /// Options used for indexing new and modified files
+    #[serde(skip)]
+    options: IndexOptions,
+    /// Whether the root is on a case-insensitive filesystem,
+    /// resolved from [`IndexOptions::case_sensitivity`]
+    #[serde(skip)]
+    case_insensitive: bool,
+    /// Lines of the index file skipped by the last load
+    #[serde(skip)]
+    load_report: LoadReport,
+    /// Files named by skipped lines, which are hashed by the next update
+    /// whatever its scope
+    #[serde(skip)]
+    damaged: Paths,
 }
 
 /// Represents an external modification detected in the filesystem.
 ///
-/// This struct holds information about resources that have been deleted
-/// or added during an update operation on the resource index. Modification
-/// of a resource is always represented as a deletion followed by an addition.
-/// Renaming of a file doesn't really introduces any new resources, but
-/// for consistency is represented same as modification
-/// of the underlying file.
-#[derive(PartialEq, Debug)]
+/// This struct holds information about resources that have been deleted,
+/// added, modified or moved during an update operation on the resource
+/// index. Replacing a file with the same content is not reported.
+/// Renaming of a file doesn't introduce any new resources, so it is
+/// represented as a move if detected and as deletion followed by addition
+/// otherwise.
+///
+/// Paths sharing an ID are aliases of one resource, see
+/// [`ResourceIndex::aliases`]. A resource is deleted only together with
+/// its last alias, and an alias modified in place becomes a new resource
+/// added by its path, since the previous resource is still indexed.
+#[derive(PartialEq, Debug, Default)]
 pub struct IndexUpdate {
     /// Set of resource IDs that have been deleted
     pub deleted: HashSet<ResourceId>,
-    /// Map of file paths to resource IDs that have been added
+    /// Map of file paths to resource IDs that have been added, including
+    /// new aliases of resources indexed before
     pub added: HashMap<PathBuf, ResourceId>,
+    /// Map of file paths to previous and current resource IDs, for files
+    /// which content has been modified in place
+    pub modified: HashMap<PathBuf, (ResourceId, ResourceId)>,
+    /// Map of resource IDs to their previous and current paths, for files
+    /// which have been moved without changing their content. If several
+    /// aliases of a resource are moved at once, only one of the moves
+    /// is reported.
+    pub moved: HashMap<ResourceId, (PathBuf, PathBuf)>,
 }
 
 impl ResourceIndex {
@@ -86,33 +300,191 @@ impl ResourceIndex {
         self.id2path.len()
     }
 
+    /// Returns the root path of the index
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns an iterator over IDs of all indexed resources
+    pub fn ids(&self) -> impl Iterator<Item = &ResourceId> {
+        self.id2path.keys()
+    }
+
+    /// Returns the path of the resource, if it is indexed
+    ///
+    /// In presence of collisions, only the smallest of the paths is
+    /// returned, [`ResourceIndex::aliases`] returns all of them
+    pub fn get_path(&self, id: &ResourceId) -> Option<&Path> {
+        self.id2path.get(id).map(|path| path.as_path())
+    }
+
+    /// Returns all paths of the resource, sorted
+    ///
+    /// Files with the same content share one ID, such paths are aliases
+    /// of the same resource. User data and generated data are kept per
+    /// ID, so they are shared by all aliases. The resource is deleted
+    /// only when its last alias is gone.
+    pub fn aliases(&self, id: &ResourceId) -> Vec<PathBuf> {
+        if !self.collisions.contains_key(id) {
+            return self
+                .id2path
+                .get(id)
+                .cloned()
+                .into_iter()
+                .collect();
+        }
+        let mut paths: Vec<PathBuf> = self
+            .path2id
+            .iter()
+            .filter(|(_, entry)| entry.id == *id)
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Checks whether the resource is indexed
+    pub fn contains(&self, id: &ResourceId) -> bool {
+        self.id2path.contains_key(id)
+    }
+
+    /// Returns the entry of the resource, if it is indexed
+    ///
+    /// In presence of collisions, only one of the entries is returned
+    pub fn get(&self, id: &ResourceId) -> Option<&IndexEntry> {
+        self.id2path
+            .get(id)
+            .and_then(|path| self.path2id.get(path))
+    }
+
+    /// Finds the only resource which ID starts with the prefix, like
+    /// abbreviated commit hashes of git
+    ///
+    /// Fails if no resource or more than one resource matches.
+    pub fn resolve_prefix(&self, prefix: &str) -> Result<ResourceId> {
+        if prefix.is_empty() {
+            return Err(ArklibError::Path("Empty ID prefix".into()));
+        }
+        let mut matching = self
+            .id2path
+            .keys()
+            .filter(|id| id.to_string().starts_with(prefix));
+        match (matching.next(), matching.next()) {
+            (Some(id), None) => Ok(*id),
+            (None, _) => Err(ArklibError::Path(format!(
+                "No resource with ID prefix {}",
+                prefix
+            ))),
+            (Some(_), Some(_)) => Err(ArklibError::Collision(format!(
+                "ID prefix {} is ambiguous, {} resources match",
+                prefix,
+                2 + matching.count()
+            ))),
+        }
+    }
+
+    /// Returns the entry of the resource by the path, if it is indexed
+    pub fn get_entry<P: AsRef<Path>>(&self, path: P) -> Option<&IndexEntry> {
+        self.path2id.get(path.as_ref())
+    }
+
+    /// Returns an iterator over all entries together with their paths,
+    /// including entries of colliding resources
+    pub fn entries(&self) -> impl Iterator<Item = (&Path, &IndexEntry)> {
+        self.path2id
+            .iter()
+            .map(|(path, entry)| (path.as_path(), entry))
+    }
+
+    /// Returns an iterator over all indexed paths together with IDs,
+    /// including paths of colliding resources
+    pub fn paths(&self) -> impl Iterator<Item = (&Path, &ResourceId)> {
+        self.path2id
+            .iter()
+            .map(|(path, entry)| (path.as_path(), &entry.id))
+    }
+
+    /// Returns the options used for indexing new and modified files
+    pub fn options(&self) -> &IndexOptions {
+        &self.options
+    }
+
+    /// Replaces the options used for indexing new and modified files,
+    /// already indexed files are not affected
+    pub fn set_options(&mut self, options: IndexOptions) {
+        self.case_insensitive = options
+            .case_sensitivity
+            .is_insensitive(&self.root);
+        self.options = options;
+    }
+
     /// Builds a new resource index from scratch using the root path
     ///
     /// This function recursively scans the directory structure starting from
     /// the root path, constructs index entries for each resource found, and
     /// populates the resource index
     pub fn build<P: AsRef<Path>>(root_path: P) -> Self {
-        let root_path = fs::canonicalize(root_path.as_ref())
-            .expect("Failed to canonicalize root path");
+        Self::build_with_options(root_path, IndexOptions::default())
+    }
 
-        log::info!(
-            "Building the index from scratch for directory: {}",
-            &root_path.display()
+    /// Same as [`ResourceIndex::build`], but with custom options
+    ///
+    /// A root inside of another root is left empty, unless
+    /// [`IndexOptions::allow_nested`] is set.
+    pub fn build_with_options<P: AsRef<Path>>(
+        root_path: P,
+        options: IndexOptions,
+    ) -> Self {
+        // a missing root yields an empty index instead of a panic
+        let root_path =
+            fs::canonicalize(root_path.as_ref()).unwrap_or_else(|e| {
+                tracing::error!(
+                    "Couldn't canonicalize {}: {}",
+                    root_path.as_ref().display(),
+                    e
+                );
+                root_path.as_ref().to_path_buf()
+            });
+        // as well as a nested one
+        let nested = nesting::check_nesting(&root_path, &options);
+
+        let span = tracing::info_span!(
+            "index_build",
+            root = %root_path.display(),
+            files = Empty,
+            elapsed_ms = Empty,
         );
+        let _timing = Timing::start(&span, &root_path, Operation::Build);
+        let _entered = span.enter();
 
-        let entries = discover_files(&root_path);
-        let entries = scan_entries(entries);
+        let entries = match nested {
+            Ok(()) => discover_files(&root_path, &root_path, &options),
+            Err(e) => {
+                tracing::error!("{}", e);
+                HashMap::new()
+            }
+        };
+        let cache = options.id_cache(&root_path);
+        let entries = scan_entries(&root_path, entries, &options, &cache);
         let mut index = ResourceIndex {
             id2path: HashMap::new(),
             path2id: HashMap::new(),
             collisions: HashMap::new(),
+            case_insensitive: options
+                .case_sensitivity
+                .is_insensitive(&root_path),
             root: root_path,
+            options,
+            load_report: LoadReport::default(),
+            damaged: HashSet::new(),
         };
         for (path, entry) in entries {
             index.insert_entry(path, entry);
         }
 
-        log::info!("Index built");
+        index.check_invariants();
+        span.record("files", index.path2id.len());
+        tracing::info!("Index built");
         index
     }
 
@@ -126,64 +498,176 @@ impl ResourceIndex {
     /// be called explicitly by the end-user. For automated updating and
     /// persisting the new index version, use [`ResourceIndex::provide()`] method.
     pub fn load<P: AsRef<Path>>(root_path: P) -> Result<Self> {
-        let root_path: PathBuf = root_path.as_ref().to_owned();
-        let root_path = fs::canonicalize(root_path)?;
+        Self::load_with_options(root_path, IndexOptions::default())
+    }
+
+    /// Loads a previously stored resource index, which will use the options
+    /// for indexing new and modified files
+    ///
+    /// Returns [`ArklibError::Busy`] if another process keeps writing
+    /// the index for longer than [`IndexOptions::lock_timeout`].
+    pub fn load_with_options<P: AsRef<Path>>(
+        root_path: P,
+        options: IndexOptions,
+    ) -> Result<Self> {
+        let root_path = fs::canonicalize(root_path.as_ref())?;
+        let _lock = IndexLock::shared(&root_path, options.lock_timeout)?;
+        Self::read(root_path, options)
+    }
 
+    /// Reads the index file, the caller must hold the lock
+    fn read(root_path: PathBuf, options: IndexOptions) -> Result<Self> {
         let index_path: PathBuf = root_path.join(ARK_FOLDER).join(INDEX_PATH);
-        log::info!("Loading the index from file {}", index_path.display());
+        let span = tracing::info_span!(
+            "index_load",
+            root = %root_path.display(),
+            files = Empty,
+            elapsed_ms = Empty,
+        );
+        let _timing = Timing::start(&span, &root_path, Operation::Load);
+        let _entered = span.enter();
+        tracing::info!("Loading the index from file {}", index_path.display());
+        if index_path.is_dir() {
+            return Err(ArklibError::Path(format!(
+                "{} has the legacy layout, it must be migrated first",
+                root_path.display()
+            )));
+        }
         let file = File::open(&index_path)?;
         let mut index = ResourceIndex {
             id2path: HashMap::new(),
             path2id: HashMap::new(),
             collisions: HashMap::new(),
             root: root_path.clone(),
+            case_insensitive: options
+                .case_sensitivity
+                .is_insensitive(&root_path),
+            options,
+            load_report: LoadReport::default(),
+            damaged: HashSet::new(),
         };
 
         // We should not return early in case of missing files
-        let lines = BufReader::new(file).lines();
+        // or damaged lines
+        let mut lines = BufReader::new(file)
+            .split(b'\n')
+            .enumerate()
+            .map(|(number, line)| {
+                let mut line = line?;
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                Ok::<_, std::io::Error>((number + 1, String::from_utf8(line)))
+            })
+            .peekable();
+        // files written before the header was introduced have no header
+        let mut version = 1;
+        if let Some(Ok((_, Ok(header)))) = lines.peek() {
+            match parse_header(header) {
+                Ok(Some(header)) => {
+                    version = header;
+                    lines.next();
+                }
+                Ok(None) => {}
+                // a damaged header is taken for the current one
+                Err(ArklibError::Parse) => {
+                    version = INDEX_FORMAT_VERSION;
+                    if let Some(Ok((number, Ok(header)))) = lines.next() {
+                        index.load_report.skip(&root_path, number, header);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
         for line in lines {
-            let line = line?;
-
-            let mut parts = line.split(' ');
-
-            let modified = {
-                let str = parts.next().ok_or(ArklibError::Parse)?;
-                UNIX_EPOCH.add(Duration::from_millis(
-                    str.parse().map_err(|_| ArklibError::Parse)?,
-                ))
-            };
-
-            let id = {
-                let str = parts.next().ok_or(ArklibError::Parse)?;
-                ResourceId::from_str(str)?
-            };
-
-            let path: String =
-                itertools::Itertools::intersperse(parts, " ").collect();
-            let path: PathBuf = root_path.join(Path::new(&path));
-            match fs::canonicalize(&path) {
-                Ok(path) => {
-                    log::trace!("[load] {} -> {}", id, path.display());
-                    index.insert_entry(path, IndexEntry { id, modified });
+            let (number, line) = line?;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    let line = String::from_utf8_lossy(e.as_bytes());
+                    index
+                        .load_report
+                        .skip(&root_path, number, line.into());
+                    continue;
                 }
+            };
+            let (entry, path) = match parse_line(&line, version) {
+                Ok(parsed) => parsed,
                 Err(_) => {
-                    log::warn!("File {} not found", path.display());
+                    index.load_report.skip(&root_path, number, line);
                     continue;
                 }
+            };
+            let Some(path) = resolve_path(&root_path, &path) else {
+                continue;
+            };
+            // on case-insensitive filesystems, a stale line can
+            // resolve into a path already loaded
+            if index.path2id.contains_key(&path) {
+                log::warn!("Path {} is listed twice", path.display());
+                continue;
             }
+            log::trace!("[load] {} -> {}", entry.id, path.display());
+            index.insert_entry(path, entry);
         }
 
+        if !index.load_report.is_clean() {
+            let lines: Vec<usize> = index
+                .load_report
+                .skipped
+                .iter()
+                .map(|skipped| skipped.line)
+                .collect();
+            tracing::warn!(
+                root = %root_path.display(),
+                skipped = lines.len(),
+                lines = ?lines,
+                "Skipped damaged lines of the index"
+            );
+            index.damaged = index
+                .load_report
+                .damaged_paths()
+                .map(Path::to_path_buf)
+                .collect();
+        }
+
+        index.check_invariants();
+        span.record("files", index.path2id.len());
         Ok(index)
     }
 
+    /// Lines of the index file skipped by the last load,
+    /// see [`LoadReport`]
+    pub fn load_report(&self) -> &LoadReport {
+        &self.load_report
+    }
+
     /// Stores the resource index to the file system
     ///
     /// This function writes the index to the file system. It writes the index
     /// to `$root_path/.ark/index` and creates the directory if it's absent.
+    /// Unless disabled in [`IndexOptions`], the cache of IDs in
+    /// `$root_path/.ark/cache/ids` is rewritten as well.
+    ///
+    /// Returns [`ArklibError::Busy`] if another process keeps reading or
+    /// writing the index for longer than [`IndexOptions::lock_timeout`].
     pub fn store(&self) -> Result<()> {
-        log::info!("Storing the index to file");
+        let _lock =
+            IndexLock::exclusive(&self.root, self.options.lock_timeout)?;
+        self.write()
+    }
 
-        let start = SystemTime::now();
+    /// Writes the index file, the caller must hold the lock
+    fn write(&self) -> Result<()> {
+        let span = tracing::info_span!(
+            "index_store",
+            root = %self.root.display(),
+            files = self.path2id.len(),
+            elapsed_ms = Empty,
+        );
+        let _timing = Timing::start(&span, &self.root, Operation::Store);
+        let _entered = span.enter();
+        tracing::info!("Storing the index to file");
 
         let index_path = self
             .root
@@ -191,17 +675,19 @@ impl ResourceIndex {
             .join(ARK_FOLDER)
             .join(INDEX_PATH);
 
-        let ark_dir = index_path.parent().unwrap();
-        fs::create_dir_all(ark_dir)?;
-
-        let mut file = File::create(index_path)?;
-
-        let mut path2id: Vec<(&PathBuf, &IndexEntry)> =
-            self.path2id.iter().collect();
-        path2id.sort_by_key(|(_, entry)| *entry);
+        // entries are ordered by relative paths, so that
+        // identical indexes are stored into identical files
+        let mut path2id: Vec<(RelativePath, &IndexEntry)> = self
+            .path2id
+            .iter()
+            .map(|(path, entry)| Ok((self.relative_path(path)?, entry)))
+            .collect::<Result<_>>()?;
+        path2id.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
 
+        let mut file = Vec::new();
+        writeln!(file, "{} {}", INDEX_HEADER, INDEX_FORMAT_VERSION)?;
         for (path, entry) in path2id.iter() {
-            log::trace!("[store] {} by path {}", entry.id, path.display());
+            log::trace!("[store] {} by path {}", entry.id, path);
 
             let timestamp = entry
                 .modified
@@ -211,21 +697,27 @@ impl ResourceIndex {
                 })?
                 .as_millis();
 
-            let path =
-                pathdiff::diff_paths(path.to_str().unwrap(), self.root.clone())
-                    .ok_or(ArklibError::Path(
-                        "Couldn't calculate path diff".into(),
-                    ))?;
-
-            writeln!(file, "{} {} {}", timestamp, entry.id, path.display())?;
+            let prefix = if entry.remote {
+                REMOTE_ID_PREFIX
+            } else if entry.sampled {
+                SAMPLED_ID_PREFIX
+            } else {
+                ""
+            };
+            writeln!(
+                file,
+                "{} {}{} {}",
+                timestamp,
+                prefix,
+                entry.id,
+                escape(path.as_str())
+            )?;
         }
+        write_file(&index_path, &file)?;
 
-        log::trace!(
-            "Storing the index took {:?}",
-            start
-                .elapsed()
-                .map_err(|_| ArklibError::Other(anyhow!("SystemTime error")))
-        );
+        if self.options.use_id_cache {
+            IdCache::store(&self.root, self.entries())?;
+        }
         Ok(())
     }
 
@@ -235,25 +727,35 @@ impl ResourceIndex {
     /// If the index exists at the provided `root_path`, it will be loaded,
     /// updated, and stored. If it doesn't exist, a new index will be built
     /// from scratch
+    ///
+    /// The index file stays locked until the updated index is stored,
+    /// so concurrent processes never update it simultaneously.
     pub fn provide<P: AsRef<Path>>(root_path: P) -> Result<Self> {
-        match Self::load(&root_path) {
-            Ok(mut index) => {
-                log::debug!("Index loaded: {} entries", index.path2id.len());
+        Self::provide_with_options(root_path, IndexOptions::default())
+    }
 
-                let update = index.update_all()?;
-                log::debug!(
-                    "Index updated: {} added, {} deleted",
-                    update.added.len(),
-                    update.deleted.len()
-                );
-                index.store()?;
+    /// Provides the resource index like [`ResourceIndex::provide`],
+    /// using the options for indexing new and modified files
+    pub fn provide_with_options<P: AsRef<Path>>(
+        root_path: P,
+        options: IndexOptions,
+    ) -> Result<Self> {
+        let root_path = fs::canonicalize(root_path.as_ref())?;
+        nesting::check_nesting(&root_path, &options)?;
+        let _lock = IndexLock::exclusive(&root_path, options.lock_timeout)?;
+        match Self::read(root_path.clone(), options.clone()) {
+            Ok(mut index) => {
+                // the spans of loading, updating and storing
+                // carry the statistics
+                index.update_all()?;
+                index.write()?;
 
                 Ok(index)
             }
             Err(e) => {
-                log::warn!("{}", e.to_string());
-                log::info!("Building the index from scratch");
-                Ok(Self::build(root_path))
+                tracing::warn!("Couldn't load the index: {}", e);
+                tracing::info!("Building the index from scratch");
+                Ok(Self::build_with_options(root_path, options))
             }
         }
     }
@@ -264,20 +766,60 @@ impl ResourceIndex {
     /// added resources
     pub fn update_all(&mut self) -> Result<IndexUpdate> {
         log::debug!("Updating the index");
-        log::trace!("[update] known paths: {:?}", self.path2id.keys());
+        self.update_scope(&self.root.clone())
+    }
+
+    /// Updates only the entries under the directory, which must be inside
+    /// of the root, based on the current state of the file system
+    ///
+    /// This is much cheaper than [`ResourceIndex::update_all`] when a
+    /// watcher reports changes in a single folder of a huge root. A missing
+    /// directory is treated as deleted together with everything in it.
+    /// Moves are detected only within the directory.
+    pub fn update_subtree(&mut self, dir: &Path) -> Result<IndexUpdate> {
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let relative = dir.strip_prefix(&self.root).map_err(|_| {
+            ArklibError::Path(format!(
+                "{} is outside of the root {}",
+                dir.display(),
+                self.root.display()
+            ))
+        })?;
+        if relative.components().any(|part| {
+            part.as_os_str()
+                .to_string_lossy()
+                .starts_with('.')
+        }) {
+            return Err(ArklibError::Path(format!(
+                "{} is hidden and never indexed",
+                dir.display()
+            )));
+        }
+
+        log::debug!("Updating the index under {}", dir.display());
+        self.update_scope(&dir)
+    }
 
-        let curr_entries = discover_files(self.root.clone());
+    /// Compares the files under the scope with the index, without hashing
+    /// the files or modifying the index
+    fn detect_changes(&self, scope: &Path) -> Result<Changes> {
+        let curr_entries = discover_files(&self.root, scope, &self.options);
 
         // assuming that collections manipulation is
         // quicker than asking `path.exists()` for every path
         let curr_paths: Paths = curr_entries.keys().cloned().collect();
-        let prev_paths: Paths = self.path2id.keys().cloned().collect();
+        let prev_paths: Paths = self
+            .path2id
+            .keys()
+            .filter(|path| path.starts_with(scope))
+            .cloned()
+            .collect();
         let preserved_paths: Paths = curr_paths
             .intersection(&prev_paths)
             .cloned()
             .collect();
 
-        let created_paths: HashMap<PathBuf, DirEntry> = curr_entries
+        let mut created_paths: HashMap<PathBuf, DirEntry> = curr_entries
             .iter()
             .filter_map(|(path, entry)| {
                 if !preserved_paths.contains(path) {
@@ -288,14 +830,94 @@ impl ResourceIndex {
             })
             .collect();
 
+        log::debug!("Checking moved paths");
+        let mut moved_paths: HashMap<PathBuf, PathBuf> = HashMap::new();
+        // On case-insensitive filesystems, a created path differing from
+        // a vanished one only in case is the same file renamed, even if
+        // its content has been modified as well
+        let mut renamed_paths: HashMap<PathBuf, PathBuf> = HashMap::new();
+        if self.case_insensitive {
+            let vanished: HashMap<String, &PathBuf> = prev_paths
+                .difference(&preserved_paths)
+                .map(|path| (case::fold(path), path))
+                .collect();
+            created_paths.retain(|path, _| {
+                match vanished.get(&case::fold(path)) {
+                    Some(from) => {
+                        moved_paths.insert((*from).clone(), path.clone());
+                        renamed_paths.insert(path.clone(), (*from).clone());
+                        false
+                    }
+                    None => true,
+                }
+            });
+        }
+
+        // A created file with the same size and modification time as
+        // a vanished one is assumed to be the same file moved,
+        // so its ID is reused instead of hashing the file. When several
+        // files share the size and the time, created ones are hashed
+        // to tell which vanished file each of them is.
+        let mut vanished: HashMap<(u64, SystemTime), Vec<PathBuf>> =
+            HashMap::new();
+        for path in prev_paths
+            .difference(&preserved_paths)
+            .filter(|path| !moved_paths.contains_key(*path))
+        {
+            let entry = &self.path2id[path];
+            vanished
+                .entry((entry.id.data_size, entry.modified))
+                .or_default()
+                .push(path.clone());
+        }
+        let keys: HashMap<PathBuf, (u64, SystemTime)> = created_paths
+            .iter()
+            .filter_map(|(path, dir_entry)| {
+                let metadata = dir_entry.metadata().ok()?;
+                let modified = self.options.clock.modified(&metadata).ok()?;
+                Some((path.clone(), (metadata.len(), modified)))
+            })
+            .filter(|(_, key)| vanished.contains_key(key))
+            .collect();
+        let mut created: HashMap<(u64, SystemTime), usize> = HashMap::new();
+        for key in keys.values() {
+            *created.entry(*key).or_default() += 1;
+        }
+        created_paths.retain(|path, _| {
+            let Some(key) = keys.get(path) else {
+                return true;
+            };
+            let Some(candidates) = vanished.get_mut(key) else {
+                return true;
+            };
+            let from = if candidates.len() == 1 && created[key] == 1 {
+                candidates.pop()
+            } else {
+                self.compute_id(path).ok().and_then(|id| {
+                    let position = candidates
+                        .iter()
+                        .position(|from| self.path2id[from].id == id)?;
+                    Some(candidates.swap_remove(position))
+                })
+            };
+            match from {
+                Some(from) => {
+                    moved_paths.insert(from, path.clone());
+                    false
+                }
+                None => true,
+            }
+        });
         log::debug!("Checking updated paths");
         let mut updated_paths: HashMap<PathBuf, DirEntry> = HashMap::new();
         for (path, dir_entry) in curr_entries.iter() {
-            if !preserved_paths.contains(path) {
-                continue;
-            }
-
-            let our_entry = &self.path2id[path];
+            // renamed files are still indexed by their previous paths
+            let indexed = match renamed_paths.get(path) {
+                Some(from) => from,
+                None if preserved_paths.contains(path) => path,
+                None => continue,
+            };
+            let our_entry = &self.path2id[indexed];
             let prev_modified = our_entry.modified;
 
             let result = dir_entry.metadata();
@@ -309,7 +931,7 @@ impl ResourceIndex {
             }
             let metadata = result.unwrap();
 
-            let result = metadata.modified();
+            let result = self.options.clock.modified(&metadata);
             if result.is_err() {
                 log::error!(
                     "Couldn't retrieve timestamp for {}: {}",
@@ -320,13 +942,24 @@ impl ResourceIndex {
             }
             let curr_modified = result.unwrap();
 
+            // restored backups and skewed clocks move times backwards
             let elapsed = curr_modified
                 .duration_since(prev_modified)
-                .map_err(|e| {
-                    ArklibError::Other(anyhow!("SystemTime error: {}", e))
-                })?;
-
-            if elapsed >= RESOURCE_UPDATED_THRESHOLD {
+                .unwrap_or_else(|e| {
+                    log::debug!(
+                        "{} went back in time by {:?}",
+                        path.display(),
+                        e.duration()
+                    );
+                    e.duration()
+                });
+
+            // placeholders can be downloaded keeping their timestamps
+            let downloaded = our_entry.remote
+                && (self.options.hash_placeholders
+                    || !is_placeholder(&metadata));
+            let racy = clock::is_racy(&*self.options.clock, curr_modified);
+            if elapsed >= RESOURCE_UPDATED_THRESHOLD || downloaded || racy {
                 log::trace!(
                     "[update] modified {} by path {}
                                 \twas {:?}
@@ -342,48 +975,120 @@ impl ResourceIndex {
             }
         }
 
+        Ok(Changes {
+            prev_paths,
+            preserved_paths,
+            created_paths,
+            moved_paths,
+            updated_paths,
+        })
+    }
+
+    fn update_scope(&mut self, scope: &Path) -> Result<IndexUpdate> {
+        let span = tracing::info_span!(
+            "index_update",
+            root = %self.root.display(),
+            scope = %scope.display(),
+            added = Empty,
+            deleted = Empty,
+            modified = Empty,
+            moved = Empty,
+            elapsed_ms = Empty,
+        );
+        let _timing = Timing::start(&span, &self.root, Operation::Update);
+        let _entered = span.enter();
+        log::trace!("[update] known paths: {:?}", self.path2id.keys());
+
+        let Changes {
+            prev_paths,
+            preserved_paths,
+            created_paths,
+            moved_paths,
+            updated_paths,
+        } = self.detect_changes(scope)?;
+        let mut moved = HashMap::new();
+        for (from, to) in moved_paths.iter() {
+            let entry = match self.path2id.remove(from) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            log::trace!(
+                "[update] moved {} from {} to {}",
+                entry.id,
+                from.display(),
+                to.display()
+            );
+            let id = entry.id;
+            moved.insert(id, (from.clone(), to.clone()));
+            self.path2id.insert(to.clone(), entry);
+            match self.collisions.contains_key(&id) {
+                true => self.elect_path(id),
+                false => {
+                    self.id2path.insert(id, to.clone());
+                }
+            }
+        }
+
+        // Scan updated paths before touching the index,
+        // so that their previous IDs are still known
+        log::debug!("Checking modified paths");
+        let cache = self.options.id_cache(&self.root);
+        let updated_keys: Paths = updated_paths.keys().cloned().collect();
+        let updated_entries =
+            scan_entries(&self.root, updated_paths, &self.options, &cache);
+
         let mut deleted: HashSet<ResourceId> = HashSet::new();
-        // Get the paths to be deleted
+        // Get the paths to be deleted, including updated paths
+        // which are not valid resources anymore
         let paths_to_delete = prev_paths
             .difference(&preserved_paths)
+            .filter(|path| !moved_paths.contains_key(*path))
+            .chain(
+                updated_keys
+                    .iter()
+                    .filter(|path| !updated_entries.contains_key(*path)),
+            )
             .cloned()
-            .chain(updated_paths.keys().cloned());
-        // Process each path: remove from the index and update the collisions
+            .collect::<Vec<_>>();
         for path in paths_to_delete {
-            if let Some(entry) = self.path2id.remove(&path) {
-                let k = self.collisions.remove(&entry.id).unwrap_or(1);
-                if k > 1 {
-                    self.collisions.insert(entry.id, k - 1);
-                } else {
-                    log::trace!(
-                        "[delete] {} by path {}",
-                        entry.id,
-                        path.display()
-                    );
-                    self.id2path.remove(&entry.id);
-                    deleted.insert(entry.id);
+            if let Some(id) = self.remove_entry(&path) {
+                deleted.insert(id);
+            }
+        }
+
+        let mut modified = HashMap::new();
+        let mut diverged = HashMap::new();
+        for (path, entry) in updated_entries {
+            let previous = self.path2id[&path].id;
+            if previous == entry.id {
+                // replaced with the same content
+                self.path2id.insert(path, entry);
+                continue;
+            }
+            log::trace!(
+                "[update] modified {} into {} by path {}",
+                previous,
+                entry.id,
+                path.display()
+            );
+            // a modified alias is a new resource, the previous one
+            // is still indexed by its other paths
+            match self.remove_entry(&path) {
+                Some(_) => {
+                    modified.insert(path.clone(), (previous, entry.id));
+                }
+                None => {
+                    diverged.insert(path.clone(), entry.id);
                 }
-            } else {
-                log::warn!(
-                    "Path {} was not found in the index",
-                    path.display()
-                );
             }
+            self.insert_entry(path, entry);
         }
 
-        // Scan entries for updated paths
         log::debug!("Checking added paths");
-        let mut updated_entries = scan_entries(updated_paths);
-        let created_entries = scan_entries(created_paths);
-        // Combine updated and created entries
-        updated_entries.extend(created_entries);
-        // Filter entries not contained in id2path
-        let added: HashMap<PathBuf, IndexEntry> = updated_entries
-            .into_iter()
-            .filter(|(_, entry)| !self.id2path.contains_key(&entry.id))
-            .collect();
-
-        for (path, entry) in added.iter() {
+        let created_entries =
+            scan_entries(&self.root, created_paths, &self.options, &cache);
+        // new files with indexed content are added as aliases
+        for (path, entry) in created_entries.iter() {
             if deleted.contains(&entry.id) {
                 // emitting the resource as both deleted and added
                 // (renaming a duplicate might remain undetected)
@@ -396,12 +1101,43 @@ impl ResourceIndex {
             self.insert_entry(path.clone(), entry.clone());
         }
 
-        let added: HashMap<PathBuf, ResourceId> = added
+        let mut added: HashMap<PathBuf, ResourceId> = created_entries
             .into_iter()
             .map(|(path, entry)| (path, entry.id))
+            .chain(diverged)
             .collect();
 
-        Ok(IndexUpdate { deleted, added })
+        // files of damaged lines of the index file are hashed
+        // by the first update, even outside of its scope
+        for path in std::mem::take(&mut self.damaged) {
+            if self.path2id.contains_key(&path) {
+                continue;
+            }
+            match fs::metadata(&path)
+                .map_err(ArklibError::from)
+                .and_then(|metadata| scan_entry(&path, metadata, &self.options))
+            {
+                Ok(entry) => {
+                    added.insert(path.clone(), entry.id);
+                    self.insert_entry(path, entry);
+                }
+                Err(e) => {
+                    log::debug!("Skipping {}: {}", path.display(), e)
+                }
+            }
+        }
+
+        span.record("added", added.len());
+        span.record("deleted", deleted.len());
+        span.record("modified", modified.len());
+        span.record("moved", moved.len());
+        self.check_invariants();
+        Ok(IndexUpdate {
+            deleted,
+            added,
+            modified,
+            moved,
+        })
     }
 
     /// Indexes a new entry identified by the provided path, updating the index
@@ -436,22 +1172,110 @@ impl ResourceIndex {
                 e
             ))
         })?;
-        let new_entry = scan_entry(path, metadata)?;
+        let new_entry = scan_entry(path, metadata, &self.options)?;
         let id = new_entry.id;
-        if let Some(nonempty) = self.collisions.get_mut(&id) {
-            *nonempty += 1;
+
+        // the path can be indexed already with a different case,
+        // if the file has been renamed on a case-insensitive filesystem
+        let mut deleted = HashSet::new();
+        let mut moved = HashMap::new();
+        let renamed = match self.case_insensitive {
+            true => case::find_variant(self.path2id.keys(), path),
+            false => None,
+        };
+        if let Some(from) = renamed {
+            let previous = self.path2id[&from].id;
+            if previous == id {
+                log::trace!(
+                    "[update] moved {} from {} by changing case",
+                    id,
+                    from.display()
+                );
+                self.remove_entry(&from);
+                self.insert_entry(path_buf.clone(), new_entry);
+                self.check_invariants();
+                moved.insert(id, (from, path_buf));
+                return Ok(IndexUpdate {
+                    added: HashMap::new(),
+                    deleted,
+                    modified: HashMap::new(),
+                    moved,
+                });
+            }
+            deleted.extend(self.remove_entry(&from));
+        }
+
+        // an already indexed path would be counted as an alias of itself
+        if self.path2id.contains_key(&path_buf) {
+            deleted.extend(self.remove_entry(&path_buf));
         }
         let mut added = HashMap::new();
         added.insert(path_buf.clone(), id);
-        self.id2path.insert(id, path_buf.clone());
-        self.path2id.insert(path_buf, new_entry);
+        self.insert_entry(path_buf, new_entry);
+        self.check_invariants();
 
         Ok(IndexUpdate {
             added,
-            deleted: HashSet::new(),
+            deleted,
+            modified: HashMap::new(),
+            moved,
         })
     }
 
+    /// Computes the ID the file would get in the index, respecting the
+    /// options of the index, without indexing the file
+    pub fn compute_id(&self, path: &Path) -> Result<ResourceId> {
+        Ok(scan_entry(path, fs::metadata(path)?, &self.options)?.id)
+    }
+
+    /// Moves the indexed file to another path inside of the root, keeping
+    /// its entry instead of hashing the content again
+    ///
+    /// Fails if the file isn't indexed or if the destination exists.
+    pub fn move_file(&mut self, from: &Path, to: &Path) -> Result<IndexUpdate> {
+        let from = fs::canonicalize(from)?;
+        let entry = self.path2id.get(&from).cloned().ok_or_else(|| {
+            ArklibError::Path(format!("{} is not indexed", from.display()))
+        })?;
+        let (parent, name) = match (to.parent(), to.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => {
+                return Err(ArklibError::Path(format!(
+                    "Illegal destination {}",
+                    to.display()
+                )))
+            }
+        };
+        fs::create_dir_all(parent)?;
+        let to = fs::canonicalize(parent)?.join(name);
+        if !to.starts_with(&self.root) || to.exists() {
+            return Err(ArklibError::Path(format!(
+                "Can't move {} to {}",
+                from.display(),
+                to.display()
+            )));
+        }
+        fs::rename(&from, &to)?;
+        Ok(self.move_entry(from, to, entry))
+    }
+
+    /// Moves the entry of a file which has been moved to another path
+    fn move_entry(
+        &mut self,
+        from: PathBuf,
+        to: PathBuf,
+        entry: IndexEntry,
+    ) -> IndexUpdate {
+        let id = entry.id;
+        self.remove_entry(&from);
+        self.insert_entry(to.clone(), entry);
+        self.check_invariants();
+        IndexUpdate {
+            moved: HashMap::from([(id, (from, to))]),
+            ..IndexUpdate::default()
+        }
+    }
+
     /// Updates a single entry in the index with a new resource located at the
     /// specified path, replacing the old resource associated with the given
     /// ID.
@@ -477,7 +1301,13 @@ impl ResourceIndex {
         log::debug!("Updating a single entry in the index");
 
         if !path.as_ref().exists() {
-            return self.forget_id(old_id);
+            // other aliases of the resource stay indexed
+            return match self.path2id.get(path.as_ref()) {
+                Some(entry) if entry.id == old_id => {
+                    self.forget_path(path.as_ref(), old_id)
+                }
+                _ => self.forget_id(old_id),
+            };
         }
 
         let path_buf = fs::canonicalize(path)?;
@@ -486,7 +1316,7 @@ impl ResourceIndex {
         log::trace!(
             "[update] paths {:?} has id {:?}",
             path,
-            self.path2id[path]
+            self.path2id.get(path)
         );
 
         let metadata = fs::metadata(path);
@@ -497,7 +1327,7 @@ impl ResourceIndex {
         // we are sure that the path exists
         let metadata = metadata.unwrap();
 
-        let new_entry = scan_entry(path, metadata);
+        let new_entry = scan_entry(path, metadata, &self.options);
         if new_entry.is_err() {
             log::debug!("Path {:?} is a directory or empty file", &path);
             return self.forget_path(path, old_id);
@@ -530,16 +1360,165 @@ impl ResourceIndex {
         }
 
         // new resource exists by the path
-        self.forget_path(path, old_id).map(|mut update| {
-            update
-                .added
-                .insert(path_buf.clone(), new_entry.id);
-            self.insert_entry(path_buf, new_entry);
+        self.replace_entry(path_buf, old_id, new_entry)
+    }
 
-            update
+    /// Replaces the entry of a file which content has been modified
+    fn replace_entry(
+        &mut self,
+        path: PathBuf,
+        old_id: ResourceId,
+        new_entry: IndexEntry,
+    ) -> Result<IndexUpdate> {
+        self.forget_path(&path, old_id)?;
+        let mut update = IndexUpdate::default();
+        match self.id2path.contains_key(&old_id) {
+            // a modified alias is a new resource
+            true => update.added.insert(path.clone(), new_entry.id),
+            false => update
+                .modified
+                .insert(path.clone(), (old_id, new_entry.id))
+                .map(|(_, id)| id),
+        };
+        self.insert_entry(path, new_entry);
+        self.check_invariants();
+        Ok(update)
+    }
+
+    /// Checks whether the resource by the path has a sampled ID
+    pub fn is_sampled(&self, path: &dyn AsRef<Path>) -> bool {
+        self.path2id
+            .get(path.as_ref())
+            .is_some_and(|entry| entry.sampled)
+    }
+
+    /// Returns paths of all resources which have sampled IDs
+    pub fn sampled_paths(&self) -> impl Iterator<Item = &Path> {
+        self.path2id
+            .iter()
+            .filter(|(_, entry)| entry.sampled)
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// Returns paths of all placeholders of files kept in the cloud,
+    /// which are indexed without their content
+    pub fn remote_paths(&self) -> impl Iterator<Item = &Path> {
+        self.path2id
+            .iter()
+            .filter(|(_, entry)| entry.remote)
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// Replaces the sampled ID of the resource by the path
+    /// with an ID computed from the whole content
+    ///
+    /// Intended to be called lazily, e.g. when the resource is opened
+    /// or while the device is idle. Returns an empty update if the ID
+    /// is full already. IDs of placeholders are replaced as well, which
+    /// downloads the files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path is not indexed or if the file
+    /// has been modified since it was indexed.
+    pub fn upgrade_id(
+        &mut self,
+        path: &dyn AsRef<Path>,
+    ) -> Result<IndexUpdate> {
+        let path_buf = fs::canonicalize(path)?;
+        let entry =
+            self.path2id
+                .get(&path_buf)
+                .cloned()
+                .ok_or(ArklibError::Path(
+                    "Couldn't find the path in the index".into(),
+                ))?;
+        if !entry.sampled && !entry.remote {
+            return Ok(IndexUpdate {
+                added: HashMap::new(),
+                deleted: HashSet::new(),
+                modified: HashMap::new(),
+                moved: HashMap::new(),
+            });
+        }
+
+        // the entry must describe the current content, otherwise the new ID
+        // would be stored with the size and time of the previous one
+        let metadata = fs::metadata(&path_buf)?;
+        let modified = self.options.clock.modified(&metadata)?;
+        let elapsed = modified
+            .duration_since(entry.modified)
+            .unwrap_or_else(|e| e.duration());
+        if metadata.len() != entry.id.data_size
+            || elapsed >= RESOURCE_UPDATED_THRESHOLD
+        {
+            return Err(ArklibError::Path(format!(
+                "{} has been modified since it was indexed",
+                path_buf.display()
+            )));
+        }
+
+        log::debug!("Upgrading sampled id of {}", path_buf.display());
+        let id = match self.options.parallel_hashing_threshold {
+            Some(threshold) if entry.id.data_size >= threshold => {
+                ResourceId::compute_parallel(entry.id.data_size, &path_buf)?
+            }
+            _ => ResourceId::compute(entry.id.data_size, &path_buf)?,
+        };
+        let id = self.options.namespaced(id);
+
+        self.forget_path(&path_buf, entry.id)?;
+        let mut modified = HashMap::new();
+        modified.insert(path_buf.clone(), (entry.id, id));
+        self.insert_entry(
+            path_buf,
+            IndexEntry {
+                id,
+                sampled: false,
+                remote: false,
+                ..entry
+            },
+        );
+        self.check_invariants();
+        Ok(IndexUpdate {
+            added: HashMap::new(),
+            deleted: HashSet::new(),
+            modified,
+            moved: HashMap::new(),
         })
     }
 
+    /// Removes the entry by the path, updating the collisions
+    ///
+    /// Returns the ID of the entry if no other path has it
+    fn remove_entry(&mut self, path: &Path) -> Option<ResourceId> {
+        let entry = match self.path2id.remove(path) {
+            Some(entry) => entry,
+            None => {
+                log::warn!(
+                    "Path {} was not found in the index",
+                    path.display()
+                );
+                return None;
+            }
+        };
+        let k = self.collisions.remove(&entry.id).unwrap_or(1);
+        if k > 1 {
+            if k > 2 {
+                self.collisions.insert(entry.id, k - 1);
+            }
+            // another alias becomes the path of the resource
+            if self.id2path.get(&entry.id).map(PathBuf::as_path) == Some(path) {
+                self.elect_path(entry.id);
+            }
+            None
+        } else {
+            log::trace!("[delete] {} by path {}", entry.id, path.display());
+            self.id2path.remove(&entry.id);
+            Some(entry.id)
+        }
+    }
+
     /// Inserts an entry into the index, updating associated data structures
     ///
     /// If the entry ID already exists in the index, it handles collisions
@@ -548,19 +1527,77 @@ impl ResourceIndex {
         log::trace!("[add] {} by path {}", entry.id, path.display());
         let id = entry.id;
 
-        if let std::collections::hash_map::Entry::Vacant(e) =
-            self.id2path.entry(id)
-        {
-            e.insert(path.clone());
-        } else if let Some(nonempty) = self.collisions.get_mut(&id) {
-            *nonempty += 1;
-        } else {
-            self.collisions.insert(id, 2);
+        match self.id2path.entry(id) {
+            std::collections::hash_map::Entry::Vacant(e) => {
+                e.insert(path.clone());
+            }
+            std::collections::hash_map::Entry::Occupied(mut e) => {
+                *self.collisions.entry(id).or_insert(1) += 1;
+                if path < *e.get() {
+                    e.insert(path.clone());
+                }
+            }
         }
 
         self.path2id.insert(path, entry);
     }
 
+    /// Makes the smallest of the paths of the resource its path, so that
+    /// it doesn't depend on the order in which the aliases were indexed
+    fn elect_path(&mut self, id: ResourceId) {
+        let path = self
+            .path2id
+            .iter()
+            .filter(|(_, entry)| entry.id == id)
+            .map(|(path, _)| path)
+            .min()
+            .cloned();
+        match path {
+            Some(path) => self.id2path.insert(id, path),
+            None => self.id2path.remove(&id),
+        };
+    }
+
+    /// Checks that the maps of the index agree with each other, in debug
+    /// builds only, so that bookkeeping bugs surface where they happen
+    fn check_invariants(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let mut counts: HashMap<ResourceId, usize> = HashMap::new();
+        for entry in self.path2id.values() {
+            *counts.entry(entry.id).or_default() += 1;
+        }
+        assert_eq!(self.id2path.len(), counts.len(), "Unknown resources");
+        for (id, count) in counts.iter() {
+            let path = &self.id2path[id];
+            assert_eq!(
+                self.path2id.get(path).map(|entry| entry.id),
+                Some(*id),
+                "Path of {} is not its alias",
+                id
+            );
+            assert_eq!(
+                self.collisions.get(id).copied().unwrap_or(1),
+                *count,
+                "Wrong number of collisions of {}",
+                id
+            );
+            assert_eq!(
+                self.aliases(id).first(),
+                Some(path),
+                "Path of {} is not the smallest alias",
+                id
+            );
+        }
+        assert!(
+            self.collisions
+                .keys()
+                .all(|id| counts.get(id).is_some_and(|count| *count > 1)),
+            "Collisions of unknown resources"
+        );
+    }
+
     /// Removes the given resource ID from the index and returns an update
     /// containing the deleted entries
     pub fn forget_id(&mut self, old_id: ResourceId) -> Result<IndexUpdate> {
@@ -579,6 +1616,8 @@ impl ResourceIndex {
             self.path2id.remove(path);
         }
         self.id2path.remove(&old_id);
+        self.collisions.remove(&old_id);
+        self.check_invariants();
 
         let mut deleted = HashSet::new();
         deleted.insert(old_id);
@@ -586,6 +1625,8 @@ impl ResourceIndex {
         Ok(IndexUpdate {
             added: HashMap::new(),
             deleted,
+            modified: HashMap::new(),
+            moved: HashMap::new(),
         })
     }
 
@@ -598,91 +1639,241 @@ impl ResourceIndex {
         path: &Path,
         old_id: ResourceId,
     ) -> Result<IndexUpdate> {
-        self.path2id.remove(path);
+        debug_assert_eq!(
+            self.path2id.get(path).map(|entry| entry.id),
+            Some(old_id),
+            "Must forget the requested path"
+        );
+        let mut update = IndexUpdate::default();
+        // the resource stays indexed if it has other aliases
+        update.deleted.extend(self.remove_entry(path));
+        self.check_invariants();
+        Ok(update)
+    }
+}
 
-        if let Some(collisions) = self.collisions.get_mut(&old_id) {
-            debug_assert!(
-                *collisions > 1,
-                "Any collision must involve at least 2 resources"
-            );
-            *collisions -= 1;
-            if *collisions == 1 {
-                self.collisions.remove(&old_id);
-            }
+/// Parses the version of the index file from its first line,
+/// `None` if the file was written before the header was introduced
+pub(crate) fn parse_header(line: &str) -> Result<Option<u32>> {
+    let Some(header) = line.strip_prefix(INDEX_HEADER) else {
+        return Ok(None);
+    };
+    let version = header
+        .trim()
+        .parse()
+        .map_err(|_| ArklibError::Parse)?;
+    if version > INDEX_FORMAT_VERSION {
+        return Err(ArklibError::Path(format!(
+            "Index format {} is newer than supported {}",
+            version, INDEX_FORMAT_VERSION
+        )));
+    }
+    Ok(Some(version))
+}
 
-            // minor performance issue:
-            // we must find path of one of the collided
-            // resources and use it as new value
-            match self.id2path.get(&old_id) {
-                Some(collided_path) => {
-                    let old_path =
-                        self.id2path.insert(old_id, collided_path.clone());
-
-                    debug_assert_eq!(
-                        old_path.unwrap().as_path(),
-                        path,
-                        "Must forget the requested path"
-                    );
-                }
-                None => {
-                    return Err(ArklibError::Collision(
-                        "Illegal state of collision tracker".into(),
-                    ));
-                }
-            }
+/// Resolves the path of a line of the index file, `None` if the file
+/// is missing or is not a resource of the root
+pub(crate) fn resolve_path(root: &Path, relative: &str) -> Option<PathBuf> {
+    // the file name may be stored in a different normalization
+    // than in the index, when the root was synced from macOS
+    let located = locate_relative(root, relative);
+    let path: PathBuf = located
+        .clone()
+        .unwrap_or_else(|| root.join(Path::new(relative)));
+    match fs::canonicalize(&path) {
+        // links outside of the root are indexed by their own paths,
+        // see `OutsideLinks::Alias`
+        Ok(target)
+            if located.is_some()
+                && !target.starts_with(root)
+                && target.is_file() =>
+        {
+            Some(path)
+        }
+        // corrupted files can point anywhere
+        Ok(path) if !path.starts_with(root) || path.is_dir() => {
+            log::warn!("Path {} is not a resource", path.display());
+            None
+        }
+        Ok(path) => Some(path),
+        Err(_) => {
+            log::warn!("File {} not found", path.display());
+            None
+        }
+    }
+}
+
+/// Parses a line of the index file of the version into the entry
+/// and the path relative to the root
+pub(crate) fn parse_line(
+    line: &str,
+    version: u32,
+) -> Result<(IndexEntry, String)> {
+    let mut parts = line.split(' ');
+
+    let modified = {
+        let str = parts.next().ok_or(ArklibError::Parse)?;
+        UNIX_EPOCH
+            .checked_add(Duration::from_millis(
+                str.parse().map_err(|_| ArklibError::Parse)?,
+            ))
+            .ok_or(ArklibError::Parse)?
+    };
+
+    // sampled IDs are prefixed with a tilde,
+    // IDs of placeholders with a question mark
+    let (id, sampled, remote) = {
+        let str = parts.next().ok_or(ArklibError::Parse)?;
+        if let Some(str) = str.strip_prefix(SAMPLED_ID_PREFIX) {
+            (ResourceId::from_str(str)?, true, false)
+        } else if let Some(str) = str.strip_prefix(REMOTE_ID_PREFIX) {
+            (ResourceId::from_str(str)?, false, true)
         } else {
-            self.id2path.remove(&old_id);
+            (ResourceId::from_str(str)?, false, false)
         }
+    };
+
+    let path: String = itertools::Itertools::intersperse(parts, " ").collect();
+    let path = match version {
+        3.. => unescape(&path).ok_or(ArklibError::Parse)?,
+        _ => path,
+    };
+    if path.is_empty() {
+        return Err(ArklibError::Parse);
+    }
+    let entry = IndexEntry {
+        id,
+        modified,
+        sampled,
+        remote,
+    };
+    Ok((entry, path))
+}
 
-        let mut deleted = HashSet::new();
-        deleted.insert(old_id);
+/// Records the duration of an operation into the `elapsed_ms` field
+/// of its span once dropped, including early returns on errors,
+/// and keeps it for [`ResourceIndex::metrics`]
+struct Timing {
+    span: tracing::Span,
+    start: Instant,
+    root: PathBuf,
+    operation: Operation,
+}
+
+impl Timing {
+    fn start(span: &tracing::Span, root: &Path, operation: Operation) -> Self {
+        Timing {
+            span: span.clone(),
+            start: Instant::now(),
+            root: root.to_path_buf(),
+            operation,
+        }
+    }
+}
+
+impl Drop for Timing {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        self.span
+            .record("elapsed_ms", elapsed.as_millis() as u64);
+        metrics::record(&self.root, self.operation, elapsed);
+    }
+}
+
+/// Escapes backslashes and line breaks in a path of the index file
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
 
-        Ok(IndexUpdate {
-            added: HashMap::new(),
-            deleted,
-        })
+/// Reverses [`escape`], absent for malformed escape sequences
+fn unescape(path: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                '\\' => unescaped.push('\\'),
+                'n' => unescaped.push('\n'),
+                'r' => unescaped.push('\r'),
+                _ => return None,
+            },
+            c => unescaped.push(c),
+        }
     }
+    Some(unescaped)
+}
+
+/// Changes of files under a scope, detected by
+/// [`ResourceIndex::detect_changes`]
+struct Changes {
+    /// Indexed paths under the scope
+    prev_paths: Paths,
+    /// Indexed paths which still exist
+    preserved_paths: Paths,
+    /// Files which are neither indexed nor moved
+    created_paths: HashMap<PathBuf, DirEntry>,
+    /// Previous paths of moved files mapped to their current paths
+    moved_paths: HashMap<PathBuf, PathBuf>,
+    /// Files modified since they were indexed, including renamed ones
+    updated_paths: HashMap<PathBuf, DirEntry>,
 }
 
-/// Discovers all files under the specified root path
+/// Discovers files under the scope, which is the root or a folder in it
 ///
 /// Returns a hashmap of canonical file paths to directory entries
-fn discover_files<P: AsRef<Path>>(root_path: P) -> HashMap<PathBuf, DirEntry> {
-    log::debug!(
-        "Discovering all files under path {}",
-        root_path.as_ref().display()
-    );
+fn discover_files(
+    root: &Path,
+    scope: &Path,
+    options: &IndexOptions,
+) -> HashMap<PathBuf, DirEntry> {
+    log::debug!("Discovering all files under path {}", scope.display());
 
     let mut discovered_files = HashMap::new();
-    let walker = WalkDir::new(root_path)
+    let mut visited = HashSet::new();
+    let walker = WalkDir::new(scope)
         .min_depth(1)
+        .follow_links(true)
         .into_iter()
         .filter_entry(|entry| {
             // skip hidden files and directories
-            !entry
+            if entry
                 .file_name()
                 .to_string_lossy()
                 .starts_with('.')
+            {
+                return false;
+            }
+            if entry.path_is_symlink() && entry.file_type().is_dir() {
+                return links::follow_folder(
+                    root,
+                    entry.path(),
+                    options,
+                    &mut visited,
+                );
+            }
+            true
         });
 
     for entry in walker {
         match entry {
             Ok(entry) => {
-                let path = entry.path().to_path_buf();
-                if !entry.file_type().is_dir() {
-                    // canonicalize the path to avoid duplicates
-                    match fs::canonicalize(&path) {
-                        Ok(canonical_path) => {
-                            discovered_files.insert(canonical_path, entry);
-                        }
-                        Err(msg) => {
-                            log::warn!(
-                                "Couldn't canonicalize {}:\n{}",
-                                path.display(),
-                                msg
-                            );
-                        }
-                    }
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+                // canonicalize the path to avoid duplicates
+                if let Some(path) =
+                    links::file_path(root, scope, entry.path(), options)
+                {
+                    discovered_files.insert(path, entry);
                 }
             }
             Err(msg) => {
@@ -697,7 +1888,11 @@ fn discover_files<P: AsRef<Path>>(root_path: P) -> HashMap<PathBuf, DirEntry> {
 /// Scans a single file entry and extracts its metadata to create an index entry
 ///
 /// Returns an error if the path is a directory or if the file is empty
-fn scan_entry(path: &Path, metadata: Metadata) -> Result<IndexEntry> {
+fn scan_entry(
+    path: &Path,
+    metadata: Metadata,
+    options: &IndexOptions,
+) -> Result<IndexEntry> {
     if metadata.is_dir() {
         return Err(ArklibError::Path("Path is expected to be a file".into()));
     }
@@ -707,56 +1902,153 @@ fn scan_entry(path: &Path, metadata: Metadata) -> Result<IndexEntry> {
         return Err(ArklibError::Path("Empty file".into()));
     }
 
-    let id = ResourceId::compute(size, path)?;
-    let modified = metadata.modified()?;
+    let sampled = options.samples(size);
+    let modified = options.clock.modified(&metadata)?;
+    if options.mirror_ids_to_xattr && !clock::is_racy(&*options.clock, modified)
+    {
+        let millis = modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        // the attribute keeps IDs without namespace, since it is copied
+        // together with the file into other roots
+        if let Some(entry) = id_xattr::read(path, millis, sampled) {
+            if entry.id.data_size == size {
+                return Ok(IndexEntry {
+                    id: options.namespaced(entry.id),
+                    ..entry
+                });
+            }
+        }
+    }
+
+    if !options.hash_placeholders && is_placeholder(&metadata) {
+        log::debug!("Not downloading placeholder {}", path.display());
+        return Ok(IndexEntry {
+            id: placeholder::placeholder_id(path, size),
+            modified,
+            sampled: false,
+            remote: true,
+        });
+    }
 
-    // We need to keep precision up to milliseconds only to avoid
-    // compatibility issues with different file systems (eg. Android)
-    let duration = modified
-        .duration_since(UNIX_EPOCH)
-        .expect("SystemTime before UNIX EPOCH!")
-        .as_millis();
-    let modified =
-        UNIX_EPOCH + std::time::Duration::from_millis(duration as u64);
+    let entry = IndexEntry {
+        id: hash_content(path, size, sampled, options)?,
+        modified,
+        sampled,
+        remote: false,
+    };
+    if options.mirror_ids_to_xattr {
+        id_xattr::write(path, &entry);
+    }
+    Ok(IndexEntry {
+        id: options.namespaced(entry.id),
+        ..entry
+    })
+}
 
-    Ok(IndexEntry { id, modified })
+/// Hashes the content of the file without namespace
+fn hash_content(
+    path: &Path,
+    size: u64,
+    sampled: bool,
+    options: &IndexOptions,
+) -> Result<ResourceId> {
+    match options.parallel_hashing_threshold {
+        _ if sampled => ResourceId::compute_sampled(size, path),
+        Some(threshold) if size >= threshold => {
+            ResourceId::compute_parallel(size, path)
+        }
+        _ => {
+            let file = File::open(path)?;
+            let mut reader =
+                BufReader::with_capacity(options.hashing_buffer_size, file);
+            ResourceId::compute_reader(size, &mut reader)
+        }
+    }
 }
 
-/// Scans multiple file entries and creates index entries for each one
+/// Scans multiple file entries and creates index entries for each one,
+/// using [`IndexOptions::scan_threads`] threads
 ///
 /// Returns a hashmap of file paths to their corresponding index entries
 fn scan_entries(
+    root: &Path,
     entries: HashMap<PathBuf, DirEntry>,
+    options: &IndexOptions,
+    cache: &IdCache,
 ) -> HashMap<PathBuf, IndexEntry> {
-    entries
-        .into_iter()
-        .filter_map(|(path_buf, entry)| {
-            let metadata = entry.metadata().ok()?;
-
-            let path = path_buf.as_path();
-            let result = scan_entry(path, metadata);
-            match result {
-                Err(msg) => {
-                    log::error!(
-                        "Couldn't retrieve metadata for {}:\n{}",
-                        path.display(),
-                        msg
-                    );
-                    None
-                }
-                Ok(entry) => Some((path_buf, entry)),
+    let scan = |(path_buf, entry): &(PathBuf, DirEntry)| {
+        let metadata = entry.metadata().ok()?;
+
+        let path = path_buf.as_path();
+        let sampled = options.samples(metadata.len());
+        let racy = options
+            .clock
+            .modified(&metadata)
+            .map_or(true, |modified| clock::is_racy(&*options.clock, modified));
+        let cached = match racy {
+            true => None,
+            false => cache.lookup(root, path, &metadata, sampled),
+        };
+        if let Some(cached) = cached {
+            log::trace!(
+                "[scan] cached {} by path {}",
+                cached.id,
+                path.display()
+            );
+            return Some((path_buf.clone(), cached));
+        }
+        let result = scan_entry(path, metadata, options);
+        match result {
+            Err(msg) => {
+                log::error!(
+                    "Couldn't retrieve metadata for {}:\n{}",
+                    path.display(),
+                    msg
+                );
+                None
             }
-        })
-        .collect()
+            Ok(entry) => Some((path_buf.clone(), entry)),
+        }
+    };
+
+    let entries: Vec<(PathBuf, DirEntry)> = entries.into_iter().collect();
+    let threads = options
+        .scan_threads
+        .clamp(1, entries.len().max(1));
+    if threads == 1 {
+        return entries.iter().filter_map(scan).collect();
+    }
+    let chunk_size = entries.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = entries
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk.iter().filter_map(scan).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::fs;
-    use crate::index::{discover_files, IndexEntry};
+    use crate::index::{
+        discover_files, IndexEntry, IndexOptions, INDEX_FORMAT_VERSION,
+        INDEX_HEADER,
+    };
     use crate::initialize;
-    use crate::resource::ResourceId;
+    use crate::resource::{ResourceId, ResourceIdTrait, PARALLEL_COMPUTATIONS};
     use crate::ResourceIndex;
+    use crate::{ArklibError, ARK_FOLDER, INDEX_PATH};
+    use proptest::prelude::*;
     use std::fs::File;
     #[cfg(target_family = "unix")]
     use std::fs::Permissions;
@@ -764,8 +2056,9 @@ mod tests {
     use std::os::unix::fs::PermissionsExt;
     use tempdir::TempDir;
 
-    use std::path::PathBuf;
-    use std::time::SystemTime;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
     use uuid::Uuid;
 
     const FILE_SIZE_1: u64 = 10;
@@ -827,6 +2120,119 @@ mod tests {
         assert_eq!(index, loaded_index);
     }
 
+    #[test]
+    fn index_file_is_deterministic() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let temp_dir = temp_dir.into_path();
+        let index_path = temp_dir.join(".ark").join("index");
+
+        let folder = create_dir_at(temp_dir.to_owned());
+        create_file_at(folder, Some(FILE_SIZE_1), Some(FILE_NAME_2));
+        create_file_at(
+            temp_dir.to_owned(),
+            Some(FILE_SIZE_2),
+            Some(FILE_NAME_1),
+        );
+        ResourceIndex::build(temp_dir.to_owned())
+            .store()
+            .unwrap();
+        let first = std::fs::read_to_string(&index_path).unwrap();
+
+        ResourceIndex::load(temp_dir.to_owned())
+            .unwrap()
+            .store()
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&index_path).unwrap(), first);
+
+        let lines: Vec<&str> = first.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            format!("{} {}", INDEX_HEADER, INDEX_FORMAT_VERSION)
+        );
+        // the folder is named by a UUID, which is ordered before "test"
+        assert!(lines[1].ends_with(&format!("/{}", FILE_NAME_2)));
+        assert!(lines[2].ends_with(&format!(" {}", FILE_NAME_1)));
+
+        // files without header are still supported
+        std::fs::write(&index_path, lines[1..].join("\n")).unwrap();
+        assert_eq!(
+            ResourceIndex::load(temp_dir.to_owned())
+                .unwrap()
+                .count_files(),
+            2
+        );
+
+        let newer = format!("{} {}\n", INDEX_HEADER, INDEX_FORMAT_VERSION + 1);
+        std::fs::write(&index_path, newer).unwrap();
+        assert!(ResourceIndex::load(temp_dir.to_owned()).is_err());
+    }
+
+    #[test]
+    fn damaged_lines_are_skipped() {
+        let temp_dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "first").unwrap();
+        fs::create_dir(root.join("docs")).unwrap();
+        fs::write(root.join("docs/b.txt"), "second").unwrap();
+        let index = ResourceIndex::build(&root);
+        index.store().unwrap();
+
+        let index_path = root.join(ARK_FOLDER).join(INDEX_PATH);
+        let content = fs::read_to_string(&index_path).unwrap();
+        let mut lines: Vec<String> =
+            content.lines().map(String::from).collect();
+        // the ID of the first file is damaged
+        lines[1] = lines[1].replacen('-', "?", 1);
+        lines.push("garbage".to_string());
+        fs::write(&index_path, lines.join("\n")).unwrap();
+
+        let mut loaded = ResourceIndex::load(&root).unwrap();
+        assert_eq!(loaded.count_files(), 1);
+        let report = loaded.load_report();
+        assert_eq!(
+            report
+                .skipped
+                .iter()
+                .map(|skipped| skipped.line)
+                .collect::<Vec<_>>(),
+            vec![2, 4]
+        );
+        assert_eq!(
+            report.damaged_paths().collect::<Vec<_>>(),
+            vec![root.join("a.txt")]
+        );
+
+        // the first update is scoped elsewhere
+        let update = loaded.update_subtree(&root.join("docs")).unwrap();
+        assert_eq!(update.added.len(), 1);
+        assert_eq!(loaded.path2id, index.path2id);
+        assert!(loaded.update_all().unwrap().added.is_empty());
+    }
+
+    #[test]
+    fn collisions_survive_restarts() {
+        let temp_dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        for name in ["c.txt", "a.txt", "b.txt"] {
+            fs::write(root.join(name), "same").unwrap();
+        }
+        let mut index = ResourceIndex::build(&root);
+        let id = index.get_entry(root.join("a.txt")).unwrap().id;
+        assert_eq!(index.collisions.get(&id), Some(&3));
+        assert_eq!(index.get_path(&id), Some(root.join("a.txt").as_path()));
+        index.store().unwrap();
+        assert_eq!(ResourceIndex::load(&root).unwrap(), index);
+
+        fs::remove_file(root.join("a.txt")).unwrap();
+        index.update_all().unwrap();
+        assert_eq!(index.collisions.get(&id), Some(&2));
+        assert_eq!(index.get_path(&id), Some(root.join("b.txt").as_path()));
+        index.store().unwrap();
+        assert_eq!(ResourceIndex::load(&root).unwrap(), index);
+    }
+
     #[test]
     fn index_build_should_process_1_file_successfully() {
         let temp_dir = TempDir::new("arklib_test")
@@ -850,6 +2256,126 @@ mod tests {
         assert_eq!(actual.count_files(), 1);
     }
 
+    #[test]
+    fn index_build_with_parallel_hashing_produces_same_ids() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let temp_dir = temp_dir.into_path();
+
+        create_file_at(temp_dir.to_owned(), Some(FILE_SIZE_1), None);
+        create_file_at(temp_dir.to_owned(), Some(FILE_SIZE_2), None);
+        let options = IndexOptions {
+            parallel_hashing_threshold: Some(FILE_SIZE_2),
+            ..Default::default()
+        };
+        let computed = PARALLEL_COMPUTATIONS.load(Ordering::Relaxed);
+        let actual =
+            ResourceIndex::build_with_options(temp_dir.to_owned(), options);
+        assert!(PARALLEL_COMPUTATIONS.load(Ordering::Relaxed) > computed);
+        let expected = ResourceIndex::build(temp_dir.to_owned());
+
+        assert_eq!(actual.id2path, expected.id2path);
+    }
+
+    #[test]
+    fn sampled_ids_are_stored_and_upgraded() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let temp_dir = temp_dir.into_path();
+
+        let (_, small) = create_file_at(
+            temp_dir.to_owned(),
+            Some(FILE_SIZE_1),
+            Some(FILE_NAME_1),
+        );
+        let (_, large) = create_file_at(
+            temp_dir.to_owned(),
+            Some(FILE_SIZE_2),
+            Some(FILE_NAME_2),
+        );
+        let options = IndexOptions {
+            sampled_hashing_threshold: Some(FILE_SIZE_2),
+            ..Default::default()
+        };
+        let index =
+            ResourceIndex::build_with_options(temp_dir.to_owned(), options);
+        assert!(!index.is_sampled(&fs::canonicalize(&small).unwrap()));
+        assert!(index.is_sampled(&fs::canonicalize(&large).unwrap()));
+        assert!(!index.id2path.contains_key(&ResourceId {
+            data_size: FILE_SIZE_2,
+            hash: CRC32_2,
+        }));
+
+        index.store().unwrap();
+        let mut loaded = ResourceIndex::load(temp_dir.to_owned()).unwrap();
+        assert_eq!(loaded.path2id, index.path2id);
+        assert_eq!(loaded.sampled_paths().count(), 1);
+
+        let update = loaded.upgrade_id(&large).unwrap();
+        let full_id = ResourceId {
+            data_size: FILE_SIZE_2,
+            hash: CRC32_2,
+        };
+        let (previous, current) =
+            update.modified[&fs::canonicalize(&large).unwrap()];
+        assert_ne!(previous, full_id);
+        assert_eq!(current, full_id);
+        assert!(loaded.id2path.contains_key(&full_id));
+        assert_eq!(loaded.sampled_paths().count(), 0);
+
+        let update = loaded.upgrade_id(&large).unwrap();
+        assert!(update.modified.is_empty());
+    }
+
+    #[test]
+    fn modified_files_are_not_upgraded() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let temp_dir = temp_dir.into_path();
+        let (_, large) = create_file_at(
+            temp_dir.to_owned(),
+            Some(FILE_SIZE_2),
+            Some(FILE_NAME_2),
+        );
+        let options = IndexOptions {
+            sampled_hashing_threshold: Some(FILE_SIZE_2),
+            ..Default::default()
+        };
+        let mut index =
+            ResourceIndex::build_with_options(temp_dir.to_owned(), options);
+        let sampled = index
+            .get_entry(fs::canonicalize(&large).unwrap())
+            .unwrap()
+            .id;
+
+        // same size, but written after indexing
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::write(&large, vec![1; FILE_SIZE_2 as usize]).unwrap();
+        File::options()
+            .write(true)
+            .open(&large)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(index.upgrade_id(&large).is_err());
+
+        // shrunk without touching the time
+        let entry = index
+            .get_entry(fs::canonicalize(&large).unwrap())
+            .unwrap();
+        let modified = entry.modified;
+        assert_eq!(entry.id, sampled);
+        fs::write(&large, [1]).unwrap();
+        File::options()
+            .write(true)
+            .open(&large)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(index.upgrade_id(&large).is_err());
+        assert_eq!(index.sampled_paths().count(), 1);
+    }
+
     #[test]
     fn index_build_should_process_colliding_files_correctly() {
         let temp_dir = TempDir::new("arklib_test")
@@ -873,6 +2399,92 @@ mod tests {
         assert_eq!(actual.count_files(), 2);
     }
 
+    #[test]
+    fn aliases_are_deleted_one_by_one() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let path = fs::canonicalize(temp_dir.into_path()).unwrap();
+
+        for name in [FILE_NAME_1, FILE_NAME_2, FILE_NAME_3] {
+            create_file_at(path.clone(), Some(FILE_SIZE_1), Some(name));
+        }
+        let mut index = ResourceIndex::build(path.clone());
+        let id = ResourceId {
+            data_size: FILE_SIZE_1,
+            hash: CRC32_1,
+        };
+        assert_eq!(
+            index.aliases(&id),
+            [FILE_NAME_1, FILE_NAME_2, FILE_NAME_3].map(|name| path.join(name))
+        );
+
+        // the path of the resource itself is deleted first
+        let first = index.get_path(&id).unwrap().to_path_buf();
+        std::fs::remove_file(&first).unwrap();
+        let update = index.update_all().unwrap();
+        assert!(update.deleted.is_empty());
+        assert_eq!(index.aliases(&id).len(), 2);
+        let second = index.get_path(&id).unwrap().to_path_buf();
+        assert_ne!(second, first);
+        assert!(second.exists());
+
+        std::fs::remove_file(&second).unwrap();
+        let update = index.update_one(&second, id).unwrap();
+        assert!(update.deleted.is_empty());
+        let last = index.aliases(&id);
+        assert_eq!(last.len(), 1);
+        assert_eq!(index.get_path(&id), Some(last[0].as_path()));
+        assert!(index.collisions.is_empty());
+
+        std::fs::remove_file(&last[0]).unwrap();
+        let update = index.update_all().unwrap();
+        assert_eq!(update.deleted, [id].into());
+        assert!(index.aliases(&id).is_empty());
+    }
+
+    #[test]
+    fn aliases_are_renamed_and_modified_one_by_one() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let path = fs::canonicalize(temp_dir.into_path()).unwrap();
+
+        create_file_at(path.clone(), Some(FILE_SIZE_1), Some(FILE_NAME_1));
+        let mut index = ResourceIndex::build(path.clone());
+        let id = ResourceId {
+            data_size: FILE_SIZE_1,
+            hash: CRC32_1,
+        };
+        // copies of indexed files are reported as added aliases
+        create_file_at(path.clone(), Some(FILE_SIZE_1), Some(FILE_NAME_2));
+        let update = index.update_all().unwrap();
+        assert_eq!(update.added, [(path.join(FILE_NAME_2), id)].into());
+        assert_eq!(index.count_files(), 2);
+        assert_eq!(index.count_resources(), 1);
+
+        std::fs::rename(path.join(FILE_NAME_2), path.join(FILE_NAME_3))
+            .unwrap();
+        let update = index.update_all().unwrap();
+        assert_eq!(
+            update.moved[&id],
+            (path.join(FILE_NAME_2), path.join(FILE_NAME_3))
+        );
+        assert!(update.added.is_empty() && update.deleted.is_empty());
+        assert_eq!(
+            index.aliases(&id),
+            [path.join(FILE_NAME_1), path.join(FILE_NAME_3)]
+        );
+
+        std::fs::write(path.join(FILE_NAME_3), "modified").unwrap();
+        let update = index
+            .update_one(&path.join(FILE_NAME_3), id)
+            .unwrap();
+        assert!(update.modified.is_empty());
+        let new_id = update.added[&path.join(FILE_NAME_3)];
+        assert_eq!(index.aliases(&id), [path.join(FILE_NAME_1)]);
+        assert_eq!(index.aliases(&new_id), [path.join(FILE_NAME_3)]);
+        assert!(index.collisions.is_empty());
+    }
+
     #[test]
     fn update_all_should_handle_renamed_file_correctly() {
         let temp_dir = TempDir::new("arklib_test")
@@ -900,8 +2512,130 @@ mod tests {
 
         assert_eq!(actual.collisions.len(), 0);
         assert_eq!(actual.count_files(), 2);
-        assert_eq!(update.deleted.len(), 1);
+        assert_eq!(update.deleted.len(), 0);
+        assert_eq!(update.added.len(), 0);
+
+        let id = ResourceId {
+            data_size: FILE_SIZE_2,
+            hash: CRC32_2,
+        };
+        let (from, to) = &update.moved[&id];
+        assert_eq!(from, &fs::canonicalize(&path).unwrap().join(FILE_NAME_2));
+        assert_eq!(to, &fs::canonicalize(&path).unwrap().join(FILE_NAME_3));
+        assert_eq!(actual.get_path(&id), Some(to.as_path()));
+        assert_eq!(actual.get_entry(to).map(|entry| entry.id), Some(id));
+    }
+
+    #[test]
+    fn moves_of_files_sharing_size_and_time_are_told_apart() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let path = fs::canonicalize(temp_dir.into_path()).unwrap();
+
+        let modified = SystemTime::now() - Duration::from_secs(60);
+        for (name, content) in [("a.txt", "first"), ("b.txt", "other")] {
+            std::fs::write(path.join(name), content).unwrap();
+            File::options()
+                .write(true)
+                .open(path.join(name))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        let mut index = ResourceIndex::build(path.to_owned());
+        let first = ResourceId::compute_bytes(b"first").unwrap();
+        let other = ResourceId::compute_bytes(b"other").unwrap();
+
+        std::fs::rename(path.join("a.txt"), path.join("d.txt")).unwrap();
+        std::fs::rename(path.join("b.txt"), path.join("c.txt")).unwrap();
+        let update = index.update_all().unwrap();
+
+        assert!(update.added.is_empty());
+        assert!(update.deleted.is_empty());
+        assert_eq!(update.moved[&first].1, path.join("d.txt"));
+        assert_eq!(update.moved[&other].1, path.join("c.txt"));
+        assert_eq!(index.get_entry(path.join("d.txt")).unwrap().id, first);
+        assert_eq!(index.get_entry(path.join("c.txt")).unwrap().id, other);
+    }
+
+    #[test]
+    fn update_all_should_report_modified_files() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let path = fs::canonicalize(temp_dir.into_path()).unwrap();
+
+        let edited = path.join(FILE_NAME_1);
+        let replaced = path.join(FILE_NAME_2);
+        std::fs::write(&edited, "first").unwrap();
+        std::fs::write(&replaced, "second").unwrap();
+        let mut index = ResourceIndex::build(path.to_owned());
+        let old_id = *index.get_entry(&edited).map(|e| &e.id).unwrap();
+
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        for (file, content) in [(&edited, "other"), (&replaced, "second")] {
+            std::fs::write(file, content).unwrap();
+            File::options()
+                .write(true)
+                .open(file)
+                .unwrap()
+                .set_modified(later)
+                .unwrap();
+        }
+
+        let update = index
+            .update_all()
+            .expect("Should update index correctly");
+        assert!(update.added.is_empty());
+        assert!(update.deleted.is_empty());
+        assert!(update.moved.is_empty());
+        assert_eq!(update.modified.len(), 1);
+        let (previous, current) = update.modified[&edited];
+        assert_eq!(previous, old_id);
+        assert_eq!(index.get_entry(&edited).map(|e| e.id), Some(current));
+        assert!(!index.contains(&old_id));
+        assert_eq!(index.count_files(), 2);
+
+        std::fs::write(&edited, "third").unwrap();
+        let update = index
+            .update_one(&edited, current)
+            .expect("Should update index correctly");
+        assert_eq!(update.modified[&edited].0, current);
+        assert!(update.added.is_empty() && update.deleted.is_empty());
+    }
+
+    #[test]
+    fn update_subtree_should_ignore_other_folders() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let path = fs::canonicalize(temp_dir.into_path()).unwrap();
+        let photos = create_dir_at(path.to_owned());
+        let notes = create_dir_at(path.to_owned());
+
+        create_file_at(photos.to_owned(), Some(FILE_SIZE_1), None);
+        let mut index = ResourceIndex::build(path.to_owned());
+
+        create_file_at(photos.to_owned(), Some(FILE_SIZE_2), None);
+        create_file_at(notes.to_owned(), Some(FILE_SIZE_2 + 1), None);
+        let update = index
+            .update_subtree(&photos)
+            .expect("Should update index correctly");
         assert_eq!(update.added.len(), 1);
+        assert_eq!(index.count_files(), 2);
+
+        std::fs::remove_dir_all(&photos).unwrap();
+        let update = index
+            .update_subtree(&photos)
+            .expect("Should update index correctly");
+        assert_eq!(update.deleted.len(), 2);
+        assert_eq!(index.count_files(), 0);
+
+        let update = index
+            .update_subtree(&notes)
+            .expect("Should update index correctly");
+        assert_eq!(update.added.len(), 1);
+
+        assert!(index.update_subtree(&path.join(".ark")).is_err());
+        assert!(index.update_subtree(Path::new("/")).is_err());
     }
 
     #[test]
@@ -1225,7 +2959,11 @@ mod tests {
 
         let mut missing_path = path.clone();
         missing_path.push("missing/directory");
-        let actual = discover_files(missing_path);
+        let actual = discover_files(
+            &missing_path,
+            &missing_path,
+            &IndexOptions::default(),
+        );
 
         assert_eq!(actual.len(), 0);
     }
@@ -1241,7 +2979,8 @@ mod tests {
         let (_, file2_path) =
             create_file_at(path.clone(), Some(FILE_SIZE_2), None);
 
-        let discovered_files = discover_files(path.clone());
+        let discovered_files =
+            discover_files(&path, &path, &IndexOptions::default());
 
         let canonical_file1_path =
             fs::canonicalize(&file1_path).expect("Failed to canonicalize path");
@@ -1277,6 +3016,104 @@ mod tests {
         assert_eq!(actual.count_files(), 1);
     }
 
+    #[test]
+    fn index_entries_are_borrowed() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let temp_dir = temp_dir.into_path();
+
+        let (_, path) = create_file_at(
+            temp_dir.to_owned(),
+            Some(FILE_SIZE_1),
+            Some(FILE_NAME_1),
+        );
+        let index = ResourceIndex::build(temp_dir.to_owned());
+        let path = fs::canonicalize(path).unwrap();
+        let id = ResourceId {
+            data_size: FILE_SIZE_1,
+            hash: CRC32_1,
+        };
+
+        let entry = index.get(&id).expect("Entry should be indexed");
+        assert_eq!(entry.id, id);
+        assert_eq!(index.get_entry(&path), Some(entry));
+        assert!(index.contains(&id));
+        assert_eq!(
+            index.entries().collect::<Vec<_>>(),
+            vec![(path.as_path(), entry)]
+        );
+        assert!(index
+            .get(&ResourceId {
+                data_size: FILE_SIZE_2,
+                hash: CRC32_2,
+            })
+            .is_none());
+    }
+
+    #[test]
+    fn ids_are_resolved_by_prefixes() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let temp_dir = temp_dir.into_path();
+
+        create_file_at(temp_dir.clone(), Some(FILE_SIZE_1), Some(FILE_NAME_1));
+        create_file_at(temp_dir.clone(), Some(FILE_SIZE_2), Some(FILE_NAME_2));
+        let index = ResourceIndex::build(temp_dir);
+        let id = ResourceId {
+            data_size: FILE_SIZE_1,
+            hash: CRC32_1,
+        };
+
+        assert_eq!(index.resolve_prefix("10-38").unwrap(), id);
+        assert_eq!(index.resolve_prefix(&id.to_string()).unwrap(), id);
+        assert!(matches!(
+            index.resolve_prefix("1"),
+            Err(ArklibError::Collision(_))
+        ));
+        assert!(matches!(
+            index.resolve_prefix("12"),
+            Err(ArklibError::Path(_))
+        ));
+        assert!(index.resolve_prefix("").is_err());
+    }
+
+    #[test]
+    fn unchanged_files_are_not_hashed_again() {
+        let temp_dir = TempDir::new("arklib_test")
+            .expect("Failed to create temporary directory");
+        let temp_dir = temp_dir.into_path();
+
+        let path = temp_dir.join(FILE_NAME_1);
+        std::fs::write(&path, "first").unwrap();
+        let index = ResourceIndex::build(temp_dir.to_owned());
+        index.store().unwrap();
+        let original = *index.ids().next().unwrap();
+
+        // same size and modification time, but different content
+        let modified = std::fs::metadata(&path)
+            .unwrap()
+            .modified()
+            .unwrap();
+        std::fs::write(&path, "other").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let cached = ResourceIndex::build(temp_dir.to_owned());
+        assert_eq!(cached.ids().collect::<Vec<_>>(), vec![&original]);
+
+        let options = IndexOptions {
+            use_id_cache: false,
+            ..Default::default()
+        };
+        let rehashed =
+            ResourceIndex::build_with_options(temp_dir.to_owned(), options);
+        assert_ne!(rehashed.ids().next().unwrap(), &original);
+    }
+
     #[test]
     fn index_entry_order() {
         let old1 = IndexEntry {
@@ -1285,6 +3122,8 @@ mod tests {
                 hash: 2,
             },
             modified: SystemTime::UNIX_EPOCH,
+            sampled: false,
+            remote: false,
         };
         let old2 = IndexEntry {
             id: ResourceId {
@@ -1292,6 +3131,8 @@ mod tests {
                 hash: 1,
             },
             modified: SystemTime::UNIX_EPOCH,
+            sampled: false,
+            remote: false,
         };
 
         let new1 = IndexEntry {
@@ -1300,6 +3141,8 @@ mod tests {
                 hash: 1,
             },
             modified: SystemTime::now(),
+            sampled: false,
+            remote: false,
         };
         let new2 = IndexEntry {
             id: ResourceId {
@@ -1307,6 +3150,8 @@ mod tests {
                 hash: 2,
             },
             modified: SystemTime::now(),
+            sampled: false,
+            remote: false,
         };
 
         assert_eq!(new1, new1);
@@ -1350,4 +3195,67 @@ mod tests {
         println!("Number of collisions: {}", index.collisions.len());
         println!("Time taken: {:?}", elapsed_time);
     }
+
+    /// Names with spaces, line breaks, backslashes and non-ASCII letters,
+    /// never hidden and in NFC like all relative paths
+    fn file_name() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9é日 \n\\\\_-][a-zA-Z0-9é日 .\n\\\\_-]{0,15}"
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn index_file_roundtrip(
+            files in proptest::collection::btree_map(
+                proptest::collection::vec(file_name(), 1..3),
+                (1u64..4_000_000_000_000, 1usize..64),
+                1..8,
+            ),
+        ) {
+            let temp_dir = TempDir::new("arklib_test").unwrap();
+            let root = fs::canonicalize(temp_dir.path()).unwrap();
+            for (names, (modified, size)) in files.iter() {
+                let path: PathBuf =
+                    names.iter().fold(root.clone(), |path, name| path.join(name));
+                // a file can't be a folder of another file at the same time
+                if fs::create_dir_all(path.parent().unwrap()).is_err()
+                    || path.is_dir()
+                {
+                    continue;
+                }
+                fs::write(&path, vec![b'x'; *size]).unwrap();
+                File::options()
+                    .write(true)
+                    .open(&path)
+                    .unwrap()
+                    .set_modified(UNIX_EPOCH + Duration::from_millis(*modified))
+                    .unwrap();
+            }
+
+            let index = ResourceIndex::build(&root);
+            index.store().unwrap();
+            let loaded = ResourceIndex::load(&root).unwrap();
+            prop_assert_eq!(&loaded.path2id, &index.path2id);
+            prop_assert_eq!(&loaded.collisions, &index.collisions);
+        }
+
+        #[test]
+        fn corrupted_index_file_never_panics(
+            lines in proptest::collection::vec(".*", 0..8),
+        ) {
+            let temp_dir = TempDir::new("arklib_test").unwrap();
+            fs::write(temp_dir.path().join("a.txt"), "a").unwrap();
+            let index_path = temp_dir.path().join(ARK_FOLDER).join(INDEX_PATH);
+            fs::create_dir_all(index_path.parent().unwrap()).unwrap();
+            fs::write(&index_path, lines.join("\n")).unwrap();
+
+            if let Ok(index) = ResourceIndex::load(temp_dir.path()) {
+                for (path, _) in index.paths() {
+                    prop_assert!(path.starts_with(index.root()));
+                    prop_assert!(path.is_file());
+                }
+            }
+        }
+    }
 }
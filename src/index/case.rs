@@ -0,0 +1,164 @@
+//! Support of case-insensitive filesystems
+//!
+//! On filesystems like FAT on Android or the default APFS on macOS, names
+//! differing only in case denote the same file. Renaming a file this way
+//! must be reported as a move, and the index must never keep two paths
+//! differing only in case, since only one of them exists.
+use std::path::{Path, PathBuf};
+
+use crate::platform::same_file;
+
+/// Whether names differing only in case denote the same file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    /// Probe the filesystem of the root when the index is built or loaded
+    #[default]
+    Detect,
+    Sensitive,
+    Insensitive,
+}
+
+impl CaseSensitivity {
+    pub(crate) fn is_insensitive(self, root: &Path) -> bool {
+        match self {
+            CaseSensitivity::Detect => is_case_insensitive(root),
+            CaseSensitivity::Sensitive => false,
+            CaseSensitivity::Insensitive => true,
+        }
+    }
+}
+
+/// Probes whether the filesystem of `path` is case-insensitive, by looking
+/// up the nearest folder having letters in its name with the case swapped
+///
+/// Paths without letters are assumed to be on a case-sensitive filesystem.
+pub fn is_case_insensitive(path: &Path) -> bool {
+    for folder in path.ancestors() {
+        let name = match folder.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => continue,
+        };
+        let swapped: String = name
+            .chars()
+            .map(|c| match c.is_uppercase() {
+                true => c.to_lowercase().collect::<String>(),
+                false => c.to_uppercase().collect::<String>(),
+            })
+            .collect();
+        if swapped == name {
+            continue;
+        }
+        let insensitive = same_file(folder, &folder.with_file_name(swapped));
+        log::debug!(
+            "{} is on a case-{} filesystem",
+            path.display(),
+            if insensitive {
+                "insensitive"
+            } else {
+                "sensitive"
+            }
+        );
+        return insensitive;
+    }
+    false
+}
+
+/// Key equal for paths differing only in case
+pub(crate) fn fold(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Finds a path among `paths` differing from `path` only in case
+pub(crate) fn find_variant<'a>(
+    paths: impl Iterator<Item = &'a PathBuf>,
+    path: &Path,
+) -> Option<PathBuf> {
+    let folded = fold(path);
+    paths
+        .filter(|other| other.as_path() != path)
+        .find(|other| fold(other) == folded)
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{IndexOptions, ResourceIndex};
+    use std::fs::{self, File};
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempdir::TempDir;
+
+    fn insensitive() -> IndexOptions {
+        IndexOptions {
+            case_sensitivity: CaseSensitivity::Insensitive,
+            ..IndexOptions::default()
+        }
+    }
+
+    fn write(path: &Path, content: &str, modified: u64) {
+        fs::write(path, content).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(modified))
+            .unwrap();
+    }
+
+    #[test]
+    fn case_sensitive_filesystem_is_detected() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        assert!(!is_case_insensitive(dir.path()));
+    }
+
+    #[test]
+    fn case_only_renames_are_moves() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        // same size and modification time, so that
+        // only the names tell the files apart
+        write(&root.join("a.txt"), "first", 1_000_000);
+        write(&root.join("b.txt"), "other", 1_000_000);
+        let mut index = ResourceIndex::build_with_options(&root, insensitive());
+        let a = index.get_entry(root.join("a.txt")).unwrap().id;
+        let b = index.get_entry(root.join("b.txt")).unwrap().id;
+
+        fs::rename(root.join("a.txt"), root.join("A.txt")).unwrap();
+        fs::rename(root.join("b.txt"), root.join("B.txt")).unwrap();
+        let update = index.update_all().unwrap();
+        assert!(update.added.is_empty());
+        assert!(update.deleted.is_empty());
+        assert_eq!(update.moved[&a], (root.join("a.txt"), root.join("A.txt")));
+        assert_eq!(update.moved[&b], (root.join("b.txt"), root.join("B.txt")));
+
+        // renamed and modified at once
+        fs::remove_file(root.join("A.txt")).unwrap();
+        write(&root.join("a.TXT"), "modified", 2_000_000);
+        let update = index.update_all().unwrap();
+        assert!(update.moved.contains_key(&a));
+        assert_eq!(update.modified[&root.join("a.TXT")].0, a);
+        assert_eq!(index.count_files(), 2);
+    }
+
+    #[test]
+    fn case_variants_are_never_indexed_twice() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("photo.jpg"), "cat").unwrap();
+        let mut index = ResourceIndex::build_with_options(&root, insensitive());
+        let id = index
+            .get_entry(root.join("photo.jpg"))
+            .unwrap()
+            .id;
+
+        fs::rename(root.join("photo.jpg"), root.join("Photo.jpg")).unwrap();
+        let update = index.index_new(&root.join("Photo.jpg")).unwrap();
+        assert!(update.added.is_empty());
+        assert_eq!(
+            update.moved[&id],
+            (root.join("photo.jpg"), root.join("Photo.jpg"))
+        );
+        assert_eq!(index.count_files(), 1);
+        assert_eq!(index.get_path(&id), Some(root.join("Photo.jpg").as_path()));
+    }
+}
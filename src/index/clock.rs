@@ -0,0 +1,259 @@
+//! Source of times the index compares modification times with
+//!
+//! An unchanged modification time proves an unchanged file only if the
+//! file was last written longer than the timestamp granularity ago, which
+//! is 2 seconds on FAT. Files modified more recently are hashed again by
+//! updates. Devices with skewed clocks and restored backups can also
+//! move modification times backwards, which is a modification as well.
+//! [`ManualClock`] lets tests simulate all of this deterministically.
+use std::fmt::Debug;
+use std::fs::Metadata;
+use std::io;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::RESOURCE_UPDATED_THRESHOLD;
+
+lazy_static! {
+    static ref SYSTEM_CLOCK: Arc<dyn Clock> = Arc::new(SystemClock::default());
+}
+
+/// Time source of [`super::ResourceIndex`], see [`IndexOptions::clock`]
+///
+/// [`IndexOptions::clock`]: super::IndexOptions::clock
+pub trait Clock: Send + Sync + Debug {
+    /// Current time
+    fn now(&self) -> SystemTime;
+
+    /// Precision of modification times of files
+    fn granularity(&self) -> Duration;
+
+    /// Modification time of the file as recorded by the index, which
+    /// keeps milliseconds only to be compatible with all filesystems
+    fn modified(&self, metadata: &Metadata) -> io::Result<SystemTime> {
+        Ok(truncate(metadata.modified()?, RESOURCE_UPDATED_THRESHOLD))
+    }
+}
+
+/// Clock of the OS, the default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SystemClock {
+    granularity: Duration,
+}
+
+impl SystemClock {
+    /// Clock of a filesystem keeping modification times with the precision,
+    /// see [`crate::probe::FsCapabilities::mtime_granularity`]
+    pub fn new(granularity: Duration) -> Self {
+        SystemClock {
+            granularity: granularity.max(RESOURCE_UPDATED_THRESHOLD),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new(RESOURCE_UPDATED_THRESHOLD)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn granularity(&self) -> Duration {
+        self.granularity
+    }
+}
+
+/// Clock set explicitly, which can also shift modification times of files
+/// and make them coarser
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+    skew_millis: AtomicI64,
+    granularity: Duration,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        ManualClock {
+            now: Mutex::new(now),
+            skew_millis: AtomicI64::new(0),
+            granularity: RESOURCE_UPDATED_THRESHOLD,
+        }
+    }
+
+    /// Truncates modification times of files to the precision
+    pub fn with_granularity(self, granularity: Duration) -> Self {
+        ManualClock {
+            granularity: granularity.max(RESOURCE_UPDATED_THRESHOLD),
+            ..self
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now += duration;
+    }
+
+    /// Shifts modification times of files by the milliseconds,
+    /// negative ones move them to the past
+    pub fn set_skew_millis(&self, millis: i64) {
+        self.skew_millis.store(millis, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn granularity(&self) -> Duration {
+        self.granularity
+    }
+
+    fn modified(&self, metadata: &Metadata) -> io::Result<SystemTime> {
+        let modified = metadata.modified()?;
+        let skew = self.skew_millis.load(Ordering::Relaxed);
+        let shift = Duration::from_millis(skew.unsigned_abs());
+        let skewed = match skew < 0 {
+            true => modified.checked_sub(shift),
+            false => modified.checked_add(shift),
+        };
+        Ok(truncate(skewed.unwrap_or(modified), self.granularity))
+    }
+}
+
+/// Clock shared by copies of [`super::IndexOptions`]
+///
+/// Options are equal only with the same clock.
+#[derive(Debug, Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        SharedClock(Arc::new(clock))
+    }
+}
+
+impl<C: Clock + 'static> From<Arc<C>> for SharedClock {
+    fn from(clock: Arc<C>) -> Self {
+        SharedClock(clock)
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock(SYSTEM_CLOCK.clone())
+    }
+}
+
+impl Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl PartialEq for SharedClock {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedClock {}
+
+/// Whether a file with this unchanged modification time may still have
+/// been modified within the same tick of the clock, which is improbable
+/// for times in the future
+pub(super) fn is_racy(clock: &dyn Clock, modified: SystemTime) -> bool {
+    clock
+        .now()
+        .duration_since(modified)
+        .is_ok_and(|age| age < clock.granularity())
+}
+
+/// Truncates the time to a multiple of the granularity, times before
+/// the epoch are treated as the epoch
+pub(super) fn truncate(time: SystemTime, granularity: Duration) -> SystemTime {
+    let since = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let ticks = since.as_nanos() / granularity.as_nanos().max(1);
+    let nanos = ticks * granularity.as_nanos().max(1);
+    UNIX_EPOCH
+        + Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{IndexOptions, ResourceIndex};
+    use std::fs::{self, File};
+    use tempdir::TempDir;
+
+    #[test]
+    fn coarse_and_backward_times_are_modifications() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let path = root.join("a.txt");
+        let written = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let set_file = |content: &str, modified: SystemTime| {
+            fs::write(&path, content).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        set_file("first", written);
+
+        let clock = Arc::new(
+            ManualClock::new(written).with_granularity(Duration::from_secs(2)),
+        );
+        let options = IndexOptions {
+            clock: clock.clone().into(),
+            ..IndexOptions::default()
+        };
+        let mut index = ResourceIndex::build_with_options(&root, options);
+        let entry = index.get_entry(&path).unwrap().clone();
+        assert_eq!(
+            entry.modified,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+
+        // written again within the same tick, so the time is unchanged
+        set_file("again", written + Duration::from_millis(700));
+        let update = index.update_all().unwrap();
+        assert_eq!(update.modified.len(), 1);
+
+        // unchanged times prove nothing only within the tick
+        clock.advance(Duration::from_secs(10));
+        set_file("later", written + Duration::from_millis(900));
+        assert!(index.update_all().unwrap().modified.is_empty());
+
+        // the clock of the writer went backwards
+        set_file("past!", written - Duration::from_secs(60));
+        let update = index.update_all().unwrap();
+        assert_eq!(update.modified.len(), 1);
+
+        clock.set_skew_millis(-60_000);
+        let update = index.update_all().unwrap();
+        assert!(update.modified.is_empty());
+        assert!(index.get_entry(&path).unwrap().modified < entry.modified);
+    }
+}
@@ -0,0 +1,280 @@
+//! Compact read-only representation of the index
+//!
+//! [`ResourceIndex`] keeps two hash maps keyed by absolute paths and IDs,
+//! so every path is stored twice together with its whole prefix. For roots
+//! with hundreds of thousands of files this is too much on phones.
+//! [`CompactIndex`] stores every entry once in an arena, interns folders
+//! so that only file names are stored per entry, and replaces both maps
+//! with sorted tables of handles into the arena.
+use std::ffi::OsStr;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{IndexEntry, ResourceIndex};
+use crate::resource::ResourceId;
+
+/// Handle of an entry in [`CompactIndex`], valid only for the index
+/// which returned it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntryHandle(u32);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CompactEntry {
+    id: ResourceId,
+    /// Milliseconds since the Unix epoch, which is the precision
+    /// of timestamps in the index anyway
+    modified: u64,
+    /// Position of the parent folder in the folder table
+    folder: u32,
+    name: Box<OsStr>,
+    sampled: bool,
+    remote: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactIndex {
+    root: PathBuf,
+    /// Interned folders relative to the root, sorted
+    folders: Vec<Box<Path>>,
+    entries: Vec<CompactEntry>,
+    /// Handles sorted by IDs, colliding entries are adjacent
+    by_id: Vec<u32>,
+    /// Handles sorted by folders and names
+    by_path: Vec<u32>,
+}
+
+impl CompactIndex {
+    /// Returns the root path of the index
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the number of entries in the index
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns handles of all entries, ordered by folders and then by names
+    pub fn handles(&self) -> impl Iterator<Item = EntryHandle> + '_ {
+        self.by_path.iter().map(|&i| EntryHandle(i))
+    }
+
+    /// Finds an entry by ID
+    ///
+    /// In presence of collisions, only one of the entries is returned
+    pub fn find_id(&self, id: &ResourceId) -> Option<EntryHandle> {
+        self.find_ids(id).next()
+    }
+
+    /// Finds all entries with the ID, including colliding ones
+    pub fn find_ids<'a>(
+        &'a self,
+        id: &'a ResourceId,
+    ) -> impl Iterator<Item = EntryHandle> + 'a {
+        let start = self
+            .by_id
+            .partition_point(|&i| self.entries[i as usize].id < *id);
+        self.by_id[start..]
+            .iter()
+            .take_while(move |&&i| self.entries[i as usize].id == *id)
+            .map(|&i| EntryHandle(i))
+    }
+
+    /// Finds an entry by its absolute path
+    pub fn find_path<P: AsRef<Path>>(&self, path: P) -> Option<EntryHandle> {
+        let relative = path.as_ref().strip_prefix(&self.root).ok()?;
+        let name = relative.file_name()?;
+        let folder = relative.parent().unwrap_or(Path::new(""));
+        let folder = self
+            .folders
+            .binary_search_by(|f| f.as_ref().cmp(folder))
+            .ok()? as u32;
+
+        self.by_path
+            .binary_search_by(|&i| {
+                let entry = &self.entries[i as usize];
+                entry
+                    .folder
+                    .cmp(&folder)
+                    .then_with(|| entry.name.as_ref().cmp(name))
+            })
+            .ok()
+            .map(|position| EntryHandle(self.by_path[position]))
+    }
+
+    pub fn id(&self, handle: EntryHandle) -> &ResourceId {
+        &self.entries[handle.0 as usize].id
+    }
+
+    /// Reconstructs the absolute path of the entry
+    pub fn path(&self, handle: EntryHandle) -> PathBuf {
+        let entry = &self.entries[handle.0 as usize];
+        self.root
+            .join(&self.folders[entry.folder as usize])
+            .join(&*entry.name)
+    }
+
+    /// Reconstructs the full entry
+    pub fn entry(&self, handle: EntryHandle) -> IndexEntry {
+        let entry = &self.entries[handle.0 as usize];
+        IndexEntry {
+            id: entry.id,
+            modified: UNIX_EPOCH + Duration::from_millis(entry.modified),
+            sampled: entry.sampled,
+            remote: entry.remote,
+        }
+    }
+
+    /// Estimates the amount of heap memory used by the index
+    pub fn heap_size(&self) -> usize {
+        let folders: usize = self
+            .folders
+            .iter()
+            .map(|folder| folder.as_os_str().len())
+            .sum();
+        let names: usize = self
+            .entries
+            .iter()
+            .map(|entry| entry.name.len())
+            .sum();
+        self.root.as_os_str().len()
+            + self.folders.capacity() * size_of::<Box<Path>>()
+            + folders
+            + self.entries.capacity() * size_of::<CompactEntry>()
+            + names
+            + (self.by_id.capacity() + self.by_path.capacity())
+                * size_of::<u32>()
+    }
+}
+
+impl From<&ResourceIndex> for CompactIndex {
+    fn from(index: &ResourceIndex) -> Self {
+        let root = index.root().to_path_buf();
+
+        let mut pending: Vec<(&Path, &OsStr, &IndexEntry)> = index
+            .path2id
+            .iter()
+            .filter_map(|(path, entry)| {
+                let relative = path.strip_prefix(&root).ok()?;
+                let folder = relative.parent().unwrap_or(Path::new(""));
+                Some((folder, relative.file_name()?, entry))
+            })
+            .collect();
+        pending.sort_unstable_by(|a, b| a.0.cmp(b.0).then(a.1.cmp(b.1)));
+
+        let mut folders: Vec<Box<Path>> = vec![];
+        let mut entries = Vec::with_capacity(pending.len());
+        for (folder, name, entry) in pending {
+            if folders.last().map(|last| last.as_ref()) != Some(folder) {
+                folders.push(folder.into());
+            }
+            let modified = entry
+                .modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            entries.push(CompactEntry {
+                id: entry.id,
+                modified,
+                folder: (folders.len() - 1) as u32,
+                name: name.into(),
+                sampled: entry.sampled,
+                remote: entry.remote,
+            });
+        }
+        folders.shrink_to_fit();
+
+        // entries are already sorted by paths
+        let by_path: Vec<u32> = (0..entries.len() as u32).collect();
+        let mut by_id = by_path.clone();
+        by_id.sort_unstable_by(|&a, &b| {
+            entries[a as usize]
+                .id
+                .cmp(&entries[b as usize].id)
+                .then(a.cmp(&b))
+        });
+
+        CompactIndex {
+            root,
+            folders,
+            entries,
+            by_id,
+            by_path,
+        }
+    }
+}
+
+impl ResourceIndex {
+    /// Creates a compact read-only copy of the index
+    pub fn compact(&self) -> CompactIndex {
+        CompactIndex::from(self)
+    }
+
+    /// Estimates the amount of heap memory used by the index,
+    /// comparable with [`CompactIndex::heap_size`]
+    pub fn heap_size(&self) -> usize {
+        let path_bytes: usize = self
+            .path2id
+            .keys()
+            .chain(self.id2path.values())
+            .map(|path| path.as_os_str().len())
+            .sum();
+        self.root.as_os_str().len()
+            + self.path2id.capacity()
+                * (size_of::<PathBuf>() + size_of::<IndexEntry>())
+            + self.id2path.capacity()
+                * (size_of::<ResourceId>() + size_of::<PathBuf>())
+            + self.collisions.capacity()
+                * (size_of::<ResourceId>() + size_of::<usize>())
+            + path_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn compact_index_matches_the_index() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        fs::create_dir_all(dir.path().join("photos/2023")).unwrap();
+        fs::write(dir.path().join("a.txt"), "first").unwrap();
+        fs::write(dir.path().join("photos/b.jpg"), "second").unwrap();
+        fs::write(dir.path().join("photos/2023/c.jpg"), "third").unwrap();
+        fs::write(dir.path().join("photos/2023/d.jpg"), "third").unwrap();
+
+        let index = ResourceIndex::build(dir.path());
+        let compact = index.compact();
+        assert_eq!(compact.len(), 4);
+        assert!(compact.heap_size() < index.heap_size());
+
+        for (path, entry) in &index.path2id {
+            let handle = compact.find_path(path).unwrap();
+            assert_eq!(&compact.path(handle), path);
+            assert_eq!(&compact.entry(handle), entry);
+            assert!(compact.find_ids(&entry.id).any(|h| h == handle));
+        }
+
+        let colliding =
+            index.path2id[&index.root().join("photos/2023/c.jpg")].id;
+        assert_eq!(compact.find_ids(&colliding).count(), 2);
+        assert!(compact.find_id(&colliding).is_some());
+        assert!(compact
+            .find_path(index.root().join("photos/e.jpg"))
+            .is_none());
+
+        let paths: Vec<PathBuf> = compact
+            .handles()
+            .map(|h| compact.path(h))
+            .collect();
+        assert_eq!(paths[0], index.root().join("a.txt"));
+        assert_eq!(paths[1], index.root().join("photos/b.jpg"));
+    }
+}
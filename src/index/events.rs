@@ -0,0 +1,239 @@
+//! Change events reported by external watchers
+//!
+//! Native notifications are unreliable on network and FUSE filesystems,
+//! and desktop apps often run Watchman or fswatch anyway. Such watchers
+//! feed their events into the index through
+//! [`ResourceIndex::apply_fs_event`]. An event only tells which path to
+//! look at, the filesystem decides what has changed, so that late,
+//! repeated or mislabeled events are harmless.
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+
+use super::clock::is_racy;
+use super::{scan_entry, IndexUpdate, ResourceIndex};
+use crate::{ArklibError, Result};
+
+/// Change of a path, which is either absolute or relative to the root
+/// as reported by Watchman
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsEvent {
+    /// A file or a folder was created
+    Created(PathBuf),
+    /// Content or metadata of a file was modified
+    Modified(PathBuf),
+    /// A file or a folder was removed
+    Removed(PathBuf),
+    /// A file or a folder was renamed
+    Renamed { from: PathBuf, to: PathBuf },
+    /// Events under the folder were lost, e.g. because the queue of
+    /// the watcher overflowed, so that it must be scanned again
+    Rescan(PathBuf),
+}
+
+impl ResourceIndex {
+    /// Updates the index with a change reported by an external watcher
+    ///
+    /// Changes of hidden paths are ignored, while paths outside of
+    /// the root are rejected.
+    pub fn apply_fs_event(&mut self, event: FsEvent) -> Result<IndexUpdate> {
+        log::debug!("Applying {:?}", event);
+        match event {
+            FsEvent::Created(path)
+            | FsEvent::Modified(path)
+            | FsEvent::Removed(path) => match self.event_path(&path)? {
+                Some(path) => self.reconcile(&path),
+                None => Ok(IndexUpdate::default()),
+            },
+            FsEvent::Renamed { from, to } => {
+                match (self.event_path(&from)?, self.event_path(&to)?) {
+                    (Some(from), Some(to)) => self.rename(from, to),
+                    // moved into or out of a hidden folder
+                    (Some(path), None) | (None, Some(path)) => {
+                        self.reconcile(&path)
+                    }
+                    (None, None) => Ok(IndexUpdate::default()),
+                }
+            }
+            FsEvent::Rescan(dir) => match self.event_path(&dir)? {
+                Some(dir) if dir == self.root => self.update_all(),
+                Some(dir) => self.update_subtree(&dir),
+                None => Ok(IndexUpdate::default()),
+            },
+        }
+    }
+
+    /// Resolves the path of an event, which may not exist anymore,
+    /// skipping hidden paths
+    fn event_path(&self, path: &Path) -> Result<Option<PathBuf>> {
+        let path = self.root.join(path);
+        let path = fs::canonicalize(&path).unwrap_or_else(|_| {
+            match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) => fs::canonicalize(parent)
+                    .map(|parent| parent.join(name))
+                    .unwrap_or(path),
+                _ => path,
+            }
+        });
+        let relative = path.strip_prefix(&self.root).map_err(|_| {
+            ArklibError::Path(format!(
+                "{} is outside of the root {}",
+                path.display(),
+                self.root.display()
+            ))
+        })?;
+        let hidden = relative.components().any(|part| {
+            part.as_os_str()
+                .to_string_lossy()
+                .starts_with('.')
+        });
+        Ok((!hidden).then_some(path))
+    }
+
+    /// Brings the entries of the path up to date with the filesystem
+    fn reconcile(&mut self, path: &Path) -> Result<IndexUpdate> {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => self.update_subtree(path),
+            Ok(metadata) => self.reconcile_file(path, metadata),
+            Err(_) => match self.path2id.get(path) {
+                Some(entry) => {
+                    let id = entry.id;
+                    self.forget_path(path, id)
+                }
+                // a removed folder
+                None if self.path2id.keys().any(|p| p.starts_with(path)) => {
+                    self.update_subtree(path)
+                }
+                None => Ok(IndexUpdate::default()),
+            },
+        }
+    }
+
+    fn reconcile_file(
+        &mut self,
+        path: &Path,
+        metadata: Metadata,
+    ) -> Result<IndexUpdate> {
+        let entry = match self.path2id.get(path) {
+            Some(entry) => entry.clone(),
+            None if metadata.len() == 0 => return Ok(IndexUpdate::default()),
+            None => return self.index_new(&path),
+        };
+        let modified = self.options.clock.modified(&metadata)?;
+        if metadata.len() == entry.id.data_size
+            && modified == entry.modified
+            && !is_racy(&*self.options.clock, modified)
+        {
+            return Ok(IndexUpdate::default());
+        }
+        match scan_entry(path, metadata, &self.options) {
+            Ok(new_entry) if new_entry.id == entry.id => {
+                // replaced with the same content
+                self.path2id.insert(path.to_path_buf(), new_entry);
+                Ok(IndexUpdate::default())
+            }
+            Ok(new_entry) => {
+                self.replace_entry(path.to_path_buf(), entry.id, new_entry)
+            }
+            // emptied files aren't resources
+            Err(_) => self.forget_path(path, entry.id),
+        }
+    }
+
+    fn rename(&mut self, from: PathBuf, to: PathBuf) -> Result<IndexUpdate> {
+        // a moved file keeps its size and modification time,
+        // so it doesn't need to be hashed again
+        if let (Some(entry), Ok(metadata), false) = (
+            self.path2id.get(&from).cloned(),
+            fs::metadata(&to),
+            from.exists() || self.path2id.contains_key(&to),
+        ) {
+            if metadata.is_file()
+                && metadata.len() == entry.id.data_size
+                && self.options.clock.modified(&metadata)? == entry.modified
+            {
+                return Ok(self.move_entry(from, to, entry));
+            }
+        }
+
+        if to.is_dir() {
+            // moves are detected only within the scanned folder
+            let mut scope = from.as_path();
+            while !to.starts_with(scope) {
+                scope = scope.parent().unwrap_or(&self.root);
+            }
+            let scope = scope.to_path_buf();
+            return match scope == self.root {
+                true => self.update_all(),
+                false => self.update_subtree(&scope),
+            };
+        }
+        let mut update = self.reconcile(&from)?;
+        let added = self.reconcile(&to)?;
+        update.deleted.extend(added.deleted);
+        update.added.extend(added.added);
+        update.modified.extend(added.modified);
+        update.moved.extend(added.moved);
+        Ok(update)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn events_are_applied() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.txt"), "first").unwrap();
+        let mut index = ResourceIndex::build(&root);
+        let id = index
+            .get_entry(root.join("docs/a.txt"))
+            .unwrap()
+            .id;
+
+        fs::write(root.join("b.txt"), "second").unwrap();
+        let update = index
+            .apply_fs_event(FsEvent::Created("b.txt".into()))
+            .unwrap();
+        assert_eq!(update.added.len(), 1);
+        assert!(update.added.contains_key(&root.join("b.txt")));
+        // repeated events don't change anything
+        let update = index
+            .apply_fs_event(FsEvent::Modified(root.join("b.txt")))
+            .unwrap();
+        assert_eq!(update, IndexUpdate::default());
+
+        fs::rename(root.join("docs/a.txt"), root.join("c.txt")).unwrap();
+        let update = index
+            .apply_fs_event(FsEvent::Renamed {
+                from: root.join("docs/a.txt"),
+                to: root.join("c.txt"),
+            })
+            .unwrap();
+        assert_eq!(
+            update.moved.get(&id),
+            Some(&(root.join("docs/a.txt"), root.join("c.txt")))
+        );
+
+        // a removal reported as modification
+        fs::remove_file(root.join("c.txt")).unwrap();
+        let update = index
+            .apply_fs_event(FsEvent::Modified(root.join("c.txt")))
+            .unwrap();
+        assert!(update.deleted.contains(&id));
+
+        fs::create_dir(root.join(".ark")).unwrap();
+        fs::write(root.join(".ark/index"), "hidden").unwrap();
+        let update = index
+            .apply_fs_event(FsEvent::Created(root.join(".ark/index")))
+            .unwrap();
+        assert_eq!(update, IndexUpdate::default());
+        assert!(index
+            .apply_fs_event(FsEvent::Rescan("/".into()))
+            .is_err());
+        assert_eq!(index.count_files(), 1);
+    }
+}
@@ -0,0 +1,99 @@
+//! Folder-level aggregation of the index, so that folder views
+//! don't need to walk the filesystem
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::ResourceIndex;
+
+/// Totals of all resources nested into a folder, at any depth
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FolderSummary {
+    pub file_count: usize,
+    pub total_size: u64,
+    /// Modification time of the most recently modified resource,
+    /// absent for folders without resources
+    pub newest_modified: Option<SystemTime>,
+}
+
+impl ResourceIndex {
+    /// Returns all folders containing indexed resources at any depth,
+    /// including the root, sorted by paths
+    pub fn folders(&self) -> BTreeSet<&Path> {
+        let mut folders = BTreeSet::new();
+        for path in self.path2id.keys() {
+            for folder in path.ancestors().skip(1) {
+                if !folder.starts_with(&self.root) || !folders.insert(folder) {
+                    break;
+                }
+            }
+        }
+        folders
+    }
+
+    /// Summarizes resources nested into the folder at any depth
+    ///
+    /// The summary is empty for folders outside of the root
+    /// or without indexed resources.
+    pub fn folder_summary<P: AsRef<Path>>(&self, path: P) -> FolderSummary {
+        let folder = path.as_ref();
+        self.path2id
+            .iter()
+            .filter(|(path, _)| path.starts_with(folder) && *path != folder)
+            .fold(FolderSummary::default(), |summary, (_, entry)| {
+                FolderSummary {
+                    file_count: summary.file_count + 1,
+                    total_size: summary.total_size + entry.id.data_size,
+                    newest_modified: summary
+                        .newest_modified
+                        .max(Some(entry.modified)),
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn folders_are_summarized() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        fs::create_dir_all(dir.path().join("photos/2023")).unwrap();
+        fs::create_dir_all(dir.path().join("empty")).unwrap();
+        fs::write(dir.path().join("a.txt"), "first").unwrap();
+        fs::write(dir.path().join("photos/b.jpg"), "second").unwrap();
+        fs::write(dir.path().join("photos/2023/c.jpg"), "third").unwrap();
+
+        let index = ResourceIndex::build(dir.path());
+        let root = index.root();
+        let folders: Vec<&Path> = index.folders().into_iter().collect();
+        assert_eq!(
+            folders,
+            vec![
+                root,
+                root.join("photos").as_path(),
+                root.join("photos/2023").as_path()
+            ]
+        );
+
+        let photos = index.folder_summary(root.join("photos"));
+        assert_eq!(photos.file_count, 2);
+        assert_eq!(photos.total_size, 11);
+        assert_eq!(
+            photos.newest_modified,
+            index
+                .entries()
+                .filter(|(path, _)| path.starts_with(root.join("photos")))
+                .map(|(_, entry)| entry.modified)
+                .max()
+        );
+        assert_eq!(index.folder_summary(root).file_count, 3);
+        assert_eq!(
+            index.folder_summary(root.join("empty")),
+            FolderSummary::default()
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Persistent cache of computed IDs
+//!
+//! The cache maps relative paths of files to their IDs together with sizes
+//! and modification times at the moment of hashing. A file which size and
+//! modification time haven't changed since is not hashed again, so building
+//! the index of a large unchanged folder from scratch is near-instant.
+//! The cache is generated data, it is silently discarded if malformed.
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::{IndexEntry, RelativePath};
+use crate::resource::ResourceId;
+use crate::util::fs::write_file;
+use crate::{Result, ARK_FOLDER, ID_CACHE_FILE};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedId {
+    path: RelativePath,
+    size: u64,
+    /// Milliseconds since the Unix epoch
+    modified: u64,
+    id: ResourceId,
+    #[serde(default)]
+    sampled: bool,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct IdCache {
+    ids: HashMap<RelativePath, CachedId>,
+}
+
+impl IdCache {
+    /// Loads the cache of the root, which is empty if absent or malformed
+    pub(crate) fn load(root: &Path) -> Self {
+        let path = root.join(ARK_FOLDER).join(ID_CACHE_FILE);
+        let ids: Vec<CachedId> = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                log::warn!("Discarding malformed id cache: {}", e);
+                vec![]
+            }),
+            Err(_) => vec![],
+        };
+        log::debug!("Loaded {} cached ids", ids.len());
+        IdCache {
+            ids: ids
+                .into_iter()
+                .map(|cached| (cached.path.clone(), cached))
+                .collect(),
+        }
+    }
+
+    /// Replaces the cache of the root with the given entries
+    pub(crate) fn store<'a>(
+        root: &Path,
+        entries: impl Iterator<Item = (&'a Path, &'a IndexEntry)>,
+    ) -> Result<()> {
+        let mut ids = vec![];
+        // IDs of placeholders are not worth caching
+        for (path, entry) in entries.filter(|(_, entry)| !entry.remote) {
+            ids.push(CachedId {
+                path: RelativePath::new(root, path)?,
+                size: entry.id.data_size,
+                modified: millis(entry),
+                id: entry.id,
+                sampled: entry.sampled,
+            });
+        }
+        ids.sort_by(|a, b| a.path.cmp(&b.path));
+        write_file(
+            &root.join(ARK_FOLDER).join(ID_CACHE_FILE),
+            &serde_json::to_vec(&ids)?,
+        )
+    }
+
+    /// Returns the cached entry of the file if it hasn't changed since
+    /// hashing, `sampled` tells which kind of ID is expected
+    pub(crate) fn lookup(
+        &self,
+        root: &Path,
+        path: &Path,
+        metadata: &Metadata,
+        sampled: bool,
+    ) -> Option<IndexEntry> {
+        let cached = self
+            .ids
+            .get(&RelativePath::new(root, path).ok()?)?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_millis() as u64;
+        if cached.size != metadata.len()
+            || cached.modified != modified
+            || cached.sampled != sampled
+        {
+            return None;
+        }
+        Some(IndexEntry {
+            id: cached.id,
+            modified: UNIX_EPOCH + Duration::from_millis(cached.modified),
+            sampled: cached.sampled,
+            remote: false,
+        })
+    }
+}
+
+fn millis(entry: &IndexEntry) -> u64 {
+    entry
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,105 @@
+//! Mirror of computed IDs in the `user.ark.id` extended attribute
+//!
+//! The attribute holds the modification time of the file at the moment
+//! of hashing together with the ID, so that stale IDs of modified files
+//! are never used. Filesystems and platforms without extended attributes
+//! are silently ignored.
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{IndexEntry, SAMPLED_ID_PREFIX};
+use crate::platform::{get_xattr, set_xattr};
+use crate::resource::ResourceId;
+
+pub const ID_ATTRIBUTE: &str = "user.ark.id";
+
+/// Reads the ID of the file if it was hashed when the file had
+/// the given modification time, and with the expected sampling
+pub(crate) fn read(
+    path: &Path,
+    modified: u64,
+    sampled: bool,
+) -> Option<IndexEntry> {
+    let value = get(path)?;
+    let (time, id) = value.split_once(' ')?;
+    if time.parse::<u64>().ok()? != modified {
+        return None;
+    }
+    let (id, is_sampled) = match id.strip_prefix(SAMPLED_ID_PREFIX) {
+        Some(id) => (id, true),
+        None => (id, false),
+    };
+    if is_sampled != sampled {
+        return None;
+    }
+    Some(IndexEntry {
+        id: ResourceId::from_str(id).ok()?,
+        modified: UNIX_EPOCH + Duration::from_millis(modified),
+        sampled,
+        remote: false,
+    })
+}
+
+/// Stores the ID of the file, failures are only logged
+pub(crate) fn write(path: &Path, entry: &IndexEntry) {
+    let modified = entry
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let prefix = if entry.sampled {
+        SAMPLED_ID_PREFIX
+    } else {
+        ""
+    };
+    let value = format!("{} {}{}", modified, prefix, entry.id);
+    set(path, &value);
+}
+
+fn get(path: &Path) -> Option<String> {
+    String::from_utf8(get_xattr(path, ID_ATTRIBUTE)?).ok()
+}
+
+fn set(path: &Path, value: &str) {
+    if let Err(e) = set_xattr(path, ID_ATTRIBUTE, value.as_bytes()) {
+        log::debug!(
+            "Couldn't set {} of {}: {}",
+            ID_ATTRIBUTE,
+            path.display(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn id_is_mirrored_until_modified() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "first").unwrap();
+        let entry = IndexEntry {
+            id: ResourceId {
+                data_size: 5,
+                hash: 1,
+            },
+            modified: UNIX_EPOCH + Duration::from_millis(1000),
+            sampled: false,
+            remote: false,
+        };
+
+        write(&path, &entry);
+        if get(&path).is_none() {
+            // extended attributes are not supported here
+            return;
+        }
+        assert_eq!(read(&path, 1000, false), Some(entry));
+        assert_eq!(read(&path, 1001, false), None);
+        assert_eq!(read(&path, 1000, true), None);
+    }
+}
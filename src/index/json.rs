@@ -0,0 +1,252 @@
+//! Stable JSON representations of updates and metrics of the index
+//!
+//! Collections are written sorted, so that the same update is always
+//! written the same way, and every representation carries the version
+//! of its schema. Representations of newer versions are rejected instead
+//! of being misread.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Durations, IndexMetrics, IndexUpdate};
+use crate::resource::ResourceId;
+
+/// Version of the JSON schema of updates, metrics and query results
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+pub(crate) fn check_version<E: Error>(version: u32) -> Result<(), E> {
+    match version <= JSON_SCHEMA_VERSION {
+        true => Ok(()),
+        false => Err(E::custom(format!(
+            "unsupported schema version {}, expected at most {}",
+            version, JSON_SCHEMA_VERSION
+        ))),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Added {
+    path: PathBuf,
+    id: ResourceId,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Modified {
+    path: PathBuf,
+    old_id: ResourceId,
+    new_id: ResourceId,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Moved {
+    id: ResourceId,
+    from: PathBuf,
+    to: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UpdateRepr {
+    version: u32,
+    deleted: Vec<ResourceId>,
+    added: Vec<Added>,
+    modified: Vec<Modified>,
+    moved: Vec<Moved>,
+}
+
+impl Serialize for IndexUpdate {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut deleted: Vec<ResourceId> =
+            self.deleted.iter().copied().collect();
+        deleted.sort();
+        let mut added: Vec<Added> = self
+            .added
+            .iter()
+            .map(|(path, id)| Added {
+                path: path.clone(),
+                id: *id,
+            })
+            .collect();
+        added.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut modified: Vec<Modified> = self
+            .modified
+            .iter()
+            .map(|(path, (old_id, new_id))| Modified {
+                path: path.clone(),
+                old_id: *old_id,
+                new_id: *new_id,
+            })
+            .collect();
+        modified.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut moved: Vec<Moved> = self
+            .moved
+            .iter()
+            .map(|(id, (from, to))| Moved {
+                id: *id,
+                from: from.clone(),
+                to: to.clone(),
+            })
+            .collect();
+        moved.sort_by(|a, b| a.from.cmp(&b.from));
+
+        UpdateRepr {
+            version: JSON_SCHEMA_VERSION,
+            deleted,
+            added,
+            modified,
+            moved,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for IndexUpdate {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let repr = UpdateRepr::deserialize(deserializer)?;
+        check_version(repr.version)?;
+        Ok(IndexUpdate {
+            deleted: repr.deleted.into_iter().collect(),
+            added: repr
+                .added
+                .into_iter()
+                .map(|added| (added.path, added.id))
+                .collect(),
+            modified: repr
+                .modified
+                .into_iter()
+                .map(|modified| {
+                    (modified.path, (modified.old_id, modified.new_id))
+                })
+                .collect(),
+            moved: repr
+                .moved
+                .into_iter()
+                .map(|moved| (moved.id, (moved.from, moved.to)))
+                .collect(),
+        })
+    }
+}
+
+/// Durations are written in milliseconds
+#[derive(Serialize, Deserialize)]
+struct MetricsRepr {
+    version: u32,
+    files: usize,
+    resources: usize,
+    collisions: usize,
+    colliding_paths: usize,
+    sampled: usize,
+    heap_size: usize,
+    build_millis: Option<u64>,
+    load_millis: Option<u64>,
+    update_millis: Option<u64>,
+    store_millis: Option<u64>,
+}
+
+impl Serialize for IndexMetrics {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let millis = |duration: Option<Duration>| {
+            duration.map(|duration| duration.as_millis() as u64)
+        };
+        MetricsRepr {
+            version: JSON_SCHEMA_VERSION,
+            files: self.files,
+            resources: self.resources,
+            collisions: self.collisions,
+            colliding_paths: self.colliding_paths,
+            sampled: self.sampled,
+            heap_size: self.heap_size,
+            build_millis: millis(self.durations.build),
+            load_millis: millis(self.durations.load),
+            update_millis: millis(self.durations.update),
+            store_millis: millis(self.durations.store),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for IndexMetrics {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let repr = MetricsRepr::deserialize(deserializer)?;
+        check_version(repr.version)?;
+        let duration = |millis: Option<u64>| millis.map(Duration::from_millis);
+        Ok(IndexMetrics {
+            files: repr.files,
+            resources: repr.resources,
+            collisions: repr.collisions,
+            colliding_paths: repr.colliding_paths,
+            sampled: repr.sampled,
+            heap_size: repr.heap_size,
+            durations: Durations {
+                build: duration(repr.build_millis),
+                load: duration(repr.load_millis),
+                update: duration(repr.update_millis),
+                store: duration(repr.store_millis),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+
+    #[test]
+    fn updates_are_written_stably() {
+        let a = ResourceId::compute_bytes(b"a").unwrap();
+        let b = ResourceId::compute_bytes(b"b").unwrap();
+        let mut update = IndexUpdate::default();
+        update.deleted.insert(b);
+        update.deleted.insert(a);
+        update
+            .added
+            .insert(PathBuf::from("/root/z.txt"), a);
+        update
+            .added
+            .insert(PathBuf::from("/root/a.txt"), b);
+        update.moved.insert(
+            a,
+            (PathBuf::from("/root/x.txt"), PathBuf::from("/root/y.txt")),
+        );
+
+        let json = serde_json::to_value(&update).unwrap();
+        assert_eq!(json["version"], JSON_SCHEMA_VERSION);
+        assert_eq!(json["added"][0]["path"], "/root/a.txt");
+        assert_eq!(json["moved"][0]["to"], "/root/y.txt");
+        let text = serde_json::to_string(&update).unwrap();
+        assert_eq!(serde_json::to_string(&update).unwrap(), text);
+        let parsed: IndexUpdate = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, update);
+
+        let mut newer = json.clone();
+        newer["version"] = (JSON_SCHEMA_VERSION + 1).into();
+        assert!(serde_json::from_value::<IndexUpdate>(newer).is_err());
+
+        let metrics = IndexMetrics {
+            files: 2,
+            durations: Durations {
+                build: Some(Duration::from_millis(15)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let json = serde_json::to_value(metrics).unwrap();
+        assert_eq!(json["build_millis"], 15);
+        assert_eq!(
+            serde_json::from_value::<IndexMetrics>(json).unwrap(),
+            metrics
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Lookups in the stored index without loading it
+//!
+//! Loading the index of a large root parses and canonicalizes every line
+//! of the index file, which dominates the startup of apps needing just
+//! a few paths, e.g. to open a shared resource. [`LazyIndex`] parses only
+//! the header when opened and answers lookups by streaming the file,
+//! resolving only the lines of queried IDs. Answers are remembered, so
+//! repeated lookups don't read the file again.
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::lock::IndexLock;
+use super::{
+    parse_header, parse_line, resolve_path, IndexOptions, ResourceIndex,
+    INDEX_FORMAT_VERSION, REMOTE_ID_PREFIX, SAMPLED_ID_PREFIX,
+};
+use crate::resource::ResourceId;
+use crate::{ArklibError, Result, ARK_FOLDER, INDEX_PATH};
+
+/// Stored index read on demand, see [`ResourceIndex::load_lazy`]
+#[derive(Debug)]
+pub struct LazyIndex {
+    root: PathBuf,
+    options: IndexOptions,
+    version: u32,
+    /// Whether the first line is the header, which files written before
+    /// the header was introduced lack
+    header: bool,
+    /// Paths of IDs looked up before, empty for missing IDs
+    found: HashMap<ResourceId, Vec<PathBuf>>,
+}
+
+impl ResourceIndex {
+    /// Opens the stored index of the root for a few lookups, reading only
+    /// the header of the index file
+    ///
+    /// Like [`ResourceIndex::load`], lookups reflect the stored index,
+    /// which can be outdated.
+    pub fn load_lazy<P: AsRef<Path>>(root_path: P) -> Result<LazyIndex> {
+        let root = fs::canonicalize(root_path)?;
+        let options = IndexOptions::default();
+        let index_path = root.join(ARK_FOLDER).join(INDEX_PATH);
+        if index_path.is_dir() {
+            return Err(ArklibError::Path(format!(
+                "{} has the legacy layout, it must be migrated first",
+                root.display()
+            )));
+        }
+        let (version, header) = {
+            let _lock = IndexLock::shared(&root, options.lock_timeout)?;
+            let mut header = String::new();
+            BufReader::new(File::open(&index_path)?).read_line(&mut header)?;
+            match parse_header(header.trim_end()) {
+                Ok(Some(version)) => (version, true),
+                Ok(None) => (1, false),
+                // a damaged header is taken for the current one
+                Err(ArklibError::Parse) => (INDEX_FORMAT_VERSION, true),
+                Err(e) => return Err(e),
+            }
+        };
+        Ok(LazyIndex {
+            root,
+            options,
+            version,
+            header,
+            found: HashMap::new(),
+        })
+    }
+}
+
+impl LazyIndex {
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn contains(&mut self, id: &ResourceId) -> Result<bool> {
+        Ok(self.get_path(id)?.is_some())
+    }
+
+    /// Returns the path of the resource, the smallest one of colliding
+    /// resources like [`ResourceIndex::get_path`]
+    pub fn get_path(&mut self, id: &ResourceId) -> Result<Option<PathBuf>> {
+        self.lookup([*id])?;
+        Ok(self
+            .found
+            .get(id)
+            .and_then(|paths| paths.iter().min())
+            .cloned())
+    }
+
+    /// Finds paths of all the IDs reading the index file once
+    pub fn lookup(
+        &mut self,
+        ids: impl IntoIterator<Item = ResourceId>,
+    ) -> Result<()> {
+        let wanted: HashSet<ResourceId> = ids
+            .into_iter()
+            .filter(|id| !self.found.contains_key(id))
+            .collect();
+        if wanted.is_empty() {
+            return Ok(());
+        }
+        for id in &wanted {
+            self.found.insert(*id, vec![]);
+        }
+
+        let index_path = self.root.join(ARK_FOLDER).join(INDEX_PATH);
+        let _lock = IndexLock::shared(&self.root, self.options.lock_timeout)?;
+        let reader = BufReader::new(File::open(index_path)?);
+        let skipped = usize::from(self.header);
+        for line in reader.split(b'\n').skip(skipped) {
+            let line = line?;
+            let Ok(line) = std::str::from_utf8(&line) else {
+                continue;
+            };
+            let line = line.trim_end_matches('\r');
+            // only lines of wanted IDs are parsed completely
+            let Some(id) = line
+                .split(' ')
+                .nth(1)
+                .map(|id| {
+                    id.trim_start_matches(SAMPLED_ID_PREFIX)
+                        .trim_start_matches(REMOTE_ID_PREFIX)
+                })
+                .and_then(|id| ResourceId::from_str(id).ok())
+            else {
+                continue;
+            };
+            if !wanted.contains(&id) {
+                continue;
+            }
+            let Ok((_, path)) = parse_line(line, self.version) else {
+                continue;
+            };
+            if let Some(path) = resolve_path(&self.root, &path) {
+                self.found.entry(id).or_default().push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the whole index, e.g. once the app needs more than
+    /// a few lookups
+    pub fn materialize(self) -> Result<ResourceIndex> {
+        ResourceIndex::load_with_options(self.root, self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use tempdir::TempDir;
+
+    #[test]
+    fn lookups_match_the_loaded_index() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("folder")).unwrap();
+        fs::write(root.join("a.txt"), "same").unwrap();
+        fs::write(root.join("folder/b c.txt"), "same").unwrap();
+        fs::write(root.join("d.txt"), "other").unwrap();
+        let index = ResourceIndex::build(&root);
+        index.store().unwrap();
+
+        let mut lazy = ResourceIndex::load_lazy(&root).unwrap();
+        let same = ResourceId::compute_bytes(b"same").unwrap();
+        let other = ResourceId::compute_bytes(b"other").unwrap();
+        let missing = ResourceId::compute_bytes(b"missing").unwrap();
+        lazy.lookup([same, other]).unwrap();
+        assert_eq!(
+            lazy.get_path(&same).unwrap().as_deref(),
+            index.get_path(&same)
+        );
+        assert_eq!(lazy.get_path(&other).unwrap(), Some(root.join("d.txt")));
+        assert!(!lazy.contains(&missing).unwrap());
+
+        assert_eq!(
+            lazy.materialize().unwrap(),
+            ResourceIndex::load(&root).unwrap()
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Symbolic links met while discovering files
+//!
+//! Files are indexed by their canonical paths, so links to files and
+//! folders inside of the root add nothing: their targets are discovered
+//! directly. Links leading outside of the root are skipped by default,
+//! or indexed by their own paths inside of the root with
+//! [`OutsideLinks::Alias`]. Folders are followed at most once, so that
+//! cycles and farms of links to the same folder don't blow up the walk.
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::IndexOptions;
+
+/// Handling of symbolic links pointing outside of the root
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutsideLinks {
+    /// Links are not indexed
+    #[default]
+    Skip,
+    /// Links are indexed by their paths inside of the root, as aliases
+    /// of the files they point to. Links to folders are followed only
+    /// with [`IndexOptions::follow_symlinks`].
+    Alias,
+}
+
+/// Whether the walk must descend into the folder behind a link
+pub(super) fn follow_folder(
+    root: &Path,
+    link: &Path,
+    options: &IndexOptions,
+    visited: &mut HashSet<PathBuf>,
+) -> bool {
+    if !options.follow_symlinks || options.outside_links == OutsideLinks::Skip {
+        return false;
+    }
+    let Ok(target) = fs::canonicalize(link) else {
+        return false;
+    };
+    // folders of the root are walked directly, and following a folder
+    // containing the root leads back to the link
+    if target.starts_with(root) || root.starts_with(&target) {
+        log::debug!("Not following {} into the root", link.display());
+        return false;
+    }
+    if !visited.insert(target) {
+        log::debug!("Folder behind {} was walked before", link.display());
+        return false;
+    }
+    true
+}
+
+/// Returns the path to index the discovered file by, if any
+pub(super) fn file_path(
+    root: &Path,
+    scope: &Path,
+    path: &Path,
+    options: &IndexOptions,
+) -> Option<PathBuf> {
+    let canonical = match fs::canonicalize(path) {
+        Ok(canonical) => canonical,
+        Err(e) => {
+            log::warn!("Couldn't canonicalize {}:\n{}", path.display(), e);
+            return None;
+        }
+    };
+    if canonical.starts_with(scope) {
+        return Some(canonical);
+    }
+    // targets elsewhere in the root are discovered directly
+    if canonical.starts_with(root) {
+        return None;
+    }
+    match options.outside_links {
+        OutsideLinks::Skip => {
+            log::debug!("Skipping {} outside of the root", path.display());
+            None
+        }
+        OutsideLinks::Alias => Some(path.to_path_buf()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::index::ResourceIndex;
+    use std::os::unix::fs::symlink;
+    use tempdir::TempDir;
+
+    #[test]
+    fn link_farms_and_cycles_are_walked_once() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let base = fs::canonicalize(dir.path()).unwrap();
+        let root = base.join("root");
+        let outside = base.join("outside");
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::create_dir_all(root.join("farm")).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(root.join("data/a.txt"), "a").unwrap();
+        fs::write(outside.join("b.txt"), "b").unwrap();
+        for i in 0..20 {
+            symlink(root.join("data"), root.join(format!("farm/{}", i)))
+                .unwrap();
+            symlink(&outside, root.join(format!("farm/out{}", i))).unwrap();
+        }
+        symlink(&root, root.join("data/cycle")).unwrap();
+        symlink(&base, root.join("data/up")).unwrap();
+        symlink(outside.join("b.txt"), root.join("b.txt")).unwrap();
+
+        let skipping = IndexOptions {
+            follow_symlinks: true,
+            ..IndexOptions::default()
+        };
+        let index = ResourceIndex::build_with_options(&root, skipping);
+        let paths: Vec<&Path> = index.entries().map(|(path, _)| path).collect();
+        assert_eq!(paths, vec![root.join("data/a.txt")]);
+
+        let aliasing = IndexOptions {
+            follow_symlinks: true,
+            outside_links: OutsideLinks::Alias,
+            ..IndexOptions::default()
+        };
+        let index = ResourceIndex::build_with_options(&root, aliasing);
+        // the outside folder is followed through one of the links only
+        assert_eq!(index.count_files(), 3);
+        assert!(index.get_entry(root.join("b.txt")).is_some());
+        let id = index.get_entry(root.join("b.txt")).unwrap().id;
+        assert_eq!(index.aliases(&id).len(), 2);
+
+        index.store().unwrap();
+        assert_eq!(ResourceIndex::load(&root).unwrap().count_files(), 3);
+    }
+}
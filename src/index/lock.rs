@@ -0,0 +1,104 @@
+//! Advisory locking of the index file between processes
+//!
+//! Readers take a shared lock and writers take an exclusive one on
+//! `.ark/index.lock`, so several processes sharing a root never observe
+//! a half-written index. The locks are released when dropped.
+use std::fs::{self, File, OpenOptions};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fs2::FileExt;
+
+use crate::platform;
+use crate::{ArklibError, Result, ARK_FOLDER, INDEX_LOCK_FILE};
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+pub(crate) struct IndexLock {
+    file: Option<File>,
+}
+
+impl IndexLock {
+    /// Locks the index for reading, which needs no lock
+    /// if the `.ark` folder doesn't exist yet
+    pub(crate) fn shared(root: &Path, timeout: Duration) -> Result<Self> {
+        let ark = root.join(ARK_FOLDER);
+        if !ark.is_dir() {
+            return Ok(IndexLock { file: None });
+        }
+        Self::acquire(&ark, timeout, false)
+    }
+
+    /// Locks the index for writing, creating the `.ark` folder if needed
+    pub(crate) fn exclusive(root: &Path, timeout: Duration) -> Result<Self> {
+        let ark = root.join(ARK_FOLDER);
+        fs::create_dir_all(&ark)?;
+        Self::acquire(&ark, timeout, true)
+    }
+
+    fn acquire(ark: &Path, timeout: Duration, exclusive: bool) -> Result<Self> {
+        let path = ark.join(INDEX_LOCK_FILE);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let start = Instant::now();
+        loop {
+            if platform::try_lock(&file, exclusive)? {
+                return Ok(IndexLock { file: Some(file) });
+            }
+            if start.elapsed() >= timeout {
+                return Err(ArklibError::Busy(format!(
+                    "{} is locked by another process",
+                    path.display()
+                )));
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            if let Err(e) = FileExt::unlock(file) {
+                log::warn!("Couldn't unlock the index: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn exclusive_lock_excludes_others() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let timeout = Duration::from_millis(50);
+
+        // nothing to lock before the index is stored
+        let reader = IndexLock::shared(dir.path(), timeout).unwrap();
+        assert!(reader.file.is_none());
+
+        let writer = IndexLock::exclusive(dir.path(), timeout).unwrap();
+        assert!(matches!(
+            IndexLock::shared(dir.path(), timeout),
+            Err(ArklibError::Busy(_))
+        ));
+        assert!(matches!(
+            IndexLock::exclusive(dir.path(), timeout),
+            Err(ArklibError::Busy(_))
+        ));
+        drop(writer);
+
+        let _reader1 = IndexLock::shared(dir.path(), timeout).unwrap();
+        let _reader2 = IndexLock::shared(dir.path(), timeout).unwrap();
+        assert!(IndexLock::exclusive(dir.path(), timeout).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Checksum manifests of the root, readable by tools outside of ARK
+//!
+//! Both formats have one `<checksum>  <path>` line per file, with paths
+//! relative to the root. SHA-256 manifests are the ones written by
+//! `sha256sum`, so they can be checked with `sha256sum -c`. ARK manifests
+//! list resource IDs instead and start with [`ARK_MANIFEST_HEADER`],
+//! followed by the name of the algorithm unless it's CRC32. IDs are of
+//! the algorithm configured for the root, see
+//! [`crate::resource::set_id_algorithm`].
+//! Paths with newlines or backslashes are escaped the way GNU coreutils
+//! do: the line starts with a backslash.
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+
+use sha2::{Digest, Sha256};
+
+use super::{RelativePath, ResourceIndex};
+use crate::resource::{
+    load_id_algorithm, IdAlgorithm, ResourceId, ResourceIdTrait,
+};
+use crate::{ArklibError, Result};
+
+pub const ARK_MANIFEST_HEADER: &str = "# ark manifest v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// SHA-256 hashes of file contents, as written by `sha256sum`
+    Sha256Sums,
+    /// Resource IDs of the algorithm of the root, the IDs of the index
+    /// unless another algorithm is configured
+    ArkManifest,
+}
+
+/// Outcome of checking files of the root against a manifest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestReport {
+    pub verified: Vec<RelativePath>,
+    /// Files which content differs from the manifest
+    pub mismatched: Vec<RelativePath>,
+    /// Files listed in the manifest but missing in the root
+    pub missing: Vec<RelativePath>,
+}
+
+impl ManifestReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl ResourceIndex {
+    /// Writes the manifest of all indexed files, sorted by paths
+    ///
+    /// SHA-256 manifests read every file, while ARK manifests are
+    /// written from the index alone unless the root is configured
+    /// to use another algorithm than CRC32.
+    pub fn export_manifest<W: Write>(
+        &self,
+        mut writer: W,
+        format: ManifestFormat,
+    ) -> Result<()> {
+        let algorithm = load_id_algorithm(&self.root)?;
+        if format == ManifestFormat::ArkManifest {
+            writeln!(writer, "{}", manifest_header(algorithm))?;
+        }
+        for (path, entry) in self.entries_with_prefix(&RelativePath::default())
+        {
+            let checksum = match format {
+                ManifestFormat::Sha256Sums => {
+                    sha256(File::open(self.absolute_path(&path))?)?
+                }
+                ManifestFormat::ArkManifest => match algorithm {
+                    IdAlgorithm::Crc32 => entry.id.to_string(),
+                    _ => algorithm.compute(self.absolute_path(&path))?,
+                },
+            };
+            writeln!(writer, "{}", format_line(&checksum, path.as_str()))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Checks files of the root against the manifest, recomputing
+    /// checksums from the current contents of the files
+    ///
+    /// The format is detected by the header of the manifest. Files with
+    /// sampled IDs in the index are verified by sampled IDs.
+    pub fn verify_manifest<R: Read>(
+        &self,
+        reader: R,
+    ) -> Result<ManifestReport> {
+        let mut report = ManifestReport::default();
+        let mut format = ManifestFormat::Sha256Sums;
+        let mut algorithm = IdAlgorithm::Crc32;
+        for (number, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if number == 0 {
+                if let Some(name) = line.strip_prefix(ARK_MANIFEST_HEADER) {
+                    format = ManifestFormat::ArkManifest;
+                    if !name.trim().is_empty() {
+                        algorithm = name.trim().parse()?;
+                    }
+                    continue;
+                }
+            }
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (checksum, path) = parse_line(&line).ok_or_else(|| {
+                ArklibError::Path(format!(
+                    "Malformed manifest line {}: {}",
+                    number + 1,
+                    line
+                ))
+            })?;
+            let path = RelativePath::parse(&path)?;
+            let absolute = self.absolute_path(&path);
+            let file = match File::open(&absolute) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    report.missing.push(path);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let matches = match format {
+                ManifestFormat::Sha256Sums => {
+                    sha256(file)?.eq_ignore_ascii_case(checksum)
+                }
+                ManifestFormat::ArkManifest
+                    if algorithm != IdAlgorithm::Crc32 =>
+                {
+                    algorithm.compute(&absolute)? == checksum
+                }
+                ManifestFormat::ArkManifest => {
+                    let size = file.metadata()?.len();
+                    let id = match self.is_sampled(&absolute) {
+                        true => ResourceId::compute_sampled(size, &absolute)?,
+                        false => ResourceId::compute(size, &absolute)?,
+                    };
+                    self.options.namespaced(id).to_string() == checksum
+                }
+            };
+            match matches {
+                true => report.verified.push(path),
+                false => report.mismatched.push(path),
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn manifest_header(algorithm: IdAlgorithm) -> String {
+    match algorithm {
+        IdAlgorithm::Crc32 => ARK_MANIFEST_HEADER.to_string(),
+        _ => format!("{} {}", ARK_MANIFEST_HEADER, algorithm),
+    }
+}
+
+fn sha256<R: Read>(mut data: R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut data, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+fn format_line(checksum: &str, path: &str) -> String {
+    match path.contains(['\\', '\n', '\r']) {
+        true => {
+            let escaped = path
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r");
+            format!("\\{}  {}", checksum, escaped)
+        }
+        false => format!("{}  {}", checksum, path),
+    }
+}
+
+fn parse_line(line: &str) -> Option<(&str, String)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    // `sha256sum` marks binary mode with `*` instead of the second space
+    let (checksum, path) = line
+        .split_once("  ")
+        .or_else(|| line.split_once(" *"))?;
+    if checksum.is_empty() || path.is_empty() {
+        return None;
+    }
+    if !escaped {
+        return Some((checksum, path.to_string()));
+    }
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, false) => unescaped.push(c),
+            (_, true) => match chars.next()? {
+                'n' => unescaped.push('\n'),
+                'r' => unescaped.push('\r'),
+                '\\' => unescaped.push('\\'),
+                _ => return None,
+            },
+        }
+    }
+    Some((checksum, unescaped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::set_id_algorithm;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn sha256_manifests_are_compatible() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.txt"), "hello\n").unwrap();
+        fs::write(root.join("b.txt"), "world").unwrap();
+        let index = ResourceIndex::build(&root);
+
+        let mut manifest = vec![];
+        index
+            .export_manifest(&mut manifest, ManifestFormat::Sha256Sums)
+            .unwrap();
+        let manifest = String::from_utf8(manifest).unwrap();
+        assert_eq!(
+            manifest.lines().nth(1),
+            Some(
+                "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03  docs/a.txt"
+            )
+        );
+        let report = index
+            .verify_manifest(manifest.as_bytes())
+            .unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.verified.len(), 2);
+
+        fs::write(root.join("b.txt"), "changed").unwrap();
+        fs::remove_file(root.join("docs/a.txt")).unwrap();
+        let report = index
+            .verify_manifest(manifest.as_bytes())
+            .unwrap();
+        assert_eq!(report.mismatched, [RelativePath::parse("b.txt").unwrap()]);
+        assert_eq!(
+            report.missing,
+            [RelativePath::parse("docs/a.txt").unwrap()]
+        );
+    }
+
+    #[test]
+    fn ark_manifests_list_ids() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "hello").unwrap();
+        let index = ResourceIndex::build(&root);
+        let id = index.get_entry(root.join("a.txt")).unwrap().id;
+
+        let mut manifest = vec![];
+        index
+            .export_manifest(&mut manifest, ManifestFormat::ArkManifest)
+            .unwrap();
+        let manifest = String::from_utf8(manifest).unwrap();
+        assert_eq!(
+            manifest,
+            format!("{}\n{}  a.txt\n", ARK_MANIFEST_HEADER, id)
+        );
+        assert!(index
+            .verify_manifest(manifest.as_bytes())
+            .unwrap()
+            .is_ok());
+        assert!(index
+            .verify_manifest(&b"broken line"[..])
+            .is_err());
+    }
+
+    #[test]
+    fn ark_manifests_use_the_algorithm_of_the_root() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "hello").unwrap();
+        set_id_algorithm(&root, IdAlgorithm::Sha256).unwrap();
+        let index = ResourceIndex::build(&root);
+
+        let mut manifest = vec![];
+        index
+            .export_manifest(&mut manifest, ManifestFormat::ArkManifest)
+            .unwrap();
+        let manifest = String::from_utf8(manifest).unwrap();
+        assert_eq!(
+            manifest,
+            format!(
+                "{} sha256\n{}  a.txt\n",
+                ARK_MANIFEST_HEADER,
+                IdAlgorithm::Sha256
+                    .compute_bytes(b"hello")
+                    .unwrap()
+            )
+        );
+        assert!(index
+            .verify_manifest(manifest.as_bytes())
+            .unwrap()
+            .is_ok());
+
+        fs::write(root.join("a.txt"), "world").unwrap();
+        let report = index
+            .verify_manifest(manifest.as_bytes())
+            .unwrap();
+        assert_eq!(report.mismatched.len(), 1);
+    }
+
+    #[test]
+    fn special_paths_are_escaped() {
+        let line = format_line("abc", "a\\b\nc");
+        assert_eq!(line, "\\abc  a\\\\b\\nc");
+        assert_eq!(parse_line(&line), Some(("abc", "a\\b\nc".to_string())));
+        assert_eq!(parse_line("abc *bin"), Some(("abc", "bin".to_string())));
+    }
+}
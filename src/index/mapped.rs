@@ -0,0 +1,401 @@
+//! Binary copy of the index shared by processes through memory mapping
+//!
+//! Every process loading [`ResourceIndex`] parses the index file and keeps
+//! its own maps, so a desktop running the CLI next to the GUI holds the same
+//! index several times. [`ResourceIndex::store_mapped`] writes the index
+//! into `.ark/cache/index.bin` as fixed-size records sorted by IDs, which
+//! [`MappedIndex`] maps read-only: the pages are shared by all processes
+//! through the page cache and lookups are binary searches in place.
+//!
+//! The file records the size and the modification time of the index file
+//! it was written from, and is ignored once the index file changes.
+//!
+//! Layout, all numbers little-endian:
+//! - header: magic, format version, number of entries, size and
+//!   modification time in nanoseconds of the index file
+//! - entries sorted by IDs and paths: data size, hash, flags, modification
+//!   time in milliseconds, offset and length of the path
+//! - positions of entries sorted by paths
+//! - paths relative to the root, with `/` as separator
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use memmap2::Mmap;
+
+use super::lock::IndexLock;
+use super::{IndexEntry, IndexOptions, ResourceIndex};
+use crate::resource::ResourceId;
+use crate::util::fs::{relative_path, write_file};
+use crate::{ArklibError, Result, ARK_FOLDER, INDEX_PATH, MAPPED_INDEX_FILE};
+
+const MAGIC: &[u8; 8] = b"ARKIDXMM";
+const FORMAT_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 32;
+const RECORD_SIZE: usize = 32;
+const SAMPLED_FLAG: u32 = 1;
+const REMOTE_FLAG: u32 = 2;
+
+/// Index memory-mapped from the binary copy of the stored index
+#[derive(Debug)]
+pub struct MappedIndex {
+    root: PathBuf,
+    map: Mmap,
+    len: usize,
+}
+
+/// Size and modification time of the index file, which the binary copy
+/// must have been written from
+fn index_stamp(root: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(root.join(ARK_FOLDER).join(INDEX_PATH))?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    Ok((metadata.len(), modified))
+}
+
+impl ResourceIndex {
+    /// Writes the binary copy of the stored index into
+    /// `$root_path/.ark/cache/index.bin`, see [`MappedIndex`]
+    ///
+    /// The index must be stored first, otherwise the copy is outdated
+    /// right away.
+    pub fn store_mapped(&self) -> Result<()> {
+        let _lock = IndexLock::shared(&self.root, self.options.lock_timeout)?;
+        self.write_mapped()
+    }
+
+    fn write_mapped(&self) -> Result<()> {
+        let (index_size, index_modified) = index_stamp(&self.root)?;
+        let mut entries: Vec<(String, &IndexEntry)> = self
+            .path2id
+            .iter()
+            .map(|(path, entry)| Ok((relative_path(&self.root, path)?, entry)))
+            .collect::<Result<_>>()?;
+        entries.sort_by(|(path1, entry1), (path2, entry2)| {
+            entry1.id.cmp(&entry2.id).then(path1.cmp(path2))
+        });
+        let mut by_path: Vec<u32> = (0..entries.len() as u32).collect();
+        by_path.sort_by_key(|&i| entries[i as usize].0.as_str());
+
+        let mut file =
+            Vec::with_capacity(HEADER_SIZE + entries.len() * (RECORD_SIZE + 4));
+        file.extend_from_slice(MAGIC);
+        file.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        file.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        file.extend_from_slice(&index_size.to_le_bytes());
+        file.extend_from_slice(&index_modified.to_le_bytes());
+
+        let mut offset = 0;
+        for (path, entry) in &entries {
+            let modified = entry
+                .modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            let mut flags = 0;
+            if entry.sampled {
+                flags |= SAMPLED_FLAG;
+            }
+            if entry.remote {
+                flags |= REMOTE_FLAG;
+            }
+            file.extend_from_slice(&entry.id.data_size.to_le_bytes());
+            file.extend_from_slice(&entry.id.hash.to_le_bytes());
+            file.extend_from_slice(&flags.to_le_bytes());
+            file.extend_from_slice(&modified.to_le_bytes());
+            file.extend_from_slice(&(offset as u32).to_le_bytes());
+            file.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            offset += path.len();
+        }
+        for position in by_path {
+            file.extend_from_slice(&position.to_le_bytes());
+        }
+        for (path, _) in &entries {
+            file.extend_from_slice(path.as_bytes());
+        }
+        write_file(&self.root.join(ARK_FOLDER).join(MAPPED_INDEX_FILE), &file)
+    }
+}
+
+impl MappedIndex {
+    /// Maps the binary copy of the index of the root, `None` if there is
+    /// no copy or it is outdated
+    pub fn open<P: AsRef<Path>>(root_path: P) -> Result<Option<Self>> {
+        let root = fs::canonicalize(root_path)?;
+        let Ok(file) =
+            File::open(root.join(ARK_FOLDER).join(MAPPED_INDEX_FILE))
+        else {
+            return Ok(None);
+        };
+        // the file is never modified in place, new copies replace it
+        // by renaming, so the mapping stays valid
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_SIZE || &map[..8] != MAGIC {
+            return Err(ArklibError::Parse);
+        }
+        let mut index = MappedIndex { root, map, len: 0 };
+        if index.u32_at(8) != FORMAT_VERSION {
+            return Ok(None);
+        }
+        index.len = index.u32_at(12) as usize;
+        let stamp = (index.u64_at(16), index.u64_at(24));
+        match index_stamp(&index.root) {
+            Ok(current) if current == stamp => {}
+            _ => return Ok(None),
+        }
+        match index.is_valid() {
+            true => Ok(Some(index)),
+            false => Err(ArklibError::Parse),
+        }
+    }
+
+    /// Checks that all positions and paths of the tables are within the
+    /// file, so that lookups in a truncated or corrupted copy can't fail
+    fn is_valid(&self) -> bool {
+        let Some(paths) = self
+            .len
+            .checked_mul(RECORD_SIZE + 4)
+            .and_then(|tables| tables.checked_add(HEADER_SIZE))
+            .filter(|paths| *paths <= self.map.len())
+        else {
+            return false;
+        };
+        let paths_size = self.map.len() - paths;
+        let positions_valid =
+            (0..self.len).all(|position| self.by_path(position) < self.len);
+        let paths_valid = (0..self.len).all(|i| {
+            let record = self.record(i);
+            let offset = self.u32_at(record + 24) as usize;
+            let length = self.u32_at(record + 28) as usize;
+            offset
+                .checked_add(length)
+                .is_some_and(|end| end <= paths_size)
+        });
+        positions_valid && paths_valid
+    }
+
+    /// Maps the binary copy of the index, writing it first from the stored
+    /// index if it is missing or outdated
+    pub fn provide<P: AsRef<Path>>(root_path: P) -> Result<Self> {
+        let root = fs::canonicalize(root_path)?;
+        if let Some(index) = Self::open(&root)? {
+            return Ok(index);
+        }
+        let options = IndexOptions::default();
+        {
+            let _lock = IndexLock::shared(&root, options.lock_timeout)?;
+            ResourceIndex::read(root.clone(), options)?.write_mapped()?;
+        }
+        Self::open(&root)?.ok_or_else(|| {
+            ArklibError::Busy(format!(
+                "the index of {} keeps changing",
+                root.display()
+            ))
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the number of entries in the index
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, id: &ResourceId) -> bool {
+        self.first_of(id).is_some()
+    }
+
+    /// Returns the path of the resource, the smallest one of colliding
+    /// resources like [`ResourceIndex::get_path`]
+    pub fn get_path(&self, id: &ResourceId) -> Option<PathBuf> {
+        self.first_of(id).map(|i| self.path(i))
+    }
+
+    /// Returns paths of all the entries of the ID
+    pub fn aliases(&self, id: &ResourceId) -> Vec<PathBuf> {
+        let Some(first) = self.first_of(id) else {
+            return vec![];
+        };
+        (first..self.len)
+            .take_while(|&i| self.id(i) == *id)
+            .map(|i| self.path(i))
+            .collect()
+    }
+
+    pub fn get_entry<P: AsRef<Path>>(&self, path: P) -> Option<IndexEntry> {
+        let relative = relative_path(&self.root, path.as_ref()).ok()?;
+        let mut low = 0;
+        let mut high = self.len;
+        while low < high {
+            let middle = (low + high) / 2;
+            let i = self.by_path(middle);
+            match self.relative(i).cmp(relative.as_bytes()) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(self.entry(i)),
+            }
+        }
+        None
+    }
+
+    /// Iterates over entries ordered by IDs
+    pub fn entries(&self) -> impl Iterator<Item = (PathBuf, IndexEntry)> + '_ {
+        (0..self.len).map(|i| (self.path(i), self.entry(i)))
+    }
+
+    /// Position of the first entry of the ID
+    fn first_of(&self, id: &ResourceId) -> Option<usize> {
+        let mut low = 0;
+        let mut high = self.len;
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.id(middle) < *id {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        (low < self.len && self.id(low) == *id).then_some(low)
+    }
+
+    fn record(&self, i: usize) -> usize {
+        HEADER_SIZE + i * RECORD_SIZE
+    }
+
+    fn id(&self, i: usize) -> ResourceId {
+        let record = self.record(i);
+        ResourceId {
+            data_size: self.u64_at(record),
+            hash: self.u32_at(record + 8),
+        }
+    }
+
+    fn entry(&self, i: usize) -> IndexEntry {
+        let record = self.record(i);
+        IndexEntry {
+            id: self.id(i),
+            sampled: self.u32_at(record + 12) & SAMPLED_FLAG != 0,
+            remote: self.u32_at(record + 12) & REMOTE_FLAG != 0,
+            modified: SystemTime::UNIX_EPOCH
+                + Duration::from_millis(self.u64_at(record + 16)),
+        }
+    }
+
+    fn by_path(&self, position: usize) -> usize {
+        self.u32_at(HEADER_SIZE + self.len * RECORD_SIZE + position * 4)
+            as usize
+    }
+
+    fn relative(&self, i: usize) -> &[u8] {
+        let record = self.record(i);
+        let paths = HEADER_SIZE + self.len * (RECORD_SIZE + 4);
+        let start = paths + self.u32_at(record + 24) as usize;
+        let end = start + self.u32_at(record + 28) as usize;
+        self.map.get(start..end).unwrap_or_default()
+    }
+
+    fn path(&self, i: usize) -> PathBuf {
+        let relative = String::from_utf8_lossy(self.relative(i));
+        let mut path = self.root.clone();
+        path.extend(relative.split('/'));
+        path
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.map[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.map[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use tempdir::TempDir;
+
+    #[test]
+    fn lookups_match_the_stored_index() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("folder")).unwrap();
+        fs::write(root.join("a.txt"), "same").unwrap();
+        fs::write(root.join("folder/b c.txt"), "same").unwrap();
+        fs::write(root.join("d.txt"), "other").unwrap();
+        let mut index = ResourceIndex::build(&root);
+        index.store().unwrap();
+        assert!(MappedIndex::open(&root).unwrap().is_none());
+
+        let mapped = MappedIndex::provide(&root).unwrap();
+        let same = ResourceId::compute_bytes(b"same").unwrap();
+        let missing = ResourceId::compute_bytes(b"missing").unwrap();
+        assert_eq!(mapped.len(), 3);
+        assert_eq!(mapped.get_path(&same).as_deref(), index.get_path(&same));
+        assert_eq!(mapped.aliases(&same), index.aliases(&same));
+        assert!(!mapped.contains(&missing));
+        let stored = ResourceIndex::load(&root).unwrap();
+        for (path, entry) in stored.entries() {
+            assert_eq!(mapped.get_entry(path).as_ref(), Some(entry));
+        }
+
+        fs::write(root.join("e.txt"), "new").unwrap();
+        index.update_all().unwrap();
+        index.store().unwrap();
+        assert!(MappedIndex::open(&root).unwrap().is_none());
+        index.store_mapped().unwrap();
+        assert_eq!(MappedIndex::open(&root).unwrap().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn corrupted_copies_are_rejected() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        let index = ResourceIndex::build(&root);
+        index.store().unwrap();
+        index.store_mapped().unwrap();
+        let path = root.join(ARK_FOLDER).join(MAPPED_INDEX_FILE);
+        let valid = fs::read(&path).unwrap();
+        let paths = HEADER_SIZE + 2 * (RECORD_SIZE + 4);
+
+        let corruptions: [(usize, u32); 4] = [
+            // number of entries
+            (12, u32::MAX),
+            // offset of the first path
+            (HEADER_SIZE + 24, u32::MAX),
+            // length of the second path
+            (HEADER_SIZE + RECORD_SIZE + 28, 100),
+            // position of the first entry sorted by paths
+            (HEADER_SIZE + 2 * RECORD_SIZE, 2),
+        ];
+        for (offset, value) in corruptions {
+            let mut corrupted = valid.clone();
+            corrupted[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            fs::write(&path, corrupted).unwrap();
+            assert!(matches!(
+                MappedIndex::open(&root),
+                Err(ArklibError::Parse)
+            ));
+        }
+        fs::write(&path, &valid[..paths + 1]).unwrap();
+        assert!(matches!(MappedIndex::open(&root), Err(ArklibError::Parse)));
+    }
+}
@@ -0,0 +1,120 @@
+//! Figures about the index for diagnostics screens of apps
+//!
+//! Durations of the last operations are kept per root for the lifetime
+//! of the process, so they are reported by any instance of the index
+//! of the root, e.g. by the one provided after the build.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::ResourceIndex;
+
+lazy_static! {
+    static ref DURATIONS: Mutex<HashMap<PathBuf, Durations>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Build,
+    Load,
+    Update,
+    Store,
+}
+
+/// Durations of the last operations on the index of a root
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Durations {
+    pub build: Option<Duration>,
+    pub load: Option<Duration>,
+    /// Updates of subtrees are counted as well
+    pub update: Option<Duration>,
+    pub store: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexMetrics {
+    pub files: usize,
+    pub resources: usize,
+    /// Resources indexed by several paths, see [`ResourceIndex::aliases`]
+    pub collisions: usize,
+    /// Paths of colliding resources besides the first one of each
+    pub colliding_paths: usize,
+    /// Entries with IDs computed from samples of the content only
+    pub sampled: usize,
+    /// See [`ResourceIndex::heap_size`]
+    pub heap_size: usize,
+    pub durations: Durations,
+}
+
+impl ResourceIndex {
+    /// Collects figures about the index, which costs as much
+    /// as going through all of its entries once
+    pub fn metrics(&self) -> IndexMetrics {
+        IndexMetrics {
+            files: self.count_files(),
+            resources: self.count_resources(),
+            collisions: self.collisions.len(),
+            colliding_paths: self
+                .collisions
+                .values()
+                .map(|count| count.saturating_sub(1))
+                .sum(),
+            sampled: self.sampled_paths().count(),
+            heap_size: self.heap_size(),
+            durations: DURATIONS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(self.root())
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub(crate) fn record(root: &Path, operation: Operation, elapsed: Duration) {
+    let mut durations = DURATIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let durations = durations.entry(root.to_path_buf()).or_default();
+    let duration = match operation {
+        Operation::Build => &mut durations.build,
+        Operation::Load => &mut durations.load,
+        Operation::Update => &mut durations.update,
+        Operation::Store => &mut durations.store,
+    };
+    *duration = Some(elapsed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn metrics_are_collected() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "same").unwrap();
+        fs::write(root.join("b.txt"), "same").unwrap();
+        fs::write(root.join("c.txt"), "other").unwrap();
+        let mut index = ResourceIndex::build(&root);
+        let metrics = index.metrics();
+        assert_eq!(metrics.files, 3);
+        assert_eq!(metrics.resources, 2);
+        assert_eq!(metrics.collisions, 1);
+        assert_eq!(metrics.colliding_paths, 1);
+        assert!(metrics.heap_size > 0);
+        assert!(metrics.durations.build.is_some());
+        assert_eq!(metrics.durations.update, None);
+
+        index.update_all().unwrap();
+        index.store().unwrap();
+        let metrics = ResourceIndex::load(&root).unwrap().metrics();
+        assert!(metrics.durations.update.is_some());
+        assert!(metrics.durations.store.is_some());
+        assert!(metrics.durations.load.is_some());
+    }
+}
@@ -0,0 +1,112 @@
+//! Roots inside of other roots
+//!
+//! Files of a root nested in another root are indexed by both roots,
+//! so they get tags and scores stored twice and their changes are watched
+//! twice. Such roots are refused with [`ArklibError::NestedRoot`] unless
+//! nesting is allowed explicitly for the inner root. Folders inside of
+//! `.ark` are never roots.
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::IndexOptions;
+use crate::storage::config::{load_section, store_section};
+use crate::{ArklibError, Result, ARK_FOLDER, INDEX_DB_FILE, INDEX_PATH};
+
+const CONFIG_SECTION: &str = "nesting";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NestingConfig {
+    allowed: bool,
+}
+
+/// Whether the root may be indexed although it is inside of another root
+pub fn is_nesting_allowed<P: AsRef<Path>>(root: P) -> Result<bool> {
+    let config: Option<NestingConfig> = load_section(root, CONFIG_SECTION)?;
+    Ok(config.unwrap_or_default().allowed)
+}
+
+/// Allows or forbids indexing the root inside of another root, which is
+/// applied by the next [`crate::provide_index`]
+pub fn set_nesting_allowed<P: AsRef<Path>>(
+    root: P,
+    allowed: bool,
+) -> Result<()> {
+    store_section(root, CONFIG_SECTION, &NestingConfig { allowed })
+}
+
+/// Returns the closest root containing the path, not counting the path
+/// itself
+pub fn find_outer_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors().skip(1).find_map(|ancestor| {
+        let ark = ancestor.join(ARK_FOLDER);
+        let is_root =
+            ark.join(INDEX_PATH).is_file() || ark.join(INDEX_DB_FILE).is_file();
+        is_root.then(|| ancestor.to_path_buf())
+    })
+}
+
+/// Fails if the canonical path must not be indexed as a root
+pub(super) fn check_nesting(root: &Path, options: &IndexOptions) -> Result<()> {
+    if root
+        .components()
+        .any(|part| part.as_os_str() == ARK_FOLDER)
+    {
+        return Err(ArklibError::Path(format!(
+            "{} is inside of a {} folder, which is never indexed",
+            root.display(),
+            ARK_FOLDER
+        )));
+    }
+    if options.allow_nested {
+        return Ok(());
+    }
+    match find_outer_root(root) {
+        Some(outer) => Err(ArklibError::NestedRoot {
+            root: root.to_path_buf(),
+            outer,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::ResourceIndex;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn nested_roots_are_refused_unless_allowed() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let outer = fs::canonicalize(dir.path()).unwrap();
+        let inner = outer.join("inner");
+        fs::create_dir(&inner).unwrap();
+        fs::write(inner.join("a.txt"), "a").unwrap();
+        ResourceIndex::build(&outer).store().unwrap();
+        assert_eq!(find_outer_root(&inner.join("a.txt")), Some(outer.clone()));
+
+        match ResourceIndex::provide(&inner) {
+            Err(ArklibError::NestedRoot { root, outer: found }) => {
+                assert_eq!(root, inner);
+                assert_eq!(found, outer);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(ResourceIndex::build(&inner).count_files(), 0);
+        let err = ResourceIndex::provide(outer.join(ARK_FOLDER)).unwrap_err();
+        assert!(matches!(err, ArklibError::Path(_)));
+
+        set_nesting_allowed(&inner, true).unwrap();
+        assert!(is_nesting_allowed(&inner).unwrap());
+        let options = IndexOptions {
+            allow_nested: true,
+            ..IndexOptions::default()
+        };
+        let index =
+            ResourceIndex::provide_with_options(&inner, options).unwrap();
+        assert_eq!(index.count_files(), 1);
+    }
+}
@@ -0,0 +1,127 @@
+//! Placeholders of files kept in the cloud
+//!
+//! Dropbox, OneDrive and iCloud Drive can keep files online only, leaving
+//! placeholders without content on the disk, and reading a placeholder
+//! downloads the whole file. Unless [`IndexOptions::hash_placeholders`]
+//! is set, placeholders are indexed without reading them: their entries
+//! are marked as [`IndexEntry::remote`] and get IDs derived from their
+//! paths, which are replaced by content IDs once the files are downloaded
+//! or [`ResourceIndex::upgrade_id`] is called.
+//!
+//! [`IndexOptions::hash_placeholders`]: super::IndexOptions::hash_placeholders
+//! [`IndexEntry::remote`]: super::IndexEntry::remote
+//! [`ResourceIndex::upgrade_id`]: super::ResourceIndex::upgrade_id
+use std::fs::Metadata;
+use std::path::Path;
+
+use crate::resource::ResourceId;
+
+/// Files smaller than this can keep their content inside of the metadata
+/// on some filesystems, so they have no blocks without being placeholders
+const INLINE_DATA_LIMIT: u64 = 4096;
+
+/// Whether the file is a placeholder whose content must be downloaded
+/// before it can be read
+pub fn is_placeholder(metadata: &Metadata) -> bool {
+    if !metadata.is_file() {
+        return false;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+        const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+        const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+        metadata.file_attributes()
+            & (FILE_ATTRIBUTE_OFFLINE
+                | FILE_ATTRIBUTE_RECALL_ON_OPEN
+                | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS)
+            != 0
+    }
+    #[cfg(unix)]
+    {
+        #[cfg(target_os = "macos")]
+        {
+            use std::os::macos::fs::MetadataExt;
+            const SF_DATALESS: u32 = 0x40000000;
+            if metadata.st_flags() & SF_DATALESS != 0 {
+                return true;
+            }
+        }
+        // placeholders of other clients are sparse files
+        use std::os::unix::fs::MetadataExt;
+        metadata.len() > INLINE_DATA_LIMIT && metadata.blocks() == 0
+    }
+    #[cfg(not(any(windows, unix)))]
+    {
+        false
+    }
+}
+
+/// ID of a placeholder, which is derived from its size and path
+/// since the content is not available
+pub(super) fn placeholder_id(path: &Path, size: u64) -> ResourceId {
+    ResourceId {
+        data_size: size,
+        hash: crc32fast::hash(path.to_string_lossy().as_bytes()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::index::{IndexOptions, ResourceIndex};
+    use crate::resource::ResourceIdTrait;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn placeholders_are_not_hashed() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let path = root.join("online.bin");
+        fs::File::create(&path)
+            .unwrap()
+            .set_len(1024 * 1024)
+            .unwrap();
+        if !is_placeholder(&fs::metadata(&path).unwrap()) {
+            // the filesystem doesn't support sparse files
+            return;
+        }
+
+        let mut index = ResourceIndex::build(&root);
+        assert!(index.get_entry(&path).unwrap().remote);
+        assert_eq!(index.remote_paths().count(), 1);
+        index.store().unwrap();
+        let mut loaded = ResourceIndex::load(&root).unwrap();
+        assert!(loaded.get_entry(&path).unwrap().remote);
+
+        // content is hashed when requested explicitly
+        let update = loaded.upgrade_id(&path).unwrap();
+        assert_eq!(update.modified.len(), 1);
+        let id = ResourceId::compute_bytes(&vec![0; 1024 * 1024]).unwrap();
+        assert_eq!(loaded.get_entry(&path).unwrap().id, id);
+        assert!(!loaded.get_entry(&path).unwrap().remote);
+
+        let options = IndexOptions {
+            hash_placeholders: true,
+            ..IndexOptions::default()
+        };
+        let forced = ResourceIndex::build_with_options(&root, options);
+        assert_eq!(forced.get_entry(&path).unwrap().id, id);
+
+        // downloaded files are hashed by the next update, although
+        // their modification time is kept
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, vec![1; 1024 * 1024]).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        index.update_all().unwrap();
+        assert!(!index.get_entry(&path).unwrap().remote);
+    }
+}
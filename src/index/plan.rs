@@ -0,0 +1,214 @@
+//! Dry runs of index updates
+//!
+//! [`ResourceIndex::plan_update`] detects the same changes as
+//! [`ResourceIndex::update_all`] without hashing files or touching the
+//! index, so that scripts and `ark-cli monitor --dry-run` can show what
+//! an update would do and how much data it would read.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use super::lock::IndexLock;
+use super::{parse_header, parse_line, Changes, IndexEntry, ResourceIndex};
+use crate::util::fs::{join_relative, locate_relative};
+use crate::{Result, ARK_FOLDER, INDEX_PATH};
+
+/// Changes which the next update of the index would make
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdatePlan {
+    /// New files, which would be hashed and added
+    pub created: Vec<PathBuf>,
+    /// Files modified since they were indexed, which would be hashed again
+    pub modified: Vec<PathBuf>,
+    /// Indexed paths of vanished files, which entries would be deleted
+    pub deleted: Vec<PathBuf>,
+    /// Previous and current paths of files detected as moved, which keep
+    /// their IDs without being hashed
+    pub moved: BTreeMap<PathBuf, PathBuf>,
+    /// Total size of the files to hash
+    pub bytes_to_hash: u64,
+}
+
+impl UpdatePlan {
+    /// Whether the update would not change anything
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty()
+            && self.modified.is_empty()
+            && self.deleted.is_empty()
+            && self.moved.is_empty()
+    }
+
+    /// Files which would be hashed, new ones first
+    pub fn files_to_hash(&self) -> impl Iterator<Item = &Path> {
+        self.created
+            .iter()
+            .chain(self.modified.iter())
+            .map(|path| path.as_path())
+    }
+}
+
+impl ResourceIndex {
+    /// Computes what [`ResourceIndex::update_all`] would change, without
+    /// modifying the index or hashing any file
+    ///
+    /// Modified files which turn out to have the same content are still
+    /// listed, since only hashing them tells.
+    pub fn plan_update(&self) -> Result<UpdatePlan> {
+        let Changes {
+            prev_paths,
+            preserved_paths,
+            created_paths,
+            moved_paths,
+            updated_paths,
+        } = self.detect_changes(&self.root)?;
+
+        let mut plan = UpdatePlan {
+            moved: moved_paths.into_iter().collect(),
+            ..UpdatePlan::default()
+        };
+        for (path, entry) in created_paths.iter().chain(updated_paths.iter()) {
+            plan.bytes_to_hash += entry
+                .metadata()
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            match created_paths.contains_key(path) {
+                true => plan.created.push(path.clone()),
+                false => plan.modified.push(path.clone()),
+            }
+        }
+        // files of damaged lines of the index file are hashed as well
+        for path in &self.damaged {
+            if !self.path2id.contains_key(path) && path.is_file() {
+                plan.created.push(path.clone());
+            }
+        }
+        plan.deleted = prev_paths
+            .difference(&preserved_paths)
+            .filter(|path| !plan.moved.contains_key(*path))
+            .cloned()
+            .collect();
+
+        plan.created.sort();
+        plan.created.dedup();
+        plan.modified.sort();
+        plan.deleted.sort();
+        Ok(plan)
+    }
+}
+
+/// Loads the stored index of the root and computes what its update
+/// would change, see [`ResourceIndex::plan_update`]
+///
+/// Loading drops entries of missing files, so the index file is read
+/// once more to plan deletions and moves of them.
+pub fn plan_update<P: AsRef<Path>>(root_path: P) -> Result<UpdatePlan> {
+    let index = ResourceIndex::load(root_path)?;
+    let mut plan = index.plan_update()?;
+    for (path, entry) in vanished_entries(&index)? {
+        let moved = plan.created.iter().position(|created| {
+            fs::metadata(created).is_ok_and(|metadata| {
+                metadata.len() == entry.id.data_size
+                    && index.options.clock.modified(&metadata).ok()
+                        == Some(entry.modified)
+            })
+        });
+        match moved {
+            Some(position) => {
+                let to = plan.created.remove(position);
+                plan.bytes_to_hash -= entry.id.data_size;
+                plan.moved.insert(path, to);
+            }
+            None => plan.deleted.push(path),
+        }
+    }
+    plan.deleted.sort();
+    Ok(plan)
+}
+
+/// Entries of the index file naming files which don't exist anymore
+fn vanished_entries(
+    index: &ResourceIndex,
+) -> Result<Vec<(PathBuf, IndexEntry)>> {
+    let root = index.root();
+    let index_path = root.join(ARK_FOLDER).join(INDEX_PATH);
+    let _lock = IndexLock::shared(root, index.options.lock_timeout)?;
+    let lines = BufReader::new(File::open(index_path)?).lines();
+    let mut version = 1;
+    let mut vanished = vec![];
+    let mut first = true;
+    for line in lines {
+        let Ok(line) = line else {
+            continue;
+        };
+        let line = line.trim_end_matches('\r');
+        if std::mem::take(&mut first) {
+            if let Ok(Some(header)) = parse_header(line) {
+                version = header;
+                continue;
+            }
+        }
+        let Ok((entry, relative)) = parse_line(line, version) else {
+            continue;
+        };
+        if locate_relative(root, &relative).is_none() {
+            vanished.push((join_relative(root, &relative)?, entry));
+        }
+    }
+    Ok(vanished)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempdir::TempDir;
+
+    #[test]
+    fn plan_matches_the_update() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("kept.txt"), "kept").unwrap();
+        fs::write(root.join("modified.txt"), "before").unwrap();
+        fs::write(root.join("deleted.txt"), "deleted").unwrap();
+        fs::write(root.join("moved.txt"), "moved").unwrap();
+        let mut index = ResourceIndex::build(&root);
+        index.store().unwrap();
+        assert!(plan_update(&root).unwrap().is_empty());
+        let index_path = root.join(ARK_FOLDER).join(INDEX_PATH);
+        let stored = fs::read(&index_path).unwrap();
+
+        let file = fs::File::options()
+            .write(true)
+            .open(root.join("modified.txt"))
+            .unwrap();
+        file.set_len(5).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        fs::remove_file(root.join("deleted.txt")).unwrap();
+        fs::rename(root.join("moved.txt"), root.join("renamed.txt")).unwrap();
+        fs::write(root.join("new.txt"), "new").unwrap();
+
+        let plan = plan_update(&root).unwrap();
+        assert_eq!(plan.created, vec![root.join("new.txt")]);
+        assert_eq!(plan.modified, vec![root.join("modified.txt")]);
+        assert_eq!(plan.deleted, vec![root.join("deleted.txt")]);
+        assert_eq!(
+            plan.moved.get(&root.join("moved.txt")),
+            Some(&root.join("renamed.txt"))
+        );
+        assert_eq!(plan.bytes_to_hash, 3 + 5);
+        assert_eq!(plan.files_to_hash().count(), 2);
+        // the stored index is left intact
+        assert_eq!(fs::read(&index_path).unwrap(), stored);
+        assert_eq!(index.plan_update().unwrap(), plan);
+
+        let update = index.update_all().unwrap();
+        assert_eq!(update.added.len(), 1);
+        assert_eq!(update.modified.len(), 1);
+        assert_eq!(update.deleted.len(), 1);
+        assert_eq!(update.moved.len(), 1);
+        assert!(index.plan_update().unwrap().is_empty());
+    }
+}
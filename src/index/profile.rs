@@ -0,0 +1,149 @@
+//! Profiles trading indexing speed for battery and IO
+//!
+//! The profile of a root is kept in the `indexing` section of
+//! `.ark/config`, so that the index provided for the root follows it.
+//! Apps switch profiles with [`set_profile`], e.g. when the device starts
+//! or stops charging.
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use canonical_path::CanonicalPathBuf;
+use serde::{Deserialize, Serialize};
+
+use super::{IndexOptions, SchedulerOptions, PARALLEL_HASHING_THRESHOLD};
+use crate::storage::config::{load_section, store_section};
+use crate::{Result, REGISTRAR};
+
+const CONFIG_SECTION: &str = "indexing";
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexingProfile {
+    /// Hashes several files at once using all cores, for charging devices
+    Aggressive,
+    /// The default options
+    #[default]
+    Balanced,
+    /// Hashes one file at a time in small chunks and updates the index
+    /// rarely, for devices on low battery
+    PowerSaver,
+}
+
+impl IndexingProfile {
+    /// Applies the profile to the options, keeping options
+    /// not related to performance
+    pub fn apply(&self, options: &mut IndexOptions) {
+        let defaults = IndexOptions::default();
+        match self {
+            IndexingProfile::Aggressive => {
+                options.scan_threads = thread::available_parallelism()
+                    .map(|threads| threads.get())
+                    .unwrap_or(1);
+                options.parallel_hashing_threshold =
+                    Some(PARALLEL_HASHING_THRESHOLD / 4);
+                options.hashing_buffer_size = defaults.hashing_buffer_size * 2;
+            }
+            IndexingProfile::Balanced => {
+                options.scan_threads = defaults.scan_threads;
+                options.parallel_hashing_threshold =
+                    defaults.parallel_hashing_threshold;
+                options.hashing_buffer_size = defaults.hashing_buffer_size;
+            }
+            IndexingProfile::PowerSaver => {
+                options.scan_threads = 1;
+                options.parallel_hashing_threshold = None;
+                options.hashing_buffer_size = defaults.hashing_buffer_size / 8;
+            }
+        }
+    }
+
+    /// Options of the profile based on the default ones
+    pub fn index_options(&self) -> IndexOptions {
+        let mut options = IndexOptions::default();
+        self.apply(&mut options);
+        options
+    }
+
+    /// How often background updates are run under the profile
+    pub fn scheduler_options(&self) -> SchedulerOptions {
+        match self {
+            IndexingProfile::Aggressive => SchedulerOptions {
+                debounce: Duration::from_millis(100),
+                max_latency: Duration::from_secs(1),
+            },
+            IndexingProfile::Balanced => SchedulerOptions::default(),
+            IndexingProfile::PowerSaver => SchedulerOptions {
+                debounce: Duration::from_secs(5),
+                max_latency: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+/// Loads the profile of the root, [`IndexingProfile::Balanced`]
+/// if never set
+pub fn load_profile<P: AsRef<Path>>(root: P) -> Result<IndexingProfile> {
+    Ok(load_section(root, CONFIG_SECTION)?.unwrap_or_default())
+}
+
+/// Stores the profile of the root and applies it to the index
+/// of the root if it has been provided already
+pub fn set_profile<P: AsRef<Path>>(
+    root: P,
+    profile: IndexingProfile,
+) -> Result<()> {
+    store_section(&root, CONFIG_SECTION, &profile)?;
+    let root = CanonicalPathBuf::canonicalize(root)?;
+    let index = REGISTRAR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&root)
+        .cloned();
+    if let Some(index) = index {
+        let mut index = index.write().unwrap_or_else(|e| e.into_inner());
+        let mut options = index.options().clone();
+        profile.apply(&mut options);
+        index.set_options(options);
+    }
+    log::info!("Indexing {} with {:?} profile", root.display(), profile);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::ResourceIndex;
+    use crate::provide_index;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn profiles_are_applied() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        for i in 0..8 {
+            fs::write(root.join(format!("{}.txt", i)), i.to_string()).unwrap();
+        }
+        assert_eq!(load_profile(&root).unwrap(), IndexingProfile::Balanced);
+        let aggressive = ResourceIndex::build_with_options(
+            &root,
+            IndexingProfile::Aggressive.index_options(),
+        );
+        let saving = ResourceIndex::build_with_options(
+            &root,
+            IndexingProfile::PowerSaver.index_options(),
+        );
+        assert_eq!(aggressive.path2id, saving.path2id);
+
+        let index = provide_index(&root).unwrap();
+        set_profile(&root, IndexingProfile::PowerSaver).unwrap();
+        assert_eq!(load_profile(&root).unwrap(), IndexingProfile::PowerSaver);
+        let options = index.read().unwrap().options().clone();
+        assert_eq!(options.parallel_hashing_threshold, None);
+        assert_eq!(options.hashing_buffer_size, 64 * 1024);
+    }
+}
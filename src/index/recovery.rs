@@ -0,0 +1,78 @@
+//! Loading of index files with damaged lines
+//!
+//! A single malformed line, e.g. left by a sync client or a full disk,
+//! used to fail the whole load, so that the index was rebuilt from
+//! scratch and every file of the root was hashed again. Such lines are
+//! skipped and reported instead, and the files they name, if the path
+//! can still be read, are hashed again by the next update.
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::unescape;
+use crate::util::fs::locate_relative;
+
+/// Line of the index file skipped while loading
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedLine {
+    /// Number of the line, starting from 1
+    pub line: usize,
+    pub content: String,
+    /// File named by the line, if it could be found
+    pub path: Option<PathBuf>,
+}
+
+/// Lines of the index file skipped by the last load
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LoadReport {
+    pub skipped: Vec<SkippedLine>,
+}
+
+impl LoadReport {
+    pub fn is_clean(&self) -> bool {
+        self.skipped.is_empty()
+    }
+
+    /// Paths of skipped lines, which are hashed again by the next update
+    pub fn damaged_paths(&self) -> impl Iterator<Item = &Path> {
+        self.skipped
+            .iter()
+            .filter_map(|skipped| skipped.path.as_deref())
+    }
+
+    pub(crate) fn skip(&mut self, root: &Path, number: usize, line: String) {
+        let path = recover_path(root, &line);
+        self.skipped.push(SkippedLine {
+            line: number,
+            content: line,
+            path,
+        });
+    }
+}
+
+/// Finds the file named by a damaged line, assuming that only the
+/// timestamp or the ID is damaged
+fn recover_path(root: &Path, line: &str) -> Option<PathBuf> {
+    let mut parts = line.splitn(3, ' ');
+    let path = parts.nth(2)?;
+    // paths of lines written before escaping was introduced are the same
+    // unless they contain backslashes
+    let path = unescape(path).unwrap_or_else(|| path.to_string());
+    if path.is_empty() {
+        return None;
+    }
+    let path = locate_relative(root, &path)
+        .unwrap_or_else(|| root.join(Path::new(&path)))
+        .canonicalize()
+        .ok()?;
+    let hidden = path
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .any(|part| {
+            part.as_os_str()
+                .to_string_lossy()
+                .starts_with('.')
+        });
+    (!hidden && path.is_file()).then_some(path)
+}
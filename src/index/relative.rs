@@ -0,0 +1,253 @@
+//! Paths of resources relative to the root
+//!
+//! Absolute paths of the same file differ between platforms and even
+//! between calls on the same platform: canonicalization on Windows yields
+//! verbatim `\\?\C:\` or `\\?\UNC\` prefixed paths, while paths passed by
+//! users usually have no such prefix. Relative paths use `/` as separator
+//! everywhere and never start with a prefix, so they can be compared as
+//! strings and stored on one device to be read on another. Names in
+//! relative paths are normalized to NFC, while names on disk may be
+//! stored in NFD by macOS.
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{IndexEntry, ResourceIndex};
+use crate::util::fs::{
+    join_relative, locate_relative, normalize_relative, relative_path,
+};
+use crate::Result;
+
+/// Path inside of a root, with `/` as separator on all platforms
+///
+/// The empty path denotes the root itself.
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+#[serde(transparent)]
+pub struct RelativePath(String);
+
+impl RelativePath {
+    /// Makes `path` relative to `root`, failing if it is outside of it
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(
+        root: P,
+        path: Q,
+    ) -> Result<Self> {
+        Ok(RelativePath(relative_path(root.as_ref(), path.as_ref())?))
+    }
+
+    /// Parses a path in the portable form, rejecting absolute paths
+    /// and paths escaping the root
+    pub fn parse(path: &str) -> Result<Self> {
+        let path = normalize_relative(path.trim_end_matches('/'));
+        if !path.is_empty() {
+            join_relative(Path::new(""), &path)?;
+        }
+        Ok(RelativePath(path))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Resolves the path against `root`
+    pub fn to_path<P: AsRef<Path>>(&self, root: P) -> PathBuf {
+        match self.is_root() {
+            true => root.as_ref().to_path_buf(),
+            false => root
+                .as_ref()
+                .join(self.components().collect::<PathBuf>()),
+        }
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0.split('/').filter(|part| !part.is_empty())
+    }
+
+    /// Whether `prefix` is this path or one of its ancestors,
+    /// only whole components are matched
+    pub fn starts_with(&self, prefix: &RelativePath) -> bool {
+        prefix.is_root()
+            || self.0 == prefix.0
+            || (self.0.starts_with(&prefix.0)
+                && self.0[prefix.0.len()..].starts_with('/'))
+    }
+
+    /// Returns the parent folder, absent for the root
+    pub fn parent(&self) -> Option<RelativePath> {
+        match self.0.rsplit_once('/') {
+            Some((parent, _)) => Some(RelativePath(parent.to_string())),
+            None if self.is_root() => None,
+            None => Some(RelativePath::default()),
+        }
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.components().last()
+    }
+
+    /// Hash of the path usable as a file name, identifying the folder
+    /// in user data storages on all devices
+    pub fn storage_key(&self) -> String {
+        blake3::hash(self.0.as_bytes()).to_hex()[..32].to_string()
+    }
+}
+
+impl fmt::Display for RelativePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ResourceIndex {
+    /// Makes `path` relative to the root of the index, both canonical
+    /// and non-canonical forms of paths inside of the root are accepted
+    pub fn relative_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<RelativePath> {
+        RelativePath::new(&self.root, path)
+    }
+
+    /// Resolves a relative path against the root of the index
+    pub fn absolute_path(&self, path: &RelativePath) -> PathBuf {
+        path.to_path(&self.root)
+    }
+
+    /// Returns the entry of the resource at the relative path
+    /// regardless of the normalization of its name on disk
+    pub fn get_relative(&self, path: &RelativePath) -> Option<&IndexEntry> {
+        if path.is_root() {
+            return None;
+        }
+        self.path2id
+            .get(&self.absolute_path(path))
+            .or_else(|| {
+                let path = locate_relative(&self.root, path.as_str())?;
+                self.path2id.get(&path)
+            })
+    }
+
+    /// Lists resources inside of the folder at any depth together with
+    /// their relative paths, sorted by the paths
+    pub fn entries_with_prefix(
+        &self,
+        prefix: &RelativePath,
+    ) -> Vec<(RelativePath, &IndexEntry)> {
+        let mut entries: Vec<(RelativePath, &IndexEntry)> = self
+            .path2id
+            .iter()
+            .filter_map(|(path, entry)| {
+                let path = self.relative_path(path).ok()?;
+                path.starts_with(prefix).then_some((path, entry))
+            })
+            .collect();
+        entries.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn relative_paths_are_portable() {
+        let root = Path::new("/root");
+        let path = RelativePath::new(root, root.join("photos").join("cat.jpg"))
+            .unwrap();
+        assert_eq!(path.as_str(), "photos/cat.jpg");
+        assert_eq!(path.file_name(), Some("cat.jpg"));
+        assert_eq!(path.parent().unwrap().as_str(), "photos");
+        assert_eq!(path.to_path(root), root.join("photos").join("cat.jpg"));
+        assert_eq!(RelativePath::parse("photos/cat.jpg").unwrap(), path);
+
+        assert!(RelativePath::new(root, "/etc/passwd").is_err());
+        assert!(RelativePath::parse("../etc/passwd").is_err());
+        assert!(RelativePath::parse("/etc/passwd").is_err());
+
+        let photos = RelativePath::parse("photos").unwrap();
+        assert!(path.starts_with(&photos));
+        assert!(path.starts_with(&RelativePath::default()));
+        assert!(!RelativePath::parse("photos2/cat.jpg")
+            .unwrap()
+            .starts_with(&photos));
+    }
+
+    #[test]
+    fn entries_are_listed_by_prefix() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        fs::create_dir_all(dir.path().join("photos/2023")).unwrap();
+        fs::create_dir(dir.path().join("photos2")).unwrap();
+        fs::write(dir.path().join("photos/2023/cat.jpg"), "cat").unwrap();
+        fs::write(dir.path().join("photos/dog.jpg"), "dog").unwrap();
+        fs::write(dir.path().join("photos2/owl.jpg"), "owl").unwrap();
+
+        let index = ResourceIndex::build(dir.path());
+        let photos = RelativePath::parse("photos/").unwrap();
+        let paths: Vec<String> = index
+            .entries_with_prefix(&photos)
+            .into_iter()
+            .map(|(path, _)| path.to_string())
+            .collect();
+        assert_eq!(paths, vec!["photos/2023/cat.jpg", "photos/dog.jpg"]);
+
+        let dog = index
+            .relative_path(dir.path().join("photos/dog.jpg"))
+            .unwrap();
+        assert!(index.get_relative(&dog).is_some());
+        assert_eq!(
+            index
+                .entries_with_prefix(&RelativePath::default())
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn mixed_normalization_root() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        // "é" written precomposed (NFC) and decomposed (NFD)
+        fs::create_dir(dir.path().join("cafe\u{301}")).unwrap();
+        fs::write(dir.path().join("cafe\u{301}/menu.txt"), "menu").unwrap();
+        fs::write(dir.path().join("th\u{e9}.txt"), "tea").unwrap();
+
+        let index = ResourceIndex::build(dir.path());
+        index.store().unwrap();
+        let content = fs::read_to_string(
+            dir.path()
+                .join(crate::ARK_FOLDER)
+                .join(crate::INDEX_PATH),
+        )
+        .unwrap();
+        assert!(content.contains("caf\u{e9}/menu.txt"));
+        assert!(!content.contains("cafe\u{301}"));
+
+        let loaded = ResourceIndex::load(dir.path()).unwrap();
+        assert_eq!(loaded.count_files(), 2);
+        for path in
+            ["caf\u{e9}/menu.txt", "cafe\u{301}/menu.txt", "th\u{e9}.txt"]
+        {
+            let path = RelativePath::parse(path).unwrap();
+            assert!(loaded.get_relative(&path).is_some(), "{}", path);
+        }
+        let cafe = RelativePath::parse("cafe\u{301}").unwrap();
+        assert_eq!(loaded.entries_with_prefix(&cafe).len(), 1);
+    }
+}
@@ -0,0 +1,248 @@
+//! Batching of change notifications into updates of the index
+//!
+//! Watchers and apps report every changed path, which comes in bursts:
+//! a camera shooting a series or a sync client downloading a folder
+//! reports hundreds of files within seconds. [`UpdateScheduler`] waits
+//! until notifications calm down for [`SchedulerOptions::debounce`], but
+//! never longer than [`SchedulerOptions::max_latency`] after the first
+//! one, and then updates every touched folder once.
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+
+use super::IndexUpdate;
+use crate::cache::enforce_budget;
+use crate::{ArklibError, ResourceIndexLock, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerOptions {
+    /// Quiet period after the last notification before updating
+    pub debounce: Duration,
+    /// Longest delay of an update after the first notification
+    /// of a batch, even if notifications keep coming
+    pub max_latency: Duration,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        SchedulerOptions {
+            debounce: Duration::from_millis(500),
+            max_latency: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Background thread updating the index in batches of notifications
+/// and keeping the caches within their budget
+pub struct UpdateScheduler {
+    sender: Option<Sender<PathBuf>>,
+    thread: Option<JoinHandle<usize>>,
+}
+
+impl UpdateScheduler {
+    /// Starts the scheduler, calling `on_update` with changes found
+    /// by every batch which found any
+    pub fn spawn<F>(
+        index: ResourceIndexLock,
+        options: SchedulerOptions,
+        mut on_update: F,
+    ) -> Self
+    where
+        F: FnMut(IndexUpdate) + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<PathBuf>();
+        let root = index
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .root()
+            .to_path_buf();
+        let thread = thread::spawn(move || {
+            let mut batches = 0;
+            // blocks until the first notification of a batch
+            while let Ok(path) = receiver.recv() {
+                let deadline = Instant::now() + options.max_latency;
+                let mut paths = BTreeSet::from([path]);
+                let mut finished = false;
+                loop {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    let timeout = options.debounce.min(deadline - now);
+                    match receiver.recv_timeout(timeout) {
+                        Ok(path) => {
+                            paths.insert(path);
+                        }
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => {
+                            finished = true;
+                            break;
+                        }
+                    }
+                }
+
+                let update = update(&index, paths);
+                batches += 1;
+                if !is_empty(&update) {
+                    on_update(update);
+                }
+                if let Err(e) = enforce_budget(&root) {
+                    log::warn!("Couldn't enforce the cache budget: {}", e);
+                }
+                if finished {
+                    break;
+                }
+            }
+            batches
+        });
+        UpdateScheduler {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    /// Reports a changed, created or deleted file or folder
+    pub fn notify<P: Into<PathBuf>>(&self, path: P) -> Result<()> {
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send(path.into()).ok())
+            .ok_or_else(|| {
+                ArklibError::Other(anyhow!("Update scheduler has stopped"))
+            })
+    }
+
+    /// Updates the index with pending notifications right away and stops,
+    /// returning the number of performed batches
+    pub fn finish(mut self) -> usize {
+        self.sender.take();
+        self.thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for UpdateScheduler {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Updates the folders of the paths, skipping folders inside
+/// of other updated folders
+fn update(index: &ResourceIndexLock, paths: BTreeSet<PathBuf>) -> IndexUpdate {
+    let mut index = index.write().unwrap_or_else(|e| e.into_inner());
+    let root = index.root().to_path_buf();
+    let folders: BTreeSet<PathBuf> = paths
+        .into_iter()
+        .filter_map(|path| folder_of(&root, &path))
+        .collect();
+    // sorted folders follow their parents
+    let mut scopes: Vec<PathBuf> = vec![];
+    for folder in folders {
+        if !scopes
+            .iter()
+            .any(|scope| folder.starts_with(scope))
+        {
+            scopes.push(folder);
+        }
+    }
+
+    let mut result = IndexUpdate::default();
+    for scope in scopes {
+        let update = match scope == root {
+            true => index.update_all(),
+            false => index.update_subtree(&scope),
+        };
+        match update {
+            Ok(update) => {
+                result.deleted.extend(update.deleted);
+                result.added.extend(update.added);
+                result.modified.extend(update.modified);
+                result.moved.extend(update.moved);
+            }
+            Err(e) => {
+                log::warn!("Couldn't update {}: {}", scope.display(), e)
+            }
+        }
+    }
+    result
+}
+
+/// Finds the closest existing folder of the path inside of the root
+fn folder_of(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut folder = match path.is_dir() {
+        true => path,
+        false => path.parent()?,
+    };
+    while !folder.is_dir() {
+        folder = folder.parent()?;
+    }
+    let folder = folder.canonicalize().ok()?;
+    match folder.starts_with(root) {
+        true => Some(folder),
+        false => {
+            log::warn!("{} is outside of the root", path.display());
+            None
+        }
+    }
+}
+
+fn is_empty(update: &IndexUpdate) -> bool {
+    update.deleted.is_empty()
+        && update.added.is_empty()
+        && update.modified.is_empty()
+        && update.moved.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::ResourceIndex;
+    use std::fs;
+    use std::sync::{Arc, Mutex, RwLock};
+    use tempdir::TempDir;
+
+    #[test]
+    fn bursts_are_updated_at_once() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("camera/burst")).unwrap();
+        fs::create_dir_all(root.join("music")).unwrap();
+        let index = Arc::new(RwLock::new(ResourceIndex::build(&root)));
+        let updates = Arc::new(Mutex::new(vec![]));
+        let options = SchedulerOptions {
+            debounce: Duration::from_secs(60),
+            max_latency: Duration::from_secs(60),
+        };
+        let scheduler = UpdateScheduler::spawn(index.clone(), options, {
+            let updates = updates.clone();
+            move |update| updates.lock().unwrap().push(update)
+        });
+
+        for i in 0..20 {
+            let path = root.join(format!("camera/burst/{}.jpg", i));
+            fs::write(&path, format!("photo {}", i)).unwrap();
+            scheduler.notify(&path).unwrap();
+        }
+        scheduler.notify(root.join("camera")).unwrap();
+        fs::write(root.join("music/song.mp3"), "song").unwrap();
+        scheduler
+            .notify(root.join("music/song.mp3"))
+            .unwrap();
+        scheduler.notify(std::env::temp_dir()).unwrap();
+
+        assert_eq!(scheduler.finish(), 1);
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].added.len(), 21);
+        assert_eq!(index.read().unwrap().count_files(), 21);
+    }
+}
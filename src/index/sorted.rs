@@ -0,0 +1,153 @@
+//! Sorted views over the index, used for rendering huge folders page by page
+use std::path::Path;
+
+use super::{IndexEntry, ResourceIndex};
+
+/// Order of entries in [`SortedEntries`]
+///
+/// Entries equal by the key are ordered by paths,
+/// so the order is stable between calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SortKey {
+    Path,
+    /// From the oldest to the most recently modified
+    Modified,
+    /// From the smallest to the largest
+    Size,
+    Id,
+}
+
+/// Entries of the index borrowed in a fixed order
+///
+/// Only references are sorted, entries themselves are never cloned.
+pub struct SortedEntries<'a> {
+    entries: Vec<(&'a Path, &'a IndexEntry)>,
+}
+
+impl<'a> SortedEntries<'a> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (&'a Path, &'a IndexEntry)> + '_ {
+        self.entries.iter().copied()
+    }
+
+    /// Returns at most `limit` entries starting from `offset`,
+    /// the page is empty if `offset` is past the last entry
+    pub fn page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> &[(&'a Path, &'a IndexEntry)] {
+        let start = offset.min(self.entries.len());
+        let end = start
+            .saturating_add(limit)
+            .min(self.entries.len());
+        &self.entries[start..end]
+    }
+}
+
+impl<'a> IntoIterator for SortedEntries<'a> {
+    type Item = (&'a Path, &'a IndexEntry);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl ResourceIndex {
+    /// Returns all entries of the index sorted by the key
+    pub fn iter_sorted_by(&self, key: SortKey) -> SortedEntries<'_> {
+        let mut entries: Vec<(&Path, &IndexEntry)> = self
+            .path2id
+            .iter()
+            .map(|(path, entry)| (path.as_path(), entry))
+            .collect();
+        entries.sort_unstable_by(|(path1, entry1), (path2, entry2)| {
+            let ordering = match key {
+                SortKey::Path => std::cmp::Ordering::Equal,
+                SortKey::Modified => entry1.modified.cmp(&entry2.modified),
+                SortKey::Size => entry1.id.data_size.cmp(&entry2.id.data_size),
+                SortKey::Id => entry1.id.cmp(&entry2.id),
+            };
+            ordering.then_with(|| path1.cmp(path2))
+        });
+        SortedEntries { entries }
+    }
+
+    /// Returns a single page of entries sorted by the key
+    ///
+    /// Every call sorts the whole index, so for rendering many pages
+    /// it is cheaper to keep the result of [`Self::iter_sorted_by`].
+    pub fn page(
+        &self,
+        key: SortKey,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<(&Path, &IndexEntry)> {
+        self.iter_sorted_by(key)
+            .page(offset, limit)
+            .to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn entries_are_sorted_and_paginated() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        fs::write(dir.path().join("c.txt"), "a").unwrap();
+        fs::write(dir.path().join("a.txt"), "ccc").unwrap();
+        fs::write(dir.path().join("b.txt"), "bb").unwrap();
+        fs::write(dir.path().join("d.txt"), "bb").unwrap();
+        let index = ResourceIndex::build(dir.path());
+
+        let names = |entries: &[(&Path, &IndexEntry)]| -> Vec<String> {
+            entries
+                .iter()
+                .map(|(path, _)| {
+                    path.file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect()
+        };
+
+        let by_path = index.iter_sorted_by(SortKey::Path);
+        assert_eq!(by_path.len(), 4);
+        assert_eq!(
+            names(by_path.page(0, 10)),
+            vec!["a.txt", "b.txt", "c.txt", "d.txt"]
+        );
+        assert_eq!(names(by_path.page(1, 2)), vec!["b.txt", "c.txt"]);
+        assert!(by_path.page(4, 2).is_empty());
+        assert!(by_path.page(10, usize::MAX).is_empty());
+
+        // equal sizes are ordered by paths
+        assert_eq!(
+            names(&index.page(SortKey::Size, 0, 4)),
+            vec!["c.txt", "b.txt", "d.txt", "a.txt"]
+        );
+        assert_eq!(names(&index.page(SortKey::Size, 3, 4)), vec!["a.txt"]);
+
+        let by_id: Vec<_> = index
+            .iter_sorted_by(SortKey::Id)
+            .into_iter()
+            .map(|(_, entry)| entry.id)
+            .collect();
+        assert!(by_id.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
@@ -0,0 +1,557 @@
+//! Index kept in an SQLite database instead of memory
+//!
+//! [`ResourceIndex`] loads all entries of the root into memory, which is
+//! too much for roots with millions of files. [`SqliteIndex`] offers the
+//! same operations on top of `.ark/index.sqlite`: lookups are served by
+//! the indices of the database, prefixes of folders are loaded partially,
+//! and several processes can read the database while another one updates
+//! it. Paths are stored relative to the root, so the database survives
+//! moving the root.
+//!
+//! [`ResourceIndex`]: super::ResourceIndex
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use walkdir::WalkDir;
+
+use super::{
+    scan_entry, IndexEntry, IndexOptions, IndexUpdate, RelativePath,
+    REMOTE_ID_PREFIX,
+};
+use crate::query::{Filter, QueryBackend};
+use crate::resource::ResourceId;
+use crate::{ArklibError, Result, ARK_FOLDER, INDEX_DB_FILE};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS entries (
+        path TEXT PRIMARY KEY NOT NULL,
+        id TEXT NOT NULL,
+        modified INTEGER NOT NULL,
+        sampled INTEGER NOT NULL,
+        generation INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS entries_by_id ON entries (id, path);
+";
+
+pub struct SqliteIndex {
+    root: PathBuf,
+    connection: Connection,
+    options: IndexOptions,
+}
+
+/// Row of the entries table
+struct Row {
+    path: String,
+    entry: IndexEntry,
+}
+
+impl SqliteIndex {
+    /// Opens the database of the root, creating it if absent. The database
+    /// is empty until [`SqliteIndex::update_all`] is called.
+    pub fn open<P: AsRef<Path>>(root_path: P) -> Result<Self> {
+        Self::open_with_options(root_path, IndexOptions::default())
+    }
+
+    /// Opens the database of the root, waiting for other processes writing
+    /// it at most [`IndexOptions::lock_timeout`]
+    pub fn open_with_options<P: AsRef<Path>>(
+        root_path: P,
+        options: IndexOptions,
+    ) -> Result<Self> {
+        let root = fs::canonicalize(root_path.as_ref())?;
+        let ark = root.join(ARK_FOLDER);
+        fs::create_dir_all(&ark)?;
+        let connection = Connection::open(ark.join(INDEX_DB_FILE))?;
+        connection.busy_timeout(options.lock_timeout)?;
+        // readers are not blocked by the writer
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(SqliteIndex {
+            root,
+            connection,
+            options,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn options(&self) -> &IndexOptions {
+        &self.options
+    }
+
+    /// Returns the number of entries in the index
+    pub fn count_files(&self) -> Result<usize> {
+        let count: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM entries",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Returns the number of resources in the index
+    pub fn count_resources(&self) -> Result<usize> {
+        let count: i64 = self.connection.query_row(
+            "SELECT COUNT(DISTINCT id) FROM entries",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    pub fn contains(&self, id: &ResourceId) -> Result<bool> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT 1 FROM entries WHERE id = ?1 LIMIT 1",
+                [id.to_string()],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Returns the path of the resource, the first one in the order
+    /// of paths if the resource has aliases
+    pub fn get_path(&self, id: &ResourceId) -> Result<Option<PathBuf>> {
+        Ok(self.aliases(id)?.into_iter().next())
+    }
+
+    /// Returns all paths of the resource, sorted
+    pub fn aliases(&self, id: &ResourceId) -> Result<Vec<PathBuf>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT path FROM entries WHERE id = ?1 ORDER BY path",
+        )?;
+        let paths = statement
+            .query_map([id.to_string()], |row| row.get::<_, String>(0))?
+            .map(|path| Ok(RelativePath::parse(&path?)?.to_path(&self.root)))
+            .collect();
+        paths
+    }
+
+    pub fn get_entry<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Option<IndexEntry>> {
+        let path = RelativePath::new(&self.root, path)?;
+        select_entry(&self.connection, path.as_str())
+    }
+
+    /// Lists resources inside of the folder at any depth together with
+    /// their relative paths, sorted by the paths, without loading
+    /// the rest of the index
+    pub fn entries_with_prefix(
+        &self,
+        prefix: &RelativePath,
+    ) -> Result<Vec<(RelativePath, IndexEntry)>> {
+        self.select(&Filter {
+            folder: Some(prefix.clone()),
+            ..Filter::default()
+        })
+    }
+
+    /// Updates the database based on the current state of the file system,
+    /// reporting changes like [`ResourceIndex::update_all`]
+    ///
+    /// Files are compared with the database one by one, so only changed
+    /// entries are kept in memory. The database is updated in a single
+    /// transaction, readers see either the previous or the updated index.
+    ///
+    /// [`ResourceIndex::update_all`]: super::ResourceIndex::update_all
+    pub fn update_all(&mut self) -> Result<IndexUpdate> {
+        let root = self.root.clone();
+        let options = self.options.clone();
+        let tx = self.connection.transaction()?;
+        let generation: i64 = tx.query_row(
+            "SELECT COALESCE(MAX(generation), 0) + 1 FROM entries",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let mut update = IndexUpdate::default();
+        let mut replaced: Vec<(PathBuf, ResourceId, ResourceId)> = vec![];
+        let mut created: Vec<(String, PathBuf, fs::Metadata)> = vec![];
+        for (path, metadata) in walk(&root) {
+            let relative = match RelativePath::new(&root, &path) {
+                Ok(relative) => relative.as_str().to_string(),
+                Err(_) => continue,
+            };
+            let Some(previous) = select_entry(&tx, &relative)? else {
+                created.push((relative, path, metadata));
+                continue;
+            };
+            let modified = options.clock.modified(&metadata).ok();
+            if modified == Some(previous.modified) {
+                tx.execute(
+                    "UPDATE entries SET generation = ?2 WHERE path = ?1",
+                    params![relative, generation],
+                )?;
+                continue;
+            }
+            match scan_entry(&path, metadata, &options) {
+                Ok(entry) => {
+                    upsert(&tx, &relative, &entry, generation)?;
+                    if entry.id != previous.id {
+                        replaced.push((path, previous.id, entry.id));
+                    }
+                }
+                // not a resource anymore, removed with vanished paths
+                Err(e) => log::warn!("Couldn't scan {}: {}", path.display(), e),
+            }
+        }
+
+        // a created file with the size and modification time
+        // of a vanished one is assumed to be the same file moved
+        let mut vanished: HashMap<(u64, SystemTime), Vec<Row>> = HashMap::new();
+        {
+            let mut statement = tx.prepare(
+                "SELECT path, id, modified, sampled FROM entries
+                 WHERE generation < ?1",
+            )?;
+            for row in statement.query_map([generation], read_row)? {
+                let row = row??;
+                vanished
+                    .entry((row.entry.id.data_size, row.entry.modified))
+                    .or_default()
+                    .push(row);
+            }
+        }
+        let mut moved_paths: HashSet<String> = HashSet::new();
+        for (relative, path, metadata) in created {
+            let key = options
+                .clock
+                .modified(&metadata)
+                .ok()
+                .map(|modified| (metadata.len(), modified));
+            if let Some(from) =
+                key.and_then(|key| vanished.get_mut(&key)?.pop())
+            {
+                tx.execute(
+                    "UPDATE entries SET path = ?2, generation = ?3
+                     WHERE path = ?1",
+                    params![from.path, relative, generation],
+                )?;
+                update.moved.insert(
+                    from.entry.id,
+                    (RelativePath::parse(&from.path)?.to_path(&root), path),
+                );
+                moved_paths.insert(from.path);
+                continue;
+            }
+            match scan_entry(&path, metadata, &options) {
+                Ok(entry) => {
+                    upsert(&tx, &relative, &entry, generation)?;
+                    update.added.insert(path, entry.id);
+                }
+                Err(e) => log::warn!("Couldn't scan {}: {}", path.display(), e),
+            }
+        }
+
+        let stale: Vec<ResourceId> = vanished
+            .into_values()
+            .flatten()
+            .filter(|row| !moved_paths.contains(&row.path))
+            .map(|row| row.entry.id)
+            .collect();
+        tx.execute("DELETE FROM entries WHERE generation < ?1", [generation])?;
+        for (path, previous, id) in replaced {
+            match contains(&tx, &previous)? {
+                true => {
+                    update.added.insert(path, id);
+                }
+                false => {
+                    update.modified.insert(path, (previous, id));
+                }
+            }
+        }
+        // resources are deleted only together with their last alias,
+        // and a resource modified in place is not deleted
+        let modified: HashSet<ResourceId> = update
+            .modified
+            .values()
+            .map(|(previous, _)| *previous)
+            .collect();
+        for id in stale {
+            if !modified.contains(&id) && !contains(&tx, &id)? {
+                update.deleted.insert(id);
+            }
+        }
+        tx.commit()?;
+        Ok(update)
+    }
+}
+
+impl QueryBackend for SqliteIndex {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn pushes_down(&self) -> bool {
+        true
+    }
+
+    fn lookup(
+        &self,
+        id: &ResourceId,
+    ) -> Result<Vec<(RelativePath, IndexEntry)>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT path, id, modified, sampled FROM entries
+             WHERE id = ?1 ORDER BY path",
+        )?;
+        let entries = statement
+            .query_map([id.to_string()], read_row)?
+            .map(|row| {
+                let row = row??;
+                Ok((RelativePath::parse(&row.path)?, row.entry))
+            })
+            .collect();
+        entries
+    }
+
+    /// Selects entries passing the filter exactly, using the index
+    /// of paths for folders
+    fn select(
+        &self,
+        filter: &Filter,
+    ) -> Result<Vec<(RelativePath, IndexEntry)>> {
+        let mut conditions: Vec<String> = vec![];
+        let mut values: Vec<rusqlite::types::Value> = vec![];
+        if let Some(folder) = filter.folder.as_ref().filter(|f| !f.is_root()) {
+            // `0` follows `/` in the order of strings
+            conditions.push("path >= ? AND path < ?".into());
+            values.push(format!("{}/", folder).into());
+            values.push(format!("{}0", folder).into());
+        }
+        if !filter.extensions.is_empty() {
+            // LIKE ignores case of ASCII letters
+            let extensions = vec!["path LIKE ?"; filter.extensions.len()];
+            conditions.push(format!("({})", extensions.join(" OR ")));
+            for extension in &filter.extensions {
+                values.push(format!("%.{}", extension).into());
+            }
+        }
+        if let Some(from) = filter.modified_from {
+            conditions.push("modified >= ?".into());
+            values.push((from as i64).into());
+        }
+        if let Some(to) = filter.modified_to {
+            conditions.push("modified < ?".into());
+            values.push((to as i64).into());
+        }
+        let mut sql =
+            String::from("SELECT path, id, modified, sampled FROM entries");
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY path");
+
+        let mut statement = self.connection.prepare_cached(&sql)?;
+        let entries = statement
+            .query_map(rusqlite::params_from_iter(values), read_row)?
+            .map(|row| {
+                let row = row??;
+                Ok((RelativePath::parse(&row.path)?, row.entry))
+            })
+            .collect();
+        entries
+    }
+}
+
+/// Lists files of the root, skipping hidden ones like
+/// [`ResourceIndex::update_all`] does
+///
+/// [`ResourceIndex::update_all`]: super::ResourceIndex::update_all
+fn walk(root: &Path) -> impl Iterator<Item = (PathBuf, fs::Metadata)> {
+    WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            !entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with('.')
+        })
+        .filter_map(|entry| match entry {
+            Ok(entry) if !entry.file_type().is_dir() => {
+                let path = fs::canonicalize(entry.path()).ok()?;
+                Some((path, entry.metadata().ok()?))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                log::error!("Error during walking: {}", e);
+                None
+            }
+        })
+}
+
+fn read_row(row: &rusqlite::Row) -> rusqlite::Result<Result<Row>> {
+    let path: String = row.get(0)?;
+    let id: String = row.get(1)?;
+    let modified: i64 = row.get(2)?;
+    let sampled: bool = row.get(3)?;
+    // IDs of placeholders are marked like in the index file
+    let (id, remote) = match id.strip_prefix(REMOTE_ID_PREFIX) {
+        Some(id) => (id, true),
+        None => (id.as_str(), false),
+    };
+    Ok(ResourceId::from_str(id).map(|id| Row {
+        path,
+        entry: IndexEntry {
+            id,
+            modified: UNIX_EPOCH + Duration::from_millis(modified as u64),
+            sampled,
+            remote,
+        },
+    }))
+}
+
+fn select_entry(
+    connection: &Connection,
+    path: &str,
+) -> Result<Option<IndexEntry>> {
+    let mut statement = connection.prepare_cached(
+        "SELECT path, id, modified, sampled FROM entries WHERE path = ?1",
+    )?;
+    match statement.query_row([path], read_row).optional()? {
+        Some(row) => Ok(Some(row?.entry)),
+        None => Ok(None),
+    }
+}
+
+fn contains(tx: &Transaction, id: &ResourceId) -> Result<bool> {
+    Ok(tx
+        .query_row(
+            "SELECT 1 FROM entries WHERE id = ?1 LIMIT 1",
+            [id.to_string()],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+fn upsert(
+    tx: &Transaction,
+    path: &str,
+    entry: &IndexEntry,
+    generation: i64,
+) -> Result<()> {
+    let modified = entry
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| ArklibError::Parse)?
+        .as_millis() as i64;
+    let id = match entry.remote {
+        true => format!("{}{}", REMOTE_ID_PREFIX, entry.id),
+        false => entry.id.to_string(),
+    };
+    tx.execute(
+        "INSERT OR REPLACE INTO entries (path, id, modified, sampled, generation)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![path, id, modified, entry.sampled, generation],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::ResourceIndex;
+    use crate::query::{query, Kind, Plan, Query};
+    use tempdir::TempDir;
+
+    #[test]
+    fn sqlite_index_matches_resource_index() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("photos")).unwrap();
+        fs::write(root.join("photos/sea.jpg"), "sea").unwrap();
+        fs::write(root.join("photos/copy.jpg"), "sea").unwrap();
+        fs::write(root.join("notes.md"), "notes").unwrap();
+        fs::write(root.join("draft.md"), "draft!").unwrap();
+
+        let mut index = SqliteIndex::open(&root).unwrap();
+        let mut memory = ResourceIndex::build(&root);
+        let update = index.update_all().unwrap();
+        assert_eq!(update.added.len(), 4);
+        assert_eq!(index.count_files().unwrap(), 4);
+        assert_eq!(index.count_resources().unwrap(), 3);
+        let sea = memory
+            .get_entry(root.join("photos/sea.jpg"))
+            .unwrap()
+            .id;
+        assert_eq!(
+            index.aliases(&sea).unwrap(),
+            [root.join("photos/copy.jpg"), root.join("photos/sea.jpg")]
+        );
+        let photos = RelativePath::parse("photos").unwrap();
+        assert_eq!(index.entries_with_prefix(&photos).unwrap().len(), 2);
+
+        fs::rename(root.join("notes.md"), root.join("photos/notes.md"))
+            .unwrap();
+        fs::remove_file(root.join("photos/copy.jpg")).unwrap();
+        fs::remove_file(root.join("draft.md")).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(root.join("photos/sea.jpg"), "storm").unwrap();
+        let update = index.update_all().unwrap();
+        assert_eq!(update, memory.update_all().unwrap());
+        assert_eq!(update.moved.len(), 1);
+        assert_eq!(update.modified.len(), 1);
+        assert_eq!(update.deleted.len(), 1);
+
+        drop(index);
+        let index = SqliteIndex::open(&root).unwrap();
+        assert_eq!(
+            index
+                .get_entry(root.join("photos/notes.md"))
+                .unwrap()
+                .as_ref(),
+            memory.get_entry(root.join("photos/notes.md"))
+        );
+        assert!(!index.contains(&sea).unwrap());
+    }
+
+    #[test]
+    fn queries_are_pushed_down() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("trips/2023")).unwrap();
+        fs::write(root.join("trips/2023/sea.JPG"), "sea").unwrap();
+        fs::write(root.join("trips/plan.md"), "plan").unwrap();
+        fs::write(root.join("trips.jpg"), "trips").unwrap();
+        let mut index = SqliteIndex::open(&root).unwrap();
+        index.update_all().unwrap();
+        let memory = ResourceIndex::build(&root);
+
+        let photos = Query {
+            folder: Some(RelativePath::parse("trips").unwrap()),
+            kinds: vec![Kind::Image],
+            ..Query::default()
+        };
+        assert_eq!(photos.plan(&index), Plan::Pushdown);
+        let selected = index.select(&photos.filter(true)).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].0.as_str(), "trips/2023/sea.JPG");
+        assert_eq!(
+            query(&index, &photos).unwrap(),
+            query(&memory, &photos).unwrap()
+        );
+        let future = Query {
+            from: Some(
+                chrono::Utc::now().naive_utc() + chrono::Duration::days(1),
+            ),
+            ..Query::default()
+        };
+        assert!(index
+            .select(&future.filter(true))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+//! Reading content of resources by ID
+//!
+//! Servers and viewers built on arklib refer to resources by ID, so the
+//! path is resolved through the index of the root. Only paths which
+//! haven't been modified since they were indexed are opened, so that the
+//! content always matches the ID.
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::ResourceIndex;
+use crate::resource::ResourceId;
+use crate::{provide_index, ArklibError, Result};
+
+/// Stream of the content of a resource, or of a range of it
+#[derive(Debug)]
+pub struct ResourceReader {
+    file: File,
+    path: PathBuf,
+    /// Offset of the range in the file
+    start: u64,
+    len: u64,
+    /// Position relative to the start of the range
+    position: u64,
+}
+
+impl ResourceReader {
+    /// Opens the range of bytes of the resource indexed by the index,
+    /// the range is cut at the end of the resource
+    pub fn open(
+        index: &ResourceIndex,
+        id: ResourceId,
+        range: Range<u64>,
+    ) -> Result<Self> {
+        let (path, mut file) = index
+            .aliases(&id)
+            .into_iter()
+            .find_map(|path| {
+                let entry = index.get_entry(&path)?;
+                let file = File::open(&path).ok()?;
+                let metadata = file.metadata().ok()?;
+                let modified = index.options.clock.modified(&metadata).ok()?;
+                (modified == entry.modified).then_some((path, file))
+            })
+            .ok_or_else(|| {
+                ArklibError::Path(format!(
+                    "Resource {} is not indexed or was modified",
+                    id
+                ))
+            })?;
+
+        let size = file.metadata()?.len();
+        let start = range.start.min(size);
+        let end = range.end.clamp(start, size);
+        file.seek(SeekFrom::Start(start))?;
+        Ok(ResourceReader {
+            file,
+            path,
+            start,
+            len: end - start,
+            position: 0,
+        })
+    }
+
+    /// Path the resource is read from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of bytes of the stream
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for ResourceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.position);
+        let limit = buf.len().min(left as usize);
+        if limit == 0 {
+            return Ok(0);
+        }
+        let read = self.file.read(&mut buf[..limit])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ResourceReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => {
+                self.position.checked_add_signed(offset)
+            }
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seeking before the start of the resource",
+            )
+        })?;
+        self.file
+            .seek(SeekFrom::Start(self.start + position))?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// Opens the content of the resource indexed in the root
+pub fn open_resource<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<ResourceReader> {
+    open_resource_range(root, id, 0..u64::MAX)
+}
+
+/// Opens the range of bytes of the resource, e.g. for HTTP range requests;
+/// the range is cut at the end of the resource
+pub fn open_resource_range<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    range: Range<u64>,
+) -> Result<ResourceReader> {
+    let index = provide_index(root)?;
+    let index = index.read().unwrap_or_else(|e| e.into_inner());
+    ResourceReader::open(&index, id, range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::SystemTime;
+    use tempdir::TempDir;
+
+    #[test]
+    fn resources_are_read_by_id() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "hello, world").unwrap();
+        let index = provide_index(&root).unwrap();
+        let id = index
+            .read()
+            .unwrap()
+            .get_entry(root.join("a.txt"))
+            .unwrap()
+            .id;
+
+        let mut content = String::new();
+        open_resource(&root, id)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "hello, world");
+
+        let mut reader = open_resource_range(&root, id, 7..100).unwrap();
+        assert_eq!(reader.len(), 5);
+        reader.seek(SeekFrom::End(-3)).unwrap();
+        let mut tail = String::new();
+        reader.read_to_string(&mut tail).unwrap();
+        assert_eq!(tail, "rld");
+        assert!(reader.seek(SeekFrom::Current(-10)).is_err());
+
+        fs::write(root.join("a.txt"), "changed").unwrap();
+        let modified = SystemTime::now() + std::time::Duration::from_secs(5);
+        File::options()
+            .write(true)
+            .open(root.join("a.txt"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(open_resource(&root, id).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Detection and migration of the layout of the `.ark` folder
+//!
+//! Roots created by early versions of ARK keep their data right in the
+//! `.ark` folder: the index as a folder, tags and scores as plain text
+//! files of `id:value` lines, and metadata, previews and thumbnails as
+//! plain files named by IDs. [`migrate_layout`] converts such data into
+//! the current layout, where user data lives in `user` and generated data
+//! in `cache`, all of it in [`AtomicFile`]s. Legacy entries are renamed
+//! into the `legacy` backup folder once converted, so an interrupted
+//! migration is simply resumed by the next one.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::index::{IndexLock, IndexOptions};
+use crate::resource::ResourceId;
+use crate::scores::{Score, ScoreStorage};
+use crate::tags::{Tag, TagStorage};
+use crate::{
+    Result, ARK_FOLDER, INDEX_PATH, METADATA_STORAGE_FOLDER,
+    PREVIEWS_STORAGE_FOLDER, SCORE_STORAGE_FILE, TAG_STORAGE_FILE,
+    THUMBNAILS_STORAGE_FOLDER,
+};
+
+/// Folder of `.ark` receiving legacy entries after migration
+pub const LEGACY_BACKUP_FOLDER: &str = "legacy";
+
+const LEGACY_TAGS_FILE: &str = "tags";
+const LEGACY_SCORES_FILE: &str = "scores";
+/// Legacy folders of generated data with their current locations
+const LEGACY_CACHE_FOLDERS: [(&str, &str); 3] = [
+    ("meta", METADATA_STORAGE_FOLDER),
+    ("previews", PREVIEWS_STORAGE_FOLDER),
+    ("thumbnails", THUMBNAILS_STORAGE_FOLDER),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// The root has no `.ark` folder yet
+    Missing,
+    /// Some data is kept in the layout of early versions
+    Legacy,
+    Current,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Legacy entries of `.ark` which were converted and backed up
+    pub migrated: Vec<String>,
+    /// Lines and files which couldn't be parsed, they are kept
+    /// in the backup only
+    pub skipped: usize,
+}
+
+/// Detects the layout of the `.ark` folder of the root
+pub fn detect_layout<P: AsRef<Path>>(root: P) -> Layout {
+    let ark = root.as_ref().join(ARK_FOLDER);
+    if !ark.is_dir() {
+        return Layout::Missing;
+    }
+    match legacy_entries(&ark).is_empty() {
+        true => Layout::Current,
+        false => Layout::Legacy,
+    }
+}
+
+/// Converts legacy data of the root into the current layout, moving
+/// the legacy entries into `.ark/legacy`
+///
+/// Data already present in the current layout takes precedence, except
+/// tags which are merged. The index of the root is locked during the
+/// migration, a legacy index is dropped and rebuilt on next load.
+pub fn migrate_layout<P: AsRef<Path>>(root: P) -> Result<MigrationReport> {
+    let root = root.as_ref();
+    let ark = root.join(ARK_FOLDER);
+    let mut report = MigrationReport::default();
+    let entries = legacy_entries(&ark);
+    if entries.is_empty() {
+        return Ok(report);
+    }
+    let _lock =
+        IndexLock::exclusive(root, IndexOptions::default().lock_timeout)?;
+    let backup = ark.join(LEGACY_BACKUP_FOLDER);
+    fs::create_dir_all(&backup)?;
+
+    for name in entries {
+        let path = ark.join(name);
+        match name {
+            LEGACY_TAGS_FILE => {
+                report.skipped += migrate_tags(&path, &ark)?;
+            }
+            LEGACY_SCORES_FILE => {
+                report.skipped += migrate_scores(&path, &ark)?;
+            }
+            INDEX_PATH => {}
+            _ => {
+                let (_, folder) = LEGACY_CACHE_FOLDERS
+                    .iter()
+                    .find(|(legacy, _)| *legacy == name)
+                    .expect("legacy entries are known");
+                report.skipped += migrate_cache(&path, &ark.join(folder))?;
+            }
+        }
+        fs::rename(&path, unique_backup_path(&backup, name))?;
+        log::info!("Migrated legacy {} of {}", name, root.display());
+        report.migrated.push(name.to_string());
+    }
+    Ok(report)
+}
+
+fn legacy_entries(ark: &Path) -> Vec<&'static str> {
+    let mut entries = vec![];
+    if ark.join(INDEX_PATH).is_dir() {
+        entries.push(INDEX_PATH);
+    }
+    for name in [LEGACY_TAGS_FILE, LEGACY_SCORES_FILE] {
+        if ark.join(name).is_file() {
+            entries.push(name);
+        }
+    }
+    for (name, _) in LEGACY_CACHE_FOLDERS {
+        if ark.join(name).is_dir() {
+            entries.push(name);
+        }
+    }
+    entries
+}
+
+/// Parses `id:value` lines, skipping the version line of the storage.
+/// Returns the number of malformed lines besides the values.
+fn parse_lines(path: &Path) -> Result<(Vec<(ResourceId, String)>, usize)> {
+    let content = fs::read_to_string(path)?;
+    let mut values = vec![];
+    let mut skipped = 0;
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("version") {
+            continue;
+        }
+        let parsed = line
+            .split_once(':')
+            .and_then(|(id, value)| Some((id.parse().ok()?, value)));
+        match parsed {
+            Some((id, value)) => values.push((id, value.to_string())),
+            None => {
+                log::warn!("Malformed line {:?} in {}", line, path.display());
+                skipped += 1;
+            }
+        }
+    }
+    Ok((values, skipped))
+}
+
+fn migrate_tags(path: &Path, ark: &Path) -> Result<usize> {
+    let (lines, skipped) = parse_lines(path)?;
+    let mut legacy: BTreeMap<ResourceId, BTreeSet<Tag>> = BTreeMap::new();
+    for (id, tags) in lines {
+        legacy.entry(id).or_default().extend(
+            tags.split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string),
+        );
+    }
+    let file = AtomicFile::new(ark.join(TAG_STORAGE_FILE))?;
+    modify_json(&file, |storage: &mut Option<TagStorage>| {
+        let tags = &mut storage
+            .get_or_insert_with(TagStorage::default)
+            .tags;
+        for (id, legacy) in &legacy {
+            tags.entry(*id)
+                .or_default()
+                .extend(legacy.iter().cloned());
+        }
+    })?;
+    Ok(skipped)
+}
+
+fn migrate_scores(path: &Path, ark: &Path) -> Result<usize> {
+    let (lines, mut skipped) = parse_lines(path)?;
+    let mut legacy: BTreeMap<ResourceId, Score> = BTreeMap::new();
+    for (id, score) in lines {
+        match score.trim().parse() {
+            Ok(0) => {}
+            Ok(score) => {
+                legacy.insert(id, score);
+            }
+            Err(_) => skipped += 1,
+        }
+    }
+    let file = AtomicFile::new(ark.join(SCORE_STORAGE_FILE))?;
+    modify_json(&file, |storage: &mut Option<ScoreStorage>| {
+        let scores = &mut storage
+            .get_or_insert_with(ScoreStorage::default)
+            .scores;
+        for (id, score) in &legacy {
+            scores.entry(*id).or_insert(*score);
+        }
+    })?;
+    Ok(skipped)
+}
+
+/// Copies plain files named by IDs into atomic files of the folder
+fn migrate_cache(legacy: &Path, folder: &Path) -> Result<usize> {
+    let mut skipped = 0;
+    for entry in fs::read_dir(legacy)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let id: ResourceId = match name.parse() {
+            Ok(id) if entry.path().is_file() => id,
+            _ => {
+                log::warn!("Unexpected legacy entry {}", name);
+                skipped += 1;
+                continue;
+            }
+        };
+        let file = AtomicFile::new(folder.join(id.to_string()))?;
+        let current = file.load()?;
+        if current.version > 0 {
+            continue;
+        }
+        let tmp = file.make_temp()?;
+        (&tmp).write_all(&fs::read(entry.path())?)?;
+        file.compare_and_swap(&current, tmp)?;
+    }
+    Ok(skipped)
+}
+
+/// Legacy entries of several migrations don't overwrite each other
+fn unique_backup_path(backup: &Path, name: &str) -> PathBuf {
+    let mut path = backup.join(name);
+    let mut n = 2;
+    while path.exists() {
+        path = backup.join(format!("{}.{}", name, n));
+        n += 1;
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::ResourceIndex;
+    use crate::scores::score_of;
+    use crate::storage::preview::load_preview;
+    use crate::tags::tags_of;
+    use tempdir::TempDir;
+
+    #[test]
+    fn legacy_layout_is_migrated() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        assert_eq!(detect_layout(&root), Layout::Missing);
+        fs::write(root.join("sea.jpg"), "sea").unwrap();
+        let id = ResourceIndex::build(&root)
+            .get_entry(root.join("sea.jpg"))
+            .unwrap()
+            .id;
+
+        let ark = root.join(ARK_FOLDER);
+        fs::create_dir_all(ark.join("index")).unwrap();
+        fs::create_dir_all(ark.join("previews")).unwrap();
+        fs::write(
+            ark.join("tags"),
+            format!("version 2\n{}:sea, summer\nbroken\n", id),
+        )
+        .unwrap();
+        fs::write(ark.join("scores"), format!("{}:5\n", id)).unwrap();
+        fs::write(ark.join("previews").join(id.to_string()), "png").unwrap();
+        assert_eq!(detect_layout(&root), Layout::Legacy);
+
+        let report = migrate_layout(&root).unwrap();
+        assert_eq!(report.migrated.len(), 4);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(detect_layout(&root), Layout::Current);
+        assert_eq!(
+            tags_of(&root, id).unwrap(),
+            ["sea".to_string(), "summer".to_string()].into()
+        );
+        assert_eq!(score_of(&root, id).unwrap(), 5);
+        assert_eq!(load_preview(&root, id).unwrap().unwrap(), b"png");
+        assert!(ark
+            .join(LEGACY_BACKUP_FOLDER)
+            .join("tags")
+            .is_file());
+
+        let index = ResourceIndex::provide(&root).unwrap();
+        assert!(index.contains(&id));
+        assert_eq!(migrate_layout(&root).unwrap(), MigrationReport::default());
+    }
+}
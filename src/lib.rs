@@ -4,23 +4,78 @@ extern crate lazy_static;
 extern crate canonical_path;
 
 pub mod errors;
-pub use errors::{ArklibError, Result};
+pub use errors::{catch_panic, ArklibError, Result};
 
 pub mod app_id;
+pub mod archive;
+pub mod backup;
+pub mod blob;
+pub mod bundle;
+pub mod cache;
+pub mod capture;
+pub mod collections;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+pub mod doctor;
+pub mod epub;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod folder_properties;
+pub mod geo;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod images;
+pub mod import;
 pub mod index;
+pub mod layout;
 
 pub mod link;
+pub mod notes;
+#[cfg(feature = "ocr")]
+pub mod ocr;
+pub mod office;
+pub mod order;
+pub mod palette;
+#[cfg(feature = "pdfium")]
 pub mod pdf;
+pub mod phash;
+pub mod pins;
+pub mod probe;
+pub mod query;
+pub mod registrar;
+pub mod relations;
+#[cfg(feature = "remote")]
+pub mod remote;
 pub mod resource;
+pub mod scores;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod sync;
+pub mod tags;
+pub mod templates;
+pub mod text;
+pub mod timeline;
+pub mod wiki;
 
 mod atomic;
+mod platform;
 mod storage;
 mod util;
 
-pub use atomic::{modify, modify_json, AtomicFile};
+/// Metadata generated from resources, see [`metadata::load`]
+pub use storage::metadata;
+
+#[cfg(feature = "async")]
+pub use atomic::modify_json_async;
+pub use atomic::{
+    modify, modify_json, subscribe, AtomicFile, ConflictingVersion, Durability,
+    FileChange, Resolution, SwapStrategy,
+};
 
 use index::ResourceIndex;
+use registrar::{RootLease, RootRole};
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
@@ -36,17 +91,33 @@ pub const ARK_FOLDER: &str = ".ark";
 pub const STATS_FOLDER: &str = "stats";
 pub const FAVORITES_FILE: &str = "favorites";
 pub const APP_ID_FILE: &str = "app_id";
+pub const CONFIG_FILE: &str = "config";
 
 // User-defined data
 pub const TAG_STORAGE_FILE: &str = "user/tags";
+pub const TAG_JOURNAL_FILE: &str = "user/tags_journal";
 pub const SCORE_STORAGE_FILE: &str = "user/scores";
 pub const PROPERTIES_STORAGE_FOLDER: &str = "user/properties";
+pub const RELATIONS_STORAGE_FILE: &str = "user/relations";
+pub const COLLECTIONS_STORAGE_FILE: &str = "user/collections";
+pub const ORDER_STORAGE_FOLDER: &str = "user/order";
+pub const FOLDER_PROPERTIES_STORAGE_FOLDER: &str = "user/folders";
+pub const TEMPLATES_STORAGE_FILE: &str = "user/templates";
+pub const PINS_STORAGE_FILE: &str = "user/pins";
 
 // Generated data
 pub const INDEX_PATH: &str = "index";
+pub const INDEX_LOCK_FILE: &str = "index.lock";
+pub const INDEX_DB_FILE: &str = "index.sqlite";
+pub const WRITER_LOCK_FILE: &str = "writer";
+pub const ID_CACHE_FILE: &str = "cache/ids";
+pub const MAPPED_INDEX_FILE: &str = "cache/index.bin";
 pub const METADATA_STORAGE_FOLDER: &str = "cache/metadata";
 pub const PREVIEWS_STORAGE_FOLDER: &str = "cache/previews";
 pub const THUMBNAILS_STORAGE_FOLDER: &str = "cache/thumbnails";
+pub const BLOBS_STORAGE_FOLDER: &str = "cache/blobs";
+pub const GEO_STORAGE_FILE: &str = "cache/geo";
+pub const TIMELINE_STORAGE_FILE: &str = "cache/timeline";
 
 pub type ResourceIndexLock = Arc<RwLock<ResourceIndex>>;
 
@@ -54,6 +125,10 @@ lazy_static! {
     pub static ref REGISTRAR: RwLock<HashMap<CanonicalPathBuf, ResourceIndexLock>> =
         RwLock::new(HashMap::new());
 }
+lazy_static! {
+    static ref LEASES: RwLock<HashMap<CanonicalPathBuf, RootLease>> =
+        RwLock::new(HashMap::new());
+}
 lazy_static! {
     pub static ref APP_ID_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
 }
@@ -61,7 +136,9 @@ lazy_static! {
 pub fn initialize() {
     INIT.call_once(|| {
         log::info!("Initializing arklib");
-        app_id::load("./").unwrap();
+        if let Err(e) = app_id::load("./") {
+            log::error!("Couldn't load the app id: {}", e);
+        }
     });
 }
 
@@ -71,7 +148,9 @@ pub fn provide_index<P: AsRef<Path>>(
     let root_path = CanonicalPathBuf::canonicalize(root_path)?;
 
     {
-        let registrar = REGISTRAR.read().unwrap();
+        let registrar = REGISTRAR
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
 
         if let Some(index) = registrar.get(&root_path) {
             log::info!("Index has been registered before");
@@ -80,11 +159,18 @@ pub fn provide_index<P: AsRef<Path>>(
     }
 
     log::info!("Index has not been registered before");
-    match ResourceIndex::provide(&root_path) {
+    let lease = RootLease::acquire(&root_path)?;
+    match open_index(root_path.as_path(), lease.is_writer()) {
         Ok(index) => {
-            let mut registrar = REGISTRAR.write().unwrap();
+            let mut registrar = REGISTRAR
+                .write()
+                .unwrap_or_else(|e| e.into_inner());
             let arc = Arc::new(RwLock::new(index));
-            registrar.insert(root_path, arc.clone());
+            registrar.insert(root_path.clone(), arc.clone());
+            LEASES
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(root_path, lease);
 
             log::info!("Index was registered");
             Ok(arc)
@@ -92,3 +178,126 @@ pub fn provide_index<P: AsRef<Path>>(
         Err(e) => Err(e),
     }
 }
+
+/// Returns the role of this process for a root registered
+/// by [`provide_index`]
+///
+/// Only the writer is supposed to store the index and user data of the root
+/// and to run watchers, so that several processes can open the same root.
+pub fn root_role<P: AsRef<Path>>(root_path: P) -> Option<RootRole> {
+    let root_path = CanonicalPathBuf::canonicalize(root_path).ok()?;
+    LEASES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&root_path)
+        .map(|lease| lease.role())
+}
+
+/// Reads the registered index of the root again, e.g. after the root
+/// was modified while the app was suspended, or provides it if it hasn't
+/// been registered
+///
+/// The index is replaced in place, so handles returned by
+/// [`provide_index`] before see the fresh state.
+pub fn refresh_index<P: AsRef<Path>>(
+    root_path: P,
+) -> Result<Arc<RwLock<ResourceIndex>>> {
+    let root_path = CanonicalPathBuf::canonicalize(root_path)?;
+    let registered = REGISTRAR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&root_path)
+        .cloned();
+    let Some(arc) = registered else {
+        return provide_index(root_path);
+    };
+
+    // only the role is taken from the lease, the index is rebuilt
+    // without blocking other roots
+    let writer = {
+        let mut leases = LEASES.write().unwrap_or_else(|e| e.into_inner());
+        match leases.entry(root_path.clone()) {
+            Entry::Occupied(entry) => entry.get().is_writer(),
+            Entry::Vacant(entry) => entry
+                .insert(RootLease::acquire(&root_path)?)
+                .is_writer(),
+        }
+    };
+    let index = open_index(root_path.as_path(), writer)?;
+    *arc.write().unwrap_or_else(|e| e.into_inner()) = index;
+    log::info!("Index of {} was refreshed", root_path.display());
+    Ok(arc)
+}
+
+/// Drops the registered index of the root and releases the root, so that
+/// another process can become its writer
+///
+/// Returns whether the index had been registered. Handles returned by
+/// [`provide_index`] before stay usable, but are not updated anymore.
+pub fn forget_index<P: AsRef<Path>>(root_path: P) -> bool {
+    let Ok(root_path) = CanonicalPathBuf::canonicalize(root_path) else {
+        return false;
+    };
+    let removed = REGISTRAR
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&root_path)
+        .is_some();
+    LEASES
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&root_path);
+    if removed {
+        log::info!("Index of {} was forgotten", root_path.display());
+    }
+    removed
+}
+
+/// Returns the roots of indexes registered by [`provide_index`], sorted
+pub fn registered_roots() -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = REGISTRAR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .keys()
+        .map(|root| root.as_path().to_path_buf())
+        .collect();
+    roots.sort();
+    roots
+}
+
+/// Provides the index as the writer, or loads and updates it in memory
+/// as a reader without storing
+fn open_index(root_path: &Path, writer: bool) -> Result<ResourceIndex> {
+    let profile = index::load_profile(root_path).unwrap_or_else(|e| {
+        log::warn!("Couldn't load the indexing profile: {}", e);
+        Default::default()
+    });
+    let mut options = profile.index_options();
+    options.namespace = resource::load_namespace(root_path)?;
+    options.allow_nested = index::is_nesting_allowed(root_path)?;
+    match probe::capabilities(root_path) {
+        Ok(capabilities) => {
+            if options.case_sensitivity == index::CaseSensitivity::Detect {
+                options.case_sensitivity = capabilities.case_sensitivity();
+            }
+            options.mirror_ids_to_xattr &= capabilities.xattrs;
+            options.clock = index::SharedClock::new(index::SystemClock::new(
+                capabilities.mtime_granularity,
+            ));
+        }
+        Err(e) => log::warn!("Couldn't probe the filesystem: {}", e),
+    }
+    if writer {
+        return ResourceIndex::provide_with_options(root_path, options);
+    }
+    match ResourceIndex::load_with_options(root_path, options.clone()) {
+        Ok(mut index) => {
+            index.update_all()?;
+            Ok(index)
+        }
+        Err(e) => {
+            log::warn!("{}", e);
+            Ok(ResourceIndex::build_with_options(root_path, options))
+        }
+    }
+}
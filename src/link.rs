@@ -1,20 +1,37 @@
+use crate::provide_index;
 use crate::resource::{ResourceId, ResourceIdTrait};
-use crate::storage::meta::store_metadata;
+use crate::storage::metadata;
+use crate::storage::preview::store_preview;
 use crate::storage::prop::store_properties;
+use crate::util::runtime::block_on;
 use crate::{
     storage::prop::load_raw_properties, AtomicFile, Result, ARK_FOLDER,
-    PREVIEWS_STORAGE_FOLDER, PROPERTIES_STORAGE_FOLDER,
+    PROPERTIES_STORAGE_FOLDER,
 };
-use reqwest::header::HeaderValue;
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::path::Path;
+use std::path::PathBuf;
 use std::str::{self, FromStr};
-use std::{io::Write, path::PathBuf};
 use url::Url;
 
-#[derive(Debug, Deserialize, Serialize)]
+mod bookmarks;
+mod fetcher;
+pub use bookmarks::{import_bookmarks, Format, ImportFailure, ImportReport};
+pub use fetcher::{
+    FetcherConfig, HttpFetcher, LinkFetcher, DEFAULT_USER_AGENT,
+};
+
+/// Query parameters added by analytics and ad networks,
+/// which don't change the page
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "yclid",
+    "igshid", "mc_cid", "mc_eid", "_ga", "_gl", "ref_src", "si",
+];
+const TRACKING_PARAM_PREFIXES: &[&str] = &["utm_", "pk_", "hsa_"];
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Link {
     pub url: Url,
     pub prop: Properties,
@@ -40,10 +57,66 @@ fn temp_and_move(
     Ok(())
 }
 
+/// Brings the URL into the canonical form, so that the same page
+/// saved twice gets the same ID
+///
+/// Scheme and host are lowercased and default ports are dropped by
+/// [`Url`] itself. Tracking parameters, empty queries and fragments, and
+/// trailing slashes of paths are removed.
+pub fn canonicalize_url(url: &Url) -> Url {
+    let mut url = url.clone();
+
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| {
+            let name = name.to_ascii_lowercase();
+            !TRACKING_PARAMS.contains(&name.as_str())
+                && !TRACKING_PARAM_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+        })
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else if url.query_pairs().count() != query.len() {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    if url.fragment() == Some("") {
+        url.set_fragment(None);
+    }
+
+    let path = url.path();
+    if path.len() > 1 && path.ends_with('/') {
+        let trimmed = path.trim_end_matches('/').to_string();
+        url.set_path(&trimmed);
+    }
+    url
+}
+
+/// Finds the file of a link to the same page in the root, whether it
+/// was saved with the canonical URL or exactly the same one
+pub fn find_existing_link<P: AsRef<Path>>(
+    root: P,
+    url: &Url,
+) -> Result<Option<PathBuf>> {
+    let index = provide_index(root)?;
+    let index = index.read().unwrap_or_else(|e| e.into_inner());
+    for url in [canonicalize_url(url), url.clone()] {
+        let id = ResourceId::compute_bytes(url.as_str().as_bytes())?;
+        if let Some(path) = index.get_path(&id) {
+            return Ok(Some(path.to_path_buf()));
+        }
+    }
+    Ok(None)
+}
+
 impl Link {
+    /// Creates a link to the page, the URL is canonicalized
+    /// using [`canonicalize_url`]
     pub fn new(url: Url, title: String, desc: Option<String>) -> Self {
         Self {
-            url,
+            url: canonicalize_url(&url),
             prop: Properties { title, desc },
         }
     }
@@ -98,22 +171,30 @@ impl Link {
         root: P,
         with_preview: bool,
     ) -> Result<()> {
-        let id = self.id()?;
-        let id_string = id.to_string();
+        let fetcher = HttpFetcher::new(&FetcherConfig::default())?;
+        self.save_with(root, with_preview, &fetcher).await
+    }
 
+    /// Saves the link, fetching its metadata and preview with `fetcher`
+    pub async fn save_with<P: AsRef<Path>, F: LinkFetcher>(
+        &self,
+        root: P,
+        with_preview: bool,
+        fetcher: &F,
+    ) -> Result<()> {
         // Resources are stored in the folder chosen by user
-        let bytes = self.url.as_str().as_bytes();
-        temp_and_move(bytes, root.as_ref(), &id_string)?;
-        //User defined properties
-        store_properties(&root, id, &self.prop)?;
+        let id = self.id()?;
+        self.write_into(&root, root.as_ref())?;
 
         // Generated data
-        if let Ok(graph) = self.get_preview().await {
+        if let Ok(graph) = self.get_preview_with(fetcher).await {
             log::debug!("Trying to save: {with_preview} with {graph:?}");
 
-            store_metadata(&root, id, &graph)?;
+            metadata::store(&root, id, &graph)?;
             if with_preview {
-                if let Some(preview_data) = graph.fetch_image().await {
+                if let Some(preview_data) =
+                    graph.fetch_image_with(fetcher).await
+                {
                     self.save_preview(root, preview_data, &id)?;
                 }
             }
@@ -121,58 +202,53 @@ impl Link {
         Ok(())
     }
 
+    /// Writes the link file into the folder inside of the root,
+    /// together with the user defined properties
+    pub(crate) fn write_into<P: AsRef<Path>>(
+        &self,
+        root: P,
+        dir: &Path,
+    ) -> Result<PathBuf> {
+        let id = self.id()?;
+        let id_string = id.to_string();
+        let bytes = self.url.as_str().as_bytes();
+        temp_and_move(bytes, dir, &id_string)?;
+        //User defined properties
+        store_properties(&root, id, &self.prop)?;
+        Ok(dir.join(id_string))
+    }
+
     fn save_preview<P: AsRef<Path>>(
         &self,
         root: P,
         image_data: Vec<u8>,
         id: &ResourceId,
     ) -> Result<()> {
-        let path = root
-            .as_ref()
-            .join(ARK_FOLDER)
-            .join(PREVIEWS_STORAGE_FOLDER)
-            .join(id.to_string());
-        let file = AtomicFile::new(path)?;
-        let tmp = file.make_temp()?;
-        (&tmp).write_all(&image_data)?;
-        let current_preview = file.load()?;
-        file.compare_and_swap(&current_preview, tmp)?;
-        Ok(())
+        store_preview(root, *id, &image_data)
     }
 
     /// Get OGP metadata of the link (synced).
+    ///
+    /// Runs on a runtime shared by all calls, so it is safe to call
+    /// from async contexts as well.
     pub fn get_preview_synced(&self) -> Result<OpenGraph> {
-        let runtime =
-            tokio::runtime::Runtime::new().expect("Unable to create a runtime");
-        return runtime.block_on(self.get_preview());
+        let link = self.clone();
+        block_on(async move { link.get_preview().await })?
     }
 
     /// Get OGP metadata of the link.
     pub async fn get_preview(&self) -> Result<OpenGraph> {
-        let mut header = reqwest::header::HeaderMap::new();
-        header.insert(
-            "User-Agent",
-            HeaderValue::from_static(
-                "Mozilla/5.0 (X11; Linux x86_64; rv:102.0) Gecko/20100101 Firefox/102.0",
-            ),
-        );
-        let client = reqwest::Client::builder()
-            .default_headers(header)
-            .build()?;
-        let url = self.url.to_string();
-        let scraper = client.get(url).send().await?.text().await?;
-        let html = Html::parse_document(scraper.as_str());
-        let title =
-            select_og(&html, OpenGraphTag::Title).or(select_title(&html));
-        Ok(OpenGraph {
-            title,
-            description: select_og(&html, OpenGraphTag::Description)
-                .or(select_desc(&html)),
-            url: select_og(&html, OpenGraphTag::Url),
-            image: select_og(&html, OpenGraphTag::Image),
-            object_type: select_og(&html, OpenGraphTag::Type),
-            locale: select_og(&html, OpenGraphTag::Locale),
-        })
+        let fetcher = HttpFetcher::new(&FetcherConfig::default())?;
+        self.get_preview_with(&fetcher).await
+    }
+
+    /// Get OGP metadata of the link using `fetcher`.
+    pub async fn get_preview_with<F: LinkFetcher>(
+        &self,
+        fetcher: &F,
+    ) -> Result<OpenGraph> {
+        let page = fetcher.fetch_page(&self.url).await?;
+        Ok(OpenGraph::parse(page.as_str()))
     }
 
     fn load_url(path: PathBuf) -> Result<Url> {
@@ -235,14 +311,44 @@ pub struct OpenGraph {
     locale: Option<String>,
 }
 impl OpenGraph {
-    pub async fn fetch_image(&self) -> Option<Vec<u8>> {
-        if let Some(url) = &self.image {
-            let res = reqwest::get(url).await.unwrap();
-            Some(res.bytes().await.unwrap().to_vec())
-        } else {
-            None
+    /// Extracts OGP metadata from a web page, falling back to the title
+    /// and the description of the page. Malformed pages yield metadata
+    /// with missing fields.
+    pub fn parse(page: &str) -> Self {
+        let html = Html::parse_document(page);
+        let title =
+            select_og(&html, OpenGraphTag::Title).or(select_title(&html));
+        OpenGraph {
+            title,
+            description: select_og(&html, OpenGraphTag::Description)
+                .or(select_desc(&html)),
+            url: select_og(&html, OpenGraphTag::Url),
+            image: select_og(&html, OpenGraphTag::Image),
+            object_type: select_og(&html, OpenGraphTag::Type),
+            locale: select_og(&html, OpenGraphTag::Locale),
         }
     }
+
+    pub async fn fetch_image(&self) -> Option<Vec<u8>> {
+        let fetcher = HttpFetcher::new(&FetcherConfig::default()).ok()?;
+        self.fetch_image_with(&fetcher).await
+    }
+
+    /// Fetch the preview image (synced), see [`Link::get_preview_synced`]
+    pub fn fetch_image_synced(&self) -> Option<Vec<u8>> {
+        let graph = self.clone();
+        block_on(async move { graph.fetch_image().await })
+            .ok()
+            .flatten()
+    }
+
+    pub async fn fetch_image_with<F: LinkFetcher>(
+        &self,
+        fetcher: &F,
+    ) -> Option<Vec<u8>> {
+        let url = Url::parse(self.image.as_ref()?).ok()?;
+        fetcher.fetch_bytes(&url).await.ok()
+    }
 }
 
 /// OpenGraphTag meta tags collection
@@ -299,6 +405,7 @@ impl OpenGraphTag {
 async fn test_create_link_file() {
     crate::initialize();
 
+    use crate::PREVIEWS_STORAGE_FOLDER;
     use tempdir::TempDir;
 
     let dir = TempDir::new("arklib_test").unwrap();
@@ -321,7 +428,7 @@ async fn test_create_link_file() {
         let url: Url =
             Url::from_str(str::from_utf8(current_bytes.as_bytes()).unwrap())
                 .unwrap();
-        assert_eq!(url.as_str(), "https://kaydee.net/blog/open-graph-image/");
+        assert_eq!(url.as_str(), "https://kaydee.net/blog/open-graph-image");
         let link = Link::load(root, &path).unwrap();
         assert_eq!(link.url.as_str(), url.as_str());
         assert_eq!(link.prop.desc.unwrap(), "test_desc");
@@ -339,3 +446,133 @@ async fn test_create_link_file() {
         }
     }
 }
+
+#[test]
+fn malformed_pages_are_parsed() {
+    let graph = OpenGraph::parse(
+        "<html><head><title>Title</title>\
+         <meta property=\"og:title\" content=\"OG title\">\
+         <meta name=\"description\" content=\"Description\"",
+    );
+    assert_eq!(graph.title.as_deref(), Some("OG title"));
+    assert_eq!(graph.image, None);
+
+    let graph = OpenGraph::parse("<title></title><meta property=og:url>");
+    assert_eq!(graph.title, None);
+    assert_eq!(graph.url, None);
+}
+
+#[test]
+fn urls_are_canonicalized() {
+    let canonical =
+        |url: &str| canonicalize_url(&Url::parse(url).unwrap()).to_string();
+    assert_eq!(
+        canonical(
+            "HTTPS://Example.COM:443/Blog/Post/?utm_source=x&id=5&fbclid=y#"
+        ),
+        "https://example.com/Blog/Post?id=5"
+    );
+    assert_eq!(
+        canonical("https://example.com/?utm_medium=social"),
+        "https://example.com/"
+    );
+    assert_eq!(
+        canonical("https://example.com/search?q=a+b&page=2#results"),
+        "https://example.com/search?q=a+b&page=2#results"
+    );
+}
+
+#[test]
+fn existing_links_are_found() {
+    use tempdir::TempDir;
+
+    let dir = TempDir::new("arklib_test").unwrap();
+    let link = Link::new(
+        Url::parse("https://example.com/page/?utm_campaign=shelf").unwrap(),
+        String::from("title"),
+        None,
+    );
+    let path = dir.path().join(link.id().unwrap().to_string());
+    std::fs::write(&path, link.url.as_str()).unwrap();
+    let legacy = Url::parse("https://example.com/legacy/").unwrap();
+    let legacy_id = ResourceId::compute_bytes(legacy.as_str().as_bytes());
+    let legacy_path = dir.path().join(legacy_id.unwrap().to_string());
+    std::fs::write(&legacy_path, legacy.as_str()).unwrap();
+
+    let found = |url: &str| {
+        find_existing_link(dir.path(), &Url::parse(url).unwrap()).unwrap()
+    };
+    let path = std::fs::canonicalize(path).unwrap();
+    let legacy_path = std::fs::canonicalize(legacy_path).unwrap();
+    assert_eq!(found("https://EXAMPLE.com/page?fbclid=1"), Some(path));
+    assert_eq!(found("https://example.com/legacy/"), Some(legacy_path));
+    assert_eq!(found("https://example.com/other"), None);
+}
+
+#[tokio::test]
+async fn links_are_saved_with_injected_fetcher() {
+    use crate::PREVIEWS_STORAGE_FOLDER;
+    use std::collections::HashMap;
+    use tempdir::TempDir;
+
+    struct MockFetcher(HashMap<String, Vec<u8>>);
+
+    impl LinkFetcher for MockFetcher {
+        async fn fetch_page(&self, url: &Url) -> Result<String> {
+            let bytes = self.fetch_bytes(url).await?;
+            Ok(String::from_utf8_lossy(&bytes).to_string())
+        }
+
+        async fn fetch_bytes(&self, url: &Url) -> Result<Vec<u8>> {
+            self.0
+                .get(url.as_str())
+                .cloned()
+                .ok_or(crate::ArklibError::Network)
+        }
+    }
+
+    crate::app_id::load(std::env::temp_dir()).unwrap();
+    let dir = TempDir::new("arklib_test").unwrap();
+    let fetcher = MockFetcher(HashMap::from([
+        (
+            "https://example.com/article".to_string(),
+            b"<meta property=\"og:image\" content=\"https://cdn.example.com/a.png\">\
+              <meta name=\"description\" content=\"Article\">"
+                .to_vec(),
+        ),
+        ("https://cdn.example.com/a.png".to_string(), vec![1, 2, 3]),
+    ]));
+    let link = Link::new(
+        Url::parse("https://example.com/article").unwrap(),
+        String::from("title"),
+        None,
+    );
+    let graph = link.get_preview_with(&fetcher).await.unwrap();
+    assert_eq!(graph.description.as_deref(), Some("Article"));
+
+    link.save_with(dir.path(), true, &fetcher)
+        .await
+        .unwrap();
+    let id = link.id().unwrap();
+    let preview = dir
+        .path()
+        .join(ARK_FOLDER)
+        .join(PREVIEWS_STORAGE_FOLDER)
+        .join(id.to_string());
+    let preview = AtomicFile::new(preview).unwrap().load().unwrap();
+    assert_eq!(preview.read_content().unwrap(), vec![1, 2, 3]);
+    let loaded = Link::load(dir.path(), &dir.path().join(id.to_string()));
+    assert_eq!(loaded.unwrap().prop.title, "title");
+}
+
+#[tokio::test]
+async fn synced_preview_is_safe_in_async_context() {
+    // nothing listens on the port, the call must fail without panicking
+    let link = Link::new(
+        Url::parse("http://127.0.0.1:9/").unwrap(),
+        String::from("title"),
+        None,
+    );
+    assert!(link.get_preview_synced().is_err());
+    assert_eq!(OpenGraph::default().fetch_image_synced(), None);
+}
@@ -0,0 +1,393 @@
+//! Import of links from bookmarks exported by browsers and read-later apps
+//!
+//! Every bookmark becomes a link file. Bookmark folders become folders
+//! of the root, so that the collections of the user are kept. Pages which
+//! are already in the root, compared by canonical URLs, are skipped.
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+use url::Url;
+
+use super::Link;
+use crate::util::fs::safe_file_name;
+use crate::{provide_index, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `bookmarks.html` exported by Firefox, Chrome, Safari and Edge
+    NetscapeHtml,
+    /// `Bookmarks` file from the profile folder of Chromium browsers
+    ChromeJson,
+    /// `ril_export.html` exported by Pocket, sections like "Unread"
+    /// become folders
+    Pocket,
+}
+
+/// Bookmark which couldn't be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportFailure {
+    /// Title of the bookmark, or its URL if untitled
+    pub entry: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Paths of created link files
+    pub imported: Vec<PathBuf>,
+    /// Canonical URLs of pages which are in the root already
+    pub duplicates: Vec<Url>,
+    pub failed: Vec<ImportFailure>,
+}
+
+struct Bookmark {
+    title: String,
+    url: String,
+    /// Names of the folders containing the bookmark, outermost first
+    folders: Vec<String>,
+}
+
+/// Imports all bookmarks of the export into the root
+///
+/// Fails only if the export can't be read or parsed as a whole,
+/// failures of single bookmarks are listed in the report.
+pub fn import_bookmarks<P: AsRef<Path>, R: Read>(
+    root: P,
+    mut reader: R,
+    format: Format,
+) -> Result<ImportReport> {
+    let root = root.as_ref();
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    let bookmarks = match format {
+        Format::NetscapeHtml => parse_netscape(&content),
+        Format::ChromeJson => parse_chrome(&content)?,
+        Format::Pocket => parse_pocket(&content),
+    };
+    log::info!("Importing {} bookmarks", bookmarks.len());
+
+    let mut known: HashSet<_> = {
+        let index = provide_index(root)?;
+        let index = index.read().unwrap_or_else(|e| e.into_inner());
+        index.ids().copied().collect()
+    };
+    let mut report = ImportReport::default();
+    for bookmark in bookmarks {
+        let entry = match bookmark.title.is_empty() {
+            true => bookmark.url.clone(),
+            false => bookmark.title.clone(),
+        };
+        let url = match Url::parse(&bookmark.url) {
+            Ok(url) => url,
+            Err(e) => {
+                report.failed.push(ImportFailure {
+                    entry,
+                    reason: format!("Invalid URL: {}", e),
+                });
+                continue;
+            }
+        };
+        let link = Link::new(url, entry.clone(), None);
+        let result = link.id().and_then(|id| {
+            if !known.insert(id) {
+                return Ok(None);
+            }
+            let dir = bookmark
+                .folders
+                .iter()
+                .fold(root.to_path_buf(), |dir, folder| {
+                    dir.join(safe_file_name(folder))
+                });
+            fs::create_dir_all(&dir)?;
+            link.write_into(root, &dir).map(Some)
+        });
+        match result {
+            Ok(Some(path)) => report.imported.push(path),
+            Ok(None) => report.duplicates.push(link.url),
+            Err(e) => report.failed.push(ImportFailure {
+                entry,
+                reason: e.to_string(),
+            }),
+        }
+    }
+    log::info!(
+        "Imported {} bookmarks, {} duplicates, {} failed",
+        report.imported.len(),
+        report.duplicates.len(),
+        report.failed.len()
+    );
+    Ok(report)
+}
+
+fn text(element: ElementRef) -> String {
+    element
+        .text()
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Folders are `<DT><H3>` followed by a `<DL>` of their bookmarks,
+/// which the HTML parser nests either into the `<DT>` or next to it
+fn parse_netscape(content: &str) -> Vec<Bookmark> {
+    let html = Html::parse_document(content);
+    let list = Selector::parse("dl").unwrap();
+    let mut bookmarks = vec![];
+    if let Some(list) = html.select(&list).next() {
+        walk_netscape(list, &mut vec![], &mut bookmarks);
+    }
+    bookmarks
+}
+
+fn walk_netscape(
+    list: ElementRef,
+    folders: &mut Vec<String>,
+    bookmarks: &mut Vec<Bookmark>,
+) {
+    let mut pending = None;
+    for child in list.children().filter_map(ElementRef::wrap) {
+        match child.value().name() {
+            "dt" => {
+                pending = None;
+                for part in child.children().filter_map(ElementRef::wrap) {
+                    match part.value().name() {
+                        "a" => {
+                            if let Some(url) = part.value().attr("href") {
+                                bookmarks.push(Bookmark {
+                                    title: text(part),
+                                    url: url.to_string(),
+                                    folders: folders.clone(),
+                                });
+                            }
+                        }
+                        "h3" => pending = Some(text(part)),
+                        "dl" => {
+                            folders.push(pending.take().unwrap_or_default());
+                            walk_netscape(part, folders, bookmarks);
+                            folders.pop();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            "dl" => {
+                folders.push(pending.take().unwrap_or_default());
+                walk_netscape(child, folders, bookmarks);
+                folders.pop();
+            }
+            // paragraphs wrapping entries in some exports
+            "p" | "dd" => walk_netscape(child, folders, bookmarks),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChromeBookmarks {
+    roots: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ChromeNode {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    url: Option<String>,
+    #[serde(default)]
+    children: Vec<ChromeNode>,
+}
+
+fn parse_chrome(content: &str) -> Result<Vec<Bookmark>> {
+    let bookmarks: ChromeBookmarks = serde_json::from_str(content)?;
+    let mut result = vec![];
+    // roots are like "bookmark_bar" and "other", with
+    // human-readable names like "Bookmarks bar"
+    for root in bookmarks.roots.into_values() {
+        if let Ok(node) = serde_json::from_value::<ChromeNode>(root) {
+            walk_chrome(node, &mut vec![], &mut result);
+        }
+    }
+    Ok(result)
+}
+
+fn walk_chrome(
+    node: ChromeNode,
+    folders: &mut Vec<String>,
+    bookmarks: &mut Vec<Bookmark>,
+) {
+    match (node.kind.as_str(), node.url) {
+        ("url", Some(url)) => bookmarks.push(Bookmark {
+            title: node.name,
+            url,
+            folders: folders.clone(),
+        }),
+        ("folder", _) => {
+            folders.push(node.name);
+            for child in node.children {
+                walk_chrome(child, folders, bookmarks);
+            }
+            folders.pop();
+        }
+        _ => {}
+    }
+}
+
+/// Sections are `<h1>` headers followed by `<ul>` lists of links
+fn parse_pocket(content: &str) -> Vec<Bookmark> {
+    let html = Html::parse_document(content);
+    let selector = Selector::parse("h1, li a").unwrap();
+    let mut section = vec![];
+    let mut bookmarks = vec![];
+    for element in html.select(&selector) {
+        match element.value().name() {
+            "h1" => section = vec![text(element)],
+            _ => {
+                if let Some(url) = element.value().attr("href") {
+                    bookmarks.push(Bookmark {
+                        title: text(element),
+                        url: url.to_string(),
+                        folders: section.clone(),
+                    });
+                }
+            }
+        }
+    }
+    bookmarks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    /// Properties of links are stored per device
+    fn root() -> TempDir {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        TempDir::new("arklib_test").unwrap()
+    }
+
+    const NETSCAPE: &str = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks</H1>
+<DL><p>
+    <DT><H3 ADD_DATE="1690000000">Recipes</H3>
+    <DL><p>
+        <DT><A HREF="https://example.com/pasta/?utm_source=rss">Pasta</A>
+        <DT><H3>Desserts/Cakes</H3>
+        <DL><p>
+            <DT><A HREF="https://example.com/cake">Cake</A>
+        </DL><p>
+    </DL><p>
+    <DT><A HREF="https://example.com/news">News</A>
+    <DT><A HREF="not a url">Broken</A>
+    <DT><A HREF="https://example.com/pasta">Pasta again</A>
+</DL><p>
+"#;
+
+    fn folders(report: &ImportReport, root: &Path) -> Vec<String> {
+        let mut folders: Vec<String> = report
+            .imported
+            .iter()
+            .map(|path| {
+                let folder = path.parent().unwrap().strip_prefix(root).unwrap();
+                folder.to_string_lossy().to_string()
+            })
+            .collect();
+        folders.sort();
+        folders
+    }
+
+    #[test]
+    fn netscape_bookmarks_are_imported() {
+        let dir = root();
+        let report = import_bookmarks(
+            dir.path(),
+            NETSCAPE.as_bytes(),
+            Format::NetscapeHtml,
+        )
+        .unwrap();
+
+        assert_eq!(
+            folders(&report, dir.path()),
+            vec!["", "Recipes", "Recipes/Desserts_Cakes"]
+        );
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].entry, "Broken");
+        assert_eq!(
+            report.duplicates,
+            vec![Url::parse("https://example.com/pasta").unwrap()]
+        );
+        let pasta = dir.path().join("Recipes").join(
+            Link::new(
+                Url::parse("https://example.com/pasta").unwrap(),
+                "".into(),
+                None,
+            )
+            .id()
+            .unwrap()
+            .to_string(),
+        );
+        assert_eq!(
+            fs::read_to_string(pasta).unwrap(),
+            "https://example.com/pasta"
+        );
+    }
+
+    #[test]
+    fn chrome_bookmarks_are_imported() {
+        let dir = root();
+        let json = r#"{"roots": {
+            "bookmark_bar": {"name": "Bookmarks bar", "type": "folder", "children": [
+                {"name": "Docs", "type": "url", "url": "https://docs.rs/"},
+                {"name": "Rust", "type": "folder", "children": [
+                    {"name": "Book", "type": "url", "url": "https://doc.rust-lang.org/book/"}
+                ]}
+            ]},
+            "other": {"name": "Other bookmarks", "type": "folder", "children": []},
+            "sync_transaction_version": "1"
+        }, "version": 1}"#;
+        let report =
+            import_bookmarks(dir.path(), json.as_bytes(), Format::ChromeJson)
+                .unwrap();
+        assert_eq!(
+            folders(&report, dir.path()),
+            vec!["Bookmarks bar", "Bookmarks bar/Rust"]
+        );
+        assert!(report.failed.is_empty());
+
+        assert!(import_bookmarks(
+            dir.path(),
+            "{".as_bytes(),
+            Format::ChromeJson
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn pocket_export_is_imported() {
+        let dir = root();
+        let html = r#"<!DOCTYPE html><html><body>
+<h1>Unread</h1>
+<ul>
+<li><a href="https://example.com/article" time_added="1690000000" tags="">Article</a></li>
+</ul>
+<h1>Read Archive</h1>
+<ul>
+<li><a href="https://example.com/old" time_added="1600000000" tags="">Old</a></li>
+</ul>
+</body></html>"#;
+        let report =
+            import_bookmarks(dir.path(), html.as_bytes(), Format::Pocket)
+                .unwrap();
+        assert_eq!(
+            folders(&report, dir.path()),
+            vec!["Read Archive", "Unread"]
+        );
+    }
+}
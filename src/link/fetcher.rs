@@ -0,0 +1,176 @@
+//! Fetching of web pages and images for previews of links
+//!
+//! Users behind corporate proxies or Tor need their own network settings,
+//! and tests need responses without network. Both are served by passing
+//! a [`LinkFetcher`] to the `*_with` methods of links.
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use reqwest::{Certificate, Client, Proxy};
+use url::Url;
+
+use crate::{ArklibError, Result};
+
+pub const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:102.0) Gecko/20100101 Firefox/102.0";
+
+/// Source of web pages and images
+pub trait LinkFetcher {
+    /// Fetches the page at the URL as text
+    fn fetch_page(
+        &self,
+        url: &Url,
+    ) -> impl Future<Output = Result<String>> + Send;
+
+    /// Fetches the resource at the URL as raw bytes
+    fn fetch_bytes(
+        &self,
+        url: &Url,
+    ) -> impl Future<Output = Result<Vec<u8>>> + Send;
+}
+
+/// Network settings of [`HttpFetcher`]
+#[derive(Debug, Clone)]
+pub struct FetcherConfig {
+    pub user_agent: String,
+    /// URL of the proxy for all requests, like `http://proxy:3128`
+    /// or `socks5h://127.0.0.1:9050` for Tor
+    pub proxy: Option<String>,
+    pub timeout: Option<Duration>,
+    /// Additional trusted root certificates in PEM format,
+    /// for proxies inspecting TLS traffic
+    pub root_certificates: Vec<Vec<u8>>,
+    pub accept_invalid_certificates: bool,
+    /// Keeps cookies set by pages between requests,
+    /// which some pages require to show their content
+    pub cookies: bool,
+}
+
+impl Default for FetcherConfig {
+    fn default() -> Self {
+        FetcherConfig {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            timeout: Some(Duration::from_secs(30)),
+            root_certificates: vec![],
+            accept_invalid_certificates: false,
+            cookies: false,
+        }
+    }
+}
+
+/// Fetcher using a [`reqwest`] client
+#[derive(Debug, Clone)]
+pub struct HttpFetcher {
+    client: Client,
+}
+
+impl HttpFetcher {
+    pub fn new(config: &FetcherConfig) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        let user_agent = HeaderValue::from_str(&config.user_agent)
+            .map_err(|e| ArklibError::Other(anyhow!(e)))?;
+        headers.insert(USER_AGENT, user_agent);
+
+        let mut builder = Client::builder()
+            .default_headers(headers)
+            .cookie_store(config.cookies)
+            .danger_accept_invalid_certs(config.accept_invalid_certificates);
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        if let Some(timeout) = config.timeout {
+            builder = builder.timeout(timeout);
+        }
+        for pem in &config.root_certificates {
+            builder = builder.add_root_certificate(Certificate::from_pem(pem)?);
+        }
+        Ok(HttpFetcher {
+            client: builder.build()?,
+        })
+    }
+
+    /// Uses a client configured by the caller,
+    /// e.g. shared with the rest of the app
+    pub fn from_client(client: Client) -> Self {
+        HttpFetcher { client }
+    }
+}
+
+impl LinkFetcher for HttpFetcher {
+    async fn fetch_page(&self, url: &Url) -> Result<String> {
+        let response = self.client.get(url.as_str()).send().await?;
+        Ok(response.error_for_status()?.text().await?)
+    }
+
+    async fn fetch_bytes(&self, url: &Url) -> Result<Vec<u8>> {
+        let response = self.client.get(url.as_str()).send().await?;
+        Ok(response
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves a single request, returning the request as received
+    async fn serve_once(listener: TcpListener, body: &'static str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![0; 4096];
+        let read = stream.read(&mut request).await.unwrap();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\
+             Set-Cookie: session=1\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream
+            .write_all(response.as_bytes())
+            .await
+            .unwrap();
+        String::from_utf8_lossy(&request[..read]).to_string()
+    }
+
+    #[tokio::test]
+    async fn configured_client_is_used() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url =
+            Url::parse(&format!("http://{}/", listener.local_addr().unwrap()))
+                .unwrap();
+        let server = tokio::spawn(serve_once(listener, "<title>Page</title>"));
+
+        let fetcher = HttpFetcher::new(&FetcherConfig {
+            user_agent: "arklib-test".to_string(),
+            cookies: true,
+            ..FetcherConfig::default()
+        })
+        .unwrap();
+        let page = fetcher.fetch_page(&url).await.unwrap();
+        assert_eq!(page, "<title>Page</title>");
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.contains("user-agent: arklib-test"), "{}", request);
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        let proxy = FetcherConfig {
+            proxy: Some("not a proxy".to_string()),
+            ..FetcherConfig::default()
+        };
+        assert!(HttpFetcher::new(&proxy).is_err());
+
+        let user_agent = FetcherConfig {
+            user_agent: "line\nbreak".to_string(),
+            ..FetcherConfig::default()
+        };
+        assert!(HttpFetcher::new(&user_agent).is_err());
+    }
+}
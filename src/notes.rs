@@ -0,0 +1,281 @@
+//! Markdown notes stored as ordinary resources of the root
+//!
+//! Notes are `.md` files, so they can be edited by any text editor and
+//! synced like other files. Titles and front matter of notes are kept in
+//! the properties storage, and notes are linked to other resources
+//! through [`crate::relations`], explicitly or by [`crate::wiki`] links.
+//! Editing a note changes its ID, so [`sync_notes`] has to be called with
+//! every update of the index to refresh the properties and to move the
+//! relations to the new ID.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::index::{IndexUpdate, RelativePath};
+use crate::relations::{relate, replace_id};
+use crate::resource::ResourceId;
+use crate::storage::prop::store_properties;
+use crate::text::{heading, split_front_matter};
+use crate::util::fs::{safe_file_name, write_file};
+use crate::{provide_index, ArklibError, Result};
+
+pub const NOTE_EXTENSION: &str = "md";
+
+/// Properties of a note extracted from its content
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteProperties {
+    pub title: String,
+    /// Top-level `key: value` fields of the YAML front matter
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub front_matter: BTreeMap<String, String>,
+}
+
+pub fn is_note<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(NOTE_EXTENSION))
+}
+
+/// Extracts the properties of the note, the title is taken from the front
+/// matter, then from the first heading and then from `fallback_title`
+pub fn parse_note(content: &str, fallback_title: &str) -> NoteProperties {
+    let (front_matter, body) = split_front_matter(content);
+    let front_matter: BTreeMap<String, String> = front_matter
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with([' ', '\t', '-', '#']))
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let value = value
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'');
+            Some((key.trim().to_string(), value.to_string()))
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect();
+    let title = front_matter
+        .get("title")
+        .filter(|title| !title.is_empty())
+        .cloned()
+        .or_else(|| heading("markdown", body))
+        .unwrap_or_else(|| fallback_title.to_string());
+    NoteProperties {
+        title,
+        front_matter,
+    }
+}
+
+/// Creates a note in the folder, named after its title, and adds it
+/// to the index of the root
+///
+/// Returns the path and the ID of the note.
+pub fn create_note<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+    title: &str,
+    body: &str,
+) -> Result<(PathBuf, ResourceId)> {
+    let index = provide_index(&root)?;
+    let mut index = index.write().unwrap_or_else(|e| e.into_inner());
+    let folder = index.absolute_path(folder);
+    let name = safe_file_name(title);
+    let path = (1..)
+        .map(|n| match n {
+            1 => folder.join(format!("{}.{}", name, NOTE_EXTENSION)),
+            n => folder.join(format!("{} ({}).{}", name, n, NOTE_EXTENSION)),
+        })
+        .find(|path| !path.exists())
+        .ok_or_else(|| ArklibError::Path(name.clone()))?;
+
+    let content = format!("# {}\n\n{}\n", title.trim(), body.trim_end());
+    write_file(&path, content.as_bytes())?;
+    let update = index.index_new(&path)?;
+    let id = find_id(&update, &path)?;
+    store_properties(&root, id, &parse_note(&content, title))?;
+    Ok((path, id))
+}
+
+/// Replaces the content of the note, updating the index, the properties
+/// and the relations of the note
+///
+/// Returns the new ID of the note.
+pub fn edit_note<P: AsRef<Path>, Q: AsRef<Path>>(
+    root: P,
+    path: Q,
+    content: &str,
+) -> Result<ResourceId> {
+    let path = path.as_ref();
+    let index = provide_index(&root)?;
+    let mut index = index.write().unwrap_or_else(|e| e.into_inner());
+    let old_id = index
+        .get_entry(path)
+        .map(|entry| entry.id)
+        .ok_or_else(|| {
+            ArklibError::Path(format!("{} is not indexed", path.display()))
+        })?;
+    if fs::read(path)? == content.as_bytes() {
+        return Ok(old_id);
+    }
+
+    write_file(path, content.as_bytes())?;
+    let update = index.update_one(&path, old_id)?;
+    let new_id = find_id(&update, path)?;
+    replace_id(&root, old_id, new_id)?;
+    store_properties(&root, new_id, &parse_note(content, &file_stem(path)))?;
+    Ok(new_id)
+}
+
+/// Relates the note to a resource mentioned in it
+pub fn link_note<P: AsRef<Path>>(
+    root: P,
+    note: ResourceId,
+    resource: ResourceId,
+) -> Result<()> {
+    relate(root, note, resource)
+}
+
+/// Refreshes properties of notes added or modified by the update and
+/// moves relations of modified notes to their new IDs
+///
+/// Returns the number of processed notes.
+pub fn sync_notes<P: AsRef<Path>>(
+    root: P,
+    update: &IndexUpdate,
+) -> Result<usize> {
+    let added = update
+        .added
+        .iter()
+        .map(|(path, id)| (path, None, *id));
+    let modified = update
+        .modified
+        .iter()
+        .map(|(path, (old_id, new_id))| (path, Some(*old_id), *new_id));
+
+    let mut synced = 0;
+    for (path, old_id, id) in added.chain(modified) {
+        if !is_note(path) {
+            continue;
+        }
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                log::warn!("Couldn't read note {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if let Some(old_id) = old_id {
+            replace_id(&root, old_id, id)?;
+        }
+        store_properties(&root, id, &parse_note(&content, &file_stem(path)))?;
+        synced += 1;
+    }
+    log::debug!("Synced properties of {} notes", synced);
+    Ok(synced)
+}
+
+pub(crate) fn find_id(update: &IndexUpdate, path: &Path) -> Result<ResourceId> {
+    let path = fs::canonicalize(path)?;
+    update
+        .added
+        .get(&path)
+        .or_else(|| {
+            update
+                .modified
+                .get(&path)
+                .map(|(_, new_id)| new_id)
+        })
+        .copied()
+        .ok_or_else(|| {
+            ArklibError::Path(format!("{} was not indexed", path.display()))
+        })
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relations::related;
+    use crate::storage::prop::load_raw_properties;
+    use tempdir::TempDir;
+
+    fn properties(root: &Path, id: ResourceId) -> NoteProperties {
+        let bytes = load_raw_properties(root, id).unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn front_matter_and_titles_are_parsed() {
+        let note = "---\ntitle: 'Trip'\ndate: 2024-05-01\ntags:\n  - travel\n\
+                    ---\n# Heading\n";
+        let properties = parse_note(note, "file");
+        assert_eq!(properties.title, "Trip");
+        assert_eq!(properties.front_matter["date"], "2024-05-01");
+        assert_eq!(properties.front_matter["tags"], "");
+        assert_eq!(parse_note("## Heading\ntext", "file").title, "Heading");
+        assert_eq!(parse_note("just text", "file").title, "file");
+    }
+
+    #[test]
+    fn notes_are_created_edited_and_linked() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("photo.jpg"), "photo").unwrap();
+
+        let folder = RelativePath::parse("notes").unwrap();
+        let (path, id) =
+            create_note(&root, &folder, "Trip: day 1", "Nice view").unwrap();
+        assert_eq!(path, root.join("notes").join("Trip_ day 1.md"));
+        assert_eq!(properties(&root, id).title, "Trip: day 1");
+        let (second, _) =
+            create_note(&root, &folder, "Trip: day 1", "Rain").unwrap();
+        assert_eq!(second, root.join("notes").join("Trip_ day 1 (2).md"));
+
+        let photo = {
+            let index = provide_index(&root).unwrap();
+            let index = index.read().unwrap();
+            index
+                .get_entry(root.join("photo.jpg"))
+                .unwrap()
+                .id
+        };
+        link_note(&root, id, photo).unwrap();
+
+        let edited = "---\ntitle: Summit\n---\nNice view\n";
+        let new_id = edit_note(&root, &path, edited).unwrap();
+        assert_ne!(new_id, id);
+        assert_eq!(properties(&root, new_id).title, "Summit");
+        assert_eq!(related(&root, new_id).unwrap(), [photo].into());
+        assert_eq!(edit_note(&root, &path, edited).unwrap(), new_id);
+    }
+
+    #[test]
+    fn notes_edited_externally_are_synced() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("todo.md"), "# Todo\n").unwrap();
+        let mut index = crate::index::ResourceIndex::build(&root);
+        let old_id = index.get_entry(root.join("todo.md")).unwrap().id;
+        let other = ResourceId {
+            hash: 1,
+            data_size: 1,
+        };
+        relate(&root, other, old_id).unwrap();
+
+        fs::write(root.join("todo.md"), "# Done\n\nall of it\n").unwrap();
+        fs::write(root.join("photo.jpg"), "photo").unwrap();
+        let update = index.update_all().unwrap();
+        assert_eq!(sync_notes(&root, &update).unwrap(), 1);
+        let new_id = index.get_entry(root.join("todo.md")).unwrap().id;
+        assert_eq!(properties(&root, new_id).title, "Done");
+        assert_eq!(related(&root, other).unwrap(), [new_id].into());
+    }
+}
@@ -0,0 +1,307 @@
+//! Text recognition in images and scanned PDF documents
+//!
+//! Recognition takes seconds per page, so resources are queued into an
+//! [`OcrWorker`] recognizing them on a background thread. Recognized text
+//! is stored into the `ocr` field of the metadata of the resource.
+//!
+//! The [`Tesseract`] backend runs the `tesseract` command, which must be
+//! installed together with the data of the recognized languages.
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use anyhow::anyhow;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::index::IndexUpdate;
+use crate::pdf::{render_pages, PDFQuality};
+use crate::resource::ResourceId;
+use crate::storage::metadata;
+use crate::{images, ArklibError, Result};
+
+/// Field of the metadata holding [`OcrMetadata`]
+pub const OCR_METADATA_FIELD: &str = "ocr";
+/// Maximum amount of pages of a PDF document which are recognized
+pub const OCR_MAX_PAGES: usize = 20;
+
+/// Engine recognizing text in images
+pub trait OcrBackend: Send + 'static {
+    fn recognize(&self, image: &DynamicImage) -> Result<String>;
+}
+
+/// Backend running the Tesseract command line tool
+#[derive(Debug, Clone)]
+pub struct Tesseract {
+    command: PathBuf,
+    /// Languages in the format of Tesseract, like `eng+deu`
+    languages: String,
+}
+
+impl Default for Tesseract {
+    fn default() -> Self {
+        Tesseract::new("eng")
+    }
+}
+
+impl Tesseract {
+    pub fn new(languages: &str) -> Self {
+        Tesseract {
+            command: PathBuf::from("tesseract"),
+            languages: languages.to_string(),
+        }
+    }
+
+    /// Uses the executable at `command` instead of the one found in `PATH`
+    pub fn with_command<P: Into<PathBuf>>(mut self, command: P) -> Self {
+        self.command = command.into();
+        self
+    }
+
+    pub fn is_available(&self) -> bool {
+        Command::new(&self.command)
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+}
+
+impl OcrBackend for Tesseract {
+    fn recognize(&self, image: &DynamicImage) -> Result<String> {
+        let name: String = std::iter::repeat_with(fastrand::alphanumeric)
+            .take(10)
+            .collect();
+        let input =
+            std::env::temp_dir().join(format!("arklib_ocr_{}.png", name));
+        image.save_with_format(&input, image::ImageFormat::Png)?;
+        let output = Command::new(&self.command)
+            .arg(&input)
+            .arg("stdout")
+            .args(["-l", &self.languages])
+            .output();
+        let _ = std::fs::remove_file(&input);
+
+        let output = output?;
+        if !output.status.success() {
+            return Err(ArklibError::Other(anyhow!(
+                "Tesseract failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .to_string())
+    }
+}
+
+/// Text recognized in a resource
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OcrMetadata {
+    pub text: String,
+    /// Amount of recognized pages, 1 for images
+    pub pages: usize,
+}
+
+/// Whether text can be recognized in files with the extension
+pub fn is_supported(extension: &str) -> bool {
+    extension.eq_ignore_ascii_case("pdf") || images::is_supported(extension)
+}
+
+/// Recognizes text in the image or in the pages of the PDF document
+pub fn recognize_file<B: OcrBackend>(
+    backend: &B,
+    path: &Path,
+) -> Result<OcrMetadata> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let pages = match extension.eq_ignore_ascii_case("pdf") {
+        true => {
+            render_pages(File::open(path)?, PDFQuality::High, OCR_MAX_PAGES)?
+        }
+        false => vec![images::decode(BufReader::new(File::open(path)?))?],
+    };
+    let mut texts = vec![];
+    for page in &pages {
+        texts.push(backend.recognize(page)?);
+    }
+    Ok(OcrMetadata {
+        text: texts.join("\n\n").trim().to_string(),
+        pages: pages.len(),
+    })
+}
+
+/// Recognizes text in the file and stores it into the metadata cache
+pub fn generate<B: OcrBackend, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    path: &Path,
+    backend: &B,
+) -> Result<OcrMetadata> {
+    let metadata = recognize_file(backend, path)?;
+    metadata::store_field(root, id, OCR_METADATA_FIELD, &metadata)?;
+    Ok(metadata)
+}
+
+/// Loads the text recognized in the resource before
+pub fn load_ocr<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<OcrMetadata>> {
+    let metadata: Option<serde_json::Value> = metadata::load(root, id)?;
+    match metadata.and_then(|mut metadata| {
+        metadata
+            .get_mut(OCR_METADATA_FIELD)
+            .map(|field| field.take())
+    }) {
+        Some(field) => Ok(Some(serde_json::from_value(field)?)),
+        None => Ok(None),
+    }
+}
+
+/// Background thread recognizing queued resources one by one
+pub struct OcrWorker {
+    sender: Option<Sender<(ResourceId, PathBuf)>>,
+    thread: Option<JoinHandle<usize>>,
+}
+
+impl OcrWorker {
+    pub fn spawn<B: OcrBackend, P: Into<PathBuf>>(root: P, backend: B) -> Self {
+        let root = root.into();
+        let (sender, receiver) = mpsc::channel::<(ResourceId, PathBuf)>();
+        let thread = thread::spawn(move || {
+            let mut recognized = 0;
+            for (id, path) in receiver {
+                match generate(&root, id, &path, &backend) {
+                    Ok(_) => recognized += 1,
+                    Err(e) => log::warn!(
+                        "Couldn't recognize text in {}: {}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+            recognized
+        });
+        OcrWorker {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn enqueue(&self, id: ResourceId, path: PathBuf) -> Result<()> {
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send((id, path)).ok())
+            .ok_or_else(|| {
+                ArklibError::Other(anyhow!("OCR worker has stopped"))
+            })
+    }
+
+    /// Queues resources added or modified by the update,
+    /// returning the number of queued resources
+    pub fn enqueue_update(&self, update: &IndexUpdate) -> Result<usize> {
+        let added = update.added.iter().map(|(path, id)| (path, *id));
+        let modified = update
+            .modified
+            .iter()
+            .map(|(path, (_, id))| (path, *id));
+        let mut queued = 0;
+        for (path, id) in added.chain(modified) {
+            let supported = path.extension().is_some_and(|extension| {
+                is_supported(&extension.to_string_lossy())
+            });
+            if supported {
+                self.enqueue(id, path.clone())?;
+                queued += 1;
+            }
+        }
+        Ok(queued)
+    }
+
+    /// Waits until all queued resources are processed,
+    /// returning the number of resources with recognized text
+    pub fn finish(mut self) -> usize {
+        self.sender.take();
+        self.thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for OcrWorker {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use std::collections::HashMap;
+    use tempdir::TempDir;
+
+    struct MockBackend;
+
+    impl OcrBackend for MockBackend {
+        fn recognize(&self, image: &DynamicImage) -> Result<String> {
+            Ok(format!("{}x{}", image.width(), image.height()))
+        }
+    }
+
+    #[test]
+    fn queued_images_are_recognized() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let image = root.join("scan.png");
+        DynamicImage::new_rgb8(30, 20)
+            .save(&image)
+            .unwrap();
+        let id =
+            ResourceId::compute_bytes(&std::fs::read(&image).unwrap()).unwrap();
+        let notes = root.join("notes.txt");
+        std::fs::write(&notes, "text").unwrap();
+        metadata::store_field(root, id, "other", &1).unwrap();
+        assert_eq!(load_ocr(root, id).unwrap(), None);
+
+        let worker = OcrWorker::spawn(root, MockBackend);
+        let update = IndexUpdate {
+            added: HashMap::from([
+                (image, id),
+                (notes, ResourceId::compute_bytes(b"text").unwrap()),
+            ]),
+            ..IndexUpdate::default()
+        };
+        assert_eq!(worker.enqueue_update(&update).unwrap(), 1);
+        let missing = root.join("missing.png");
+        worker.enqueue(id, missing).unwrap();
+        assert_eq!(worker.finish(), 1);
+
+        let metadata: HashMap<String, serde_json::Value> =
+            metadata::load(root, id).unwrap().unwrap();
+        assert_eq!(metadata["other"], 1);
+        let ocr = load_ocr(root, id).unwrap().unwrap();
+        assert_eq!(ocr.text, "30x20");
+        assert_eq!(ocr.pages, 1);
+    }
+
+    #[test]
+    fn missing_tesseract_is_reported() {
+        let tesseract =
+            Tesseract::default().with_command("/nonexistent/tesseract");
+        assert!(!tesseract.is_available());
+        assert!(tesseract
+            .recognize(&DynamicImage::new_rgb8(1, 1))
+            .is_err());
+    }
+}
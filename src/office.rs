@@ -0,0 +1,301 @@
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::resource::ResourceId;
+use crate::storage::metadata;
+use crate::storage::preview::store_preview;
+use crate::util::xml::{attribute, first_text, read_zip_entry};
+use crate::Result;
+
+/// Maximum amount of characters kept in [`OfficeMetadata::text`]
+pub const TEXT_SNIPPET_LENGTH: usize = 512;
+
+const OOXML_RELATIONSHIPS: &str = "_rels/.rels";
+const OOXML_THUMBNAIL_TYPE: &str = "/metadata/thumbnail";
+const OOXML_CORE: &str = "docProps/core.xml";
+const ODF_THUMBNAIL: &str = "Thumbnails/thumbnail.png";
+const ODF_META: &str = "meta.xml";
+
+/// Office formats which can be previewed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfficeFormat {
+    /// Office Open XML text document
+    Docx,
+    /// Office Open XML spreadsheet
+    Xlsx,
+    /// Office Open XML presentation
+    Pptx,
+    /// OpenDocument text document
+    Odt,
+    /// OpenDocument spreadsheet
+    Ods,
+    /// OpenDocument presentation
+    Odp,
+}
+
+impl OfficeFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "docx" => Some(OfficeFormat::Docx),
+            "xlsx" => Some(OfficeFormat::Xlsx),
+            "pptx" => Some(OfficeFormat::Pptx),
+            "odt" => Some(OfficeFormat::Odt),
+            "ods" => Some(OfficeFormat::Ods),
+            "odp" => Some(OfficeFormat::Odp),
+            _ => None,
+        }
+    }
+
+    fn is_open_document(&self) -> bool {
+        matches!(
+            self,
+            OfficeFormat::Odt | OfficeFormat::Ods | OfficeFormat::Odp
+        )
+    }
+
+    /// Archive entry holding the main text of the document together
+    /// with the local name of its text elements
+    fn text_entry(&self) -> Option<(&'static str, &'static [u8])> {
+        match self {
+            OfficeFormat::Docx => Some(("word/document.xml", b"t")),
+            OfficeFormat::Odt => Some(("content.xml", b"p")),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata extracted from an office document
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfficeMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// Beginning of the document text, for text documents only
+    pub text: Option<String>,
+}
+
+/// An opened office document
+pub struct OfficeDocument<R: Read + Seek> {
+    archive: ZipArchive<R>,
+    format: OfficeFormat,
+}
+
+impl<R: Read + Seek> OfficeDocument<R> {
+    pub fn open(data: R, format: OfficeFormat) -> Result<Self> {
+        let archive = ZipArchive::new(data)?;
+        Ok(Self { archive, format })
+    }
+
+    /// Returns the thumbnail embedded by the authoring application
+    ///
+    /// Both OOXML and OpenDocument editors save a rendering of the first
+    /// page along with the document, unless disabled by the user.
+    pub fn thumbnail(&mut self) -> Result<Option<Vec<u8>>> {
+        let path = if self.format.is_open_document() {
+            Some(ODF_THUMBNAIL.to_string())
+        } else {
+            self.ooxml_thumbnail_path()?
+        };
+
+        match path {
+            Some(path) if self.archive.by_name(&path).is_ok() => {
+                Ok(Some(read_zip_entry(&mut self.archive, &path)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn metadata(&mut self) -> Result<OfficeMetadata> {
+        let (meta_path, author_tag): (&str, &[u8]) =
+            if self.format.is_open_document() {
+                (ODF_META, b"initial-creator")
+            } else {
+                (OOXML_CORE, b"creator")
+            };
+
+        let mut metadata = OfficeMetadata::default();
+        if self.archive.by_name(meta_path).is_ok() {
+            let meta = read_zip_entry(&mut self.archive, meta_path)?;
+            metadata.title = first_text(&meta, b"title")?;
+            metadata.author = first_text(&meta, author_tag)?;
+            if metadata.author.is_none() {
+                metadata.author = first_text(&meta, b"creator")?;
+            }
+        }
+        if let Some((path, tag)) = self.format.text_entry() {
+            let content = read_zip_entry(&mut self.archive, path)?;
+            metadata.text = Some(text_snippet(&content, tag)?);
+        }
+        Ok(metadata)
+    }
+
+    fn ooxml_thumbnail_path(&mut self) -> Result<Option<String>> {
+        if self.archive.by_name(OOXML_RELATIONSHIPS).is_err() {
+            return Ok(None);
+        }
+        let rels = read_zip_entry(&mut self.archive, OOXML_RELATIONSHIPS)?;
+
+        let mut reader = Reader::from_reader(rels.as_slice());
+        let mut buf = vec![];
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(e) | Event::Empty(e)
+                    if e.local_name().as_ref() == b"Relationship" =>
+                {
+                    let is_thumbnail = attribute(&e, b"Type")
+                        .map(|t| t.ends_with(OOXML_THUMBNAIL_TYPE))
+                        .unwrap_or(false);
+                    if is_thumbnail {
+                        return Ok(attribute(&e, b"Target")
+                            .map(|t| t.trim_start_matches('/').to_string()));
+                    }
+                }
+                Event::Eof => return Ok(None),
+                _ => {}
+            }
+            buf.clear();
+        }
+    }
+}
+
+/// Stores metadata of the document into the metadata cache and its
+/// embedded thumbnail into the previews cache
+pub fn generate<R: Read + Seek, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    data: R,
+    format: OfficeFormat,
+) -> Result<OfficeMetadata> {
+    let mut document = OfficeDocument::open(data, format)?;
+    let metadata = document.metadata()?;
+    metadata::store(&root, id, &metadata)?;
+    if let Some(thumbnail) = document.thumbnail()? {
+        store_preview(&root, id, &thumbnail)?;
+    }
+    Ok(metadata)
+}
+
+/// Concatenates text of the paragraphs until the snippet length is reached
+///
+/// Both OOXML and OpenDocument name paragraphs `p`, but OOXML keeps the
+/// text itself in nested `t` runs.
+fn text_snippet(content: &[u8], tag: &[u8]) -> Result<String> {
+    let mut reader = Reader::from_reader(content);
+    let mut buf = vec![];
+    let mut snippet = String::new();
+    let mut depth = 0;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) if e.local_name().as_ref() == tag => depth += 1,
+            Event::End(e) => {
+                if e.local_name().as_ref() == tag {
+                    depth -= 1;
+                }
+                if e.local_name().as_ref() == b"p" {
+                    snippet.push(' ');
+                }
+            }
+            Event::Text(text) if depth > 0 => {
+                snippet.push_str(&text.unescape()?)
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        if snippet.chars().count() >= TEXT_SNIPPET_LENGTH {
+            break;
+        }
+        buf.clear();
+    }
+    Ok(snippet
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(TEXT_SNIPPET_LENGTH)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    fn build_archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in entries {
+            zip.start_file(*name, FileOptions::default())
+                .unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn docx_thumbnail_and_metadata() {
+        let thumbnail = std::fs::read("tests/lena.jpg").unwrap();
+        let rels = br#"<?xml version="1.0"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
+  <Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/thumbnail" Target="docProps/thumbnail.jpeg"/>
+</Relationships>"#;
+        let core = br#"<?xml version="1.0"?>
+<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <dc:title>Quarterly report</dc:title>
+  <dc:creator>Jane Doe</dc:creator>
+</cp:coreProperties>"#;
+        let document = br#"<?xml version="1.0"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+  <w:body>
+    <w:p><w:r><w:t>Hel</w:t></w:r><w:r><w:t>lo</w:t></w:r></w:p>
+    <w:p><w:r><w:t>world</w:t></w:r></w:p>
+  </w:body>
+</w:document>"#;
+        let data = build_archive(&[
+            (OOXML_RELATIONSHIPS, rels),
+            (OOXML_CORE, core),
+            ("word/document.xml", document),
+            ("docProps/thumbnail.jpeg", &thumbnail),
+        ]);
+
+        let mut doc =
+            OfficeDocument::open(Cursor::new(data), OfficeFormat::Docx)
+                .unwrap();
+        assert_eq!(doc.thumbnail().unwrap(), Some(thumbnail));
+        assert_eq!(
+            doc.metadata().unwrap(),
+            OfficeMetadata {
+                title: Some("Quarterly report".to_string()),
+                author: Some("Jane Doe".to_string()),
+                text: Some("Hello world".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn odt_without_thumbnail() {
+        let content = br#"<?xml version="1.0"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0">
+  <office:body><office:text>
+    <text:p>First paragraph</text:p>
+    <text:p>Second paragraph</text:p>
+  </office:text></office:body>
+</office:document-content>"#;
+        let data = build_archive(&[("content.xml", content)]);
+
+        let mut doc =
+            OfficeDocument::open(Cursor::new(data), OfficeFormat::Odt).unwrap();
+        assert_eq!(doc.thumbnail().unwrap(), None);
+        let metadata = doc.metadata().unwrap();
+        assert_eq!(metadata.title, None);
+        assert_eq!(
+            metadata.text.as_deref(),
+            Some("First paragraph Second paragraph")
+        );
+    }
+}
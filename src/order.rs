@@ -0,0 +1,278 @@
+//! Manual ordering of resources within folders
+//!
+//! Every folder has its own [`AtomicFile`] under `user/order`, named by
+//! [`RelativePath::storage_key`]. Resources are placed at fractional
+//! positions, and every placement is stamped, so that orderings written
+//! concurrently on different devices are merged placement by placement,
+//! the latest placement of a resource winning.
+use std::collections::BTreeMap;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::index::RelativePath;
+use crate::resource::ResourceId;
+use crate::{Result, ARK_FOLDER, ORDER_STORAGE_FOLDER};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Placement {
+    position: f64,
+    /// Resources removed from the ordering are kept as tombstones,
+    /// so that merging doesn't bring them back
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    removed: bool,
+    /// Milliseconds since the epoch, greater than all stamps seen before
+    stamp: u64,
+    /// Random number breaking ties between devices
+    nonce: u64,
+}
+
+impl Placement {
+    fn is_newer(&self, other: &Placement) -> bool {
+        (self.stamp, self.nonce) > (other.stamp, other.nonce)
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+struct Ordering {
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    placements: BTreeMap<ResourceId, Placement>,
+}
+
+impl Ordering {
+    fn merge(&mut self, other: Ordering) {
+        for (id, placement) in other.placements {
+            match self.placements.get(&id) {
+                Some(current) if !placement.is_newer(current) => {}
+                _ => {
+                    self.placements.insert(id, placement);
+                }
+            }
+        }
+    }
+
+    fn ids(&self) -> Vec<ResourceId> {
+        let mut placed: Vec<(&ResourceId, &Placement)> = self
+            .placements
+            .iter()
+            .filter(|(_, placement)| !placement.removed)
+            .collect();
+        placed.sort_by(|(a_id, a), (b_id, b)| {
+            a.position
+                .total_cmp(&b.position)
+                .then_with(|| a_id.cmp(b_id))
+        });
+        placed.into_iter().map(|(id, _)| *id).collect()
+    }
+
+    fn place(&mut self, id: ResourceId, position: f64, removed: bool) {
+        let stamp = self
+            .placements
+            .values()
+            .map(|placement| placement.stamp + 1)
+            .max()
+            .unwrap_or_default()
+            .max(now());
+        let placement = Placement {
+            position,
+            removed,
+            stamp,
+            nonce: fastrand::u64(..),
+        };
+        self.placements.insert(id, placement);
+    }
+
+    fn position(&self, id: &ResourceId) -> Option<f64> {
+        self.placements
+            .get(id)
+            .filter(|placement| !placement.removed)
+            .map(|placement| placement.position)
+    }
+}
+
+/// Loads the manual ordering of the folder, empty if it was never set
+pub fn load_order<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+) -> Result<Vec<ResourceId>> {
+    let path = storage_path(root, folder);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    Ok(load_merged(&AtomicFile::new(path)?)?.ids())
+}
+
+/// Replaces the ordering of the folder, resources missing in `ids`
+/// are removed from it
+pub fn set_order<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+    ids: &[ResourceId],
+) -> Result<()> {
+    modify(root, folder, |ordering| {
+        let current = ordering.ids();
+        if current == ids {
+            return;
+        }
+        for (i, id) in ids.iter().enumerate() {
+            ordering.place(*id, (i + 1) as f64, false);
+        }
+        for id in current {
+            if !ids.contains(&id) {
+                ordering.place(id, 0.0, true);
+            }
+        }
+    })
+}
+
+/// Moves the resource right after `after`, or to the beginning,
+/// changing only the placement of the moved resource
+pub fn move_after<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+    id: ResourceId,
+    after: Option<ResourceId>,
+) -> Result<()> {
+    modify(root, folder, |ordering| {
+        let ids: Vec<ResourceId> = ordering
+            .ids()
+            .into_iter()
+            .filter(|other| *other != id)
+            .collect();
+        let index = match after {
+            Some(after) => match ids.iter().position(|other| *other == after) {
+                Some(index) => index + 1,
+                None => ids.len(),
+            },
+            None => 0,
+        };
+        let previous = index
+            .checked_sub(1)
+            .and_then(|i| ordering.position(&ids[i]));
+        let next = ids
+            .get(index)
+            .and_then(|next| ordering.position(next));
+        let position = match (previous, next) {
+            (Some(previous), Some(next)) => (previous + next) / 2.0,
+            (Some(previous), None) => previous + 1.0,
+            (None, Some(next)) => next - 1.0,
+            (None, None) => 1.0,
+        };
+        ordering.place(id, position, false);
+    })
+}
+
+/// Removes the resource from the ordering of the folder
+pub fn remove_from_order<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+    id: ResourceId,
+) -> Result<()> {
+    modify(root, folder, |ordering| {
+        if ordering.position(&id).is_some() {
+            ordering.place(id, 0.0, true);
+        }
+    })
+}
+
+/// Merges the latest versions written by all devices
+fn load_merged(file: &AtomicFile) -> Result<Ordering> {
+    let (_, files) = file.latest_version()?;
+    let mut merged = Ordering::default();
+    for version in files {
+        if let Some(file) = version.open()? {
+            merged.merge(serde_json::from_reader(BufReader::new(file))?);
+        }
+    }
+    Ok(merged)
+}
+
+fn modify<P: AsRef<Path>>(
+    root: P,
+    folder: &RelativePath,
+    mut operator: impl FnMut(&mut Ordering),
+) -> Result<()> {
+    let file = AtomicFile::new(storage_path(root, folder))?;
+    modify_json(&file, |current: &mut Option<Ordering>| {
+        let mut ordering = current.take().unwrap_or_default();
+        match load_merged(&file) {
+            Ok(merged) => ordering.merge(merged),
+            Err(e) => log::warn!("Couldn't merge orderings: {}", e),
+        }
+        operator(&mut ordering);
+        *current = Some(ordering);
+    })?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn storage_path<P: AsRef<Path>>(root: P, folder: &RelativePath) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(ORDER_STORAGE_FOLDER)
+        .join(folder.storage_key())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn id(hash: u32) -> ResourceId {
+        ResourceId { hash, data_size: 1 }
+    }
+
+    #[test]
+    fn resources_are_ordered() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let folder = RelativePath::parse("photos/2024").unwrap();
+        assert!(load_order(root, &folder).unwrap().is_empty());
+
+        set_order(root, &folder, &[id(3), id(1), id(2)]).unwrap();
+        assert_eq!(load_order(root, &folder).unwrap(), [id(3), id(1), id(2)]);
+        move_after(root, &folder, id(2), Some(id(3))).unwrap();
+        move_after(root, &folder, id(4), None).unwrap();
+        assert_eq!(
+            load_order(root, &folder).unwrap(),
+            [id(4), id(3), id(2), id(1)]
+        );
+        remove_from_order(root, &folder, id(3)).unwrap();
+        set_order(root, &folder, &[id(1), id(4)]).unwrap();
+        assert_eq!(load_order(root, &folder).unwrap(), [id(1), id(4)]);
+
+        let other = RelativePath::parse("photos").unwrap();
+        assert!(load_order(root, &other).unwrap().is_empty());
+    }
+
+    #[test]
+    fn concurrent_orderings_are_merged() {
+        let mut local = Ordering::default();
+        local.place(id(1), 1.0, false);
+        local.place(id(2), 2.0, false);
+        let mut remote = local.clone();
+        // moved on one device, removed on another
+        local.place(id(3), 1.5, false);
+        remote.place(id(1), 3.0, false);
+        remote.place(id(2), 0.0, true);
+
+        let mut merged = local.clone();
+        merged.merge(remote.clone());
+        assert_eq!(merged.ids(), [id(3), id(1)]);
+        remote.merge(local);
+        assert_eq!(remote, merged);
+    }
+}
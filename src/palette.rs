@@ -0,0 +1,254 @@
+//! Dominant colors of images
+//!
+//! Colors are stored in the `palette` field of the metadata of the
+//! resource, so that apps can show a colored placeholder until the
+//! thumbnail is loaded.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Seek};
+use std::path::Path;
+use std::str::FromStr;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::resource::ResourceId;
+use crate::storage::metadata;
+use crate::{images, ArklibError, Result};
+
+/// Field of the metadata holding the palette
+pub const PALETTE_METADATA_FIELD: &str = "palette";
+/// Maximum amount of colors in a palette
+pub const PALETTE_SIZE: usize = 5;
+
+/// Side of the downscaled image which colors are counted in
+const SAMPLE_SIZE: u32 = 64;
+/// Bits kept of every channel when grouping similar colors
+const BUCKET_BITS: u8 = 4;
+/// Colors closer than this to a more frequent one are merged into it
+const MERGE_DISTANCE: f32 = 32.0;
+
+/// Color stored as a `#rrggbb` string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    /// Euclidean distance in the RGB space
+    pub fn distance(&self, other: &Color) -> f32 {
+        let channel = |a: u8, b: u8| (a as f32 - b as f32).powi(2);
+        (channel(self.r, other.r)
+            + channel(self.g, other.g)
+            + channel(self.b, other.b))
+        .sqrt()
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl FromStr for Color {
+    type Err = ArklibError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6)
+            .ok_or(ArklibError::Parse)?;
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| ArklibError::Parse)
+        };
+        Ok(Color {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        })
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// One of the dominant colors of an image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Swatch {
+    pub color: Color,
+    /// Fraction of the image covered by the color
+    pub share: f32,
+}
+
+/// Finds the dominant colors of the image, the most frequent first
+pub fn extract(image: &DynamicImage) -> Vec<Swatch> {
+    let sample = image
+        .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_rgba8();
+    let shift = 8 - BUCKET_BITS;
+    // sums of channels and counts of opaque pixels in every bucket
+    let mut buckets: HashMap<(u8, u8, u8), ([u32; 3], u32)> = HashMap::new();
+    let mut total = 0;
+    for pixel in sample.pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a < 128 {
+            continue;
+        }
+        let (sum, count) = buckets
+            .entry((r >> shift, g >> shift, b >> shift))
+            .or_default();
+        sum[0] += r as u32;
+        sum[1] += g as u32;
+        sum[2] += b as u32;
+        *count += 1;
+        total += 1;
+    }
+
+    let mut buckets: Vec<(Color, u32)> = buckets
+        .into_values()
+        .map(|(sum, count)| {
+            let color = Color {
+                r: (sum[0] / count) as u8,
+                g: (sum[1] / count) as u8,
+                b: (sum[2] / count) as u8,
+            };
+            (color, count)
+        })
+        .collect();
+    buckets.sort_by(|(a, a_count), (b, b_count)| {
+        b_count
+            .cmp(a_count)
+            .then_with(|| (a.r, a.g, a.b).cmp(&(b.r, b.g, b.b)))
+    });
+
+    let mut palette: Vec<(Color, u32)> = vec![];
+    for (color, count) in buckets {
+        match palette
+            .iter_mut()
+            .find(|(dominant, _)| dominant.distance(&color) < MERGE_DISTANCE)
+        {
+            Some((_, dominant_count)) => *dominant_count += count,
+            None => palette.push((color, count)),
+        }
+    }
+    palette.sort_by(|(_, a), (_, b)| b.cmp(a));
+    palette
+        .into_iter()
+        .take(PALETTE_SIZE)
+        .map(|(color, count)| Swatch {
+            color,
+            share: count as f32 / total as f32,
+        })
+        .collect()
+}
+
+/// Extracts the palette of the image and stores it into the metadata cache
+pub fn generate<R: BufRead + Seek, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    data: R,
+) -> Result<Vec<Swatch>> {
+    let palette = extract(&images::decode(data)?);
+    metadata::store_field(root, id, PALETTE_METADATA_FIELD, &palette)?;
+    Ok(palette)
+}
+
+/// Loads the palette of the resource, if generated before
+pub fn load_palette<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<Vec<Swatch>>> {
+    let metadata: Option<serde_json::Value> = metadata::load(root, id)?;
+    match metadata.and_then(|mut metadata| {
+        metadata
+            .get_mut(PALETTE_METADATA_FIELD)
+            .map(|field| field.take())
+    }) {
+        Some(field) => Ok(Some(serde_json::from_value(field)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use std::io::Cursor;
+    use tempdir::TempDir;
+
+    /// Image with the left 3/4 red and the rest blue with some noise
+    fn two_colors() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(80, 40, |x, y| {
+            match x < 60 {
+                true => Rgb([200 + (y % 5) as u8, 20, 20]),
+                false => Rgb([10, 30, 180 + (x % 3) as u8]),
+            }
+        }))
+    }
+
+    #[test]
+    fn dominant_colors_are_found() {
+        let palette = extract(&two_colors());
+        assert!(palette.len() >= 2);
+        assert!(
+            palette[0]
+                .color
+                .distance(&"#c81414".parse().unwrap())
+                < 10.0
+        );
+        assert!(
+            palette[1]
+                .color
+                .distance(&"#0a1eb4".parse().unwrap())
+                < 10.0
+        );
+        assert!((palette[0].share - 0.75).abs() < 0.05);
+
+        let transparent = DynamicImage::new_rgba8(10, 10);
+        assert!(extract(&transparent).is_empty());
+    }
+
+    #[test]
+    fn palettes_are_stored() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let id = ResourceId {
+            hash: 1,
+            data_size: 1,
+        };
+        assert_eq!(load_palette(dir.path(), id).unwrap(), None);
+        let jpeg = images::encode(&two_colors()).unwrap();
+        let palette = generate(dir.path(), id, Cursor::new(jpeg)).unwrap();
+        assert_eq!(load_palette(dir.path(), id).unwrap(), Some(palette));
+
+        let color = Color {
+            r: 255,
+            g: 0,
+            b: 16,
+        };
+        assert_eq!(color.to_string(), "#ff0010");
+        assert_eq!("#ff0010".parse::<Color>().unwrap(), color);
+        assert!("ff0010".parse::<Color>().is_err());
+    }
+}
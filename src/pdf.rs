@@ -1,41 +1,87 @@
-use std::{
-    env,
-    io::{Read, Seek},
-    path::PathBuf,
-};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use image::DynamicImage;
 use once_cell::sync::OnceCell;
 use pdfium_render::prelude::*;
+use walkdir::WalkDir;
+
+use crate::cache::stamp_entry;
+use crate::resource::ResourceId;
+use crate::util::fs::write_file;
+use crate::{images, Result, ARK_FOLDER, PREVIEWS_STORAGE_FOLDER};
 
 static PDFIUM: OnceCell<Pdfium> = OnceCell::new(); // static initializers must impl Sync + Send
 
+/// Bytes taken by rendered pages in the cache of a root by default
+pub const PAGE_CACHE_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// Folder of rendered pages inside of the preview entry of a document,
+/// so that pages are invalidated together with the preview
+const PAGES_FOLDER: &str = "pages";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PDFQuality {
     High,
     Medium,
     Low,
 }
 
-fn initialize_pdfium() {
-    let out_path = env!("OUT_DIR");
-    let pdfium_lib_path =
-        PathBuf::from(&out_path).join(Pdfium::pdfium_platform_library_name());
-    let bindings = Pdfium::bind_to_library(
-        #[cfg(target_os = "android")]
-        Pdfium::pdfium_platform_library_name_at_path("./"),
-        #[cfg(not(target_os = "android"))]
-        pdfium_lib_path.to_str().unwrap(),
-    )
-    .or_else(|_| Pdfium::bind_to_system_library())
-    .unwrap();
-    PDFIUM
-        .set(Pdfium::new(bindings))
-        .ok()
-        .expect("Can't set PDFIUM"); // Instead of returning the bindings, we
-                                     // cache them in the static initializer
+fn initialize_pdfium() -> Result<Pdfium> {
+    // pdfium is embedded into the app bundle on iOS and is found
+    // by the dynamic linker through the rpath of the app
+    #[cfg(target_os = "ios")]
+    let bindings = Pdfium::bind_to_system_library()?;
+    #[cfg(not(target_os = "ios"))]
+    let bindings = {
+        let out_path = env!("OUT_DIR");
+        let pdfium_lib_path = std::path::PathBuf::from(&out_path)
+            .join(Pdfium::pdfium_platform_library_name());
+        Pdfium::bind_to_library(
+            #[cfg(target_os = "android")]
+            Pdfium::pdfium_platform_library_name_at_path("./"),
+            #[cfg(not(target_os = "android"))]
+            pdfium_lib_path.to_string_lossy().to_string(),
+        )
+        .or_else(|_| Pdfium::bind_to_system_library())?
+    };
+    // Instead of returning the bindings, we
+    // cache them in the static initializer
+    Ok(Pdfium::new(bindings))
 }
 
-pub fn render_preview_page<R>(data: R, quailty: PDFQuality) -> DynamicImage
+impl PDFQuality {
+    fn name(&self) -> &'static str {
+        match self {
+            PDFQuality::High => "high",
+            PDFQuality::Medium => "medium",
+            PDFQuality::Low => "low",
+        }
+    }
+}
+
+/// Renders the first page of the PDF document
+///
+/// Fails if Pdfium can't be loaded or if the document is malformed
+/// or has no pages, never panics on malformed input.
+pub fn render_preview_page<R>(
+    data: R,
+    quailty: PDFQuality,
+) -> Result<DynamicImage>
+where
+    R: Read + Seek + 'static,
+{
+    render_preview_of_page(data, 0, quailty)
+}
+
+/// Renders the page of the PDF document like [`render_preview_page`]
+pub fn render_preview_of_page<R>(
+    data: R,
+    page: u16,
+    quailty: PDFQuality,
+) -> Result<DynamicImage>
 where
     R: Read + Seek + 'static,
 {
@@ -47,20 +93,225 @@ where
     }
     .rotate_if_landscape(PdfBitmapRotation::Degrees90, true);
 
-    if PDFIUM.get().is_none() {
-        initialize_pdfium();
+    let pdfium = PDFIUM.get_or_try_init(initialize_pdfium)?;
+    let image = pdfium
+        .load_pdf_from_reader(data, None)?
+        .pages()
+        .get(page)?
+        .render_with_config(&render_cfg)?
+        .as_image();
+    Ok(image)
+}
+
+/// Renders up to `max_pages` pages of the PDF document in their original
+/// orientation, e.g. for text recognition of scanned documents
+pub fn render_pages<R>(
+    data: R,
+    quality: PDFQuality,
+    max_pages: usize,
+) -> Result<Vec<DynamicImage>>
+where
+    R: Read + Seek + 'static,
+{
+    let render_cfg = PdfRenderConfig::new();
+    let render_cfg = match quality {
+        PDFQuality::High => render_cfg.set_target_width(2000),
+        PDFQuality::Medium => render_cfg,
+        PDFQuality::Low => render_cfg.thumbnail(50),
+    };
+
+    let pdfium = PDFIUM.get_or_try_init(initialize_pdfium)?;
+    let document = pdfium.load_pdf_from_reader(data, None)?;
+    let mut images = vec![];
+    for page in document.pages().iter().take(max_pages) {
+        images.push(page.render_with_config(&render_cfg)?.as_image());
+    }
+    Ok(images)
+}
+
+/// Pages of PDF documents rendered before, so that scrolling through
+/// a document renders every page only once
+///
+/// Pages are cached by ID of the document, number of the page and quality,
+/// together with the preview of the document. When the cached pages of
+/// the root take more than the limit, the least recently used are evicted.
+pub struct PageCache {
+    root: PathBuf,
+    limit: u64,
+}
+
+impl PageCache {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self::with_limit(root, PAGE_CACHE_LIMIT)
+    }
+
+    pub fn with_limit<P: AsRef<Path>>(root: P, limit: u64) -> Self {
+        PageCache {
+            root: root.as_ref().to_path_buf(),
+            limit,
+        }
+    }
+
+    /// Takes the page of the document by the path from the cache,
+    /// or renders and caches it
+    pub fn render(
+        &self,
+        id: ResourceId,
+        path: &Path,
+        page: u16,
+        quality: PDFQuality,
+    ) -> Result<DynamicImage> {
+        if let Some(image) = self.load(id, page, quality)? {
+            return Ok(image);
+        }
+        let image = render_preview_of_page(File::open(path)?, page, quality)?;
+        self.store(id, page, quality, &image)?;
+        Ok(image)
+    }
+
+    fn load(
+        &self,
+        id: ResourceId,
+        page: u16,
+        quality: PDFQuality,
+    ) -> Result<Option<DynamicImage>> {
+        let path = self.page_path(id, page, quality);
+        let file = match File::options().read(true).write(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // the modification time tracks the last use of the page
+        file.set_modified(SystemTime::now())?;
+        let image = images::decode(BufReader::new(file))?;
+        Ok(Some(image))
+    }
+
+    fn store(
+        &self,
+        id: ResourceId,
+        page: u16,
+        quality: PDFQuality,
+        image: &DynamicImage,
+    ) -> Result<()> {
+        write_file(
+            &self.page_path(id, page, quality),
+            &images::encode(image)?,
+        )?;
+        stamp_entry(&self.entry_path(id), id)?;
+        self.evict()?;
+        Ok(())
+    }
+
+    /// Removes the least recently used pages until the cached pages fit
+    /// into the limit, returning the number of freed bytes
+    pub fn evict(&self) -> Result<u64> {
+        let folder = self
+            .root
+            .join(ARK_FOLDER)
+            .join(PREVIEWS_STORAGE_FOLDER);
+        let mut pages: Vec<(SystemTime, u64, PathBuf)> = WalkDir::new(folder)
+            .min_depth(3)
+            .max_depth(3)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .path()
+                    .parent()
+                    .is_some_and(|parent| parent.ends_with(PAGES_FOLDER))
+            })
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((
+                    metadata.modified().ok()?,
+                    metadata.len(),
+                    entry.into_path(),
+                ))
+            })
+            .collect();
+        let mut total: u64 = pages.iter().map(|(_, len, _)| len).sum();
+        pages.sort();
+
+        let mut freed = 0;
+        for (_, len, path) in pages {
+            if total <= self.limit {
+                break;
+            }
+            fs::remove_file(path)?;
+            total -= len;
+            freed += len;
+        }
+        if freed > 0 {
+            log::debug!("Evicted {} bytes of rendered pages", freed);
+        }
+        Ok(freed)
     }
-    PDFIUM
-        .get()
+
+    fn entry_path(&self, id: ResourceId) -> PathBuf {
+        self.root
+            .join(ARK_FOLDER)
+            .join(PREVIEWS_STORAGE_FOLDER)
+            .join(id.to_string())
+    }
+
+    fn page_path(
+        &self,
+        id: ResourceId,
+        page: u16,
+        quality: PDFQuality,
+    ) -> PathBuf {
+        self.entry_path(id)
+            .join(PAGES_FOLDER)
+            .join(format!("{}-{}", page, quality.name()))
+    }
+}
+
+#[test]
+fn test_page_cache_eviction() {
+    use crate::resource::ResourceIdTrait;
+    use image::RgbImage;
+    use tempdir::TempDir;
+    let dir = TempDir::new("arklib_test").unwrap();
+    let first = ResourceId::compute_bytes(b"first").unwrap();
+    let second = ResourceId::compute_bytes(b"second").unwrap();
+    let page = DynamicImage::ImageRgb8(RgbImage::new(64, 64));
+    let size = images::encode(&page).unwrap().len() as u64;
+
+    let cache = PageCache::with_limit(dir.path(), size * 2);
+    cache
+        .store(first, 0, PDFQuality::Low, &page)
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    cache
+        .store(first, 1, PDFQuality::Low, &page)
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    // using the first page makes the second one the least recently used
+    let cached = cache.load(first, 0, PDFQuality::Low).unwrap();
+    assert_eq!(cached.unwrap().width(), 64);
+    assert!(cache
+        .load(first, 0, PDFQuality::High)
         .unwrap()
-        .load_pdf_from_reader(data, None)
+        .is_none());
+
+    cache
+        .store(second, 0, PDFQuality::Low, &page)
+        .unwrap();
+    assert!(cache
+        .load(first, 1, PDFQuality::Low)
         .unwrap()
-        .pages()
-        .get(0)
+        .is_none());
+    assert!(cache
+        .load(first, 0, PDFQuality::Low)
         .unwrap()
-        .render_with_config(&render_cfg)
+        .is_some());
+    assert!(cache
+        .load(second, 0, PDFQuality::Low)
         .unwrap()
-        .as_image()
+        .is_some());
 }
 
 #[test]
@@ -74,7 +325,7 @@ fn test_multi_pdf_generate() {
         let pdf_reader = File::open("tests/test.pdf").unwrap();
 
         println!("Rendering {}", &i);
-        let img = render_preview_page(pdf_reader, PDFQuality::High);
+        let img = render_preview_page(pdf_reader, PDFQuality::High).unwrap();
 
         img.save(root.join(format!("test{}.png", &i)))
             .expect("cannot save image");
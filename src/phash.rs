@@ -0,0 +1,228 @@
+//! Perceptual hashes for finding near-duplicate images
+//!
+//! Unlike resource IDs, perceptual hashes of an image stay close after
+//! recompression, resizing or small edits, so the Hamming distance between
+//! two hashes tells how similar the images look. Hashes are stored in the
+//! `phash` field of the metadata of the resource.
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+#[cfg(feature = "pdfium")]
+use crate::pdf::{render_preview_page, PDFQuality};
+use crate::resource::ResourceId;
+use crate::storage::metadata;
+use crate::{images, ArklibError, Result};
+
+/// Field of the metadata holding the hash
+pub const PHASH_METADATA_FIELD: &str = "phash";
+
+/// Side of the downscaled image which the DCT is computed from
+const SAMPLE_SIZE: usize = 32;
+/// Side of the block of lowest frequencies forming the hash
+const HASH_SIZE: usize = 8;
+
+/// Computes the 64-bit DCT hash of the image
+pub fn compute(image: &DynamicImage) -> u64 {
+    let sample = image
+        .resize_exact(
+            SAMPLE_SIZE as u32,
+            SAMPLE_SIZE as u32,
+            FilterType::Triangle,
+        )
+        .to_luma8();
+    let pixels: Vec<f64> = sample
+        .pixels()
+        .map(|pixel| pixel.0[0] as f64)
+        .collect();
+    let frequencies = dct(&pixels);
+
+    let mut lowest = Vec::with_capacity(HASH_SIZE * HASH_SIZE);
+    for y in 0..HASH_SIZE {
+        for x in 0..HASH_SIZE {
+            lowest.push(frequencies[y * SAMPLE_SIZE + x]);
+        }
+    }
+    // the first coefficient is the average brightness, which would
+    // dominate the median
+    let mut sorted = lowest[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+
+    lowest
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value > median)
+        .fold(0, |hash, (bit, _)| hash | 1 << bit)
+}
+
+/// Number of differing bits of the hashes
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Hashes the image, or the first page of the PDF document
+pub fn hash_file(path: &Path) -> Result<u64> {
+    #[cfg(feature = "pdfium")]
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"))
+    {
+        let page = render_preview_page(File::open(path)?, PDFQuality::Low)?;
+        return Ok(compute(&page));
+    }
+    let image = images::decode(BufReader::new(File::open(path)?))?;
+    Ok(compute(&image))
+}
+
+/// Hashes the file and stores the hash into the metadata cache
+pub fn generate<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    path: &Path,
+) -> Result<u64> {
+    let hash = hash_file(path)?;
+    metadata::store_field(root, id, PHASH_METADATA_FIELD, &encode(hash))?;
+    Ok(hash)
+}
+
+/// Loads the hash of the resource, if generated before
+pub fn load_phash<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<u64>> {
+    let metadata: Option<serde_json::Value> = metadata::load(root, id)?;
+    match metadata
+        .as_ref()
+        .and_then(|metadata| metadata.get(PHASH_METADATA_FIELD))
+        .and_then(|field| field.as_str())
+    {
+        Some(hash) => u64::from_str_radix(hash, 16)
+            .map(Some)
+            .map_err(|_| ArklibError::Parse),
+        None => Ok(None),
+    }
+}
+
+/// Finds resources which hashes differ from the hash of `id` by at most
+/// `max_distance` bits, the most similar ones first
+pub fn find_similar<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    max_distance: u32,
+) -> Result<Vec<(ResourceId, u32)>> {
+    let hash = load_phash(&root, id)?.ok_or_else(|| {
+        ArklibError::Path(format!("Perceptual hash of {} is missing", id))
+    })?;
+    let mut similar = vec![];
+    for other in metadata::list(&root)? {
+        if other == id {
+            continue;
+        }
+        match load_phash(&root, other) {
+            Ok(Some(other_hash)) => {
+                let distance = distance(hash, other_hash);
+                if distance <= max_distance {
+                    similar.push((other, distance));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Couldn't load metadata of {}: {}", other, e),
+        }
+    }
+    similar.sort_by_key(|(id, distance)| (*distance, *id));
+    Ok(similar)
+}
+
+/// Hashes are stored as hex strings, JSON numbers lose precision
+/// above 2^53 in many parsers
+fn encode(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// Two-dimensional DCT-II of the square sample
+fn dct(pixels: &[f64]) -> Vec<f64> {
+    let n = SAMPLE_SIZE;
+    let cosines: Vec<f64> = (0..n * n)
+        .map(|i| {
+            let (k, x) = (i / n, i % n);
+            ((2 * x + 1) as f64 * k as f64 * std::f64::consts::PI
+                / (2 * n) as f64)
+                .cos()
+        })
+        .collect();
+    let transform = |input: &[f64], stride: usize, step: usize| {
+        let mut output = vec![0.0; n * n];
+        for line in 0..n {
+            for k in 0..n {
+                output[line * stride + k * step] = (0..n)
+                    .map(|x| {
+                        input[line * stride + x * step] * cosines[k * n + x]
+                    })
+                    .sum();
+            }
+        }
+        output
+    };
+    // rows first, then columns
+    let rows = transform(pixels, n, 1);
+    transform(&rows, 1, n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceIdTrait;
+    use image::{Rgb, RgbImage};
+    use tempdir::TempDir;
+
+    fn gradient(width: u32, height: u32, shift: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let value = ((x * 255 / width) as u8)
+                .wrapping_add(((y * 128 / height) as u8) ^ shift);
+            Rgb([value, value / 2, 255 - value])
+        }))
+    }
+
+    #[test]
+    fn resized_images_have_close_hashes() {
+        let original = compute(&gradient(400, 300, 0));
+        let resized = compute(&gradient(200, 150, 0));
+        let blurred = compute(&gradient(400, 300, 0).blur(1.0));
+        let different = compute(&gradient(300, 400, 0xAA).rotate90());
+        assert!(distance(original, resized) <= 4);
+        assert!(distance(original, blurred) <= 4);
+        assert!(distance(original, different) > 10);
+    }
+
+    #[test]
+    fn similar_images_are_found() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let mut ids = vec![];
+        for (name, image) in [
+            ("original.png", gradient(400, 300, 0)),
+            ("copy.jpg", gradient(200, 150, 0)),
+            ("other.png", gradient(300, 400, 0xAA).rotate90()),
+        ] {
+            let path = root.join(name);
+            image.save(&path).unwrap();
+            let id = ResourceId::compute(
+                std::fs::metadata(&path).unwrap().len(),
+                &path,
+            )
+            .unwrap();
+            generate(root, id, &path).unwrap();
+            ids.push(id);
+        }
+
+        let similar = find_similar(root, ids[0], 6).unwrap();
+        assert_eq!(similar.len(), 1);
+        assert_eq!(similar[0].0, ids[1]);
+        assert!(load_phash(root, ids[2]).unwrap().is_some());
+    }
+}
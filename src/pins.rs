@@ -0,0 +1,98 @@
+//! Resources pinned to be kept offline
+//!
+//! Pins are user data kept in a single [`AtomicFile`]. Cached data of
+//! pinned resources is never evicted by [`crate::cache::enforce_budget`],
+//! and [`crate::sync::deletable_ids`] never offers pinned resources for
+//! deletion, even if peers don't have them anymore.
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::resource::ResourceId;
+use crate::{Result, ARK_FOLDER, PINS_STORAGE_FILE};
+
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+struct PinStorage {
+    #[serde_as(as = "BTreeSet<DisplayFromStr>")]
+    pins: BTreeSet<ResourceId>,
+}
+
+/// Loads IDs of all pinned resources of the root
+pub fn load_pins<P: AsRef<Path>>(root: P) -> Result<BTreeSet<ResourceId>> {
+    let path = storage_path(root);
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    match AtomicFile::new(path)?.load()?.open()? {
+        Some(file) => {
+            let storage: PinStorage =
+                serde_json::from_reader(std::io::BufReader::new(file))?;
+            Ok(storage.pins)
+        }
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+pub fn is_pinned<P: AsRef<Path>>(root: P, id: ResourceId) -> Result<bool> {
+    Ok(load_pins(root)?.contains(&id))
+}
+
+/// Pins the resource, returning `false` if it was pinned already
+pub fn pin<P: AsRef<Path>>(root: P, id: ResourceId) -> Result<bool> {
+    let mut inserted = false;
+    let file = AtomicFile::new(storage_path(root))?;
+    modify_json(&file, |storage: &mut Option<PinStorage>| {
+        inserted = storage
+            .get_or_insert_with(PinStorage::default)
+            .pins
+            .insert(id);
+    })?;
+    Ok(inserted)
+}
+
+/// Unpins the resource, returning `false` if it wasn't pinned
+pub fn unpin<P: AsRef<Path>>(root: P, id: ResourceId) -> Result<bool> {
+    let mut removed = false;
+    let file = AtomicFile::new(storage_path(root))?;
+    modify_json(&file, |storage: &mut Option<PinStorage>| {
+        removed = storage
+            .get_or_insert_with(PinStorage::default)
+            .pins
+            .remove(&id);
+    })?;
+    Ok(removed)
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(PINS_STORAGE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn pins_are_toggled() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let id = ResourceId {
+            hash: 1,
+            data_size: 1,
+        };
+        assert!(!is_pinned(dir.path(), id).unwrap());
+        assert!(pin(dir.path(), id).unwrap());
+        assert!(!pin(dir.path(), id).unwrap());
+        assert!(is_pinned(dir.path(), id).unwrap());
+        assert!(unpin(dir.path(), id).unwrap());
+        assert!(!unpin(dir.path(), id).unwrap());
+        assert!(load_pins(dir.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+//! File operations differing between operating systems
+//!
+//! Locks taken by `fs2` are advisory on Unix but mandatory on Windows,
+//! where reading a locked file fails. Windows also refuses to open, rename
+//! or delete files kept open without sharing by another process, e.g. an
+//! antivirus or a sync client scanning a new version, which is transient
+//! and retried by [`retry_shared`].
+use std::fs::{File, Metadata};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use fs2::FileExt;
+
+/// `ERROR_SHARING_VIOLATION`
+#[cfg(windows)]
+const SHARING_VIOLATION: i32 = 32;
+/// `ERROR_LOCK_VIOLATION`
+#[cfg(windows)]
+const LOCK_VIOLATION: i32 = 33;
+
+const SHARING_RETRIES: u32 = 5;
+const SHARING_BACKOFF: Duration = Duration::from_millis(20);
+
+/// Whether the operation failed because another process has the file
+/// open without sharing it
+pub(crate) fn is_sharing_violation(err: &Error) -> bool {
+    #[cfg(windows)]
+    {
+        err.raw_os_error() == Some(SHARING_VIOLATION)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+/// Whether the operation failed because another handle holds a lock
+/// of the file
+pub(crate) fn is_lock_contended(err: &Error) -> bool {
+    #[cfg(windows)]
+    {
+        matches!(err.raw_os_error(), Some(LOCK_VIOLATION | SHARING_VIOLATION))
+    }
+    #[cfg(not(windows))]
+    {
+        err.kind() == ErrorKind::WouldBlock
+            || err.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+    }
+}
+
+/// Runs the operation again with a growing delay while it fails with
+/// a sharing violation
+pub(crate) fn retry_shared<T>(
+    mut operation: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut delay = SHARING_BACKOFF;
+    for _ in 1..SHARING_RETRIES {
+        match operation() {
+            Err(err) if is_sharing_violation(&err) => {
+                log::debug!("Retrying after a sharing violation: {}", err);
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    operation()
+}
+
+/// Tries to lock the file, returning `false` if it's locked elsewhere
+pub(crate) fn try_lock(file: &File, exclusive: bool) -> Result<bool> {
+    let result = match exclusive {
+        true => FileExt::try_lock_exclusive(file),
+        false => FileExt::try_lock_shared(file),
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(err) if is_lock_contended(&err) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Number of hard links of the file, if the platform tells it
+pub(crate) fn link_count(metadata: &Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.nlink())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Whether both paths lead to the same file
+#[cfg(unix)]
+pub(crate) fn same_file(path1: &Path, path2: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (std::fs::metadata(path1), std::fs::metadata(path2)) {
+        (Ok(meta1), Ok(meta2)) => {
+            meta1.dev() == meta2.dev() && meta1.ino() == meta2.ino()
+        }
+        _ => false,
+    }
+}
+
+/// Whether both paths lead to the same file
+#[cfg(not(unix))]
+pub(crate) fn same_file(path1: &Path, path2: &Path) -> bool {
+    match (std::fs::canonicalize(path1), std::fs::canonicalize(path2)) {
+        (Ok(path1), Ok(path2)) => path1 == path2,
+        _ => false,
+    }
+}
+
+/// Makes the file or folder readable and writable by the user
+#[cfg(unix)]
+pub(crate) fn grant_access(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    let mode = match path.is_dir() {
+        true => 0o700,
+        false => 0o600,
+    };
+    permissions.set_mode(permissions.mode() | mode);
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Makes the file or folder readable and writable by the user
+#[cfg(not(unix))]
+#[allow(clippy::permissions_set_readonly_false)]
+pub(crate) fn grant_access(path: &Path) -> Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_readonly(false);
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Reads an extended attribute, `None` if it's missing or unsupported
+#[cfg(unix)]
+pub(crate) fn get_xattr(path: &Path, name: &str) -> Option<Vec<u8>> {
+    xattr::get(path, name).ok()?
+}
+
+#[cfg(unix)]
+pub(crate) fn set_xattr(path: &Path, name: &str, value: &[u8]) -> Result<()> {
+    xattr::set(path, name, value)
+}
+
+/// Reads an extended attribute, `None` if it's missing or unsupported
+#[cfg(not(unix))]
+pub(crate) fn get_xattr(_path: &Path, _name: &str) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_xattr(
+    _path: &Path,
+    _name: &str,
+    _value: &[u8],
+) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "extended attributes are not supported",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn contended_locks_are_reported() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let path = dir.path().join("lock");
+        let first = File::create(&path).unwrap();
+        let second = File::open(&path).unwrap();
+        assert!(try_lock(&first, true).unwrap());
+        assert!(!try_lock(&second, false).unwrap());
+        FileExt::unlock(&first).unwrap();
+        assert!(try_lock(&second, false).unwrap());
+
+        fs::hard_link(&path, dir.path().join("link")).unwrap();
+        if cfg!(unix) {
+            assert_eq!(link_count(&fs::metadata(&path).unwrap()), Some(2));
+            assert!(same_file(&path, &dir.path().join("link")));
+        }
+        let mut attempts = 0;
+        let result: Result<()> = retry_shared(|| {
+            attempts += 1;
+            Err(Error::from(ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,230 @@
+//! Capabilities of the filesystem of a root
+//!
+//! Roots live on very different filesystems: ext4 and APFS on internal
+//! storage, FAT32 and exFAT on SD cards, network shares and mounts of the
+//! Android storage access framework. [`probe`] tries out the features
+//! arklib relies on in `.ark` of the root and stores the results in the
+//! root config, so that the index and atomic files pick strategies which
+//! work there instead of failing or guessing.
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::index::CaseSensitivity;
+use crate::platform::{get_xattr, set_xattr};
+use crate::storage::config::{load_section, store_section};
+use crate::{Result, SwapStrategy, ARK_FOLDER};
+
+const CONFIG_SECTION: &str = "filesystem";
+
+lazy_static! {
+    /// Roots probed or loaded in this process whose filesystem has no hard
+    /// links, consulted by [`crate::AtomicFile::new`]
+    static ref WITHOUT_HARD_LINKS: RwLock<Vec<PathBuf>> = RwLock::new(vec![]);
+}
+
+/// Precisions of timestamps of common filesystems, from the finest
+const GRANULARITIES: [Duration; 7] = [
+    Duration::from_nanos(1),
+    // NTFS
+    Duration::from_nanos(100),
+    Duration::from_micros(1),
+    Duration::from_millis(1),
+    // exFAT
+    Duration::from_millis(10),
+    Duration::from_secs(1),
+    // FAT32
+    Duration::from_secs(2),
+];
+
+/// Features of the filesystem of a root, detected by [`probe`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FsCapabilities {
+    /// Precision of modification times of files
+    pub mtime_granularity: Duration,
+    /// Whether names differing only in case denote the same file
+    pub case_insensitive: bool,
+    /// Whether files can be hard linked, which [`crate::AtomicFile`]
+    /// uses to detect concurrent writes
+    pub hard_links: bool,
+    /// Whether files can have extended attributes, which IDs are mirrored
+    /// into, see [`crate::index::ID_ATTRIBUTE`]
+    pub xattrs: bool,
+}
+
+impl FsCapabilities {
+    /// Case sensitivity to index the root with
+    pub fn case_sensitivity(&self) -> CaseSensitivity {
+        match self.case_insensitive {
+            true => CaseSensitivity::Insensitive,
+            false => CaseSensitivity::Sensitive,
+        }
+    }
+}
+
+/// Detects capabilities of the filesystem of the root and stores them
+/// in the root config
+pub fn probe<P: AsRef<Path>>(root: P) -> Result<FsCapabilities> {
+    let folder = root.as_ref().join(ARK_FOLDER);
+    fs::create_dir_all(&folder)?;
+    let name: String = std::iter::repeat_with(fastrand::alphabetic)
+        .take(10)
+        .collect();
+    let path = folder.join(format!("probe_{}", name));
+    let file = File::create(&path)?;
+    let capabilities = FsCapabilities {
+        mtime_granularity: probe_mtime(&file, &path),
+        case_insensitive: probe_case(&path),
+        hard_links: probe_hard_links(&path),
+        xattrs: probe_xattrs(&path),
+    };
+    drop(file);
+    fs::remove_file(&path)?;
+    // the config itself is stored with the right strategy
+    remember(root.as_ref(), &capabilities);
+
+    log::info!(
+        "Filesystem of {} has {:?}",
+        root.as_ref().display(),
+        capabilities
+    );
+    store_section(root, CONFIG_SECTION, &capabilities)?;
+    Ok(capabilities)
+}
+
+/// Loads the capabilities stored by [`probe`], `None` if the root
+/// has never been probed
+pub fn load_capabilities<P: AsRef<Path>>(
+    root: P,
+) -> Result<Option<FsCapabilities>> {
+    let capabilities = load_section(&root, CONFIG_SECTION)?;
+    if let Some(capabilities) = &capabilities {
+        remember(root.as_ref(), capabilities);
+    }
+    Ok(capabilities)
+}
+
+/// Loads the capabilities of the root, probing it the first time
+pub fn capabilities<P: AsRef<Path>>(root: P) -> Result<FsCapabilities> {
+    match load_capabilities(&root)? {
+        Some(capabilities) => Ok(capabilities),
+        None => probe(root),
+    }
+}
+
+/// Strategy of swapping versions of atomic files in the directory,
+/// according to the probe of the root containing it
+pub(crate) fn swap_strategy(directory: &Path) -> SwapStrategy {
+    let without = WITHOUT_HARD_LINKS
+        .read()
+        .unwrap_or_else(|e| e.into_inner());
+    match without
+        .iter()
+        .any(|root| directory.starts_with(root))
+    {
+        true => SwapStrategy::Rename,
+        false => SwapStrategy::HardLink,
+    }
+}
+
+fn remember(root: &Path, capabilities: &FsCapabilities) {
+    let mut roots = vec![root.to_path_buf()];
+    if let Ok(canonical) = fs::canonicalize(root) {
+        roots.push(canonical);
+    }
+    roots.dedup();
+    let mut without = WITHOUT_HARD_LINKS
+        .write()
+        .unwrap_or_else(|e| e.into_inner());
+    without.retain(|known| !roots.contains(known));
+    if !capabilities.hard_links {
+        without.extend(roots);
+    }
+}
+
+/// Sets an odd number of seconds with all digits of nanoseconds and
+/// finds the precision the filesystem keeps of it
+fn probe_mtime(file: &File, path: &Path) -> Duration {
+    let set = UNIX_EPOCH + Duration::new(1_700_000_001, 123_456_789);
+    let got = file
+        .set_modified(set)
+        .and_then(|_| fs::metadata(path)?.modified());
+    let error = match got {
+        Ok(got) => got
+            .duration_since(set)
+            .or_else(|_| set.duration_since(got))
+            .unwrap_or_default(),
+        Err(e) => {
+            log::warn!("Couldn't set modification time: {}", e);
+            return GRANULARITIES[GRANULARITIES.len() - 1];
+        }
+    };
+    GRANULARITIES
+        .into_iter()
+        .find(|granularity| error < *granularity)
+        .unwrap_or(GRANULARITIES[GRANULARITIES.len() - 1])
+}
+
+fn probe_case(path: &Path) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let upper: PathBuf =
+        path.with_file_name(name.to_string_lossy().to_uppercase());
+    fs::metadata(upper).is_ok()
+}
+
+fn probe_hard_links(path: &Path) -> bool {
+    let link = path.with_extension("link");
+    match fs::hard_link(path, &link) {
+        Ok(()) => {
+            let _ = fs::remove_file(link);
+            true
+        }
+        Err(e) => {
+            log::debug!("Couldn't create a hard link: {}", e);
+            false
+        }
+    }
+}
+
+fn probe_xattrs(path: &Path) -> bool {
+    const ATTRIBUTE: &str = "user.ark.probe";
+    set_xattr(path, ATTRIBUTE, b"1").is_ok()
+        && get_xattr(path, ATTRIBUTE).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn capabilities_are_probed_once() {
+        crate::initialize();
+        let dir = TempDir::new("arklib_test").unwrap();
+        assert_eq!(load_capabilities(dir.path()).unwrap(), None);
+
+        let probed = capabilities(dir.path()).unwrap();
+        assert_eq!(load_capabilities(dir.path()).unwrap(), Some(probed));
+        assert!(probed.mtime_granularity <= Duration::from_secs(2));
+        assert_eq!(
+            probed.case_insensitive,
+            crate::index::is_case_insensitive(dir.path())
+        );
+        if cfg!(target_os = "linux") {
+            assert!(probed.hard_links);
+            assert!(!probed.case_insensitive);
+        }
+        // only the config is left behind
+        let files: Vec<_> = fs::read_dir(dir.path().join(ARK_FOLDER))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().starts_with("probe"))
+            .collect();
+        assert!(files.is_empty());
+    }
+}
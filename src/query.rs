@@ -0,0 +1,520 @@
+//! Filtering resources of an index by tags, kinds, scores and dates
+//!
+//! User data of the root is loaded once per query, so evaluating
+//! a query costs the same as reading the tag and score storages.
+//!
+//! Queries run against any [`QueryBackend`]. Depending on the query and
+//! the backend, [`Query::plan`] either looks up resources having the
+//! required tags, lets the backend select entries by folder, kinds and
+//! dates, or scans all entries of the index. Entries found either way
+//! are checked against the whole query.
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::index::json::check_version;
+use crate::index::{
+    IndexEntry, RelativePath, ResourceIndex, JSON_SCHEMA_VERSION,
+};
+use crate::resource::ResourceId;
+use crate::scores::{load_scores, Score};
+use crate::tags::{load_tags, Tag};
+use crate::timeline::Timeline;
+use crate::Result;
+
+/// Kinds of resources, recognized by file extensions
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Text,
+    Archive,
+    /// Links saved by [`crate::link::Link`], named by their IDs
+    Link,
+    Other,
+}
+
+const KINDS: &[(Kind, &[&str])] = &[
+    (
+        Kind::Image,
+        &[
+            "jpg", "jpeg", "png", "webp", "gif", "bmp", "tif", "tiff", "heic",
+            "heif", "svg", "raw", "dng",
+        ],
+    ),
+    (
+        Kind::Video,
+        &["mp4", "mkv", "webm", "mov", "avi", "m4v", "3gp"],
+    ),
+    (
+        Kind::Audio,
+        &["mp3", "ogg", "opus", "flac", "wav", "m4a", "aac"],
+    ),
+    (
+        Kind::Document,
+        &[
+            "pdf", "epub", "doc", "docx", "odt", "xls", "xlsx", "ods", "ppt",
+            "pptx", "odp", "djvu",
+        ],
+    ),
+    (
+        Kind::Text,
+        &[
+            "txt", "md", "org", "adoc", "rst", "json", "csv", "html", "xml",
+        ],
+    ),
+    (
+        Kind::Archive,
+        &["zip", "tar", "gz", "tgz", "7z", "rar", "xz", "bz2"],
+    ),
+];
+
+impl Kind {
+    /// Extensions of files of the kind, empty for links and other files
+    /// which are not recognized by extensions
+    pub fn extensions(&self) -> &'static [&'static str] {
+        KINDS
+            .iter()
+            .find(|(kind, _)| kind == self)
+            .map(|(_, extensions)| *extensions)
+            .unwrap_or_default()
+    }
+
+    pub fn of<P: AsRef<Path>>(path: P) -> Kind {
+        let path = path.as_ref();
+        let extension = match path.extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => {
+                let is_link = path.file_name().is_some_and(|name| {
+                    name.to_string_lossy()
+                        .parse::<ResourceId>()
+                        .is_ok()
+                });
+                return match is_link {
+                    true => Kind::Link,
+                    false => Kind::Other,
+                };
+            }
+        };
+        KINDS
+            .iter()
+            .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+            .map(|(kind, _)| *kind)
+            .unwrap_or(Kind::Other)
+    }
+}
+
+/// Conditions which all must hold for a resource to match,
+/// empty conditions match everything
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Query {
+    /// Resources must have all of the tags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub all_tags: Vec<Tag>,
+    /// Resources must have at least one of the tags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub any_tags: Vec<Tag>,
+    /// Resources must have none of the tags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_tags: Vec<Tag>,
+    /// Resources must be of one of the kinds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kinds: Vec<Kind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_score: Option<Score>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_score: Option<Score>,
+    /// Resources must be dated at or after, see [`crate::timeline`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<NaiveDateTime>,
+    /// Resources must be dated before
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<NaiveDateTime>,
+    /// Resources must be inside of the folder at any depth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<RelativePath>,
+}
+
+/// Ways of finding candidates of a query, see [`Query::plan`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plan {
+    /// Resources having the required tags are looked up in the backend
+    Tags,
+    /// The backend selects entries by folder, kinds and dates
+    Pushdown,
+    /// All entries of the index are checked, narrowed by folder only
+    Scan,
+}
+
+/// Part of a query which backends can evaluate by themselves
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub folder: Option<RelativePath>,
+    /// Files must have one of the extensions, compared ignoring case,
+    /// any file matches if empty
+    pub extensions: Vec<&'static str>,
+    /// Files must be modified at or after, in milliseconds since the epoch
+    pub modified_from: Option<u64>,
+    /// Files must be modified before, in milliseconds since the epoch
+    pub modified_to: Option<u64>,
+}
+
+/// Index which queries can be run against
+pub trait QueryBackend {
+    fn root(&self) -> &Path;
+
+    /// Whether [`QueryBackend::select`] evaluates filters without
+    /// going through all entries
+    fn pushes_down(&self) -> bool;
+
+    /// Lists entries of the resource, empty if it is not indexed
+    fn lookup(
+        &self,
+        id: &ResourceId,
+    ) -> Result<Vec<(RelativePath, IndexEntry)>>;
+
+    /// Lists entries which may pass the filter, backends are allowed
+    /// to return entries not passing it
+    fn select(
+        &self,
+        filter: &Filter,
+    ) -> Result<Vec<(RelativePath, IndexEntry)>>;
+}
+
+impl QueryBackend for ResourceIndex {
+    fn root(&self) -> &Path {
+        ResourceIndex::root(self)
+    }
+
+    fn pushes_down(&self) -> bool {
+        false
+    }
+
+    fn lookup(
+        &self,
+        id: &ResourceId,
+    ) -> Result<Vec<(RelativePath, IndexEntry)>> {
+        self.aliases(id)
+            .into_iter()
+            .filter_map(|path| {
+                let entry = self.get_entry(&path)?.clone();
+                Some(Ok((self.relative_path(&path).ok()?, entry)))
+            })
+            .collect()
+    }
+
+    fn select(
+        &self,
+        filter: &Filter,
+    ) -> Result<Vec<(RelativePath, IndexEntry)>> {
+        let folder = filter.folder.clone().unwrap_or_default();
+        Ok(self
+            .entries_with_prefix(&folder)
+            .into_iter()
+            .map(|(path, entry)| (path, entry.clone()))
+            .collect())
+    }
+}
+
+impl Query {
+    /// Whether the resource with the given attributes matches
+    pub fn matches(
+        &self,
+        tags: &BTreeSet<Tag>,
+        kind: Kind,
+        score: Score,
+        date: NaiveDateTime,
+    ) -> bool {
+        self.all_tags.iter().all(|tag| tags.contains(tag))
+            && (self.any_tags.is_empty()
+                || self.any_tags.iter().any(|tag| tags.contains(tag)))
+            && !self.no_tags.iter().any(|tag| tags.contains(tag))
+            && (self.kinds.is_empty() || self.kinds.contains(&kind))
+            && self.min_score.is_none_or(|min| score >= min)
+            && self.max_score.is_none_or(|max| score <= max)
+            && self.from.is_none_or(|from| date >= from)
+            && self.to.is_none_or(|to| date < to)
+    }
+
+    fn uses_dates(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+
+    /// Chooses how candidates of the query are found in the backend
+    ///
+    /// Tags are stored apart from the index, so resources having required
+    /// tags are looked up one by one, which beats any other way for all
+    /// but the most popular tags.
+    pub fn plan<B: QueryBackend>(&self, backend: &B) -> Plan {
+        if !self.all_tags.is_empty() || !self.any_tags.is_empty() {
+            Plan::Tags
+        } else if backend.pushes_down() {
+            Plan::Pushdown
+        } else {
+            Plan::Scan
+        }
+    }
+
+    /// Extracts the part of the query evaluated by backends, dates are
+    /// passed down only when resources are dated by their files
+    pub fn filter(&self, dated_by_files: bool) -> Filter {
+        // links and other files are not recognized by extensions
+        let extensions = match self
+            .kinds
+            .iter()
+            .all(|kind| !kind.extensions().is_empty())
+        {
+            true => self
+                .kinds
+                .iter()
+                .flat_map(|kind| kind.extensions())
+                .copied()
+                .collect(),
+            false => vec![],
+        };
+        let millis = |date: NaiveDateTime| {
+            date.and_utc().timestamp_millis().max(0) as u64
+        };
+        Filter {
+            folder: self.folder.clone(),
+            extensions,
+            modified_from: self.from.filter(|_| dated_by_files).map(millis),
+            modified_to: self.to.filter(|_| dated_by_files).map(millis),
+        }
+    }
+}
+
+/// Resources found by a query together with the query,
+/// e.g. for JSON output of tools
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryResults {
+    pub query: Query,
+    /// Sorted IDs of the found resources
+    pub resources: Vec<ResourceId>,
+}
+
+impl QueryResults {
+    pub fn run<B: QueryBackend>(backend: &B, query: &Query) -> Result<Self> {
+        Ok(QueryResults {
+            query: query.clone(),
+            resources: self::query(backend, query)?.into_iter().collect(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct QueryResultsRef<'a> {
+    version: u32,
+    query: &'a Query,
+    resources: &'a [ResourceId],
+}
+
+#[derive(Deserialize)]
+struct QueryResultsRepr {
+    version: u32,
+    query: Query,
+    resources: Vec<ResourceId>,
+}
+
+impl Serialize for QueryResults {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        QueryResultsRef {
+            version: JSON_SCHEMA_VERSION,
+            query: &self.query,
+            resources: &self.resources,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for QueryResults {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let repr = QueryResultsRepr::deserialize(deserializer)?;
+        check_version(repr.version)?;
+        Ok(QueryResults {
+            query: repr.query,
+            resources: repr.resources,
+        })
+    }
+}
+
+/// Finds resources of the index matching the query
+///
+/// Resources are dated by the timeline of the root if it is built,
+/// otherwise by modification times of their files.
+pub fn query<B: QueryBackend>(
+    backend: &B,
+    query: &Query,
+) -> Result<BTreeSet<ResourceId>> {
+    let root = backend.root();
+    let tags = load_tags(root)?;
+    let scores = load_scores(root)?;
+    let dates: HashMap<ResourceId, NaiveDateTime> = match query.uses_dates() {
+        true => Timeline::load(root)?
+            .entries()
+            .iter()
+            .map(|entry| (entry.id, entry.date))
+            .collect(),
+        false => HashMap::new(),
+    };
+    let no_tags = BTreeSet::new();
+
+    let plan = query.plan(backend);
+    log::debug!("Running {:?} with plan {:?}", query, plan);
+    let candidates = match plan {
+        Plan::Tags => {
+            let mut candidates = vec![];
+            for (id, resource_tags) in &tags {
+                let has = |tag: &Tag| resource_tags.contains(tag);
+                if query.all_tags.iter().all(has)
+                    && (query.any_tags.is_empty()
+                        || query.any_tags.iter().any(has))
+                {
+                    candidates.extend(backend.lookup(id)?);
+                }
+            }
+            candidates
+        }
+        Plan::Pushdown | Plan::Scan => {
+            backend.select(&query.filter(dates.is_empty()))?
+        }
+    };
+
+    Ok(candidates
+        .into_iter()
+        .filter(|(path, entry)| {
+            let date = dates.get(&entry.id).copied().unwrap_or_else(|| {
+                DateTime::<Utc>::from(entry.modified).naive_utc()
+            });
+            query
+                .folder
+                .as_ref()
+                .is_none_or(|folder| path.starts_with(folder))
+                && query.matches(
+                    tags.get(&entry.id).unwrap_or(&no_tags),
+                    Kind::of(path.as_str()),
+                    scores.get(&entry.id).copied().unwrap_or_default(),
+                    date,
+                )
+        })
+        .map(|(_, entry)| entry.id)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scores::set_score;
+    use crate::tags::add_tags;
+    use tempdir::TempDir;
+
+    #[test]
+    fn kinds_are_recognized() {
+        assert_eq!(Kind::of("a/photo.JPG"), Kind::Image);
+        assert_eq!(Kind::of("notes.md"), Kind::Text);
+        assert_eq!(Kind::of("links/12-345"), Kind::Link);
+        assert_eq!(Kind::of("Makefile"), Kind::Other);
+    }
+
+    #[test]
+    fn resources_are_filtered() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        std::fs::write(root.join("sea.jpg"), "sea").unwrap();
+        std::fs::write(root.join("city.jpg"), "city").unwrap();
+        std::fs::write(root.join("trip.md"), "trip").unwrap();
+        let index = ResourceIndex::build(&root);
+        let id = |name: &str| index.get_entry(root.join(name)).unwrap().id;
+        add_tags(&root, id("sea.jpg"), &["travel".into(), "sea".into()])
+            .unwrap();
+        add_tags(&root, id("city.jpg"), &["travel".into()]).unwrap();
+        add_tags(&root, id("trip.md"), &["travel".into()]).unwrap();
+        set_score(&root, id("sea.jpg"), 3).unwrap();
+
+        let photos = Query {
+            all_tags: vec!["travel".into()],
+            kinds: vec![Kind::Image],
+            ..Query::default()
+        };
+        assert_eq!(
+            query(&index, &photos).unwrap(),
+            [id("sea.jpg"), id("city.jpg")].into()
+        );
+        let best = Query {
+            no_tags: vec!["city".into()],
+            min_score: Some(1),
+            ..photos
+        };
+        assert_eq!(query(&index, &best).unwrap(), [id("sea.jpg")].into());
+        let future = Query {
+            from: Some(Utc::now().naive_utc() + chrono::Duration::days(1)),
+            ..Query::default()
+        };
+        assert!(query(&index, &future).unwrap().is_empty());
+    }
+
+    #[test]
+    fn queries_are_planned() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        std::fs::create_dir(root.join("trips")).unwrap();
+        std::fs::write(root.join("trips/sea.jpg"), "sea").unwrap();
+        std::fs::write(root.join("trips/plan.md"), "plan").unwrap();
+        std::fs::write(root.join("city.jpg"), "city").unwrap();
+        let index = ResourceIndex::build(&root);
+        let id = |name: &str| index.get_entry(root.join(name)).unwrap().id;
+        add_tags(&root, id("city.jpg"), &["travel".into()]).unwrap();
+
+        let trips = Query {
+            folder: Some(RelativePath::parse("trips").unwrap()),
+            kinds: vec![Kind::Image, Kind::Text],
+            ..Query::default()
+        };
+        assert_eq!(trips.plan(&index), Plan::Scan);
+        assert_eq!(trips.filter(true).extensions.len(), 22);
+        assert_eq!(
+            query(&index, &trips).unwrap(),
+            [id("trips/sea.jpg"), id("trips/plan.md")].into()
+        );
+        let tagged = Query {
+            any_tags: vec!["travel".into()],
+            ..Query::default()
+        };
+        assert_eq!(tagged.plan(&index), Plan::Tags);
+        assert_eq!(query(&index, &tagged).unwrap(), [id("city.jpg")].into());
+        let results = QueryResults::run(&index, &tagged).unwrap();
+        let json = serde_json::to_value(&results).unwrap();
+        assert_eq!(json["version"], JSON_SCHEMA_VERSION);
+        assert_eq!(json["query"]["any_tags"][0], "travel");
+        let parsed: QueryResults = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, results);
+        let links = Query {
+            kinds: vec![Kind::Image, Kind::Link],
+            ..Query::default()
+        };
+        assert!(links.filter(true).extensions.is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+//! Coordination of processes opening the same root
+//!
+//! The first process opening a root becomes its writer by locking
+//! `.ark/writer` exclusively for as long as the root stays open. The
+//! writer keeps its process ID and a heartbeat timestamp in the file, so
+//! other processes opening the root as readers can tell who is writing
+//! and whether it is still alive. Only the writer stores the index and
+//! runs watchers, readers keep their updates in memory.
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+
+use crate::platform;
+use crate::{Result, ARK_FOLDER, WRITER_LOCK_FILE};
+
+/// How often the writer refreshes its heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// Writers which haven't refreshed the heartbeat for this long
+/// are considered dead, even if the lock is still held
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootRole {
+    /// Stores the index and user data of the root
+    Writer,
+    /// Only reads the root, another process is the writer
+    Reader,
+}
+
+/// Contents of the writer lock file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriterInfo {
+    pub pid: u32,
+    /// Milliseconds since the Unix epoch
+    pub heartbeat: u64,
+}
+
+impl WriterInfo {
+    pub fn is_alive(&self) -> bool {
+        now_millis().saturating_sub(self.heartbeat)
+            < HEARTBEAT_TIMEOUT.as_millis() as u64
+    }
+}
+
+/// Role of the current process for a root, the writer lock
+/// is released when the lease is dropped
+pub struct RootLease {
+    role: RootRole,
+    lock_path: PathBuf,
+    /// Locked file shared with the heartbeat thread, which stops
+    /// once the file is taken away
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl RootLease {
+    /// Becomes the writer of the root if there is none yet,
+    /// or a reader otherwise
+    pub fn acquire<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let ark = root.as_ref().join(ARK_FOLDER);
+        fs::create_dir_all(&ark)?;
+        let lock_path = ark.join(WRITER_LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)?;
+
+        // failing to lock at all, e.g. on some network shares, also
+        // leaves writing to others
+        if !platform::try_lock(&file, true).unwrap_or(false) {
+            log::info!("{} has another writer", root.as_ref().display());
+            return Ok(RootLease {
+                role: RootRole::Reader,
+                lock_path,
+                file: Arc::new(Mutex::new(None)),
+            });
+        }
+
+        log::info!("Became the writer of {}", root.as_ref().display());
+        beat(&mut file)?;
+        let file = Arc::new(Mutex::new(Some(file)));
+        let heartbeat = Arc::downgrade(&file);
+        thread::spawn(move || loop {
+            thread::sleep(HEARTBEAT_INTERVAL);
+            let file = match heartbeat.upgrade() {
+                Some(file) => file,
+                None => break,
+            };
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            match file.as_mut() {
+                Some(file) => {
+                    if let Err(e) = beat(file) {
+                        log::warn!("Couldn't refresh the heartbeat: {}", e);
+                    }
+                }
+                None => break,
+            }
+        });
+
+        Ok(RootLease {
+            role: RootRole::Writer,
+            lock_path,
+            file,
+        })
+    }
+
+    pub fn role(&self) -> RootRole {
+        self.role
+    }
+
+    pub fn is_writer(&self) -> bool {
+        self.role == RootRole::Writer
+    }
+
+    /// Reads the process ID and the heartbeat of the current writer,
+    /// absent if the writer hasn't written them yet
+    pub fn writer(&self) -> Option<WriterInfo> {
+        let bytes = fs::read(&self.lock_path).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+impl Drop for RootLease {
+    fn drop(&mut self) {
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(file) = file.take() {
+            if let Err(e) = file.set_len(0) {
+                log::warn!("Couldn't clear the writer lock: {}", e);
+            }
+            if let Err(e) = FileExt::unlock(&file) {
+                log::warn!("Couldn't release the writer lock: {}", e);
+            }
+        }
+    }
+}
+
+fn beat(file: &mut File) -> Result<()> {
+    let info = WriterInfo {
+        pid: std::process::id(),
+        heartbeat: now_millis(),
+    };
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&serde_json::to_vec(&info)?)?;
+    Ok(())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn single_writer_many_readers() {
+        let dir = TempDir::new("arklib_test").unwrap();
+
+        let writer = RootLease::acquire(dir.path()).unwrap();
+        assert_eq!(writer.role(), RootRole::Writer);
+        let reader1 = RootLease::acquire(dir.path()).unwrap();
+        let reader2 = RootLease::acquire(dir.path()).unwrap();
+        assert_eq!(reader1.role(), RootRole::Reader);
+        assert_eq!(reader2.role(), RootRole::Reader);
+
+        let info = reader1.writer().unwrap();
+        assert_eq!(info.pid, std::process::id());
+        assert!(info.is_alive());
+
+        drop(writer);
+        assert_eq!(reader1.writer(), None);
+        let writer = RootLease::acquire(dir.path()).unwrap();
+        assert!(writer.is_writer());
+    }
+
+    #[test]
+    fn registered_indexes_are_refreshed_and_forgotten() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+
+        let index = crate::provide_index(&root).unwrap();
+        assert!(crate::registered_roots().contains(&root));
+        assert_eq!(crate::root_role(&root), Some(RootRole::Writer));
+
+        fs::write(root.join("b.txt"), "b").unwrap();
+        let refreshed = crate::refresh_index(&root).unwrap();
+        assert!(Arc::ptr_eq(&index, &refreshed));
+        assert_eq!(index.read().unwrap().count_files(), 2);
+
+        assert!(crate::forget_index(&root));
+        assert!(!crate::forget_index(&root));
+        assert!(!crate::registered_roots().contains(&root));
+        assert_eq!(crate::root_role(&root), None);
+        // the root is released for other writers
+        assert!(RootLease::acquire(&root).unwrap().is_writer());
+    }
+
+    #[test]
+    fn stale_heartbeat_is_detected() {
+        let info = WriterInfo {
+            pid: 1,
+            heartbeat: now_millis() - 2 * HEARTBEAT_TIMEOUT.as_millis() as u64,
+        };
+        assert!(!info.is_alive());
+    }
+}
@@ -0,0 +1,171 @@
+//! Directed relations between resources, like a note mentioning a photo
+//!
+//! Relations are user data, so they are kept in an [`AtomicFile`] and
+//! survive deletion of the related resources. Resources change their IDs
+//! when their content is edited, [`replace_id`] moves the relations of
+//! the old ID to the new one.
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::resource::ResourceId;
+use crate::{Result, ARK_FOLDER, RELATIONS_STORAGE_FILE};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+pub struct Relation {
+    pub from: ResourceId,
+    pub to: ResourceId,
+}
+
+/// Loads all relations of the root
+pub fn load_relations<P: AsRef<Path>>(root: P) -> Result<BTreeSet<Relation>> {
+    let path = storage_path(root);
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    match AtomicFile::new(path)?.load()?.open()? {
+        Some(file) => {
+            Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+        }
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+/// Relates `from` to `to`, relating twice has no effect
+pub fn relate<P: AsRef<Path>>(
+    root: P,
+    from: ResourceId,
+    to: ResourceId,
+) -> Result<()> {
+    modify_relations(root, |relations| {
+        relations.insert(Relation { from, to });
+    })
+}
+
+pub fn unrelate<P: AsRef<Path>>(
+    root: P,
+    from: ResourceId,
+    to: ResourceId,
+) -> Result<()> {
+    modify_relations(root, |relations| {
+        relations.remove(&Relation { from, to });
+    })
+}
+
+/// Returns resources which `id` is related to
+pub fn related<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<BTreeSet<ResourceId>> {
+    Ok(load_relations(root)?
+        .into_iter()
+        .filter(|relation| relation.from == id)
+        .map(|relation| relation.to)
+        .collect())
+}
+
+/// Returns resources related to `id`
+pub fn backlinks<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<BTreeSet<ResourceId>> {
+    Ok(load_relations(root)?
+        .into_iter()
+        .filter(|relation| relation.to == id)
+        .map(|relation| relation.from)
+        .collect())
+}
+
+/// Moves relations in both directions from `old_id` to `new_id`
+pub fn replace_id<P: AsRef<Path>>(
+    root: P,
+    old_id: ResourceId,
+    new_id: ResourceId,
+) -> Result<()> {
+    if load_relations(&root)?
+        .iter()
+        .all(|relation| relation.from != old_id && relation.to != old_id)
+    {
+        return Ok(());
+    }
+    let replace = |id| {
+        if id == old_id {
+            new_id
+        } else {
+            id
+        }
+    };
+    modify_relations(root, |relations| {
+        *relations = relations
+            .iter()
+            .map(|relation| Relation {
+                from: replace(relation.from),
+                to: replace(relation.to),
+            })
+            .filter(|relation| relation.from != relation.to)
+            .collect();
+    })
+}
+
+pub(crate) fn modify_relations<P: AsRef<Path>>(
+    root: P,
+    mut operator: impl FnMut(&mut BTreeSet<Relation>),
+) -> Result<()> {
+    let file = AtomicFile::new(storage_path(root))?;
+    modify_json(&file, |relations: &mut Option<BTreeSet<Relation>>| {
+        operator(relations.get_or_insert_with(BTreeSet::new));
+    })?;
+    Ok(())
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(RELATIONS_STORAGE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn id(hash: u32) -> ResourceId {
+        ResourceId { hash, data_size: 1 }
+    }
+
+    #[test]
+    fn relations_follow_edited_resources() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        assert!(load_relations(root).unwrap().is_empty());
+
+        relate(root, id(1), id(2)).unwrap();
+        relate(root, id(1), id(3)).unwrap();
+        relate(root, id(1), id(2)).unwrap();
+        relate(root, id(4), id(1)).unwrap();
+        assert_eq!(related(root, id(1)).unwrap(), [id(2), id(3)].into());
+        assert_eq!(backlinks(root, id(1)).unwrap(), [id(4)].into());
+
+        replace_id(root, id(1), id(5)).unwrap();
+        assert!(related(root, id(1)).unwrap().is_empty());
+        assert_eq!(related(root, id(5)).unwrap(), [id(2), id(3)].into());
+        assert_eq!(backlinks(root, id(5)).unwrap(), [id(4)].into());
+
+        unrelate(root, id(5), id(3)).unwrap();
+        assert_eq!(load_relations(root).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,617 @@
+//! Pushing and pulling of user data and resources to cloud storage
+//!
+//! User data is kept in [`crate::AtomicFile`]s, and every version of such
+//! a file is an immutable file named after the device which wrote it.
+//! Stores only mirror these version files, so devices never overwrite
+//! each other's data: versions written concurrently by different devices
+//! are resolved by `AtomicFile` just like after any other file sync.
+//!
+//! Resources are stored under their IDs, so the same content
+//! is uploaded only once no matter how many copies of it exist.
+//! Placeholders of files kept in the cloud are not uploaded, since their
+//! IDs don't depend on the content. Downloaded resources are verified with
+//! the options of the local root, so devices sharing a store must agree
+//! on sampling and namespace of IDs.
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use tokio::io::AsyncWriteExt;
+use walkdir::WalkDir;
+
+use crate::index::IndexOptions;
+use crate::resource::ResourceId;
+use crate::sync::rules::SyncRules;
+use crate::sync::{destination_path, missing_ids, place_file};
+use crate::util::fs::{join_relative, relative_path};
+use crate::{ArklibError, ResourceIndexLock, Result, ARK_FOLDER};
+
+pub mod s3;
+pub mod webdav;
+
+pub use s3::S3Store;
+pub use webdav::WebDavStore;
+
+const USER_DATA_FOLDER: &str = "user";
+const RESOURCES_FOLDER: &str = "resources";
+
+/// Characters which are left as is in URLs, everything else is encoded
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Storage of immutable objects addressed by keys with `/` as separator
+///
+/// Objects are streamed from and into files, so that resources of any size
+/// never have to fit into memory.
+pub trait RemoteStore {
+    /// Lists keys of all objects starting with the prefix
+    fn list(
+        &self,
+        prefix: &str,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    /// Writes the object into the file, returning `false` without creating
+    /// the file if there is no object with the key
+    fn get(
+        &self,
+        key: &str,
+        file: &Path,
+    ) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Uploads the content of the file as the object
+    fn put(
+        &self,
+        key: &str,
+        file: &Path,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn delete(&self, key: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Uploads versions of user data files which the store doesn't have yet,
+/// returning the number of uploaded files
+///
+/// Keys of the files are their paths relative to the `.ark` folder.
+pub async fn push_user_data<S: RemoteStore, P: AsRef<Path>>(
+    store: &S,
+    root: P,
+) -> Result<usize> {
+    let ark = root.as_ref().join(ARK_FOLDER);
+    let prefix = format!("{}/", USER_DATA_FOLDER);
+    let remote: HashSet<String> =
+        store.list(&prefix).await?.into_iter().collect();
+
+    let mut pushed = 0;
+    for entry in WalkDir::new(ark.join(USER_DATA_FOLDER))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+    {
+        if !is_version_file(entry.path()) {
+            continue;
+        }
+        let key = relative_path(&ark, entry.path())?;
+        if !remote.contains(&key) {
+            log::debug!("Pushing {}", key);
+            store.put(&key, entry.path()).await?;
+            pushed += 1;
+        }
+    }
+    log::info!("Pushed {} user data files", pushed);
+    Ok(pushed)
+}
+
+/// Downloads versions of user data files which are absent locally,
+/// returning the number of downloaded files
+pub async fn pull_user_data<S: RemoteStore, P: AsRef<Path>>(
+    store: &S,
+    root: P,
+) -> Result<usize> {
+    let ark = root.as_ref().join(ARK_FOLDER);
+    let prefix = format!("{}/", USER_DATA_FOLDER);
+
+    let mut pulled = 0;
+    for key in store.list(&prefix).await? {
+        let path = user_data_path(&ark, &key)?;
+        if path.exists() || !is_version_file(&path) {
+            continue;
+        }
+        log::debug!("Pulling {}", key);
+        let tmp = partial_path(&path)?;
+        let downloaded = store.get(&key, &tmp).await;
+        if !matches!(downloaded, Ok(true)) {
+            remove_partial(&tmp);
+        }
+        if !downloaded? {
+            continue;
+        }
+        if place_file(&tmp, &path)? {
+            pulled += 1;
+        } else {
+            remove_partial(&tmp);
+        }
+    }
+    log::info!("Pulled {} user data files", pulled);
+    Ok(pulled)
+}
+
+/// Uploads resources of the index which the store doesn't have yet,
+/// returning IDs of the uploaded resources
+///
+/// Resources are stored as `resources/<id>/<path relative to the root>`,
+/// resources excluded by [`SyncRules`] of the root are skipped.
+pub async fn push_resources<S: RemoteStore>(
+    store: &S,
+    index: &ResourceIndexLock,
+) -> Result<Vec<ResourceId>> {
+    let root = index_root(index)?;
+    let filter = SyncRules::load(&root)?.filter()?;
+    if !filter.syncs_content() {
+        return Ok(vec![]);
+    }
+
+    let prefix = format!("{}/", RESOURCES_FOLDER);
+    let remote: HashSet<ResourceId> = store
+        .list(&prefix)
+        .await?
+        .iter()
+        .filter_map(|key| parse_resource_key(key))
+        .map(|(id, _)| id)
+        .collect();
+
+    let paths: Vec<(ResourceId, PathBuf)> = {
+        let index = index.read().map_err(|_| lock_error())?;
+        index
+            .ids()
+            .filter(|id| !remote.contains(id))
+            .filter_map(|id| {
+                index
+                    .get_path(id)
+                    .map(|path| (*id, path.to_path_buf()))
+            })
+            .filter(|(_, path)| {
+                !index
+                    .get_entry(path)
+                    .is_some_and(|entry| entry.remote)
+            })
+            .collect()
+    };
+
+    let mut pushed = vec![];
+    for (id, path) in paths {
+        let relative = relative_path(&root, &path)?;
+        if !filter.allows(&relative, &id) {
+            continue;
+        }
+        let key = format!("{}/{}/{}", RESOURCES_FOLDER, id, relative);
+        log::debug!("Pushing {}", key);
+        store.put(&key, &path).await?;
+        pushed.push(id);
+    }
+    log::info!("Pushed {} resources", pushed.len());
+    Ok(pushed)
+}
+
+/// Downloads resources which the store has and the local index doesn't,
+/// placing them by the same relative paths as they were pushed from
+///
+/// Resources excluded by [`SyncRules`] of the root are skipped. Every
+/// downloaded resource is verified against its ID before being indexed.
+/// Returns IDs of the downloaded resources.
+pub async fn pull_resources<S: RemoteStore>(
+    store: &S,
+    index: &ResourceIndexLock,
+) -> Result<Vec<ResourceId>> {
+    let root = index_root(index)?;
+    let filter = SyncRules::load(&root)?.filter()?;
+    if !filter.syncs_content() {
+        return Ok(vec![]);
+    }
+
+    let prefix = format!("{}/", RESOURCES_FOLDER);
+    let mut keys: HashMap<ResourceId, String> = HashMap::new();
+    let mut remote = vec![];
+    for key in store.list(&prefix).await? {
+        if let Some((id, path)) = parse_resource_key(&key) {
+            if !filter.allows(path, &id) {
+                continue;
+            }
+            if let Entry::Vacant(entry) = keys.entry(id) {
+                entry.insert(path.to_string());
+                remote.push(id);
+            }
+        }
+    }
+
+    let (missing, options) = {
+        let index = index.read().map_err(|_| lock_error())?;
+        (missing_ids(&index, &remote), index.options().clone())
+    };
+    log::info!("{} resources are missing locally", missing.len());
+
+    let mut pulled = vec![];
+    for id in missing {
+        let path = &keys[&id];
+        let key = format!("{}{}/{}", prefix, id, path);
+        let destination = destination_path(&root, path, id)?;
+        let tmp = partial_path(&destination)?;
+        let downloaded = download(store, &key, &tmp, id, &options).await;
+        if !matches!(downloaded, Ok(true)) {
+            remove_partial(&tmp);
+        }
+        if !downloaded? {
+            continue;
+        }
+
+        let mut index = index.write().map_err(|_| lock_error())?;
+        if !place_file(&tmp, &destination)? {
+            log::warn!(
+                "{} appeared meanwhile, discarding pulled {}",
+                destination.display(),
+                id
+            );
+            remove_partial(&tmp);
+            continue;
+        }
+        index.index_new(&destination)?;
+        pulled.push(id);
+    }
+    Ok(pulled)
+}
+
+/// Downloads the resource into the temporary file, returning whether it
+/// has the expected ID
+async fn download<S: RemoteStore>(
+    store: &S,
+    key: &str,
+    tmp: &Path,
+    id: ResourceId,
+    options: &IndexOptions,
+) -> Result<bool> {
+    if !store.get(key, tmp).await? {
+        return Ok(false);
+    }
+    let size = fs::metadata(tmp)?.len();
+    if size != id.data_size {
+        log::warn!("Pulled {} has {} bytes, discarding", id, size);
+        return Ok(false);
+    }
+
+    let (options, tmp) = (options.clone(), tmp.to_path_buf());
+    let sampled = options.samples(size);
+    let actual =
+        tokio::task::spawn_blocking(move || options.content_id(&tmp, sampled))
+            .await
+            .map_err(std::io::Error::other)??;
+    if actual != id {
+        log::warn!("Pulled {} has id {}, discarding", id, actual);
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Hidden file next to the destination which objects are downloaded into,
+/// creating the folder of the destination
+fn partial_path(destination: &Path) -> Result<PathBuf> {
+    let parent = destination.parent().ok_or_else(|| {
+        ArklibError::Path(format!("{} has no parent", destination.display()))
+    })?;
+    fs::create_dir_all(parent)?;
+    let name: String = std::iter::repeat_with(fastrand::alphanumeric)
+        .take(10)
+        .collect();
+    Ok(parent.join(format!(".{}.part", name)))
+}
+
+fn remove_partial(tmp: &Path) {
+    if let Err(e) = fs::remove_file(tmp) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("Couldn't remove {}: {}", tmp.display(), e);
+        }
+    }
+}
+
+/// Writes the body of the response into the file chunk by chunk
+async fn write_body(
+    mut response: reqwest::Response,
+    path: &Path,
+) -> Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Body streaming the content of the file, together with its size
+async fn file_body(path: &Path) -> Result<(reqwest::Body, u64)> {
+    let file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    Ok((file.into(), size))
+}
+
+/// Local path of the user data file with the key, refusing keys outside
+/// of the user data folder, so that a store can't overwrite the index
+/// or anything else in `.ark`
+fn user_data_path(ark: &Path, key: &str) -> Result<PathBuf> {
+    let relative = key
+        .strip_prefix(USER_DATA_FOLDER)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .filter(|rest| !rest.split('/').any(|part| part == ".."));
+    match relative {
+        Some(relative) => join_relative(&ark.join(USER_DATA_FOLDER), relative),
+        None => Err(ArklibError::Path(format!("Illegal key: {}", key))),
+    }
+}
+
+/// Versions of atomic files are named `<name>_<device>.<version>`,
+/// anything else in the user data folder is a temporary file
+fn is_version_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(stem, version)| {
+            stem.contains('_') && version.parse::<usize>().is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Percent-encodes every segment of the key, keeping `/` separators
+fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(|segment| utf8_percent_encode(segment, UNRESERVED).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn parse_resource_key(key: &str) -> Option<(ResourceId, &str)> {
+    let rest = key
+        .strip_prefix(RESOURCES_FOLDER)?
+        .strip_prefix('/')?;
+    let (id, path) = rest.split_once('/')?;
+    Some((id.parse().ok()?, path))
+}
+
+fn index_root(index: &ResourceIndexLock) -> Result<PathBuf> {
+    let index = index.read().map_err(|_| lock_error())?;
+    Ok(index.root().to_path_buf())
+}
+
+fn lock_error() -> ArklibError {
+    ArklibError::Other(anyhow!("Could not lock the index"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::ResourceIndex;
+    use crate::resource::{Namespace, ResourceIdTrait};
+    use std::sync::{Arc, Mutex, RwLock};
+    use tempdir::TempDir;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        objects: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl RemoteStore for MemoryStore {
+        async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+            let objects = self.objects.lock().unwrap();
+            let mut keys: Vec<String> = objects
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect();
+            keys.sort();
+            Ok(keys)
+        }
+
+        async fn get(&self, key: &str, file: &Path) -> Result<bool> {
+            let data = self.objects.lock().unwrap().get(key).cloned();
+            match data {
+                Some(data) => fs::write(file, data)?,
+                None => return Ok(false),
+            }
+            Ok(true)
+        }
+
+        async fn put(&self, key: &str, file: &Path) -> Result<()> {
+            let data = fs::read(file)?;
+            self.objects
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), data);
+            Ok(())
+        }
+
+        async fn delete(&self, key: &str) -> Result<()> {
+            self.objects.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn user_data_versions_are_mirrored() {
+        let store = MemoryStore::default();
+        let first = TempDir::new("arklib_test").unwrap();
+        let second = TempDir::new("arklib_test").unwrap();
+
+        let tags = first.path().join(ARK_FOLDER).join("user/tags");
+        fs::create_dir_all(&tags).unwrap();
+        fs::write(tags.join("tags_first.1"), "a").unwrap();
+        fs::write(tags.join("tags_first.2"), "a,b").unwrap();
+        // temporary file of an unfinished write
+        fs::write(tags.join("Xk2jT9qLw0"), "a,b,c").unwrap();
+
+        assert_eq!(
+            push_user_data(&store, first.path())
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            push_user_data(&store, first.path())
+                .await
+                .unwrap(),
+            0
+        );
+
+        // the second device has written the same version concurrently
+        let tags = second.path().join(ARK_FOLDER).join("user/tags");
+        fs::create_dir_all(&tags).unwrap();
+        fs::write(tags.join("tags_second.2"), "a,c").unwrap();
+
+        assert_eq!(
+            pull_user_data(&store, second.path())
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            fs::read_to_string(tags.join("tags_first.2")).unwrap(),
+            "a,b"
+        );
+        assert_eq!(
+            push_user_data(&store, second.path())
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            pull_user_data(&store, first.path())
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    /// Store listing keys regardless of the prefix
+    struct ForgedStore(Vec<String>);
+
+    impl RemoteStore for ForgedStore {
+        async fn list(&self, _prefix: &str) -> Result<Vec<String>> {
+            Ok(self.0.clone())
+        }
+
+        async fn get(&self, _key: &str, file: &Path) -> Result<bool> {
+            fs::write(file, "forged")?;
+            Ok(true)
+        }
+
+        async fn put(&self, _key: &str, _file: &Path) -> Result<()> {
+            Ok(())
+        }
+
+        async fn delete(&self, _key: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_outside_of_user_data_are_rejected() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        for key in ["index_forged.1", "user/../index_forged.1", "user"] {
+            let store = ForgedStore(vec![key.to_string()]);
+            assert!(pull_user_data(&store, dir.path()).await.is_err());
+        }
+        assert!(!dir.path().join(ARK_FOLDER).exists());
+    }
+
+    #[tokio::test]
+    async fn only_missing_resources_are_pulled() {
+        let store = MemoryStore::default();
+        let first = TempDir::new("arklib_test").unwrap();
+        let second = TempDir::new("arklib_test").unwrap();
+
+        fs::write(first.path().join("a.txt"), "shared").unwrap();
+        fs::create_dir(first.path().join("sub")).unwrap();
+        fs::write(first.path().join("sub/b.txt"), "first only").unwrap();
+        fs::write(second.path().join("renamed.txt"), "shared").unwrap();
+
+        let first: ResourceIndexLock =
+            Arc::new(RwLock::new(ResourceIndex::build(first.path())));
+        let local: ResourceIndexLock =
+            Arc::new(RwLock::new(ResourceIndex::build(second.path())));
+
+        assert_eq!(
+            push_resources(&store, &first)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(push_resources(&store, &first)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let pulled = pull_resources(&store, &local).await.unwrap();
+        let expected = ResourceId::compute_bytes(b"first only").unwrap();
+        assert_eq!(pulled, vec![expected]);
+        assert_eq!(
+            fs::read_to_string(second.path().join("sub/b.txt")).unwrap(),
+            "first only"
+        );
+        assert_eq!(local.read().unwrap().count_resources(), 2);
+    }
+
+    #[tokio::test]
+    async fn namespaced_resources_are_verified() {
+        let store = MemoryStore::default();
+        let first = TempDir::new("arklib_test").unwrap();
+        let second = TempDir::new("arklib_test").unwrap();
+        fs::write(first.path().join("a.txt"), "namespaced").unwrap();
+
+        // both devices share the namespace of the root
+        let options = IndexOptions {
+            namespace: Some(Namespace::new(uuid::Uuid::new_v4())),
+            ..IndexOptions::default()
+        };
+        let first: ResourceIndexLock = Arc::new(RwLock::new(
+            ResourceIndex::build_with_options(first.path(), options.clone()),
+        ));
+        let local: ResourceIndexLock = Arc::new(RwLock::new(
+            ResourceIndex::build_with_options(second.path(), options),
+        ));
+
+        let pushed = push_resources(&store, &first).await.unwrap();
+        assert_eq!(pushed.len(), 1);
+        assert_eq!(pull_resources(&store, &local).await.unwrap(), pushed);
+        assert!(second.path().join("a.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn sampled_resources_are_verified() {
+        let store = MemoryStore::default();
+        let first = TempDir::new("arklib_test").unwrap();
+        let second = TempDir::new("arklib_test").unwrap();
+        fs::write(first.path().join("a.bin"), vec![7; 64 * 1024]).unwrap();
+
+        let options = IndexOptions {
+            sampled_hashing_threshold: Some(1024),
+            ..IndexOptions::default()
+        };
+        let first: ResourceIndexLock = Arc::new(RwLock::new(
+            ResourceIndex::build_with_options(first.path(), options.clone()),
+        ));
+        let local: ResourceIndexLock = Arc::new(RwLock::new(
+            ResourceIndex::build_with_options(second.path(), options),
+        ));
+
+        let pushed = push_resources(&store, &first).await.unwrap();
+        assert_eq!(pushed.len(), 1);
+        assert_eq!(pull_resources(&store, &local).await.unwrap(), pushed);
+        assert_eq!(
+            fs::read(second.path().join("a.bin")).unwrap(),
+            vec![7; 64 * 1024]
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! WebDAV servers such as Nextcloud
+//!
+//! Folders of keys are mapped to collections, which are created
+//! on demand when an object is put into a missing collection.
+use std::path::Path;
+
+use percent_encoding::percent_decode_str;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use url::Url;
+
+use super::{encode_key, file_body, write_body, RemoteStore};
+use crate::{ArklibError, Result};
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/></d:prop></d:propfind>"#;
+
+pub struct WebDavStore {
+    client: Client,
+    /// Collection which all keys are relative to
+    base: Url,
+    credentials: Option<(String, String)>,
+}
+
+impl WebDavStore {
+    pub fn new(
+        mut base: Url,
+        credentials: Option<(String, String)>,
+    ) -> Result<Self> {
+        if base.cannot_be_a_base() {
+            return Err(ArklibError::Path(format!(
+                "{} can't be used as a base",
+                base
+            )));
+        }
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Ok(Self {
+            client: Client::new(),
+            base,
+            credentials,
+        })
+    }
+
+    fn request(&self, method: &[u8], key: &str) -> Result<RequestBuilder> {
+        let method = Method::from_bytes(method)
+            .map_err(|e| ArklibError::Other(e.into()))?;
+        let url = self.base.join(&encode_key(key))?;
+        let request = self.client.request(method, url);
+        Ok(match &self.credentials {
+            Some((user, password)) => request.basic_auth(user, Some(password)),
+            None => request,
+        })
+    }
+
+    /// Lists a single collection, returning keys of its members
+    /// together with whether they are collections themselves
+    async fn list_collection(
+        &self,
+        collection: &str,
+    ) -> Result<Vec<(String, bool)>> {
+        let response = self
+            .request(b"PROPFIND", collection)?
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        let response = response.error_for_status()?;
+
+        let mut members = vec![];
+        for (href, is_collection) in
+            parse_multistatus(&response.bytes().await?)?
+        {
+            let key = match self.key_of(&href) {
+                Some(key) => key,
+                None => continue,
+            };
+            // the collection itself is listed as well
+            if key.trim_end_matches('/') != collection.trim_end_matches('/') {
+                members.push((key, is_collection));
+            }
+        }
+        Ok(members)
+    }
+
+    /// Converts a `href` of a response back into a key
+    fn key_of(&self, href: &str) -> Option<String> {
+        let url = self.base.join(href).ok()?;
+        let relative = url.path().strip_prefix(self.base.path())?;
+        let key = percent_decode_str(relative)
+            .decode_utf8()
+            .ok()?
+            .to_string();
+        Some(key)
+    }
+
+    /// Creates all collections which the key is nested into
+    async fn create_collections(&self, key: &str) -> Result<()> {
+        let parts: Vec<&str> = key.split('/').collect();
+        for depth in 1..parts.len() {
+            let collection = format!("{}/", parts[..depth].join("/"));
+            let response = self
+                .request(b"MKCOL", &collection)?
+                .send()
+                .await?;
+            // 405 means that the collection exists already
+            if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+                response.error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RemoteStore for WebDavStore {
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let root = match prefix.rfind('/') {
+            Some(position) => &prefix[..=position],
+            None => "",
+        };
+        let mut keys = vec![];
+        let mut pending = vec![root.to_string()];
+        while let Some(collection) = pending.pop() {
+            for (key, is_collection) in
+                self.list_collection(&collection).await?
+            {
+                if is_collection {
+                    pending.push(key);
+                } else if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn get(&self, key: &str, file: &Path) -> Result<bool> {
+        let response = self.request(b"GET", key)?.send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        write_body(response.error_for_status()?, file).await?;
+        Ok(true)
+    }
+
+    async fn put(&self, key: &str, file: &Path) -> Result<()> {
+        let (body, size) = file_body(file).await?;
+        let response = self
+            .request(b"PUT", key)?
+            .header(CONTENT_LENGTH, size)
+            .body(body)
+            .send()
+            .await?;
+        // 409 means that the parent collection is missing
+        if response.status() != StatusCode::CONFLICT {
+            response.error_for_status()?;
+            return Ok(());
+        }
+        self.create_collections(key).await?;
+        let (body, size) = file_body(file).await?;
+        self.request(b"PUT", key)?
+            .header(CONTENT_LENGTH, size)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let response = self.request(b"DELETE", key)?.send().await?;
+        if response.status() != StatusCode::NOT_FOUND {
+            response.error_for_status()?;
+        }
+        Ok(())
+    }
+}
+
+/// Extracts `href` of every response in a `multistatus` document
+/// together with whether it is a collection
+fn parse_multistatus(xml: &[u8]) -> Result<Vec<(String, bool)>> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = vec![];
+    let mut in_href = false;
+    let mut href = String::new();
+    let mut is_collection = false;
+    let mut responses = vec![];
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"response" => {
+                    href.clear();
+                    is_collection = false;
+                }
+                b"href" => in_href = true,
+                b"collection" => is_collection = true,
+                _ => {}
+            },
+            Event::Empty(e) if e.local_name().as_ref() == b"collection" => {
+                is_collection = true
+            }
+            Event::Text(text) if in_href => {
+                href.push_str(text.unescape()?.trim())
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"href" => in_href = false,
+                b"response" => responses.push((href.clone(), is_collection)),
+                _ => {}
+            },
+            Event::Eof => return Ok(responses),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_propfind_response() {
+        let xml = br#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>/dav/ark/user/</d:href>
+    <d:propstat><d:prop>
+      <d:resourcetype><d:collection/></d:resourcetype>
+    </d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/ark/user/tags/</d:href>
+    <d:propstat><d:prop>
+      <d:resourcetype><d:collection/></d:resourcetype>
+    </d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/ark/user/my%20scores_a.1</d:href>
+    <d:propstat><d:prop><d:resourcetype/></d:prop></d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let responses = parse_multistatus(xml).unwrap();
+        assert_eq!(
+            responses,
+            vec![
+                ("/dav/ark/user/".to_string(), true),
+                ("/dav/ark/user/tags/".to_string(), true),
+                ("/dav/ark/user/my%20scores_a.1".to_string(), false),
+            ]
+        );
+
+        let store = WebDavStore::new(
+            Url::parse("https://example.com/dav/ark").unwrap(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            store.key_of(&responses[2].0).as_deref(),
+            Some("user/my scores_a.1")
+        );
+        assert_eq!(store.key_of("/elsewhere/file"), None);
+    }
+}
@@ -0,0 +1,186 @@
+//! Selection of the algorithm of IDs exchanged with other tools
+//!
+//! The index identifies resources by CRC32 IDs, while other algorithms
+//! serve interoperability: SHA-256 IDs match checksums computed by
+//! external tools and XXH3 IDs are cheap enough for deduplication of
+//! large collections. The algorithm of a root is kept in the `ids`
+//! section of `.ark/config`, and ARK manifests of the root list IDs
+//! of this algorithm, see [`crate::index::ManifestFormat`].
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ResourceId, ResourceIdBlake3, ResourceIdSha256, ResourceIdTrait,
+    ResourceIdXxh3,
+};
+use crate::index::ResourceIndex;
+use crate::storage::config::{load_section, store_section};
+use crate::{ArklibError, Result};
+
+const CONFIG_SECTION: &str = "ids";
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum IdAlgorithm {
+    /// [`ResourceId`], used by the index
+    #[default]
+    Crc32,
+    /// [`ResourceIdBlake3`]
+    Blake3,
+    /// [`ResourceIdXxh3`]
+    Xxh3,
+    /// [`ResourceIdSha256`]
+    Sha256,
+}
+
+impl IdAlgorithm {
+    pub const ALL: [IdAlgorithm; 4] = [
+        IdAlgorithm::Crc32,
+        IdAlgorithm::Blake3,
+        IdAlgorithm::Xxh3,
+        IdAlgorithm::Sha256,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IdAlgorithm::Crc32 => "crc32",
+            IdAlgorithm::Blake3 => "blake3",
+            IdAlgorithm::Xxh3 => "xxh3",
+            IdAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// Computes the ID of the file, in the form produced by `Display`
+    pub fn compute<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let size = path.as_ref().metadata()?.len();
+        let path = path.as_ref();
+        Ok(match self {
+            IdAlgorithm::Crc32 => ResourceId::compute(size, path)?.to_string(),
+            IdAlgorithm::Blake3 => {
+                ResourceIdBlake3::compute(size, path)?.to_string()
+            }
+            IdAlgorithm::Xxh3 => {
+                ResourceIdXxh3::compute(size, path)?.to_string()
+            }
+            IdAlgorithm::Sha256 => {
+                ResourceIdSha256::compute(size, path)?.to_string()
+            }
+        })
+    }
+
+    /// Computes the ID of the bytes, in the form produced by `Display`
+    pub fn compute_bytes(&self, bytes: &[u8]) -> Result<String> {
+        Ok(match self {
+            IdAlgorithm::Crc32 => ResourceId::compute_bytes(bytes)?.to_string(),
+            IdAlgorithm::Blake3 => {
+                ResourceIdBlake3::compute_bytes(bytes)?.to_string()
+            }
+            IdAlgorithm::Xxh3 => {
+                ResourceIdXxh3::compute_bytes(bytes)?.to_string()
+            }
+            IdAlgorithm::Sha256 => {
+                ResourceIdSha256::compute_bytes(bytes)?.to_string()
+            }
+        })
+    }
+}
+
+impl Display for IdAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for IdAlgorithm {
+    type Err = ArklibError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        IdAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name() == s)
+            .ok_or(ArklibError::Parse)
+    }
+}
+
+/// Loads the algorithm of IDs of the root, CRC32 unless configured
+pub fn load_id_algorithm<P: AsRef<Path>>(root: P) -> Result<IdAlgorithm> {
+    Ok(load_section(root, CONFIG_SECTION)?.unwrap_or_default())
+}
+
+pub fn set_id_algorithm<P: AsRef<Path>>(
+    root: P,
+    algorithm: IdAlgorithm,
+) -> Result<()> {
+    store_section(root, CONFIG_SECTION, &algorithm)
+}
+
+/// Computes IDs of another algorithm for resources of the index, e.g. to
+/// export data keyed by IDs, hashing their content completely
+///
+/// Resources modified since they were indexed are skipped.
+pub fn convert_ids<Id>(
+    index: &ResourceIndex,
+) -> Result<BTreeMap<ResourceId, Id>>
+where
+    Id: for<'de> ResourceIdTrait<'de>,
+{
+    let mut ids = BTreeMap::new();
+    for id in index.ids() {
+        let Some(path) = index.get_path(id) else {
+            continue;
+        };
+        match Id::compute(id.data_size, path) {
+            Ok(converted) => {
+                ids.insert(*id, converted);
+            }
+            Err(ArklibError::SizeMismatch { .. }) => {
+                log::warn!("{} was modified since indexing", path.display());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn ids_are_converted() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "abc").unwrap();
+        let index = ResourceIndex::build(&root);
+        let id = index.get_entry(root.join("a.txt")).unwrap().id;
+
+        let converted = convert_ids::<ResourceIdSha256>(&index).unwrap();
+        assert_eq!(
+            converted[&id],
+            ResourceIdSha256::compute_bytes(b"abc").unwrap()
+        );
+        for algorithm in IdAlgorithm::ALL {
+            assert_eq!(
+                algorithm.compute(root.join("a.txt")).unwrap(),
+                algorithm.compute_bytes(b"abc").unwrap()
+            );
+        }
+
+        for algorithm in IdAlgorithm::ALL {
+            let name = algorithm.to_string();
+            assert_eq!(name.parse::<IdAlgorithm>().unwrap(), algorithm);
+        }
+        assert_eq!(load_id_algorithm(&root).unwrap(), IdAlgorithm::Crc32);
+        set_id_algorithm(&root, IdAlgorithm::Xxh3).unwrap();
+        assert_eq!(load_id_algorithm(&root).unwrap(), IdAlgorithm::Xxh3);
+    }
+}
@@ -0,0 +1,225 @@
+use ::blake3::Hasher;
+use log;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::Read;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::resource::{parse_decimal, read_samples, split_id, ResourceIdTrait};
+use crate::{ArklibError, Result};
+
+const KILOBYTE: u64 = 1024;
+const MEGABYTE: u64 = 1024 * KILOBYTE;
+const BUFFER_CAPACITY: usize = 512 * KILOBYTE as usize;
+
+/// Represents a resource identifier using the Blake3 algorithm.
+///
+/// Uses `blake3` crate to compute the hash value. Unlike CRC32, Blake3
+/// is a cryptographic hash, so collisions are practically impossible.
+#[derive(
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Hash,
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+)]
+pub struct ResourceIdBlake3 {
+    pub data_size: u64,
+    pub hash: [u8; 32],
+}
+
+impl Display for ResourceIdBlake3 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let hash = ::blake3::Hash::from_bytes(self.hash);
+        write!(f, "{}-{}", self.data_size, hash.to_hex())
+    }
+}
+
+impl FromStr for ResourceIdBlake3 {
+    type Err = ArklibError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (l, r) = split_id(s)?;
+        let data_size: u64 = parse_decimal(l, s)?;
+        // uppercase digits would make the same ID
+        // representable by different strings
+        if r.bytes().any(|b| b.is_ascii_uppercase()) {
+            log::warn!("Malformed ID {:?}: the hash is not lowercase", s);
+            return Err(ArklibError::Parse);
+        }
+        let hash = ::blake3::Hash::from_hex(r).map_err(|e| {
+            log::warn!("Malformed ID {:?}: {}", s, e);
+            ArklibError::Parse
+        })?;
+
+        Ok(ResourceIdBlake3 {
+            data_size,
+            hash: *hash.as_bytes(),
+        })
+    }
+}
+
+impl ResourceIdTrait<'_> for ResourceIdBlake3 {
+    type HashType = [u8; 32];
+
+    fn compute<P: AsRef<Path>>(data_size: u64, file_path: P) -> Result<Self> {
+        log::trace!(
+            "[compute] file {} with size {} mb",
+            file_path.as_ref().display(),
+            data_size / MEGABYTE
+        );
+
+        let source = fs::OpenOptions::new()
+            .read(true)
+            .open(file_path.as_ref())?;
+
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, source);
+        ResourceIdBlake3::compute_reader(data_size, &mut reader)
+    }
+
+    /// Memory-maps the file and hashes it using all available cores
+    fn compute_parallel<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self> {
+        log::trace!(
+            "[compute] file {} with size {} mb in parallel",
+            file_path.as_ref().display(),
+            data_size / MEGABYTE
+        );
+
+        let actual = fs::metadata(file_path.as_ref())?.len();
+        if actual != data_size {
+            return Err(ArklibError::SizeMismatch {
+                expected: data_size,
+                actual,
+            });
+        }
+
+        let mut hasher = Hasher::new();
+        hasher.update_mmap_rayon(file_path)?;
+        Ok(ResourceIdBlake3 {
+            data_size,
+            hash: *hasher.finalize().as_bytes(),
+        })
+    }
+
+    fn compute_sampled<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self> {
+        let samples = read_samples(data_size, file_path)?;
+        Ok(ResourceIdBlake3 {
+            data_size,
+            hash: *::blake3::hash(&samples).as_bytes(),
+        })
+    }
+
+    fn compute_bytes(bytes: &[u8]) -> Result<Self> {
+        let hash = ::blake3::hash(bytes);
+        Ok(ResourceIdBlake3 {
+            data_size: bytes.len() as u64,
+            hash: *hash.as_bytes(),
+        })
+    }
+
+    fn compute_reader_with_progress<R: Read, F: FnMut(u64) -> bool>(
+        data_size: u64,
+        reader: &mut BufReader<R>,
+        mut progress: F,
+    ) -> Result<Self> {
+        let mut hasher = Hasher::new();
+        let mut bytes_read: u64 = 0;
+        loop {
+            let bytes_read_iteration: usize = reader.fill_buf()?.len();
+            if bytes_read_iteration == 0 {
+                break;
+            }
+            hasher.update(reader.buffer());
+            reader.consume(bytes_read_iteration);
+            bytes_read += bytes_read_iteration as u64;
+            if !progress(bytes_read) {
+                log::debug!("[compute] cancelled after {} bytes", bytes_read);
+                return Err(ArklibError::Cancelled);
+            }
+        }
+
+        log::trace!("[compute] {} bytes has been read", bytes_read);
+        if bytes_read != data_size {
+            return Err(ArklibError::SizeMismatch {
+                expected: data_size,
+                actual: bytes_read,
+            });
+        }
+
+        Ok(ResourceIdBlake3 {
+            data_size,
+            hash: *hasher.finalize().as_bytes(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_id_test() {
+        let file_path = Path::new("./tests/lena.jpg");
+        let data_size = fs::metadata(file_path).unwrap().len();
+
+        let id1 = ResourceIdBlake3::compute(data_size, file_path).unwrap();
+        assert_eq!(id1.data_size, 128760);
+
+        let raw_bytes = fs::read(file_path).unwrap();
+        let id2 = ResourceIdBlake3::compute_bytes(&raw_bytes).unwrap();
+        assert_eq!(id1, id2);
+
+        let id3 =
+            ResourceIdBlake3::compute_parallel(data_size, file_path).unwrap();
+        assert_eq!(id1, id3);
+
+        let parsed: ResourceIdBlake3 = id1.to_string().parse().unwrap();
+        assert_eq!(parsed, id1);
+    }
+
+    #[test]
+    fn malformed_ids_are_rejected() {
+        let id = ResourceIdBlake3::compute_bytes(b"content").unwrap();
+        let hex = ::blake3::hash(b"content").to_hex().to_string();
+        for malformed in [
+            hex.clone(),
+            format!("7-{}", &hex[1..]),
+            format!("7-{}0", hex),
+            format!("7-{}", hex.to_uppercase()),
+            format!("+7-{}", hex),
+            format!("07-{}", hex),
+        ] {
+            assert!(malformed.parse::<ResourceIdBlake3>().is_err());
+        }
+        assert_eq!(
+            format!("7-{}", hex)
+                .parse::<ResourceIdBlake3>()
+                .unwrap(),
+            id
+        );
+    }
+
+    #[test]
+    fn parallel_size_mismatch_is_an_error() {
+        let file_path = Path::new("./tests/lena.jpg");
+        let result = ResourceIdBlake3::compute_parallel(1, file_path);
+        assert!(matches!(
+            result,
+            Err(ArklibError::SizeMismatch { expected: 1, .. })
+        ));
+    }
+}
@@ -4,17 +4,28 @@ use log;
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display, Formatter};
 use std::fs;
-use std::io::Read;
 use std::io::{BufRead, BufReader};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 use std::str::FromStr;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
-use crate::resource::ResourceIdTrait;
+use crate::resource::{parse_decimal, read_samples, split_id, ResourceIdTrait};
 use crate::{ArklibError, Result};
 
 const KILOBYTE: u64 = 1024;
 const MEGABYTE: u64 = 1024 * KILOBYTE;
 const BUFFER_CAPACITY: usize = 512 * KILOBYTE as usize;
+/// Files are split into parts of at least this size,
+/// which are hashed by separate threads
+const PARALLEL_PART_SIZE: u64 = 8 * MEGABYTE;
+
+/// Number of IDs computed by [`ResourceId::compute_parallel`],
+/// for tests checking that the index hashes in parallel
+#[cfg(test)]
+pub(crate) static PARALLEL_COMPUTATIONS: AtomicUsize = AtomicUsize::new(0);
 
 /// Represents a resource identifier using the CRC32 algorithm.
 ///
@@ -46,14 +57,45 @@ impl FromStr for ResourceId {
     type Err = ArklibError;
 
     fn from_str(s: &str) -> Result<Self> {
-        let (l, r) = s.split_once('-').ok_or(ArklibError::Parse)?;
-        let data_size: u64 = l.parse().map_err(|_| ArklibError::Parse)?;
-        let hash: u32 = r.parse().map_err(|_| ArklibError::Parse)?;
+        let (l, r) = split_id(s)?;
+        let data_size: u64 = parse_decimal(l, s)?;
+        let hash: u32 = parse_decimal(r, s)?;
 
         Ok(ResourceId { data_size, hash })
     }
 }
 
+impl ResourceId {
+    /// Size of the binary representation, see [`ResourceId::to_bytes`]
+    pub const BYTES: usize = 12;
+
+    /// Encodes both halves of the ID as little-endian bytes,
+    /// for passing IDs through bindings without truncation
+    pub fn to_bytes(&self) -> [u8; Self::BYTES] {
+        let mut bytes = [0; Self::BYTES];
+        bytes[..8].copy_from_slice(&self.data_size.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.hash.to_le_bytes());
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for ResourceId {
+    type Error = ArklibError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; Self::BYTES] =
+            bytes.try_into().map_err(|_| ArklibError::Parse)?;
+        let mut data_size = [0; 8];
+        let mut hash = [0; 4];
+        data_size.copy_from_slice(&bytes[..8]);
+        hash.copy_from_slice(&bytes[8..]);
+        Ok(ResourceId {
+            data_size: u64::from_le_bytes(data_size),
+            hash: u32::from_le_bytes(hash),
+        })
+    }
+}
+
 impl ResourceIdTrait<'_> for ResourceId {
     type HashType = u32;
 
@@ -72,6 +114,40 @@ impl ResourceIdTrait<'_> for ResourceId {
         ResourceId::compute_reader(data_size, &mut reader)
     }
 
+    /// Hashes parts of the file by separate threads and combines
+    /// their checksums
+    fn compute_parallel<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self> {
+        log::trace!(
+            "[compute] file {} with size {} mb in parallel",
+            file_path.as_ref().display(),
+            data_size / MEGABYTE
+        );
+        #[cfg(test)]
+        PARALLEL_COMPUTATIONS.fetch_add(1, Ordering::Relaxed);
+
+        let threads = thread::available_parallelism()
+            .map(|threads| threads.get() as u64)
+            .unwrap_or(1);
+        let parts = threads.min(data_size / PARALLEL_PART_SIZE).max(1);
+        compute_in_parts(data_size, file_path.as_ref(), parts)
+    }
+
+    fn compute_sampled<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self> {
+        let samples = read_samples(data_size, file_path)?;
+        let mut hasher = Hasher::new();
+        hasher.update(&samples);
+        Ok(ResourceId {
+            data_size,
+            hash: hasher.finalize(),
+        })
+    }
+
     fn compute_bytes(bytes: &[u8]) -> Result<Self> {
         let data_size = bytes.len().try_into().map_err(|_| {
             ArklibError::Other(anyhow!("Can't convert usize to u64"))
@@ -80,19 +156,18 @@ impl ResourceIdTrait<'_> for ResourceId {
         ResourceId::compute_reader(data_size, &mut reader)
     }
 
-    fn compute_reader<R: Read>(
+    fn compute_reader_with_progress<R: Read, F: FnMut(u64) -> bool>(
         data_size: u64,
         reader: &mut BufReader<R>,
+        mut progress: F,
     ) -> Result<Self> {
-        assert!(reader.buffer().is_empty());
-
         log::trace!(
             "Calculating hash of raw bytes (given size is {} megabytes)",
             data_size / MEGABYTE
         );
 
         let mut hasher = Hasher::new();
-        let mut bytes_read: u32 = 0;
+        let mut bytes_read: u64 = 0;
         loop {
             let bytes_read_iteration: usize = reader.fill_buf()?.len();
             if bytes_read_iteration == 0 {
@@ -100,21 +175,103 @@ impl ResourceIdTrait<'_> for ResourceId {
             }
             hasher.update(reader.buffer());
             reader.consume(bytes_read_iteration);
-            bytes_read +=
-                u32::try_from(bytes_read_iteration).map_err(|_| {
-                    ArklibError::Other(anyhow!("Can't convert usize to u32"))
-                })?;
+            bytes_read += bytes_read_iteration as u64;
+            if !progress(bytes_read) {
+                log::debug!("[compute] cancelled after {} bytes", bytes_read);
+                return Err(ArklibError::Cancelled);
+            }
         }
 
         let hash: u32 = hasher.finalize();
         log::trace!("[compute] {} bytes has been read", bytes_read);
         log::trace!("[compute] checksum: {:#02x}", hash);
-        assert_eq!(std::convert::Into::<u64>::into(bytes_read), data_size);
+        if bytes_read != data_size {
+            return Err(ArklibError::SizeMismatch {
+                expected: data_size,
+                actual: bytes_read,
+            });
+        }
 
         Ok(ResourceId { data_size, hash })
     }
 }
 
+fn compute_in_parts(
+    data_size: u64,
+    file_path: &Path,
+    parts: u64,
+) -> Result<ResourceId> {
+    let actual = fs::metadata(file_path)?.len();
+    if actual != data_size {
+        return Err(ArklibError::SizeMismatch {
+            expected: data_size,
+            actual,
+        });
+    }
+
+    let part_size = data_size.div_ceil(parts).max(1);
+    let hashers = thread::scope(|scope| {
+        let handles: Vec<_> = (0..parts)
+            .map(|part| part * part_size)
+            .take_while(|start| *start < data_size)
+            .map(|start| {
+                let length = part_size.min(data_size - start);
+                scope.spawn(move || hash_range(file_path, start, length))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    let mut hasher = Hasher::new();
+    let mut bytes_read = 0;
+    for (part, read) in &hashers {
+        hasher.combine(part);
+        bytes_read += read;
+    }
+    if bytes_read != data_size {
+        return Err(ArklibError::SizeMismatch {
+            expected: data_size,
+            actual: bytes_read,
+        });
+    }
+    Ok(ResourceId {
+        data_size,
+        hash: hasher.finalize(),
+    })
+}
+
+/// Hashes `length` bytes of the file from `start`,
+/// returning the hasher and the amount of bytes actually read
+fn hash_range(
+    file_path: &Path,
+    start: u64,
+    length: u64,
+) -> Result<(Hasher, u64)> {
+    let mut file = fs::File::open(file_path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader =
+        BufReader::with_capacity(BUFFER_CAPACITY, file.take(length));
+    let mut hasher = Hasher::new();
+    let mut bytes_read = 0;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok((hasher, bytes_read));
+        }
+        hasher.update(buffer);
+        let length = buffer.len();
+        reader.consume(length);
+        bytes_read += length as u64;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::initialize;
@@ -145,6 +302,127 @@ mod tests {
         assert_eq!(id2.data_size, 128760);
     }
 
+    #[test]
+    fn parts_are_combined_into_the_same_id() {
+        let file_path = Path::new("./tests/lena.jpg");
+        let data_size = fs::metadata(file_path).unwrap().len();
+        let expected = ResourceId::compute(data_size, file_path).unwrap();
+        for parts in [1, 2, 7, 64] {
+            let id = compute_in_parts(data_size, file_path, parts).unwrap();
+            assert_eq!(id, expected);
+        }
+        assert_eq!(
+            ResourceId::compute_parallel(data_size, file_path).unwrap(),
+            expected
+        );
+        assert!(matches!(
+            compute_in_parts(data_size + 1, file_path, 4),
+            Err(ArklibError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let id = ResourceId {
+            data_size: 128760,
+            hash: 0x342a3d4a,
+        };
+        let bytes = id.to_bytes();
+        assert_eq!(ResourceId::try_from(&bytes[..]).unwrap(), id);
+        assert!(ResourceId::try_from(&bytes[..8]).is_err());
+        assert!("128760".parse::<ResourceId>().is_err());
+        assert!("128760-342a3d4a".parse::<ResourceId>().is_err());
+    }
+
+    #[test]
+    fn size_mismatch_is_an_error() {
+        let bytes = b"some content";
+        let mut reader = BufReader::new(&bytes[..]);
+        match ResourceId::compute_reader(100, &mut reader) {
+            Err(ArklibError::SizeMismatch { expected, actual }) => {
+                assert_eq!(expected, 100);
+                assert_eq!(actual, bytes.len() as u64);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn progress_is_reported_and_cancellable() {
+        let bytes = vec![7u8; 3 * BUFFER_CAPACITY];
+        let size = bytes.len() as u64;
+
+        let mut reported = vec![];
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, &bytes[..]);
+        let id = ResourceId::compute_reader_with_progress(
+            size,
+            &mut reader,
+            |read| {
+                reported.push(read);
+                true
+            },
+        )
+        .unwrap();
+        assert_eq!(id, ResourceId::compute_bytes(&bytes).unwrap());
+        assert_eq!(reported.len(), 3);
+        assert_eq!(reported.last(), Some(&size));
+
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, &bytes[..]);
+        let result = ResourceId::compute_reader_with_progress(
+            size,
+            &mut reader,
+            |read| read < size / 2,
+        );
+        assert!(matches!(result, Err(ArklibError::Cancelled)));
+    }
+
+    #[test]
+    fn malformed_ids_are_rejected() {
+        let id: ResourceId = "1024-3817498742".parse().unwrap();
+        assert_eq!(id.to_string(), "1024-3817498742");
+        assert_eq!("0-0".parse::<ResourceId>().unwrap().data_size, 0);
+
+        for malformed in [
+            "",
+            "-",
+            "1024",
+            "1024-",
+            "-3817498742",
+            "+1024-3817498742",
+            "1024-+3817498742",
+            "01024-3817498742",
+            "1024-3817498742-1",
+            "1024-4294967296",
+            "1024 -3817498742",
+            "1024-38174987\u{663}2",
+            "18446744073709551616-1",
+        ] {
+            assert!(
+                matches!(
+                    malformed.parse::<ResourceId>(),
+                    Err(ArklibError::Parse)
+                ),
+                "{:?} must be rejected",
+                malformed
+            );
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn parsing_never_panics(s in ".*") {
+            if let Ok(id) = s.parse::<ResourceId>() {
+                proptest::prop_assert_eq!(id.to_string(), s);
+            }
+        }
+
+        #[test]
+        fn display_roundtrip(data_size: u64, hash: u32) {
+            let id = ResourceId { data_size, hash };
+            proptest::prop_assert_eq!(id.to_string().parse::<ResourceId>().unwrap(), id);
+        }
+    }
+
     #[test]
     fn resource_id_order() {
         let id1 = ResourceId {
@@ -0,0 +1,443 @@
+//! Merkle tree variant of the Blake3 ID
+//!
+//! The content is split into chunks of [`MERKLE_CHUNK_SIZE`] bytes, which
+//! are hashed separately, and the ID is the root of the binary tree built
+//! over hashes of the chunks. Hashes of the chunks are kept in the `merkle`
+//! field of the metadata of the resource, so that a receiver of a large
+//! file can verify every chunk as it arrives, resume an interrupted
+//! transfer after the last verified chunk and find out which chunks of
+//! a damaged copy need to be transferred again.
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+use ::blake3::Hasher;
+use serde::{Deserialize, Serialize};
+
+use super::{read_samples, ResourceId, ResourceIdBlake3, ResourceIdTrait};
+use crate::storage::metadata;
+use crate::{ArklibError, Result};
+
+/// Size of chunks hashed separately
+pub const MERKLE_CHUNK_SIZE: u64 = 1024 * 1024;
+/// Field of the metadata holding hashes of the chunks
+pub const MERKLE_METADATA_FIELD: &str = "merkle";
+
+const BUFFER_CAPACITY: usize = 512 * 1024;
+// distinct prefixes prevent passing off inner nodes as chunks
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// Represents a resource identifier by the root of the Merkle tree
+/// of Blake3 hashes of chunks of the resource
+///
+/// The ID doesn't match [`ResourceIdBlake3`] of the same content.
+#[derive(
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Hash,
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+)]
+pub struct ResourceIdMerkle {
+    pub data_size: u64,
+    pub hash: [u8; 32],
+}
+
+impl Display for ResourceIdMerkle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let id = ResourceIdBlake3 {
+            data_size: self.data_size,
+            hash: self.hash,
+        };
+        write!(f, "{}", id)
+    }
+}
+
+impl FromStr for ResourceIdMerkle {
+    type Err = ArklibError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let id: ResourceIdBlake3 = s.parse()?;
+        Ok(ResourceIdMerkle {
+            data_size: id.data_size,
+            hash: id.hash,
+        })
+    }
+}
+
+impl ResourceIdTrait<'_> for ResourceIdMerkle {
+    type HashType = [u8; 32];
+
+    fn compute<P: AsRef<Path>>(data_size: u64, file_path: P) -> Result<Self> {
+        let file = File::open(file_path)?;
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, file);
+        ResourceIdMerkle::compute_reader(data_size, &mut reader)
+    }
+
+    fn compute_sampled<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self> {
+        let samples = read_samples(data_size, file_path)?;
+        Ok(ResourceIdMerkle {
+            data_size,
+            hash: MerkleTree::compute_bytes(&samples).root(),
+        })
+    }
+
+    fn compute_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(MerkleTree::compute_bytes(bytes).id())
+    }
+
+    fn compute_reader_with_progress<R: Read, F: FnMut(u64) -> bool>(
+        data_size: u64,
+        reader: &mut BufReader<R>,
+        progress: F,
+    ) -> Result<Self> {
+        let tree = MerkleTree::compute_reader(
+            data_size,
+            MERKLE_CHUNK_SIZE,
+            reader,
+            progress,
+        )?;
+        Ok(tree.id())
+    }
+}
+
+/// Hashes of chunks of a resource
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleTree {
+    pub data_size: u64,
+    pub chunk_size: u64,
+    #[serde(with = "hex_hashes")]
+    chunks: Vec<[u8; 32]>,
+}
+
+impl MerkleTree {
+    /// Hashes the file in chunks of [`MERKLE_CHUNK_SIZE`] bytes
+    pub fn compute<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        let data_size = file.metadata()?.len();
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, file);
+        MerkleTree::compute_reader(
+            data_size,
+            MERKLE_CHUNK_SIZE,
+            &mut reader,
+            |_| true,
+        )
+    }
+
+    pub fn compute_bytes(bytes: &[u8]) -> Self {
+        MerkleTree {
+            data_size: bytes.len() as u64,
+            chunk_size: MERKLE_CHUNK_SIZE,
+            chunks: bytes
+                .chunks(MERKLE_CHUNK_SIZE as usize)
+                .map(hash_chunk)
+                .collect(),
+        }
+    }
+
+    /// Hashes the data in chunks of `chunk_size` bytes, see
+    /// [`ResourceIdTrait::compute_reader_with_progress`]
+    pub fn compute_reader<R: Read, F: FnMut(u64) -> bool>(
+        data_size: u64,
+        chunk_size: u64,
+        reader: &mut BufReader<R>,
+        mut progress: F,
+    ) -> Result<Self> {
+        if chunk_size == 0 {
+            return Err(ArklibError::Other(anyhow::anyhow!(
+                "Chunks must not be empty"
+            )));
+        }
+        let mut chunks = vec![];
+        let mut hasher = leaf_hasher();
+        let mut filled: u64 = 0;
+        let mut bytes_read: u64 = 0;
+        loop {
+            let buffer = reader.fill_buf()?;
+            let bytes_read_iteration = buffer.len();
+            if bytes_read_iteration == 0 {
+                break;
+            }
+            let mut rest = buffer;
+            while !rest.is_empty() {
+                let taken =
+                    (chunk_size - filled).min(rest.len() as u64) as usize;
+                hasher.update(&rest[..taken]);
+                filled += taken as u64;
+                rest = &rest[taken..];
+                if filled == chunk_size {
+                    chunks.push(*hasher.finalize().as_bytes());
+                    hasher = leaf_hasher();
+                    filled = 0;
+                }
+            }
+            reader.consume(bytes_read_iteration);
+            bytes_read += bytes_read_iteration as u64;
+            if !progress(bytes_read) {
+                log::debug!("[compute] cancelled after {} bytes", bytes_read);
+                return Err(ArklibError::Cancelled);
+            }
+        }
+        if filled > 0 {
+            chunks.push(*hasher.finalize().as_bytes());
+        }
+
+        if bytes_read != data_size {
+            return Err(ArklibError::SizeMismatch {
+                expected: data_size,
+                actual: bytes_read,
+            });
+        }
+        Ok(MerkleTree {
+            data_size,
+            chunk_size,
+            chunks,
+        })
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Offsets of the chunk in the data
+    pub fn chunk_range(&self, index: usize) -> Option<Range<u64>> {
+        if index >= self.chunks.len() {
+            return None;
+        }
+        let start = index as u64 * self.chunk_size;
+        Some(start..(start + self.chunk_size).min(self.data_size))
+    }
+
+    /// Whether the bytes are the chunk of the resource
+    pub fn verify_chunk(&self, index: usize, bytes: &[u8]) -> bool {
+        self.chunks
+            .get(index)
+            .is_some_and(|hash| *hash == hash_chunk(bytes))
+    }
+
+    /// Root of the tree
+    pub fn root(&self) -> [u8; 32] {
+        let mut level = self.chunks.clone();
+        if level.is_empty() {
+            return hash_chunk(&[]);
+        }
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => {
+                        let mut hasher = Hasher::new();
+                        hasher.update(&[NODE_PREFIX]);
+                        hasher.update(left);
+                        hasher.update(right);
+                        *hasher.finalize().as_bytes()
+                    }
+                    // the last node of odd levels is promoted as is
+                    _ => pair[0],
+                })
+                .collect();
+        }
+        level[0]
+    }
+
+    pub fn id(&self) -> ResourceIdMerkle {
+        ResourceIdMerkle {
+            data_size: self.data_size,
+            hash: self.root(),
+        }
+    }
+
+    /// Chunks differing from the chunks of the other tree, including
+    /// chunks missing in it
+    pub fn diff(&self, other: &MerkleTree) -> Vec<usize> {
+        if self.chunk_size != other.chunk_size {
+            return (0..self.chunks.len()).collect();
+        }
+        (0..self.chunks.len())
+            .filter(|index| self.chunks.get(*index) != other.chunks.get(*index))
+            .collect()
+    }
+
+    /// Chunks of the file not matching the tree, e.g. to transfer them
+    /// again into a damaged copy
+    pub fn corrupted_chunks<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Vec<usize>> {
+        let file = File::open(path)?;
+        let data_size = file.metadata()?.len();
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, file);
+        let actual = MerkleTree::compute_reader(
+            data_size,
+            self.chunk_size,
+            &mut reader,
+            |_| true,
+        )?;
+        Ok(self.diff(&actual))
+    }
+
+    /// Amount of leading bytes of the partially transferred file matching
+    /// the tree, which is the offset to resume the transfer from
+    pub fn verified_prefix<P: AsRef<Path>>(&self, path: P) -> Result<u64> {
+        let mut reader =
+            BufReader::with_capacity(BUFFER_CAPACITY, File::open(path)?);
+        let mut chunk = Vec::with_capacity(self.chunk_size as usize);
+        let mut verified = 0;
+        for index in 0..self.chunks.len() {
+            chunk.clear();
+            (&mut reader)
+                .take(self.chunk_size)
+                .read_to_end(&mut chunk)?;
+            if !self.verify_chunk(index, &chunk) {
+                break;
+            }
+            verified += chunk.len() as u64;
+        }
+        Ok(verified)
+    }
+}
+
+/// Hashes the file and stores hashes of its chunks into the metadata
+/// cache of the resource
+pub fn generate<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    path: &Path,
+) -> Result<MerkleTree> {
+    let size = fs::metadata(path)?.len();
+    if size != id.data_size {
+        return Err(ArklibError::SizeMismatch {
+            expected: id.data_size,
+            actual: size,
+        });
+    }
+    let tree = MerkleTree::compute(path)?;
+    metadata::store_field(root, id, MERKLE_METADATA_FIELD, &tree)?;
+    Ok(tree)
+}
+
+/// Loads hashes of chunks of the resource, if generated before
+pub fn load_merkle<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<MerkleTree>> {
+    let metadata: Option<serde_json::Value> = metadata::load(root, id)?;
+    match metadata.and_then(|mut metadata| {
+        metadata
+            .get_mut(MERKLE_METADATA_FIELD)
+            .map(serde_json::Value::take)
+    }) {
+        Some(field) => Ok(Some(serde_json::from_value(field)?)),
+        None => Ok(None),
+    }
+}
+
+fn leaf_hasher() -> Hasher {
+    let mut hasher = Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher
+}
+
+fn hash_chunk(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = leaf_hasher();
+    hasher.update(bytes);
+    *hasher.finalize().as_bytes()
+}
+
+/// Hashes are stored as hex strings to keep the metadata readable
+mod hex_hashes {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        hashes: &[[u8; 32]],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(hashes.iter().map(|hash| {
+            ::blake3::Hash::from_bytes(*hash)
+                .to_hex()
+                .to_string()
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<[u8; 32]>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|hex| {
+                ::blake3::Hash::from_hex(hex)
+                    .map(|hash| *hash.as_bytes())
+                    .map_err(D::Error::custom)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn corrupted_chunks_are_localized() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let data: Vec<u8> = (0..10).collect();
+        let path = dir.path().join("data");
+        fs::write(&path, &data).unwrap();
+
+        let mut reader = BufReader::new(data.as_slice());
+        let tree =
+            MerkleTree::compute_reader(10, 4, &mut reader, |_| true).unwrap();
+        assert_eq!(tree.chunk_count(), 3);
+        assert_eq!(tree.chunk_range(2), Some(8..10));
+        assert!(tree.verify_chunk(1, &data[4..8]));
+        assert!(!tree.verify_chunk(1, &data[0..4]));
+
+        let mut damaged = data.clone();
+        damaged[5] = 0;
+        fs::write(&path, &damaged).unwrap();
+        assert_eq!(tree.corrupted_chunks(&path).unwrap(), vec![1]);
+        assert_eq!(tree.verified_prefix(&path).unwrap(), 4);
+        fs::write(&path, &data[..9]).unwrap();
+        assert_eq!(tree.verified_prefix(&path).unwrap(), 8);
+
+        let id = ResourceIdMerkle::compute_bytes(&data).unwrap();
+        let parsed: ResourceIdMerkle = id.to_string().parse().unwrap();
+        assert_eq!(parsed, id);
+        assert_ne!(
+            id.hash,
+            ResourceIdBlake3::compute_bytes(&data)
+                .unwrap()
+                .hash
+        );
+    }
+
+    #[test]
+    fn trees_are_stored_in_metadata() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let path = root.join("lena.jpg");
+        fs::copy("tests/lena.jpg", &path).unwrap();
+        let size = fs::metadata(&path).unwrap().len();
+        let id = ResourceId::compute(size, &path).unwrap();
+
+        assert_eq!(load_merkle(root, id).unwrap(), None);
+        let tree = generate(root, id, &path).unwrap();
+        assert_eq!(load_merkle(root, id).unwrap(), Some(tree.clone()));
+        assert_eq!(tree.id(), ResourceIdMerkle::compute(size, &path).unwrap());
+    }
+}
@@ -2,16 +2,41 @@ use core::fmt::Display;
 use core::str::FromStr;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use std::fs::File;
 use std::hash::Hash;
 use std::io::BufReader;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 
 use crate::Result;
 
+/// Amount of bytes taken from the beginning and from the end of a file
+/// when computing a sampled identifier
+pub const SAMPLED_EDGE_SIZE: u64 = 256 * 1024;
+/// Amount of bytes in every sample taken between the edges of a file
+pub const SAMPLE_SIZE: u64 = 16 * 1024;
+/// Number of samples taken evenly between the edges of a file
+pub const SAMPLES_COUNT: u64 = 16;
+
+mod algorithm;
+mod blake3;
 mod crc32;
+pub mod merkle;
+mod namespace;
+mod sha256;
+mod xxh3;
 
+pub use self::blake3::ResourceIdBlake3;
+pub use algorithm::{
+    convert_ids, load_id_algorithm, set_id_algorithm, IdAlgorithm,
+};
 pub use crc32::ResourceId;
+#[cfg(test)]
+pub(crate) use crc32::PARALLEL_COMPUTATIONS;
+pub use merkle::{MerkleTree, ResourceIdMerkle};
+pub use namespace::{load_namespace, set_namespaced, Namespace};
+pub use sha256::ResourceIdSha256;
+pub use xxh3::ResourceIdXxh3;
 
 /// This trait defines a generic type representing a resource identifier.
 ///
@@ -43,6 +68,37 @@ pub trait ResourceIdTrait<'de>:
     /// * `file_path` - Path to the file containing the data.
     fn compute<P: AsRef<Path>>(data_size: u64, file_path: P) -> Result<Self>;
 
+    /// Creates a new resource identifier from the given path using multiple
+    /// threads, which speeds up hashing of large files on multi-core devices.
+    ///
+    /// Algorithms which can't be parallelized compute the identifier
+    /// in the same way as [`ResourceIdTrait::compute`].
+    ///
+    /// # Arguments
+    /// * `data_size` - Size of the data being identified.
+    /// * `file_path` - Path to the file containing the data.
+    fn compute_parallel<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self> {
+        Self::compute(data_size, file_path)
+    }
+
+    /// Creates a new "quick" resource identifier from the given path, hashing
+    /// only the size, the head, the tail and sparse samples of the data.
+    ///
+    /// Sampled identifiers are orders of magnitude faster to compute for
+    /// gigantic files like videos, but they don't match full identifiers
+    /// of the same data and can miss modifications between the samples.
+    ///
+    /// # Arguments
+    /// * `data_size` - Size of the data being identified.
+    /// * `file_path` - Path to the file containing the data.
+    fn compute_sampled<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self>;
+
     /// Creates a new resource identifier from raw bytes.
     ///
     /// # Arguments
@@ -51,11 +107,134 @@ pub trait ResourceIdTrait<'de>:
 
     /// Creates a new resource identifier from a buffered reader.
     ///
+    /// Fails with [`crate::ArklibError::SizeMismatch`] if the reader
+    /// provides a different amount of bytes than `data_size`.
+    ///
     /// # Arguments
     /// * `data_size` - Size of the data being read.
     /// * `reader` - Buffered reader providing access to the data.
     fn compute_reader<R: Read>(
         data_size: u64,
         reader: &mut BufReader<R>,
+    ) -> Result<Self> {
+        Self::compute_reader_with_progress(data_size, reader, |_| true)
+    }
+
+    /// Same as [`ResourceIdTrait::compute_reader`], but reports progress
+    /// of hashing large data, e.g. to display it in UI.
+    ///
+    /// Fails with [`crate::ArklibError::Cancelled`] as soon as
+    /// `progress` returns `false`.
+    ///
+    /// # Arguments
+    /// * `data_size` - Size of the data being read.
+    /// * `reader` - Buffered reader providing access to the data.
+    /// * `progress` - Called after every chunk with the amount
+    ///   of bytes read so far.
+    fn compute_reader_with_progress<R: Read, F: FnMut(u64) -> bool>(
+        data_size: u64,
+        reader: &mut BufReader<R>,
+        progress: F,
     ) -> Result<Self>;
 }
+
+/// Parses a part of an ID in the canonical decimal form produced by
+/// `Display`: digits only, without sign or leading zeros
+pub(crate) fn parse_decimal<T: FromStr>(part: &str, id: &str) -> Result<T> {
+    let canonical = !part.is_empty()
+        && part.bytes().all(|b| b.is_ascii_digit())
+        && (part == "0" || !part.starts_with('0'));
+    match canonical {
+        true => part.parse().ok(),
+        false => None,
+    }
+    .ok_or_else(|| {
+        log::warn!("Malformed ID {:?}: {:?} is not a decimal number", id, part);
+        crate::ArklibError::Parse
+    })
+}
+
+/// Splits an ID into its size and hash parts
+pub(crate) fn split_id(id: &str) -> Result<(&str, &str)> {
+    id.split_once('-').ok_or_else(|| {
+        log::warn!("Malformed ID {:?}: no separator after the size", id);
+        crate::ArklibError::Parse
+    })
+}
+
+/// Reads the parts of the file which a sampled identifier is computed from,
+/// prefixed by the size of the file
+///
+/// Files too small to be sampled are read completely.
+pub(crate) fn read_samples<P: AsRef<Path>>(
+    data_size: u64,
+    file_path: P,
+) -> Result<Vec<u8>> {
+    let mut file = File::open(file_path)?;
+    let actual = file.metadata()?.len();
+    if actual != data_size {
+        return Err(crate::ArklibError::SizeMismatch {
+            expected: data_size,
+            actual,
+        });
+    }
+
+    let mut samples = data_size.to_le_bytes().to_vec();
+    if data_size <= 2 * SAMPLED_EDGE_SIZE + SAMPLES_COUNT * SAMPLE_SIZE {
+        file.read_to_end(&mut samples)?;
+        return Ok(samples);
+    }
+
+    let middle = data_size - 2 * SAMPLED_EDGE_SIZE;
+    let stride = middle / SAMPLES_COUNT;
+    let mut regions = vec![(0, SAMPLED_EDGE_SIZE)];
+    regions.extend((0..SAMPLES_COUNT).map(|i| {
+        (
+            SAMPLED_EDGE_SIZE + i * stride + (stride - SAMPLE_SIZE) / 2,
+            SAMPLE_SIZE,
+        )
+    }));
+    regions.push((data_size - SAMPLED_EDGE_SIZE, SAMPLED_EDGE_SIZE));
+
+    for (offset, length) in regions {
+        file.seek(SeekFrom::Start(offset))?;
+        let start = samples.len();
+        samples.resize(start + length as usize, 0);
+        file.read_exact(&mut samples[start..])?;
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn sampled_id_ignores_bytes_between_samples() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let path = dir.path().join("video.mp4");
+        let mut data = vec![0u8; 4 * 1024 * 1024];
+        std::fs::write(&path, &data).unwrap();
+        let size = data.len() as u64;
+
+        let sampled = ResourceId::compute_sampled(size, &path).unwrap();
+        assert_eq!(sampled.data_size, size);
+        assert_ne!(sampled, ResourceId::compute(size, &path).unwrap());
+
+        // a byte right after the head is never sampled
+        data[SAMPLED_EDGE_SIZE as usize] = 1;
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(ResourceId::compute_sampled(size, &path).unwrap(), sampled);
+
+        // but the tail is
+        let last = data.len() - 1;
+        data[last] = 1;
+        std::fs::write(&path, &data).unwrap();
+        assert_ne!(ResourceId::compute_sampled(size, &path).unwrap(), sampled);
+        assert_ne!(
+            ResourceIdBlake3::compute_sampled(size, &path).unwrap(),
+            ResourceIdBlake3::compute(size, &path).unwrap()
+        );
+    }
+}
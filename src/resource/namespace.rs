@@ -0,0 +1,153 @@
+//! Namespaced IDs of resources
+//!
+//! IDs depend on the content only, so IDs of different roots can't be
+//! combined in one database: the same file in two roots gets the same ID
+//! and unrelated files may collide. A root can opt into mixing its own
+//! UUID into its IDs. The UUID is kept in the `namespace` section of
+//! `.ark/config`, so devices syncing the root compute the same IDs, but
+//! IDs stop matching the ones computed by other roots and tools.
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use canonical_path::CanonicalPathBuf;
+use crc32fast::Hasher;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::ResourceId;
+use crate::storage::config::{load_section, store_section};
+use crate::{
+    ArklibError, Result, ARK_FOLDER, ID_CACHE_FILE, INDEX_PATH, REGISTRAR,
+};
+
+const CONFIG_SECTION: &str = "namespace";
+
+/// Namespace of IDs of a root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Namespace(Uuid);
+
+impl Namespace {
+    pub fn new(uuid: Uuid) -> Self {
+        Namespace(uuid)
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.0
+    }
+
+    /// Mixes the namespace into the ID computed from the content,
+    /// keeping the size of the resource
+    pub fn apply(&self, id: ResourceId) -> ResourceId {
+        let mut hasher = Hasher::new();
+        hasher.update(self.0.as_bytes());
+        hasher.update(&id.hash.to_le_bytes());
+        ResourceId {
+            data_size: id.data_size,
+            hash: hasher.finalize(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NamespaceConfig {
+    uuid: String,
+    enabled: bool,
+}
+
+/// Loads the namespace of the root, `None` unless the root opted in
+pub fn load_namespace<P: AsRef<Path>>(root: P) -> Result<Option<Namespace>> {
+    let config: Option<NamespaceConfig> = load_section(root, CONFIG_SECTION)?;
+    match config {
+        Some(config) if config.enabled => {
+            let uuid = Uuid::parse_str(&config.uuid)
+                .map_err(|_| ArklibError::Parse)?;
+            Ok(Some(Namespace(uuid)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Enables or disables namespaced IDs of the root
+///
+/// The UUID is generated once and kept while disabled, so enabling
+/// namespaced IDs again restores the same IDs. The index and cached IDs
+/// of the root are discarded to be rebuilt with new IDs, so the index
+/// must not be provided at the moment. Data stored by IDs is not
+/// migrated.
+pub fn set_namespaced<P: AsRef<Path>>(
+    root: P,
+    enabled: bool,
+) -> Result<Option<Namespace>> {
+    let root = CanonicalPathBuf::canonicalize(root)?;
+    if REGISTRAR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains_key(&root)
+    {
+        return Err(ArklibError::Busy(format!(
+            "Index of {} is in use",
+            root.display()
+        )));
+    }
+
+    let config: Option<NamespaceConfig> = load_section(&root, CONFIG_SECTION)?;
+    let uuid = match config {
+        Some(config) => config.uuid,
+        None => Uuid::new_v4().to_string(),
+    };
+    store_section(&root, CONFIG_SECTION, &NamespaceConfig { uuid, enabled })?;
+    for file in [INDEX_PATH, ID_CACHE_FILE] {
+        match fs::remove_file(root.as_path().join(ARK_FOLDER).join(file)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    log::info!(
+        "Namespaced IDs of {} are {}",
+        root.display(),
+        if enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    load_namespace(&root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{IndexOptions, ResourceIndex};
+    use crate::resource::ResourceIdTrait;
+    use tempdir::TempDir;
+
+    #[test]
+    fn namespaced_ids_differ_between_roots() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let first = TempDir::new("arklib_test").unwrap();
+        let second = TempDir::new("arklib_test").unwrap();
+        let mut ids = vec![];
+        for dir in [&first, &second] {
+            let root = fs::canonicalize(dir.path()).unwrap();
+            fs::write(root.join("a.txt"), "abc").unwrap();
+            assert_eq!(load_namespace(&root).unwrap(), None);
+
+            let namespace = set_namespaced(&root, true).unwrap();
+            assert!(namespace.is_some());
+            let options = IndexOptions {
+                namespace,
+                ..IndexOptions::default()
+            };
+            let index = ResourceIndex::build_with_options(&root, options);
+            ids.push(index.get_entry(root.join("a.txt")).unwrap().id);
+
+            assert_eq!(set_namespaced(&root, false).unwrap(), None);
+            assert_eq!(set_namespaced(&root, true).unwrap(), namespace);
+        }
+        let plain = ResourceId::compute_bytes(b"abc").unwrap();
+        assert_ne!(ids[0], ids[1]);
+        assert!(!ids.contains(&plain));
+        assert_eq!(ids[0].data_size, plain.data_size);
+    }
+}
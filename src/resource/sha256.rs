@@ -0,0 +1,175 @@
+use log;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::Read;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::resource::{parse_decimal, read_samples, split_id, ResourceIdTrait};
+use crate::{ArklibError, Result};
+
+const KILOBYTE: u64 = 1024;
+const MEGABYTE: u64 = 1024 * KILOBYTE;
+const BUFFER_CAPACITY: usize = 512 * KILOBYTE as usize;
+
+/// Represents a resource identifier using the SHA-256 algorithm.
+///
+/// Uses `sha2` crate to compute the hash value. It is slower than Blake3,
+/// but the hash matches the output of `sha256sum` and of most external
+/// tools and services.
+#[derive(
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Hash,
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+)]
+pub struct ResourceIdSha256 {
+    pub data_size: u64,
+    pub hash: [u8; 32],
+}
+
+impl Display for ResourceIdSha256 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-", self.data_size)?;
+        for byte in self.hash {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ResourceIdSha256 {
+    type Err = ArklibError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (l, r) = split_id(s)?;
+        let data_size: u64 = parse_decimal(l, s)?;
+        // uppercase digits would make the same ID
+        // representable by different strings
+        let valid = r.len() == 64
+            && r.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        if !valid {
+            log::warn!("Malformed ID {:?}: the hash is not lowercase hex", s);
+            return Err(ArklibError::Parse);
+        }
+        let mut hash = [0; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&r[2 * i..2 * i + 2], 16)
+                .map_err(|_| ArklibError::Parse)?;
+        }
+
+        Ok(ResourceIdSha256 { data_size, hash })
+    }
+}
+
+impl ResourceIdTrait<'_> for ResourceIdSha256 {
+    type HashType = [u8; 32];
+
+    fn compute<P: AsRef<Path>>(data_size: u64, file_path: P) -> Result<Self> {
+        log::trace!(
+            "[compute] file {} with size {} mb",
+            file_path.as_ref().display(),
+            data_size / MEGABYTE
+        );
+
+        let source = fs::OpenOptions::new()
+            .read(true)
+            .open(file_path.as_ref())?;
+
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, source);
+        ResourceIdSha256::compute_reader(data_size, &mut reader)
+    }
+
+    fn compute_sampled<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self> {
+        let samples = read_samples(data_size, file_path)?;
+        Ok(ResourceIdSha256 {
+            data_size,
+            hash: Sha256::digest(&samples).into(),
+        })
+    }
+
+    fn compute_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(ResourceIdSha256 {
+            data_size: bytes.len() as u64,
+            hash: Sha256::digest(bytes).into(),
+        })
+    }
+
+    fn compute_reader_with_progress<R: Read, F: FnMut(u64) -> bool>(
+        data_size: u64,
+        reader: &mut BufReader<R>,
+        mut progress: F,
+    ) -> Result<Self> {
+        let mut hasher = Sha256::new();
+        let mut bytes_read: u64 = 0;
+        loop {
+            let bytes_read_iteration: usize = reader.fill_buf()?.len();
+            if bytes_read_iteration == 0 {
+                break;
+            }
+            hasher.update(reader.buffer());
+            reader.consume(bytes_read_iteration);
+            bytes_read += bytes_read_iteration as u64;
+            if !progress(bytes_read) {
+                log::debug!("[compute] cancelled after {} bytes", bytes_read);
+                return Err(ArklibError::Cancelled);
+            }
+        }
+
+        log::trace!("[compute] {} bytes has been read", bytes_read);
+        if bytes_read != data_size {
+            return Err(ArklibError::SizeMismatch {
+                expected: data_size,
+                actual: bytes_read,
+            });
+        }
+
+        Ok(ResourceIdSha256 {
+            data_size,
+            hash: hasher.finalize().into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_id_test() {
+        let id = ResourceIdSha256::compute_bytes(b"abc").unwrap();
+        // the test vector of FIPS 180-2
+        assert_eq!(
+            id.to_string(),
+            "3-ba7816bf8f01cfea414140de5dae2223\
+             b00361a396177a9cb410ff61f20015ad"
+        );
+        let parsed: ResourceIdSha256 = id.to_string().parse().unwrap();
+        assert_eq!(parsed, id);
+        assert!(id
+            .to_string()
+            .to_uppercase()
+            .parse::<ResourceIdSha256>()
+            .is_err());
+
+        let file_path = Path::new("./tests/lena.jpg");
+        let data_size = fs::metadata(file_path).unwrap().len();
+        let id1 = ResourceIdSha256::compute(data_size, file_path).unwrap();
+        let raw_bytes = fs::read(file_path).unwrap();
+        let id2 = ResourceIdSha256::compute_bytes(&raw_bytes).unwrap();
+        assert_eq!(id1, id2);
+    }
+}
@@ -0,0 +1,150 @@
+use log;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::Read;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
+
+use crate::resource::{parse_decimal, read_samples, split_id, ResourceIdTrait};
+use crate::{ArklibError, Result};
+
+const KILOBYTE: u64 = 1024;
+const MEGABYTE: u64 = 1024 * KILOBYTE;
+const BUFFER_CAPACITY: usize = 512 * KILOBYTE as usize;
+
+/// Represents a resource identifier using the 64-bit XXH3 algorithm.
+///
+/// Uses `xxhash-rust` crate to compute the hash value. XXH3 is not
+/// cryptographic, but it is faster than CRC32 and collides much less
+/// often thanks to the wider hash.
+#[derive(
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Hash,
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+)]
+pub struct ResourceIdXxh3 {
+    pub data_size: u64,
+    pub hash: u64,
+}
+
+impl Display for ResourceIdXxh3 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.data_size, self.hash)
+    }
+}
+
+impl FromStr for ResourceIdXxh3 {
+    type Err = ArklibError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (l, r) = split_id(s)?;
+        let data_size: u64 = parse_decimal(l, s)?;
+        let hash: u64 = parse_decimal(r, s)?;
+
+        Ok(ResourceIdXxh3 { data_size, hash })
+    }
+}
+
+impl ResourceIdTrait<'_> for ResourceIdXxh3 {
+    type HashType = u64;
+
+    fn compute<P: AsRef<Path>>(data_size: u64, file_path: P) -> Result<Self> {
+        log::trace!(
+            "[compute] file {} with size {} mb",
+            file_path.as_ref().display(),
+            data_size / MEGABYTE
+        );
+
+        let source = fs::OpenOptions::new()
+            .read(true)
+            .open(file_path.as_ref())?;
+
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, source);
+        ResourceIdXxh3::compute_reader(data_size, &mut reader)
+    }
+
+    fn compute_sampled<P: AsRef<Path>>(
+        data_size: u64,
+        file_path: P,
+    ) -> Result<Self> {
+        let samples = read_samples(data_size, file_path)?;
+        Ok(ResourceIdXxh3 {
+            data_size,
+            hash: xxh3_64(&samples),
+        })
+    }
+
+    fn compute_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(ResourceIdXxh3 {
+            data_size: bytes.len() as u64,
+            hash: xxh3_64(bytes),
+        })
+    }
+
+    fn compute_reader_with_progress<R: Read, F: FnMut(u64) -> bool>(
+        data_size: u64,
+        reader: &mut BufReader<R>,
+        mut progress: F,
+    ) -> Result<Self> {
+        let mut hasher = Xxh3::new();
+        let mut bytes_read: u64 = 0;
+        loop {
+            let bytes_read_iteration: usize = reader.fill_buf()?.len();
+            if bytes_read_iteration == 0 {
+                break;
+            }
+            hasher.update(reader.buffer());
+            reader.consume(bytes_read_iteration);
+            bytes_read += bytes_read_iteration as u64;
+            if !progress(bytes_read) {
+                log::debug!("[compute] cancelled after {} bytes", bytes_read);
+                return Err(ArklibError::Cancelled);
+            }
+        }
+
+        log::trace!("[compute] {} bytes has been read", bytes_read);
+        if bytes_read != data_size {
+            return Err(ArklibError::SizeMismatch {
+                expected: data_size,
+                actual: bytes_read,
+            });
+        }
+
+        Ok(ResourceIdXxh3 {
+            data_size,
+            hash: hasher.digest(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_id_test() {
+        let file_path = Path::new("./tests/lena.jpg");
+        let data_size = fs::metadata(file_path).unwrap().len();
+
+        let id1 = ResourceIdXxh3::compute(data_size, file_path).unwrap();
+        assert_eq!(id1.data_size, 128760);
+
+        let raw_bytes = fs::read(file_path).unwrap();
+        let id2 = ResourceIdXxh3::compute_bytes(&raw_bytes).unwrap();
+        assert_eq!(id1, id2);
+
+        let parsed: ResourceIdXxh3 = id1.to_string().parse().unwrap();
+        assert_eq!(parsed, id1);
+        assert!("7-01".parse::<ResourceIdXxh3>().is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Scores of resources
+//!
+//! Scores are user data kept in a single [`AtomicFile`], resources
+//! without a score have the score of 0 and are not stored.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::resource::ResourceId;
+use crate::{Result, ARK_FOLDER, SCORE_STORAGE_FILE};
+
+pub type Score = i32;
+
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct ScoreStorage {
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    pub(crate) scores: BTreeMap<ResourceId, Score>,
+}
+
+/// Loads non-zero scores of all resources of the root
+pub fn load_scores<P: AsRef<Path>>(
+    root: P,
+) -> Result<BTreeMap<ResourceId, Score>> {
+    let path = storage_path(root);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    match AtomicFile::new(path)?.load()?.open()? {
+        Some(file) => {
+            let storage: ScoreStorage =
+                serde_json::from_reader(std::io::BufReader::new(file))?;
+            Ok(storage.scores)
+        }
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+pub fn score_of<P: AsRef<Path>>(root: P, id: ResourceId) -> Result<Score> {
+    Ok(load_scores(root)?
+        .get(&id)
+        .copied()
+        .unwrap_or_default())
+}
+
+pub fn set_score<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    score: Score,
+) -> Result<()> {
+    let file = AtomicFile::new(storage_path(root))?;
+    modify_json(&file, |storage: &mut Option<ScoreStorage>| {
+        let scores = &mut storage
+            .get_or_insert_with(ScoreStorage::default)
+            .scores;
+        match score {
+            0 => scores.remove(&id),
+            score => scores.insert(id, score),
+        };
+    })?;
+    Ok(())
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(SCORE_STORAGE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn zero_scores_are_not_stored() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let id = ResourceId {
+            hash: 1,
+            data_size: 1,
+        };
+        assert_eq!(score_of(dir.path(), id).unwrap(), 0);
+        set_score(dir.path(), id, -2).unwrap();
+        assert_eq!(score_of(dir.path(), id).unwrap(), -2);
+        set_score(dir.path(), id, 0).unwrap();
+        assert!(load_scores(dir.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,558 @@
+//! Local HTTP server of the REST API of a root
+//!
+//! Desktop and web UIs talk to one root through the same API instead
+//! of linking arklib themselves:
+//!
+//! - `GET /resources` lists IDs and paths of all resources
+//! - `POST /query` lists resources matching the [`Query`] in the body
+//! - `GET /resources/{id}` returns the content, honoring `Range` headers
+//! - `GET /resources/{id}/preview` and `/thumbnail` return cached images
+//! - `GET` and `PUT /resources/{id}/tags` read and replace tags
+//! - `GET` and `PUT /resources/{id}/properties` read and merge properties
+//!
+//! Paths are relative to the root, with `/` as separator. Every request
+//! must carry the token passed to [`serve`] as `Authorization: Bearer`,
+//! see [`new_token`]. Requests with a `Host` or an `Origin` other than
+//! `localhost` or an IP address are refused as well, so that web pages
+//! can't reach the server through DNS rebinding. The server should still
+//! listen on the loopback interface only.
+use std::convert::Infallible;
+use std::io::Read;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use hyper::body::HttpBody;
+use hyper::header::{
+    AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, HOST, ORIGIN,
+    RANGE,
+};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use url::{Host, Url};
+
+use crate::index::ResourceReader;
+use crate::query::{query, Query};
+use crate::resource::ResourceId;
+use crate::storage::preview::{load_preview, load_thumbnail};
+use crate::storage::prop::{load_raw_properties, store_properties};
+use crate::tags::{set_tags, tags_of, Tag};
+use crate::util::fs::relative_path;
+use crate::{ArklibError, ResourceIndexLock, Result};
+
+const CHUNK_SIZE: usize = 256 * 1024;
+/// Requests with larger bodies are refused
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceEntry {
+    pub id: ResourceId,
+    /// Path relative to the root, with `/` as separator
+    pub path: String,
+}
+
+/// Generates a random token for [`serve`], to be handed to the UI
+/// over a channel only it can read, e.g. a file or its command line
+pub fn new_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Answers requests carrying the token until the listener fails
+pub async fn serve(
+    listener: TcpListener,
+    index: ResourceIndexLock,
+    token: String,
+) -> Result<()> {
+    let incoming = AddrIncoming::from_listener(listener)
+        .map_err(|e| ArklibError::Other(e.into()))?;
+    let token: Arc<str> = token.into();
+    let make_service = make_service_fn(move |_| {
+        let index = index.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let index = index.clone();
+                let token = token.clone();
+                async move {
+                    let response = match authorize(&request, &token) {
+                        Ok(()) => respond(request, index).await,
+                        Err(status) => status_response(status),
+                    };
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    Server::builder(incoming)
+        .serve(make_service)
+        .await
+        .map_err(|e| ArklibError::Other(e.into()))
+}
+
+async fn respond(
+    request: Request<Body>,
+    index: ResourceIndexLock,
+) -> Response<Body> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    match route(request, index).await {
+        Ok(response) => response,
+        Err(e) => {
+            log::warn!("{} {} failed: {}", method, path, e);
+            let status = match e {
+                ArklibError::Path(_) => StatusCode::NOT_FOUND,
+                ArklibError::Parse => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            status_response(status)
+        }
+    }
+}
+
+/// Checks the token and that the request comes from a local page
+fn authorize(
+    request: &Request<Body>,
+    token: &str,
+) -> std::result::Result<(), StatusCode> {
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap_or_default())
+    };
+    if !header(HOST).is_some_and(|host| is_local(&format!("http://{}", host))) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if header(ORIGIN).is_some_and(|origin| !is_local(origin)) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let bearer = header(AUTHORIZATION)
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    match constant_time_eq(bearer.as_bytes(), token.as_bytes()) {
+        true => Ok(()),
+        false => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Whether the URL leads to `localhost` or an IP address, names of
+/// other hosts can be rebound to local addresses
+fn is_local(url: &str) -> bool {
+    match Url::parse(url).ok().as_ref().and_then(Url::host) {
+        Some(Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(Host::Ipv4(_) | Host::Ipv6(_)) => true,
+        None => false,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Reads the body of the request and hands it over to a blocking thread,
+/// since handlers lock the index and read or write files
+async fn route(
+    request: Request<Body>,
+    index: ResourceIndexLock,
+) -> Result<Response<Body>> {
+    let method = request.method().clone();
+    let segments: Vec<String> = request
+        .uri()
+        .path()
+        .trim_matches('/')
+        .split('/')
+        .map(|segment| segment.to_string())
+        .collect();
+    let range = request
+        .headers()
+        .get(RANGE)
+        .map(|range| range.to_str().unwrap_or_default().to_string());
+    let body = match read_body(request).await? {
+        Some(body) => body,
+        None => return Ok(status_response(StatusCode::PAYLOAD_TOO_LARGE)),
+    };
+    tokio::task::spawn_blocking(move || {
+        let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+        handle(&method, &segments, range.as_deref(), &body, &index)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+fn handle(
+    method: &Method,
+    segments: &[&str],
+    range: Option<&str>,
+    body: &[u8],
+    index: &ResourceIndexLock,
+) -> Result<Response<Body>> {
+    let root = root(index);
+    match (method, segments) {
+        (&Method::GET, ["resources"]) => {
+            let index = index.read().unwrap_or_else(|e| e.into_inner());
+            let ids: Vec<ResourceId> = index.ids().copied().collect();
+            json(&entries(&index, ids)?)
+        }
+        (&Method::POST, ["query"]) => {
+            let conditions: Query = serde_json::from_slice(body)?;
+            let index = index.read().unwrap_or_else(|e| e.into_inner());
+            let ids = query(&*index, &conditions)?;
+            json(&entries(&index, ids)?)
+        }
+        (&Method::GET, ["resources", id]) => {
+            content(index, parse_id(id)?, range)
+        }
+        (&Method::GET, ["resources", id, "preview"]) => {
+            image(load_preview(&root, parse_id(id)?)?)
+        }
+        (&Method::GET, ["resources", id, "thumbnail"]) => {
+            image(load_thumbnail(&root, parse_id(id)?)?)
+        }
+        (&Method::GET, ["resources", id, "tags"]) => {
+            json(&tags_of(&root, parse_id(id)?)?)
+        }
+        (&Method::PUT, ["resources", id, "tags"]) => {
+            let id = parse_id(id)?;
+            let tags: Vec<Tag> = serde_json::from_slice(body)?;
+            set_tags(&root, id, &tags)?;
+            json(&tags_of(&root, id)?)
+        }
+        (&Method::GET, ["resources", id, "properties"]) => {
+            let id = parse_id(id)?;
+            match load_raw_properties(&root, id) {
+                Ok(properties) => Ok(bytes(properties, "application/json")),
+                Err(ArklibError::Io(e))
+                    if e.kind() == std::io::ErrorKind::NotFound =>
+                {
+                    json(&serde_json::json!({}))
+                }
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::PUT, ["resources", id, "properties"]) => {
+            let id = parse_id(id)?;
+            let properties: serde_json::Value = serde_json::from_slice(body)?;
+            if !properties.is_object() {
+                return Err(ArklibError::Parse);
+            }
+            store_properties(&root, id, &properties)?;
+            Ok(bytes(load_raw_properties(&root, id)?, "application/json"))
+        }
+        _ => Ok(status_response(StatusCode::NOT_FOUND)),
+    }
+}
+
+/// Streams the content of the resource, or the range of it
+fn content(
+    index: &ResourceIndexLock,
+    id: ResourceId,
+    range: Option<&str>,
+) -> Result<Response<Body>> {
+    let index = index.read().unwrap_or_else(|e| e.into_inner());
+    let mut reader = ResourceReader::open(&index, id, 0..u64::MAX)?;
+    let size = reader.len();
+    let mut response = Response::builder();
+    if let Some(range) = range {
+        let Some(range) = parse_range(range, size)? else {
+            let mut response =
+                status_response(StatusCode::RANGE_NOT_SATISFIABLE);
+            if let Ok(value) = format!("bytes */{}", size).parse() {
+                response
+                    .headers_mut()
+                    .insert(CONTENT_RANGE, value);
+            }
+            return Ok(response);
+        };
+        response = response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+            );
+        reader = ResourceReader::open(&index, id, range)?;
+    }
+    drop(index);
+    let response = response.header(CONTENT_LENGTH, reader.len());
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            let chunk = tokio::task::spawn_blocking(move || {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let read = reader.read(&mut chunk)?;
+                chunk.truncate(read);
+                Ok::<_, std::io::Error>((reader, chunk))
+            })
+            .await;
+            match chunk {
+                Ok(Ok((_, chunk))) if chunk.is_empty() => break,
+                Ok(Ok((next, chunk))) => {
+                    reader = next;
+                    if sender.send_data(chunk.into()).await.is_err() {
+                        break;
+                    }
+                }
+                _ => {
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+    response
+        .body(body)
+        .map_err(|e| ArklibError::Other(e.into()))
+}
+
+fn entries<I: IntoIterator<Item = ResourceId>>(
+    index: &crate::ResourceIndex,
+    ids: I,
+) -> Result<Vec<ResourceEntry>> {
+    ids.into_iter()
+        .filter_map(|id| Some((id, index.get_path(&id)?)))
+        .map(|(id, path)| {
+            Ok(ResourceEntry {
+                id,
+                path: relative_path(index.root(), path)?,
+            })
+        })
+        .collect()
+}
+
+fn root(index: &ResourceIndexLock) -> PathBuf {
+    index
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .root()
+        .to_path_buf()
+}
+
+fn parse_id(id: &str) -> Result<ResourceId> {
+    id.parse().map_err(|_| ArklibError::Parse)
+}
+
+/// Parses a single range of the `Range` header, e.g. `bytes=0-99`,
+/// `bytes=100-` or `bytes=-100`, returning `None` if the range
+/// is outside of the resource
+fn parse_range(header: &str, size: u64) -> Result<Option<Range<u64>>> {
+    let spec = header
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .ok_or(ArklibError::Parse)?;
+    let (start, end) = spec.split_once('-').ok_or(ArklibError::Parse)?;
+    let number = |n: &str| {
+        n.trim()
+            .parse::<u64>()
+            .map_err(|_| ArklibError::Parse)
+    };
+    let range = match (start.trim().is_empty(), end.trim().is_empty()) {
+        (false, true) => number(start)?..size,
+        (false, false) => {
+            number(start)?..number(end)?.saturating_add(1).min(size)
+        }
+        (true, false) => size.saturating_sub(number(end)?)..size,
+        (true, true) => return Err(ArklibError::Parse),
+    };
+    Ok((range.start < range.end).then_some(range))
+}
+
+/// Reads the body, returning `None` if it is larger than [`MAX_BODY_SIZE`]
+async fn read_body(request: Request<Body>) -> Result<Option<Vec<u8>>> {
+    let mut body = request.into_body();
+    let mut data = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| ArklibError::Network)?;
+        if data.len() + chunk.len() > MAX_BODY_SIZE {
+            return Ok(None);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Some(data))
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Response<Body>> {
+    Ok(bytes(serde_json::to_vec(value)?, "application/json"))
+}
+
+fn image(data: Option<Vec<u8>>) -> Result<Response<Body>> {
+    let data = data.ok_or_else(|| {
+        ArklibError::Path("Preview has not been generated".to_string())
+    })?;
+    let content_type = match data.starts_with(&[0x89, b'P', b'N', b'G']) {
+        true => "image/png",
+        false if data.starts_with(b"RIFF") => "image/webp",
+        false => "image/jpeg",
+    };
+    Ok(bytes(data, content_type))
+}
+
+fn bytes(data: Vec<u8>, content_type: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(data));
+    if let Ok(value) = content_type.parse() {
+        response.headers_mut().insert(CONTENT_TYPE, value);
+    }
+    response
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResourceIndex;
+    use serde::de::DeserializeOwned;
+    use std::fs;
+    use std::sync::{Arc, RwLock};
+    use tempdir::TempDir;
+
+    async fn fetch<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> T {
+        let text = request
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn root_is_browsed_over_http() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.txt"), "hello, world").unwrap();
+        let index = Arc::new(RwLock::new(ResourceIndex::build(&root)));
+        let id = index
+            .read()
+            .unwrap()
+            .ids()
+            .next()
+            .copied()
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let token = new_token();
+        tokio::spawn(serve(listener, index, token.clone()));
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+
+        let entries: Vec<ResourceEntry> =
+            fetch(client.get(format!("{}/resources", base))).await;
+        let entry = ResourceEntry {
+            id,
+            path: "docs/a.txt".to_string(),
+        };
+        assert_eq!(entries, vec![entry]);
+
+        let response = client
+            .get(format!("{}/resources/{}", base, id))
+            .header(RANGE, "bytes=-5")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.text().await.unwrap(), "world");
+
+        let tags: Vec<Tag> = fetch(
+            client
+                .put(format!("{}/resources/{}/tags", base, id))
+                .body(r#"["greeting", " "]"#),
+        )
+        .await;
+        assert_eq!(tags, vec!["greeting".to_string()]);
+        let found: Vec<ResourceEntry> = fetch(
+            client
+                .post(format!("{}/query", base))
+                .body(r#"{"all_tags": ["greeting"]}"#),
+        )
+        .await;
+        assert_eq!(found.len(), 1);
+
+        let response = client
+            .get(format!("{}/resources/{}/preview", base, id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn foreign_requests_are_refused() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let index = Arc::new(RwLock::new(ResourceIndex::build(dir.path())));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let token = new_token();
+        tokio::spawn(serve(listener, index, token.clone()));
+        let client = reqwest::Client::new();
+        let status = |request: reqwest::RequestBuilder| async move {
+            request.send().await.unwrap().status()
+        };
+
+        let resources = format!("{}/resources", base);
+        assert_eq!(
+            status(client.get(&resources)).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(client.get(&resources).bearer_auth("guess")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(client.get(&resources).bearer_auth(&token)).await,
+            StatusCode::OK
+        );
+        // a page of a rebound domain
+        assert_eq!(
+            status(
+                client
+                    .get(&resources)
+                    .bearer_auth(&token)
+                    .header(HOST, "attacker.example")
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(
+                client
+                    .get(&resources)
+                    .bearer_auth(&token)
+                    .header(ORIGIN, "https://attacker.example")
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(
+                client
+                    .post(format!("{}/query", base))
+                    .bearer_auth(&token)
+                    .body(vec![b' '; MAX_BODY_SIZE + 1])
+            )
+            .await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::{Result, ARK_FOLDER, CONFIG_FILE};
+
+/// Loads a section of the root config, returning `None` if the section
+/// has never been stored
+pub fn load_section<T: DeserializeOwned, P: AsRef<Path>>(
+    root: P,
+    section: &str,
+) -> Result<Option<T>> {
+    let path = config_path(root);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = AtomicFile::new(path)?;
+    let config: Map<String, Value> = match file.load()?.open()? {
+        Some(file) => serde_json::from_reader(BufReader::new(file))?,
+        None => return Ok(None),
+    };
+    match config.get(section) {
+        Some(value) => Ok(Some(serde_json::from_value(value.clone())?)),
+        None => Ok(None),
+    }
+}
+
+/// Replaces a section of the root config, other sections are kept as is
+pub fn store_section<T: Serialize, P: AsRef<Path>>(
+    root: P,
+    section: &str,
+    value: &T,
+) -> Result<()> {
+    let value = serde_json::to_value(value)?;
+    let file = AtomicFile::new(config_path(root))?;
+    modify_json(&file, |config: &mut Option<Map<String, Value>>| {
+        config
+            .get_or_insert_with(Map::new)
+            .insert(section.to_string(), value.clone());
+    })?;
+    Ok(())
+}
+
+fn config_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref().join(ARK_FOLDER).join(CONFIG_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::initialize;
+
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn sections_are_stored_independently() {
+        initialize();
+
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        assert_eq!(load_section::<u32, _>(root, "first").unwrap(), None);
+
+        store_section(root, "first", &1).unwrap();
+        store_section(root, "second", &"two").unwrap();
+        store_section(root, "first", &3).unwrap();
+
+        assert_eq!(load_section(root, "first").unwrap(), Some(3));
+        assert_eq!(
+            load_section(root, "second").unwrap(),
+            Some("two".to_string())
+        );
+        assert_eq!(load_section::<u32, _>(root, "third").unwrap(), None);
+    }
+}
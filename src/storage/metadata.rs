@@ -0,0 +1,148 @@
+//! Metadata generated from resources
+//!
+//! Metadata of a resource is a JSON value kept in an [`AtomicFile`] per
+//! ID in the metadata cache. Generators either [`store`] the whole value
+//! or [`store_field`] one field of an object shared with others.
+use crate::atomic::{modify_json, AtomicFile};
+use crate::cache::stamp_entry;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::Debug;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::resource::ResourceId;
+use crate::{Result, ARK_FOLDER, METADATA_STORAGE_FOLDER};
+
+/// Replaces the metadata of the resource
+pub fn store<
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: AsRef<Path>,
+>(
+    root: P,
+    id: ResourceId,
+    metadata: &S,
+) -> Result<()> {
+    let path = metadata_path(root, id);
+    let file = AtomicFile::new(&path)?;
+    modify_json(&file, |current_meta: &mut Option<S>| {
+        let new_meta = metadata.clone();
+        match current_meta {
+            Some(file_data) => {
+                // This is fine because generated metadata must always
+                // be generated in same way on any device.
+                *file_data = new_meta;
+                // Different versions of the lib should
+                // not be used on synced devices.
+            }
+            None => *current_meta = Some(new_meta),
+        }
+    })?;
+    stamp_entry(&path, id)
+}
+
+/// Replaces one field of the metadata of the resource, keeping the other
+/// fields, so that metadata extracted by several generators is kept
+/// together in the same file
+pub fn store_field<S: Serialize, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    field: &str,
+    value: &S,
+) -> Result<()> {
+    let value = serde_json::to_value(value)?;
+    let path = metadata_path(root, id);
+    let file = AtomicFile::new(&path)?;
+    modify_json(&file, |current_meta: &mut Option<Value>| {
+        let mut fields = match current_meta.take() {
+            Some(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+        fields.insert(field.to_string(), value.clone());
+        *current_meta = Some(Value::Object(fields));
+    })?;
+    stamp_entry(&path, id)
+}
+
+/// Loads the metadata of the resource, if generated before
+pub fn load<T: DeserializeOwned, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<T>> {
+    let path = metadata_path(root, id);
+    if !path.exists() {
+        return Ok(None);
+    }
+    match AtomicFile::new(path)?.load()?.open()? {
+        Some(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        None => Ok(None),
+    }
+}
+
+/// Removes the metadata of the resource, returning whether there was any
+pub fn remove<P: AsRef<Path>>(root: P, id: ResourceId) -> Result<bool> {
+    match std::fs::remove_dir_all(metadata_path(root, id)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Lists resources with generated metadata
+pub fn list<P: AsRef<Path>>(root: P) -> Result<Vec<ResourceId>> {
+    let folder = root
+        .as_ref()
+        .join(ARK_FOLDER)
+        .join(METADATA_STORAGE_FOLDER);
+    if !folder.exists() {
+        return Ok(vec![]);
+    }
+    let mut ids = vec![];
+    for entry in std::fs::read_dir(folder)? {
+        let name = entry?.file_name();
+        match name.to_string_lossy().parse() {
+            Ok(id) => ids.push(id),
+            Err(_) => log::warn!("Unexpected metadata entry {:?}", name),
+        }
+    }
+    Ok(ids)
+}
+
+fn metadata_path<P: AsRef<Path>>(root: P, id: ResourceId) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(METADATA_STORAGE_FOLDER)
+        .join(id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn fields_are_stored_together() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let id = ResourceId {
+            hash: 1,
+            data_size: 1,
+        };
+        assert_eq!(load::<Value, _>(root, id).unwrap(), None);
+        store_field(root, id, "lines", &2).unwrap();
+        store_field(root, id, "words", &5).unwrap();
+        assert_eq!(
+            load::<Value, _>(root, id).unwrap(),
+            Some(serde_json::json!({"lines": 2, "words": 5}))
+        );
+        let replaced = serde_json::json!({"pages": 1});
+        store(root, id, &replaced).unwrap();
+        assert_eq!(load::<Value, _>(root, id).unwrap(), Some(replaced));
+        assert_eq!(list(root).unwrap(), vec![id]);
+
+        assert!(remove(root, id).unwrap());
+        assert!(!remove(root, id).unwrap());
+        assert!(list(root).unwrap().is_empty());
+    }
+}
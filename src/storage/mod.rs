@@ -1,2 +1,4 @@
-pub mod meta;
+pub mod config;
+pub mod metadata;
+pub mod preview;
 pub mod prop;
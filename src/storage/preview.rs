@@ -0,0 +1,78 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::AtomicFile;
+use crate::cache::stamp_entry;
+use crate::resource::ResourceId;
+use crate::{
+    Result, ARK_FOLDER, PREVIEWS_STORAGE_FOLDER, THUMBNAILS_STORAGE_FOLDER,
+};
+
+/// Write preview bytes of the resource into the previews cache
+///
+/// Previews are generated data, so the latest version simply replaces
+/// the previous one.
+pub fn store_preview<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    data: &[u8],
+) -> Result<()> {
+    store(cache_path(root, PREVIEWS_STORAGE_FOLDER, id), id, data)
+}
+
+/// Write thumbnail bytes of the resource into the thumbnails cache,
+/// replacing the previous version like [`store_preview`]
+pub fn store_thumbnail<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    data: &[u8],
+) -> Result<()> {
+    store(cache_path(root, THUMBNAILS_STORAGE_FOLDER, id), id, data)
+}
+
+/// Read preview bytes of the resource, if generated before
+pub fn load_preview<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<Vec<u8>>> {
+    load(cache_path(root, PREVIEWS_STORAGE_FOLDER, id))
+}
+
+/// Read thumbnail bytes of the resource, if generated before
+pub fn load_thumbnail<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<Option<Vec<u8>>> {
+    load(cache_path(root, THUMBNAILS_STORAGE_FOLDER, id))
+}
+
+fn cache_path<P: AsRef<Path>>(
+    root: P,
+    folder: &str,
+    id: ResourceId,
+) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(folder)
+        .join(id.to_string())
+}
+
+fn store(path: PathBuf, id: ResourceId, data: &[u8]) -> Result<()> {
+    let file = AtomicFile::new(&path)?;
+    let tmp = file.make_temp()?;
+    (&tmp).write_all(data)?;
+    let current = file.load()?;
+    file.compare_and_swap(&current, tmp)?;
+    stamp_entry(&path, id)
+}
+
+fn load(path: PathBuf) -> Result<Option<Vec<u8>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let current = AtomicFile::new(path)?.load()?;
+    match current.version {
+        0 => Ok(None),
+        _ => Ok(Some(current.read_content()?)),
+    }
+}
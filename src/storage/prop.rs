@@ -23,13 +23,12 @@ pub fn store_properties<
             .join(PROPERTIES_STORAGE_FOLDER)
             .join(id.to_string()),
     )?;
+    // serializing fails for maps with non-string keys
+    let new_value = serde_json::to_value(properties)?;
     modify_json(&file, |current_data: &mut Option<Value>| {
-        let new_value = serde_json::to_value(properties).unwrap();
-        match current_data {
-            Some(old_data) => {
-                // Should not failed unless serialize failed which should never
-                // happen
-                let old_value = serde_json::to_value(old_data).unwrap();
+        let new_value = new_value.clone();
+        match current_data.take() {
+            Some(old_value) => {
                 *current_data = Some(merge(old_value, new_value));
             }
             None => *current_data = Some(new_value),
@@ -0,0 +1,163 @@
+//! LAN discovery of other ARK devices using mDNS/DNS-SD
+//!
+//! Every device serving a root advertises a service instance named after
+//! its app id and the root id, carrying both ids in TXT records as well.
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::{app_id, Result};
+
+pub const SERVICE_TYPE: &str = "_ark-sync._tcp.local.";
+
+const DEVICE_PROPERTY: &str = "device";
+const ROOT_PROPERTY: &str = "root";
+
+/// Another device serving a root for sync
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub device_id: String,
+    pub root_id: String,
+    /// Addresses to pass to [`super::net::pull`]
+    pub addresses: Vec<SocketAddr>,
+}
+
+pub struct Discovery {
+    daemon: ServiceDaemon,
+    device_id: String,
+    advertised: Vec<String>,
+}
+
+impl Discovery {
+    /// Starts the mDNS daemon, the app id must be initialized beforehand
+    pub fn new() -> Result<Self> {
+        let device_id = app_id::read()?;
+        let daemon = ServiceDaemon::new()?;
+        Ok(Self {
+            daemon,
+            device_id,
+            advertised: vec![],
+        })
+    }
+
+    /// Announces that this device serves the root on the given port
+    pub fn advertise(&mut self, root_id: &str, port: u16) -> Result<()> {
+        let instance = format!("{}-{}", self.device_id, root_id);
+        let host = format!("{}.local.", self.device_id);
+        let properties = [
+            (DEVICE_PROPERTY, self.device_id.as_str()),
+            (ROOT_PROPERTY, root_id),
+        ];
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance,
+            &host,
+            "",
+            port,
+            &properties[..],
+        )?
+        .enable_addr_auto();
+        self.advertised
+            .push(info.get_fullname().to_string());
+        self.daemon.register(info)?;
+        log::info!("Advertising root {} on port {}", root_id, port);
+        Ok(())
+    }
+
+    /// Collects peers which answered within the timeout,
+    /// this device itself is excluded
+    pub fn browse(&self, timeout: Duration) -> Result<Vec<Peer>> {
+        let receiver = self.daemon.browse(SERVICE_TYPE)?;
+        let deadline = Instant::now() + timeout;
+
+        let mut peers: Vec<Peer> = vec![];
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match receiver.recv_timeout(left) {
+                Ok(ServiceEvent::ServiceResolved(info)) => {
+                    if let Some(peer) = peer_from(&info) {
+                        if peer.device_id != self.device_id
+                            && !peers.contains(&peer)
+                        {
+                            log::debug!("Discovered peer {:?}", peer);
+                            peers.push(peer);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        self.daemon.stop_browse(SERVICE_TYPE)?;
+        Ok(peers)
+    }
+
+    /// Withdraws all advertisements and stops the daemon
+    pub fn shutdown(self) -> Result<()> {
+        for fullname in self.advertised.iter() {
+            self.daemon.unregister(fullname)?;
+        }
+        self.daemon.shutdown()?;
+        Ok(())
+    }
+}
+
+fn peer_from(info: &ServiceInfo) -> Option<Peer> {
+    let device_id = info.get_property_val_str(DEVICE_PROPERTY)?;
+    let root_id = info.get_property_val_str(ROOT_PROPERTY)?;
+    let mut addresses: Vec<SocketAddr> = info
+        .get_addresses()
+        .iter()
+        .map(|ip| SocketAddr::new(*ip, info.get_port()))
+        .collect();
+    addresses.sort();
+    Some(Peer {
+        device_id: device_id.to_string(),
+        root_id: root_id.to_string(),
+        addresses,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_from_service_info() {
+        let properties =
+            [(DEVICE_PROPERTY, "phone"), (ROOT_PROPERTY, "photos")];
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            "phone-photos",
+            "phone.local.",
+            "192.168.1.7",
+            4321,
+            &properties[..],
+        )
+        .unwrap();
+
+        assert_eq!(
+            peer_from(&info),
+            Some(Peer {
+                device_id: "phone".to_string(),
+                root_id: "photos".to_string(),
+                addresses: vec!["192.168.1.7:4321".parse().unwrap()],
+            })
+        );
+    }
+
+    #[test]
+    fn foreign_service_is_ignored() {
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            "printer",
+            "printer.local.",
+            "192.168.1.8",
+            631,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(peer_from(&info), None);
+    }
+}
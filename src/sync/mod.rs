@@ -0,0 +1,155 @@
+//! Synchronization of resources between devices
+//!
+//! Resources are compared by their IDs only: a resource is transferred
+//! when the receiving device doesn't have any file with the same content,
+//! no matter where the file is located.
+use std::collections::HashSet;
+
+use crate::index::ResourceIndex;
+use crate::pins::load_pins;
+use crate::resource::ResourceId;
+use crate::Result;
+
+#[cfg(feature = "net")]
+pub mod discovery;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod rules;
+
+/// Returns IDs known to the peer which are absent from the local index,
+/// preserving the order of `remote`
+pub fn missing_ids(
+    local: &ResourceIndex,
+    remote: &[ResourceId],
+) -> Vec<ResourceId> {
+    let local: HashSet<&ResourceId> = local.ids().collect();
+    let mut seen = HashSet::new();
+    remote
+        .iter()
+        .filter(|id| !local.contains(id) && seen.insert(*id))
+        .cloned()
+        .collect()
+}
+
+/// Returns IDs of the local index which the peer doesn't have, except
+/// pinned ones, for syncs mirroring deletions of the peer
+///
+/// Pinned resources are kept offline whatever peers do, see
+/// [`crate::pins`].
+pub fn deletable_ids(
+    local: &ResourceIndex,
+    remote: &[ResourceId],
+) -> Result<Vec<ResourceId>> {
+    let remote: HashSet<&ResourceId> = remote.iter().collect();
+    let pins = load_pins(local.root())?;
+    let mut ids: Vec<ResourceId> = local
+        .ids()
+        .filter(|id| !remote.contains(id) && !pins.contains(id))
+        .cloned()
+        .collect();
+    ids.sort();
+    Ok(ids)
+}
+
+/// Resolves where a resource received from elsewhere should be placed
+///
+/// The path must not escape the root or point into `.ark`. If different
+/// content occupies the path already, the resource is placed next to it
+/// prefixed by its ID.
+#[cfg(any(feature = "net", feature = "remote"))]
+pub(crate) fn destination_path(
+    root: &std::path::Path,
+    path: &str,
+    id: ResourceId,
+) -> crate::Result<std::path::PathBuf> {
+    use crate::{ArklibError, ARK_FOLDER};
+
+    if path.split('/').any(|part| part == ARK_FOLDER) {
+        return Err(ArklibError::Path(format!("Illegal path: {}", path)));
+    }
+    let mut destination = crate::util::fs::join_relative(root, path)?;
+    if destination.exists() {
+        let name = destination
+            .file_name()
+            .ok_or_else(|| ArklibError::Path(path.to_string()))?
+            .to_string_lossy()
+            .to_string();
+        destination.set_file_name(format!("{}-{}", id, name));
+    }
+    Ok(destination)
+}
+
+/// Moves the verified file to its destination unless something has
+/// appeared there meanwhile, returning whether the file was moved
+///
+/// Unlike renaming, linking fails if the destination exists. Filesystems
+/// without hard links fall back to checking the destination right before
+/// renaming.
+#[cfg(any(feature = "net", feature = "remote"))]
+pub(crate) fn place_file(
+    tmp: &std::path::Path,
+    destination: &std::path::Path,
+) -> crate::Result<bool> {
+    use std::fs;
+    use std::io::ErrorKind;
+
+    match fs::hard_link(tmp, destination) {
+        Ok(()) => {
+            fs::remove_file(tmp)?;
+            Ok(true)
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => {
+            log::debug!("Couldn't link {}: {}", destination.display(), e);
+            if fs::symlink_metadata(destination).is_ok() {
+                return Ok(false);
+            }
+            fs::rename(tmp, destination)?;
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn pinned_resources_are_never_deletable() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        let index = ResourceIndex::build(&root);
+        let a = index.get_entry(root.join("a.txt")).unwrap().id;
+        let b = index.get_entry(root.join("b.txt")).unwrap().id;
+
+        assert_eq!(deletable_ids(&index, &[a]).unwrap(), vec![b]);
+        crate::pins::pin(&root, b).unwrap();
+        assert!(deletable_ids(&index, &[a]).unwrap().is_empty());
+    }
+
+    #[cfg(any(feature = "net", feature = "remote"))]
+    #[test]
+    fn placed_files_never_replace_others() {
+        let dir = TempDir::new("arklib_test").unwrap();
+        let tmp = dir.path().join(".a.part");
+        let destination = dir.path().join("a.txt");
+        fs::write(&tmp, "received").unwrap();
+        fs::write(&destination, "created meanwhile").unwrap();
+
+        assert!(!place_file(&tmp, &destination).unwrap());
+        assert_eq!(
+            fs::read_to_string(&destination).unwrap(),
+            "created meanwhile"
+        );
+
+        fs::remove_file(&destination).unwrap();
+        assert!(place_file(&tmp, &destination).unwrap());
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "received");
+        assert!(!tmp.exists());
+    }
+}
@@ -0,0 +1,776 @@
+//! Network transport of the delta sync
+//!
+//! Peers exchange bincode-encoded messages, each prefixed with its length
+//! as big-endian `u32`. The pulling side first requests the index of the
+//! peer, then requests only the resources missing locally. Every resource
+//! is sent as a header followed by any number of data frames.
+//!
+//! Only paired devices are answered: the serving side opens every
+//! connection with a random challenge, which the pulling side must answer
+//! with a hash of it keyed by the pairing key shared by both devices,
+//! see [`new_pairing_key`]. The key itself never goes over the network.
+//!
+//! [`SyncRules`] of both roots are respected: the serving side doesn't
+//! offer excluded resources and the pulling side doesn't request them.
+//!
+//! Placeholders of files kept in the cloud are never offered, since their
+//! IDs don't depend on the content and can't be verified by the peer.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use super::rules::SyncRules;
+use super::{destination_path, missing_ids, place_file};
+use crate::resource::ResourceId;
+use crate::util::fs::relative_path;
+use crate::{ArklibError, ResourceIndexLock, Result};
+
+/// Frames larger than this are rejected as malformed
+pub const MAX_FRAME_SIZE: u32 = 4 * 1024 * 1024;
+const DATA_FRAME_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    /// Answer to [`Response::Challenge`], required before anything else
+    Authenticate([u8; 32]),
+    /// Ask for IDs of all resources of the peer
+    Index,
+    /// Ask for contents of the listed resources
+    Resources(Vec<ResourceId>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub id: ResourceId,
+    /// Path relative to the root, with `/` as separator
+    pub path: String,
+    /// Whether the ID is computed from samples of the content
+    pub sampled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    /// Random bytes sent to every new connection
+    Challenge([u8; 16]),
+    /// The answer to the challenge is wrong, the connection is closed
+    Refused,
+    Index(Vec<IndexEntry>),
+    /// Header of a resource, its content follows as [`Response::Data`]
+    Resource {
+        id: ResourceId,
+        /// Path relative to the root, with `/` as separator
+        path: String,
+        size: u64,
+        /// Whether the ID is computed from samples of the content
+        sampled: bool,
+    },
+    Data(Vec<u8>),
+    /// All requested resources have been sent
+    Done,
+}
+
+/// Generates a random key for pairing devices, to be transferred
+/// between them by the user, e.g. as a QR code
+pub fn new_pairing_key() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Answers sync requests of peers paired by the key until the listener
+/// fails
+pub async fn serve(
+    listener: TcpListener,
+    index: ResourceIndexLock,
+    pairing_key: String,
+) -> Result<()> {
+    let key = derive_key(&pairing_key);
+    loop {
+        let (stream, address) = listener.accept().await?;
+        log::debug!("Sync connection from {}", address);
+        let index = index.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, index, key).await {
+                log::warn!("Sync connection from {} failed: {}", address, e);
+            }
+        });
+    }
+}
+
+/// Fetches resources which the peer has and the local index doesn't,
+/// placing them by the same relative paths as on the peer
+///
+/// Every received file is verified against its ID before being indexed.
+/// Returns IDs of the received resources.
+pub async fn pull<A: ToSocketAddrs>(
+    address: A,
+    index: &ResourceIndexLock,
+    pairing_key: &str,
+) -> Result<Vec<ResourceId>> {
+    let root = index
+        .read()
+        .map_err(|_| lock_error())?
+        .root()
+        .to_path_buf();
+    let filter = SyncRules::load(&root)?.filter()?;
+    if !filter.syncs_content() {
+        return Ok(vec![]);
+    }
+
+    let mut stream = TcpStream::connect(address).await?;
+    let challenge = match read_frame(&mut stream).await? {
+        Some(Response::Challenge(challenge)) => challenge,
+        _ => return Err(ArklibError::Network),
+    };
+    let proof = blake3::keyed_hash(&derive_key(pairing_key), &challenge);
+    write_frame(&mut stream, &Request::Authenticate(*proof.as_bytes())).await?;
+    write_frame(&mut stream, &Request::Index).await?;
+    let remote: Vec<ResourceId> = match read_frame(&mut stream).await? {
+        Some(Response::Index(entries)) => entries
+            .into_iter()
+            .filter(|entry| filter.allows(&entry.path, &entry.id))
+            .map(|entry| entry.id)
+            .collect(),
+        _ => return Err(ArklibError::Network),
+    };
+
+    let requested = {
+        let index = index.read().map_err(|_| lock_error())?;
+        missing_ids(&index, &remote)
+    };
+    log::info!("{} resources are missing locally", requested.len());
+    if requested.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // the peer may send each requested resource once and nothing else
+    let mut missing: HashSet<ResourceId> = requested.iter().cloned().collect();
+    write_frame(&mut stream, &Request::Resources(requested)).await?;
+    let mut received = vec![];
+    let mut current: Option<Incoming> = None;
+    loop {
+        let response = read_frame(&mut stream)
+            .await?
+            .ok_or(ArklibError::Network)?;
+        match response {
+            Response::Data(data) => {
+                let incoming = current.as_mut().ok_or(ArklibError::Network)?;
+                incoming.write(&data).await?;
+            }
+            Response::Resource {
+                id,
+                path,
+                size,
+                sampled,
+            } => {
+                if let Some(incoming) = current.take() {
+                    received.extend(incoming.finish(index).await?);
+                }
+                if !missing.remove(&id) {
+                    log::warn!("Peer sent {}, which wasn't requested", id);
+                    return Err(ArklibError::Network);
+                }
+                current = Some(
+                    Incoming::create(&root, &path, id, size, sampled).await?,
+                );
+            }
+            Response::Done => {
+                if let Some(incoming) = current.take() {
+                    received.extend(incoming.finish(index).await?);
+                }
+                return Ok(received);
+            }
+            _ => return Err(ArklibError::Network),
+        }
+    }
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    index: ResourceIndexLock,
+    key: [u8; 32],
+) -> Result<()> {
+    let challenge = *uuid::Uuid::new_v4().as_bytes();
+    write_frame(&mut stream, &Response::Challenge(challenge)).await?;
+    let expected = blake3::keyed_hash(&key, &challenge);
+    match read_frame::<Request>(&mut stream).await? {
+        // comparison of hashes takes constant time
+        Some(Request::Authenticate(proof)) if expected == proof => {}
+        _ => {
+            write_frame(&mut stream, &Response::Refused).await?;
+            return Err(ArklibError::Network);
+        }
+    }
+
+    while let Some(request) = read_frame::<Request>(&mut stream).await? {
+        match request {
+            Request::Authenticate(_) => return Err(ArklibError::Network),
+            Request::Index => {
+                let (root, paths) = offered(&index, None)?;
+                let entries = paths
+                    .into_iter()
+                    .map(|resource| {
+                        Ok(IndexEntry {
+                            id: resource.id,
+                            path: relative_path(&root, &resource.path)?,
+                            sampled: resource.sampled,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                write_frame(&mut stream, &Response::Index(entries)).await?;
+            }
+            Request::Resources(ids) => {
+                let (root, paths) = offered(&index, Some(&ids))?;
+                for resource in paths {
+                    send_resource(&mut stream, &root, &resource).await?;
+                }
+                write_frame(&mut stream, &Response::Done).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resource of the serving root
+struct Offered {
+    id: ResourceId,
+    path: PathBuf,
+    sampled: bool,
+}
+
+/// Collects the resources allowed by the sync rules of the root,
+/// optionally only the requested ones
+fn offered(
+    index: &ResourceIndexLock,
+    requested: Option<&[ResourceId]>,
+) -> Result<(PathBuf, Vec<Offered>)> {
+    let index = index.read().map_err(|_| lock_error())?;
+    let root = index.root().to_path_buf();
+    let filter = SyncRules::load(&root)?.filter()?;
+    let ids: Vec<ResourceId> = match requested {
+        Some(ids) => ids.to_vec(),
+        None => index.ids().cloned().collect(),
+    };
+
+    let mut offered = vec![];
+    for id in ids {
+        let Some(path) = index.get_path(&id) else {
+            continue;
+        };
+        let Some(entry) = index.get_entry(path) else {
+            continue;
+        };
+        if !entry.remote && filter.allows(&relative_path(&root, path)?, &id) {
+            offered.push(Offered {
+                id,
+                path: path.to_path_buf(),
+                sampled: entry.sampled,
+            });
+        }
+    }
+    Ok((root, offered))
+}
+
+async fn send_resource(
+    stream: &mut TcpStream,
+    root: &Path,
+    resource: &Offered,
+) -> Result<()> {
+    let mut file = File::open(&resource.path).await?;
+    let size = file.metadata().await?.len();
+    let header = Response::Resource {
+        id: resource.id,
+        path: relative_path(root, &resource.path)?,
+        size,
+        sampled: resource.sampled,
+    };
+    write_frame(stream, &header).await?;
+
+    let mut buf = vec![0; DATA_FRAME_SIZE];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        write_frame(stream, &Response::Data(buf[..read].to_vec())).await?;
+    }
+}
+
+/// A resource being received into a hidden temporary file, which is
+/// invisible to the index until verified
+///
+/// The temporary file is removed when the resource is dropped before
+/// being verified, e.g. because the peer sent too much data or the
+/// connection failed.
+struct Incoming {
+    id: ResourceId,
+    /// Size announced by the peer
+    size: u64,
+    sampled: bool,
+    written: u64,
+    tmp: PathBuf,
+    destination: PathBuf,
+    file: Option<File>,
+}
+
+impl Incoming {
+    async fn create(
+        root: &Path,
+        path: &str,
+        id: ResourceId,
+        size: u64,
+        sampled: bool,
+    ) -> Result<Self> {
+        if size != id.data_size {
+            return Err(ArklibError::SizeMismatch {
+                expected: id.data_size,
+                actual: size,
+            });
+        }
+        let destination = destination_path(root, path, id)?;
+        let parent = destination
+            .parent()
+            .ok_or_else(|| ArklibError::Path(path.to_string()))?;
+        fs::create_dir_all(parent).await?;
+        let tmp = parent.join(format!(".{}.part", id));
+        let file = File::create(&tmp).await?;
+        Ok(Self {
+            id,
+            size,
+            sampled,
+            written: 0,
+            tmp,
+            destination,
+            file: Some(file),
+        })
+    }
+
+    /// Appends the data, refusing anything beyond the announced size
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        let written = self.written + data.len() as u64;
+        if written > self.size {
+            return Err(ArklibError::SizeMismatch {
+                expected: self.size,
+                actual: written,
+            });
+        }
+        let file = self.file.as_mut().ok_or(ArklibError::Network)?;
+        file.write_all(data).await?;
+        self.written = written;
+        Ok(())
+    }
+
+    async fn finish(
+        mut self,
+        index: &ResourceIndexLock,
+    ) -> Result<Option<ResourceId>> {
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+        }
+        if self.written != self.size {
+            return Err(ArklibError::SizeMismatch {
+                expected: self.size,
+                actual: self.written,
+            });
+        }
+
+        let options = index
+            .read()
+            .map_err(|_| lock_error())?
+            .options()
+            .clone();
+        let (sampled, tmp) = (self.sampled, self.tmp.clone());
+        let actual = tokio::task::spawn_blocking(move || {
+            options.content_id(&tmp, sampled)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        if actual != self.id {
+            log::warn!("Received {} has id {}, discarding", self.id, actual);
+            return Ok(None);
+        }
+
+        let mut index = index.write().map_err(|_| lock_error())?;
+        if !place_file(&self.tmp, &self.destination)? {
+            log::warn!(
+                "{} appeared meanwhile, discarding received {}",
+                self.destination.display(),
+                self.id
+            );
+            return Ok(None);
+        }
+        index.index_new(&self.destination)?;
+        Ok(Some(self.id))
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        // closed first, Windows can't delete open files
+        drop(self.file.take());
+        if let Err(e) = std::fs::remove_file(&self.tmp) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Couldn't remove {}: {}", self.tmp.display(), e);
+            }
+        }
+    }
+}
+
+async fn write_frame<T: Serialize>(
+    stream: &mut TcpStream,
+    message: &T,
+) -> Result<()> {
+    let bytes = bincode::serialize(message)?;
+    let length = u32::try_from(bytes.len())
+        .ok()
+        .filter(|length| *length <= MAX_FRAME_SIZE)
+        .ok_or(ArklibError::Network)?;
+    stream.write_u32(length).await?;
+    stream.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Returns `None` if the peer has closed the connection
+async fn read_frame<T: DeserializeOwned>(
+    stream: &mut TcpStream,
+) -> Result<Option<T>> {
+    let length = match stream.read_u32().await {
+        Ok(length) => length,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if length > MAX_FRAME_SIZE {
+        return Err(ArklibError::Network);
+    }
+    let mut bytes = vec![0; length as usize];
+    stream.read_exact(&mut bytes).await?;
+    Ok(Some(bincode::deserialize(&bytes)?))
+}
+
+fn derive_key(pairing_key: &str) -> [u8; 32] {
+    blake3::derive_key("arklib sync pairing key", pairing_key.as_bytes())
+}
+
+fn lock_error() -> ArklibError {
+    ArklibError::Other(anyhow!("Could not lock the index"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{IndexOptions, ResourceIndex};
+    use crate::resource::{Namespace, ResourceIdTrait};
+    use std::sync::{Arc, RwLock};
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn pull_transfers_only_missing_resources() {
+        let remote_dir = TempDir::new("arklib_test").unwrap();
+        let local_dir = TempDir::new("arklib_test").unwrap();
+
+        std::fs::write(remote_dir.path().join("a.txt"), "shared").unwrap();
+        std::fs::create_dir(remote_dir.path().join("sub")).unwrap();
+        std::fs::write(remote_dir.path().join("sub/b.txt"), "remote only")
+            .unwrap();
+        std::fs::write(local_dir.path().join("renamed.txt"), "shared").unwrap();
+
+        let remote = ResourceIndex::build(remote_dir.path());
+        let remote: ResourceIndexLock = Arc::new(RwLock::new(remote));
+        let local = ResourceIndex::build(local_dir.path());
+        let local: ResourceIndexLock = Arc::new(RwLock::new(local));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let key = new_pairing_key();
+        tokio::spawn(serve(listener, remote, key.clone()));
+
+        let received = pull(address, &local, &key).await.unwrap();
+        let expected = ResourceId::compute_bytes(b"remote only").unwrap();
+        assert_eq!(received, vec![expected]);
+        assert_eq!(
+            std::fs::read_to_string(local_dir.path().join("sub/b.txt"))
+                .unwrap(),
+            "remote only"
+        );
+        assert!(!local_dir.path().join("a.txt").exists());
+        assert_eq!(local.read().unwrap().count_resources(), 2);
+
+        // nothing is missing anymore
+        assert!(pull(address, &local, &key)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn unpaired_peers_are_refused() {
+        let remote_dir = TempDir::new("arklib_test").unwrap();
+        let local_dir = TempDir::new("arklib_test").unwrap();
+        std::fs::write(remote_dir.path().join("a.txt"), "secret").unwrap();
+
+        let remote = ResourceIndex::build(remote_dir.path());
+        let remote: ResourceIndexLock = Arc::new(RwLock::new(remote));
+        let local = ResourceIndex::build(local_dir.path());
+        let local: ResourceIndexLock = Arc::new(RwLock::new(local));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, remote, new_pairing_key()));
+
+        assert!(pull(address, &local, &new_pairing_key())
+            .await
+            .is_err());
+        assert!(!local_dir.path().join("a.txt").exists());
+
+        // peers skipping the challenge get nothing either
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let challenge = read_frame::<Response>(&mut stream).await.unwrap();
+        assert!(matches!(challenge, Some(Response::Challenge(_))));
+        write_frame(&mut stream, &Request::Index)
+            .await
+            .unwrap();
+        let response = read_frame::<Response>(&mut stream).await.unwrap();
+        assert!(matches!(response, Some(Response::Refused)));
+        assert!(read_frame::<Response>(&mut stream)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn pull_verifies_namespaced_ids() {
+        let remote_dir = TempDir::new("arklib_test").unwrap();
+        let local_dir = TempDir::new("arklib_test").unwrap();
+        std::fs::write(remote_dir.path().join("a.txt"), "namespaced").unwrap();
+
+        // both devices share the namespace of the root
+        let options = IndexOptions {
+            namespace: Some(Namespace::new(uuid::Uuid::new_v4())),
+            ..IndexOptions::default()
+        };
+        let remote = ResourceIndex::build_with_options(
+            remote_dir.path(),
+            options.clone(),
+        );
+        let remote: ResourceIndexLock = Arc::new(RwLock::new(remote));
+        let local =
+            ResourceIndex::build_with_options(local_dir.path(), options);
+        let local: ResourceIndexLock = Arc::new(RwLock::new(local));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let key = new_pairing_key();
+        tokio::spawn(serve(listener, remote.clone(), key.clone()));
+
+        let received = pull(address, &local, &key).await.unwrap();
+        let expected = remote
+            .read()
+            .unwrap()
+            .ids()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(received, expected);
+        assert!(local_dir.path().join("a.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn pull_verifies_sampled_ids() {
+        let remote_dir = TempDir::new("arklib_test").unwrap();
+        let local_dir = TempDir::new("arklib_test").unwrap();
+        std::fs::write(remote_dir.path().join("a.bin"), vec![7; 64 * 1024])
+            .unwrap();
+
+        let options = IndexOptions {
+            sampled_hashing_threshold: Some(1024),
+            ..IndexOptions::default()
+        };
+        let remote =
+            ResourceIndex::build_with_options(remote_dir.path(), options);
+        let remote: ResourceIndexLock = Arc::new(RwLock::new(remote));
+        // the local root hashes everything completely
+        let local = ResourceIndex::build(local_dir.path());
+        let local: ResourceIndexLock = Arc::new(RwLock::new(local));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let key = new_pairing_key();
+        tokio::spawn(serve(listener, remote.clone(), key.clone()));
+
+        let received = pull(address, &local, &key).await.unwrap();
+        let expected = remote
+            .read()
+            .unwrap()
+            .ids()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(received, expected);
+        let full = ResourceId::compute_bytes(&vec![7; 64 * 1024]).unwrap();
+        assert_ne!(received, vec![full]);
+        assert!(local_dir.path().join("a.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn placeholders_are_not_offered() {
+        let remote_dir = TempDir::new("arklib_test").unwrap();
+        let local_dir = TempDir::new("arklib_test").unwrap();
+        let path = remote_dir.path().join("online.bin");
+        std::fs::File::create(&path)
+            .unwrap()
+            .set_len(1024 * 1024)
+            .unwrap();
+        std::fs::write(remote_dir.path().join("a.txt"), "local").unwrap();
+
+        let remote = ResourceIndex::build(remote_dir.path());
+        if remote.remote_paths().count() == 0 {
+            // the filesystem doesn't support sparse files
+            return;
+        }
+        let remote: ResourceIndexLock = Arc::new(RwLock::new(remote));
+        let local = ResourceIndex::build(local_dir.path());
+        let local: ResourceIndexLock = Arc::new(RwLock::new(local));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let key = new_pairing_key();
+        tokio::spawn(serve(listener, remote, key.clone()));
+
+        let received = pull(address, &local, &key).await.unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(!local_dir.path().join("online.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn pull_respects_sync_rules() {
+        crate::initialize();
+
+        let remote_dir = TempDir::new("arklib_test").unwrap();
+        let local_dir = TempDir::new("arklib_test").unwrap();
+        std::fs::write(remote_dir.path().join("notes.txt"), "text").unwrap();
+        std::fs::write(remote_dir.path().join("debug.log"), "log").unwrap();
+
+        let rules = SyncRules {
+            exclude: vec!["*.log".to_string()],
+            ..Default::default()
+        };
+        rules.store(local_dir.path()).unwrap();
+
+        let remote = ResourceIndex::build(remote_dir.path());
+        let remote: ResourceIndexLock = Arc::new(RwLock::new(remote));
+        let local = ResourceIndex::build(local_dir.path());
+        let local: ResourceIndexLock = Arc::new(RwLock::new(local));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let key = new_pairing_key();
+        tokio::spawn(serve(listener, remote, key.clone()));
+
+        let received = pull(address, &local, &key).await.unwrap();
+        let expected = ResourceId::compute_bytes(b"text").unwrap();
+        assert_eq!(received, vec![expected]);
+        assert!(!local_dir.path().join("debug.log").exists());
+    }
+
+    /// Peer offering the resource and answering any request for it
+    /// with the frames
+    fn serve_forged(
+        listener: TcpListener,
+        offered: ResourceId,
+        frames: Vec<Response>,
+    ) {
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            write_frame(&mut stream, &Response::Challenge([0; 16]))
+                .await
+                .unwrap();
+            read_frame::<Request>(&mut stream).await.unwrap();
+            read_frame::<Request>(&mut stream).await.unwrap();
+            let entries = vec![IndexEntry {
+                id: offered,
+                path: "small.txt".to_string(),
+                sampled: false,
+            }];
+            write_frame(&mut stream, &Response::Index(entries))
+                .await
+                .unwrap();
+            read_frame::<Request>(&mut stream).await.unwrap();
+            for frame in frames {
+                // the puller may hang up early
+                let _ = write_frame(&mut stream, &frame).await;
+            }
+        });
+    }
+
+    fn header(id: ResourceId, path: &str) -> Response {
+        Response::Resource {
+            id,
+            path: path.to_string(),
+            size: id.data_size,
+            sampled: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn pull_rejects_data_beyond_announced_size() {
+        let local_dir = TempDir::new("arklib_test").unwrap();
+        let local = ResourceIndex::build(local_dir.path());
+        let local: ResourceIndexLock = Arc::new(RwLock::new(local));
+
+        let id = ResourceId::compute_bytes(b"small").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut frames = vec![header(id, "small.txt")];
+        for _ in 0..3 {
+            frames.push(Response::Data(b"much more than announced".to_vec()));
+        }
+        serve_forged(listener, id, frames);
+
+        assert!(pull(address, &local, "key").await.is_err());
+        let leftovers = std::fs::read_dir(local_dir.path())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(".part")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+        assert!(!local_dir.path().join("small.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn pull_rejects_resources_which_werent_requested() {
+        let local_dir = TempDir::new("arklib_test").unwrap();
+        let local = ResourceIndex::build(local_dir.path());
+        let local: ResourceIndexLock = Arc::new(RwLock::new(local));
+
+        let id = ResourceId::compute_bytes(b"small").unwrap();
+        let other = ResourceId::compute_bytes(b"other").unwrap();
+        let unrequested = vec![
+            header(other, "other.txt"),
+            Response::Data(b"other".to_vec()),
+            Response::Done,
+        ];
+        let repeated = vec![
+            header(id, "small.txt"),
+            Response::Data(b"small".to_vec()),
+            header(id, "again.txt"),
+            Response::Data(b"small".to_vec()),
+            Response::Done,
+        ];
+        for frames in [unrequested, repeated] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            serve_forged(listener, id, frames);
+            assert!(pull(address, &local, "key").await.is_err());
+        }
+        assert!(!local_dir.path().join("other.txt").exists());
+        assert!(!local_dir.path().join("again.txt").exists());
+    }
+}
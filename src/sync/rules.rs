@@ -0,0 +1,174 @@
+//! Per-root rules restricting which resources are synced
+//!
+//! Rules are stored in the `sync` section of `.ark/config`. They apply
+//! to both directions: resources excluded by the rules of a root are
+//! neither sent from it nor received into it. User data is not affected
+//! by the rules, so a device syncing only user data still sees tags,
+//! scores and properties of all resources.
+use std::path::Path;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::resource::ResourceId;
+use crate::storage::config::{load_section, store_section};
+use crate::Result;
+
+const CONFIG_SECTION: &str = "sync";
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum SyncMode {
+    /// User data and content of resources
+    #[default]
+    Full,
+    /// User data only, content of resources is never transferred
+    UserDataOnly,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncRules {
+    pub mode: SyncMode,
+    /// Globs matched against paths relative to the root,
+    /// all paths are included if empty
+    pub include: Vec<String>,
+    /// Globs of paths excluded even if they are included
+    pub exclude: Vec<String>,
+    /// Larger resources are not synced
+    pub max_file_size: Option<u64>,
+}
+
+impl SyncRules {
+    /// Loads rules of the root, which are permissive if never stored
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Self> {
+        Ok(load_section(root, CONFIG_SECTION)?.unwrap_or_default())
+    }
+
+    pub fn store<P: AsRef<Path>>(&self, root: P) -> Result<()> {
+        store_section(root, CONFIG_SECTION, self)
+    }
+
+    /// Compiles the globs, failing if any of them is malformed
+    pub fn filter(&self) -> Result<SyncFilter> {
+        let include = if self.include.is_empty() {
+            None
+        } else {
+            Some(glob_set(&self.include)?)
+        };
+        Ok(SyncFilter {
+            mode: self.mode,
+            include,
+            exclude: glob_set(&self.exclude)?,
+            max_file_size: self.max_file_size,
+        })
+    }
+}
+
+/// Compiled [`SyncRules`]
+pub struct SyncFilter {
+    mode: SyncMode,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    max_file_size: Option<u64>,
+}
+
+impl SyncFilter {
+    /// Whether content of resources is synced at all
+    pub fn syncs_content(&self) -> bool {
+        self.mode == SyncMode::Full
+    }
+
+    /// Checks a resource by its path relative to the root,
+    /// with `/` as separator
+    pub fn allows(&self, path: &str, id: &ResourceId) -> bool {
+        if !self.syncs_content() {
+            return false;
+        }
+        if let Some(max) = self.max_file_size {
+            if id.data_size > max {
+                return false;
+            }
+        }
+        if let Some(include) = &self.include {
+            if !include.is_match(path) {
+                return false;
+            }
+        }
+        !self.exclude.is_match(path)
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::initialize;
+
+    use super::*;
+    use tempdir::TempDir;
+
+    fn id(data_size: u64) -> ResourceId {
+        ResourceId {
+            data_size,
+            hash: 0x342a3d4a,
+        }
+    }
+
+    #[test]
+    fn rules_are_persisted() {
+        initialize();
+
+        let dir = TempDir::new("arklib_test").unwrap();
+        assert_eq!(SyncRules::load(dir.path()).unwrap(), SyncRules::default());
+
+        let rules = SyncRules {
+            mode: SyncMode::UserDataOnly,
+            ..Default::default()
+        };
+        rules.store(dir.path()).unwrap();
+        assert_eq!(SyncRules::load(dir.path()).unwrap(), rules);
+    }
+
+    #[test]
+    fn filter_by_globs_and_size() {
+        let rules = SyncRules {
+            include: vec!["photos/**".to_string(), "*.pdf".to_string()],
+            exclude: vec!["**/*.raw".to_string()],
+            max_file_size: Some(1000),
+            ..Default::default()
+        };
+        let filter = rules.filter().unwrap();
+        assert!(filter.allows("photos/2023/cat.jpg", &id(10)));
+        assert!(filter.allows("books/guide.pdf", &id(10)));
+        assert!(!filter.allows("photos/2023/cat.raw", &id(10)));
+        assert!(!filter.allows("notes/todo.txt", &id(10)));
+        assert!(!filter.allows("photos/2023/cat.jpg", &id(1001)));
+
+        let rules = SyncRules {
+            mode: SyncMode::UserDataOnly,
+            ..Default::default()
+        };
+        assert!(!rules.filter().unwrap().allows("a.txt", &id(1)));
+        assert!(SyncRules::default()
+            .filter()
+            .unwrap()
+            .allows("a.txt", &id(1)));
+    }
+
+    #[test]
+    fn malformed_glob_is_rejected() {
+        let rules = SyncRules {
+            exclude: vec!["photos/[".to_string()],
+            ..Default::default()
+        };
+        assert!(rules.filter().is_err());
+    }
+}
@@ -0,0 +1,469 @@
+//! Tags of resources
+//!
+//! Tags are user data, all of them are kept in a single [`AtomicFile`]
+//! mapping IDs to sets of tags. Tags are trimmed, and empty tags are
+//! ignored.
+//!
+//! Tag pickers complete tags from a [`TagTrie`] cached per root, which is
+//! rebuilt only when a new version of the tag storage is written.
+//!
+//! Bulk operations are recorded into a journal as their inverse, so the
+//! last ones can be undone with [`undo_bulk`].
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::resource::ResourceId;
+use crate::{Result, ARK_FOLDER, TAG_JOURNAL_FILE, TAG_STORAGE_FILE};
+
+pub type Tag = String;
+pub type Tags = BTreeSet<Tag>;
+
+/// Maximum amount of bulk operations which can be undone
+pub const JOURNAL_LENGTH: usize = 20;
+
+lazy_static! {
+    /// Tries of roots with the versions of tag storages they were built from
+    static ref TRIES: Mutex<HashMap<PathBuf, (usize, Arc<TagTrie>)>> =
+        Mutex::new(HashMap::new());
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct TagStorage {
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    pub(crate) tags: BTreeMap<ResourceId, Tags>,
+}
+
+/// Loads tags of all resources of the root
+pub fn load_tags<P: AsRef<Path>>(
+    root: P,
+) -> Result<BTreeMap<ResourceId, Tags>> {
+    let path = storage_path(root);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    match AtomicFile::new(path)?.load()?.open()? {
+        Some(file) => {
+            let storage: TagStorage =
+                serde_json::from_reader(std::io::BufReader::new(file))?;
+            Ok(storage.tags)
+        }
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Counts resources of every tag, the most used tags first
+pub fn cloud<P: AsRef<Path>>(root: P) -> Result<Vec<(Tag, usize)>> {
+    Ok(trie(root)?.cloud())
+}
+
+/// Finds at most `limit` tags starting with the prefix, ignoring case,
+/// the most used tags first
+pub fn autocomplete<P: AsRef<Path>>(
+    root: P,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<(Tag, usize)>> {
+    Ok(trie(root)?.complete(prefix, limit))
+}
+
+/// Returns the cached trie of the root, rebuilding it if the tag storage
+/// has changed since the trie was built
+fn trie<P: AsRef<Path>>(root: P) -> Result<Arc<TagTrie>> {
+    let path = storage_path(&root);
+    let version = match path.exists() {
+        true => AtomicFile::new(&path)?.load()?.version,
+        false => 0,
+    };
+    let mut tries = TRIES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((built, trie)) = tries.get(&path) {
+        if *built == version {
+            return Ok(trie.clone());
+        }
+    }
+    let trie = Arc::new(TagTrie::build(&load_tags(root)?));
+    tries.insert(path, (version, trie.clone()));
+    Ok(trie)
+}
+
+/// Prefix tree of lowercased tags
+#[derive(Debug, Default)]
+pub struct TagTrie {
+    children: BTreeMap<char, TagTrie>,
+    /// Tags ending at this node with their usage counts, tags differing
+    /// only in case end at the same node
+    tags: Vec<(Tag, usize)>,
+}
+
+impl TagTrie {
+    pub fn build(storage: &BTreeMap<ResourceId, Tags>) -> Self {
+        let mut counts: BTreeMap<&Tag, usize> = BTreeMap::new();
+        for tag in storage.values().flatten() {
+            *counts.entry(tag).or_default() += 1;
+        }
+        let mut trie = TagTrie::default();
+        for (tag, count) in counts {
+            let mut node = &mut trie;
+            for c in tag.chars().flat_map(char::to_lowercase) {
+                node = node.children.entry(c).or_default();
+            }
+            node.tags.push((tag.clone(), count));
+        }
+        trie
+    }
+
+    /// All tags of the trie, the most used first
+    pub fn cloud(&self) -> Vec<(Tag, usize)> {
+        let mut tags = vec![];
+        self.collect(&mut tags);
+        sort_by_usage(&mut tags);
+        tags
+    }
+
+    pub fn complete(&self, prefix: &str, limit: usize) -> Vec<(Tag, usize)> {
+        let mut node = self;
+        for c in prefix.chars().flat_map(char::to_lowercase) {
+            match node.children.get(&c) {
+                Some(child) => node = child,
+                None => return vec![],
+            }
+        }
+        let mut tags = node.cloud();
+        tags.truncate(limit);
+        tags
+    }
+
+    fn collect(&self, tags: &mut Vec<(Tag, usize)>) {
+        tags.extend(self.tags.iter().cloned());
+        for child in self.children.values() {
+            child.collect(tags);
+        }
+    }
+}
+
+fn sort_by_usage(tags: &mut [(Tag, usize)]) {
+    tags.sort_by(|(a, a_count), (b, b_count)| {
+        b_count.cmp(a_count).then_with(|| a.cmp(b))
+    });
+}
+
+pub fn tags_of<P: AsRef<Path>>(root: P, id: ResourceId) -> Result<Tags> {
+    Ok(load_tags(root)?.remove(&id).unwrap_or_default())
+}
+
+pub fn add_tags<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    tags: &[Tag],
+) -> Result<()> {
+    modify_tags(root, |storage| {
+        let current = storage.entry(id).or_default();
+        current.extend(tags.iter().filter_map(|tag| normalize(tag)));
+        if current.is_empty() {
+            storage.remove(&id);
+        }
+    })
+}
+
+pub fn remove_tags<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    tags: &[Tag],
+) -> Result<()> {
+    modify_tags(root, |storage| {
+        if let Some(current) = storage.get_mut(&id) {
+            for tag in tags.iter().filter_map(|tag| normalize(tag)) {
+                current.remove(&tag);
+            }
+            if current.is_empty() {
+                storage.remove(&id);
+            }
+        }
+    })
+}
+
+/// Replaces all tags of the resource
+pub fn set_tags<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    tags: &[Tag],
+) -> Result<()> {
+    let tags: Tags = tags
+        .iter()
+        .filter_map(|tag| normalize(tag))
+        .collect();
+    modify_tags(root, |storage| match tags.is_empty() {
+        true => {
+            storage.remove(&id);
+        }
+        false => {
+            storage.insert(id, tags.clone());
+        }
+    })
+}
+
+/// Trims the tag, returning `None` for blank tags
+pub fn normalize(tag: &str) -> Option<Tag> {
+    let tag = tag.trim();
+    (!tag.is_empty()).then(|| tag.to_string())
+}
+
+/// Tags added to and removed from one resource
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagChange {
+    pub added: Tags,
+    pub removed: Tags,
+}
+
+/// Bulk operation, with changes listing only tags which were actually
+/// added or removed
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkTagging {
+    pub id: String,
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    pub changes: BTreeMap<ResourceId, TagChange>,
+}
+
+impl BulkTagging {
+    /// Operation reverting this one
+    pub fn inverse(&self) -> BulkTagging {
+        let changes = self
+            .changes
+            .iter()
+            .map(|(id, change)| {
+                let change = TagChange {
+                    added: change.removed.clone(),
+                    removed: change.added.clone(),
+                };
+                (*id, change)
+            })
+            .collect();
+        BulkTagging {
+            id: self.id.clone(),
+            changes,
+        }
+    }
+}
+
+/// Adds and removes tags of all the resources in one write of the tag
+/// storage, and records the inverse operation into the undo journal
+///
+/// Tags present in both `add` and `remove` are removed.
+pub fn apply_bulk<P: AsRef<Path>>(
+    root: P,
+    ids: &[ResourceId],
+    add: Vec<Tag>,
+    remove: Vec<Tag>,
+) -> Result<BulkTagging> {
+    let changes: BTreeMap<ResourceId, TagChange> = ids
+        .iter()
+        .map(|id| {
+            let change = TagChange {
+                added: add
+                    .iter()
+                    .filter_map(|tag| normalize(tag))
+                    .collect(),
+                removed: remove
+                    .iter()
+                    .filter_map(|tag| normalize(tag))
+                    .collect(),
+            };
+            (*id, change)
+        })
+        .collect();
+    let operation = BulkTagging {
+        id: uuid::Uuid::new_v4().to_string(),
+        changes,
+    };
+    let applied = apply(&root, &operation)?;
+
+    let file = AtomicFile::new(journal_path(&root))?;
+    let inverse = applied.inverse();
+    modify_json(&file, |journal: &mut Option<Vec<BulkTagging>>| {
+        let journal = journal.get_or_insert_with(Vec::new);
+        journal.push(inverse.clone());
+        let excess = journal.len().saturating_sub(JOURNAL_LENGTH);
+        journal.drain(..excess);
+    })?;
+    Ok(applied)
+}
+
+/// Reverts the last bulk operation recorded in the journal,
+/// returning the reverted operation
+pub fn undo_bulk<P: AsRef<Path>>(root: P) -> Result<Option<BulkTagging>> {
+    let file = AtomicFile::new(journal_path(&root))?;
+    let mut last = None;
+    modify_json(&file, |journal: &mut Option<Vec<BulkTagging>>| {
+        last = journal.get_or_insert_with(Vec::new).pop();
+    })?;
+    match last {
+        Some(inverse) => {
+            apply(&root, &inverse)?;
+            Ok(Some(inverse.inverse()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Applies the operation, returning the changes which took effect
+fn apply<P: AsRef<Path>>(
+    root: P,
+    operation: &BulkTagging,
+) -> Result<BulkTagging> {
+    let mut applied = BTreeMap::new();
+    modify_tags(root, |storage| {
+        // the operator is repeated if the storage was changed concurrently
+        applied.clear();
+        for (id, change) in &operation.changes {
+            let current = storage.entry(*id).or_default();
+            let mut effect = TagChange::default();
+            for tag in change.added.difference(&change.removed) {
+                if current.insert(tag.clone()) {
+                    effect.added.insert(tag.clone());
+                }
+            }
+            for tag in &change.removed {
+                if current.remove(tag) {
+                    effect.removed.insert(tag.clone());
+                }
+            }
+            if current.is_empty() {
+                storage.remove(id);
+            }
+            if effect != TagChange::default() {
+                applied.insert(*id, effect);
+            }
+        }
+    })?;
+    Ok(BulkTagging {
+        id: operation.id.clone(),
+        changes: applied,
+    })
+}
+
+pub(crate) fn modify_tags<P: AsRef<Path>>(
+    root: P,
+    mut operator: impl FnMut(&mut BTreeMap<ResourceId, Tags>),
+) -> Result<()> {
+    let file = AtomicFile::new(storage_path(root))?;
+    modify_json(&file, |storage: &mut Option<TagStorage>| {
+        operator(
+            &mut storage
+                .get_or_insert_with(TagStorage::default)
+                .tags,
+        );
+    })?;
+    Ok(())
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(TAG_STORAGE_FILE)
+}
+
+fn journal_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(TAG_JOURNAL_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn tags_are_added_and_removed() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let id = ResourceId {
+            hash: 1,
+            data_size: 1,
+        };
+        assert!(tags_of(root, id).unwrap().is_empty());
+
+        let tags = ["travel ".to_string(), " ".to_string(), "sea".to_string()];
+        add_tags(root, id, &tags).unwrap();
+        assert_eq!(
+            tags_of(root, id).unwrap(),
+            ["sea".into(), "travel".into()].into()
+        );
+        remove_tags(root, id, &["sea".to_string()]).unwrap();
+        assert_eq!(tags_of(root, id).unwrap(), ["travel".into()].into());
+
+        set_tags(root, id, &[]).unwrap();
+        assert!(load_tags(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tags_are_completed() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        assert!(cloud(root).unwrap().is_empty());
+        for (hash, tags) in [
+            (1, vec!["travel", "Trip"]),
+            (2, vec!["travel", "tree"]),
+            (3, vec!["travel", "trip", "sea"]),
+        ] {
+            let id = ResourceId { hash, data_size: 1 };
+            let tags: Vec<Tag> = tags.into_iter().map(Tag::from).collect();
+            set_tags(root, id, &tags).unwrap();
+        }
+
+        let count = |tag: &str, count| (tag.to_string(), count);
+        assert_eq!(cloud(root).unwrap()[0], count("travel", 3));
+        assert_eq!(
+            autocomplete(root, "TR", 3).unwrap(),
+            [count("travel", 3), count("Trip", 1), count("tree", 1)]
+        );
+        assert_eq!(autocomplete(root, "tri", 5).unwrap().len(), 2);
+        assert!(autocomplete(root, "x", 5).unwrap().is_empty());
+
+        // the cached trie is rebuilt after the storage is changed
+        let id = ResourceId {
+            hash: 4,
+            data_size: 1,
+        };
+        add_tags(root, id, &["sea".to_string()]).unwrap();
+        assert_eq!(autocomplete(root, "s", 1).unwrap(), [count("sea", 2)]);
+    }
+
+    #[test]
+    fn bulk_tagging_is_undone() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = dir.path();
+        let ids: Vec<ResourceId> = (1..=3)
+            .map(|hash| ResourceId { hash, data_size: 1 })
+            .collect();
+        add_tags(root, ids[0], &["sea".into(), "draft".into()]).unwrap();
+        add_tags(root, ids[1], &["draft".into()]).unwrap();
+        let before = load_tags(root).unwrap();
+        assert_eq!(undo_bulk(root).unwrap(), None);
+
+        let operation =
+            apply_bulk(root, &ids, vec!["sea".into()], vec!["draft".into()])
+                .unwrap();
+        // the first resource was tagged with `sea` already
+        assert!(operation.changes[&ids[0]].added.is_empty());
+        assert_eq!(operation.changes[&ids[2]].added, ["sea".into()].into());
+        for id in &ids {
+            assert_eq!(tags_of(root, *id).unwrap(), ["sea".into()].into());
+        }
+
+        let undone = undo_bulk(root).unwrap().unwrap();
+        assert_eq!(undone, operation);
+        assert_eq!(load_tags(root).unwrap(), before);
+        assert_eq!(undo_bulk(root).unwrap(), None);
+    }
+}
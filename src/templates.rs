@@ -0,0 +1,277 @@
+//! Creating resources from templates
+//!
+//! Any indexed resource can serve as a template. Copies of text templates
+//! have their `{{placeholders}}` substituted by parameters, other files are
+//! copied as they are. Defaults applied to created resources, like tags
+//! and properties, are registered per template in `user/templates`.
+//! Editing a template changes its ID, so it has to be registered again.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::index::RelativePath;
+use crate::notes::find_id;
+use crate::query::Kind;
+use crate::resource::ResourceId;
+use crate::storage::prop::store_properties;
+use crate::tags::{add_tags, Tag};
+use crate::util::fs::{safe_file_name, unique_path, write_file};
+use crate::{
+    provide_index, ArklibError, Result, ARK_FOLDER, TEMPLATES_STORAGE_FILE,
+};
+
+/// Defaults of resources created from a template, all of them
+/// may contain placeholders
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Template {
+    /// Name of created files, the name of the template by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    /// Folder of created files, the root by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<RelativePath>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<Tag>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, String>,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+struct Templates {
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    templates: BTreeMap<ResourceId, Template>,
+}
+
+/// Loads defaults of all registered templates
+pub fn load_templates<P: AsRef<Path>>(
+    root: P,
+) -> Result<BTreeMap<ResourceId, Template>> {
+    let path = storage_path(root);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    match AtomicFile::new(path)?.load()?.open()? {
+        Some(file) => {
+            let templates: Templates =
+                serde_json::from_reader(std::io::BufReader::new(file))?;
+            Ok(templates.templates)
+        }
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Registers the resource as a template or replaces its defaults
+pub fn register_template<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    template: &Template,
+) -> Result<()> {
+    modify(root, |templates| {
+        templates.insert(id, template.clone());
+    })
+}
+
+/// Removes the defaults of the template, returning whether it existed
+pub fn unregister_template<P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+) -> Result<bool> {
+    let mut removed = false;
+    modify(root, |templates| {
+        removed = templates.remove(&id).is_some();
+    })?;
+    Ok(removed)
+}
+
+/// Substitutes `{{name}}` placeholders, spaces inside of the braces are
+/// allowed and unknown placeholders are kept
+pub fn substitute(text: &str, params: &BTreeMap<String, String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find("}}") else {
+            break;
+        };
+        match params.get(rest[2..end].trim()) {
+            Some(value) => result.push_str(value),
+            None => result.push_str(&rest[..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Copies the template into a new resource and adds it to the index
+/// of the root, applying the defaults of the template
+///
+/// Besides `params`, the placeholders `date` and `time` are replaced by
+/// the current local date and time. Returns the path and the ID of the
+/// created resource.
+pub fn create_from_template<P: AsRef<Path>>(
+    root: P,
+    template_id: ResourceId,
+    params: &BTreeMap<String, String>,
+) -> Result<(PathBuf, ResourceId)> {
+    let template = load_templates(&root)?
+        .remove(&template_id)
+        .unwrap_or_default();
+    let now = chrono::Local::now();
+    let mut params = params.clone();
+    for (name, format) in [("date", "%Y-%m-%d"), ("time", "%H:%M")] {
+        params
+            .entry(name.to_string())
+            .or_insert_with(|| now.format(format).to_string());
+    }
+
+    let index = provide_index(&root)?;
+    let mut index = index.write().unwrap_or_else(|e| e.into_inner());
+    let source = index
+        .get_path(&template_id)
+        .map(Path::to_path_buf)
+        .ok_or_else(|| {
+            ArklibError::Path(format!(
+                "Template {} is not indexed",
+                template_id
+            ))
+        })?;
+    let data = fs::read(&source)?;
+    let data = match Kind::of(&source) {
+        Kind::Text => match String::from_utf8(data) {
+            Ok(text) => substitute(&text, &params).into_bytes(),
+            Err(e) => e.into_bytes(),
+        },
+        _ => data,
+    };
+
+    let folder = match &template.folder {
+        Some(folder) => index.absolute_path(folder),
+        None => index.root().to_path_buf(),
+    };
+    let file_name = match &template.file_name {
+        Some(file_name) => safe_file_name(&substitute(file_name, &params)),
+        None => source
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    let path = unique_path(&folder, &file_name);
+
+    write_file(&path, &data)?;
+    let update = index.index_new(&path)?;
+    let id = find_id(&update, &path)?;
+    drop(index);
+
+    if !template.tags.is_empty() {
+        let tags: Vec<Tag> = template
+            .tags
+            .iter()
+            .map(|tag| substitute(tag, &params))
+            .collect();
+        add_tags(&root, id, &tags)?;
+    }
+    if !template.properties.is_empty() {
+        let properties: BTreeMap<String, String> = template
+            .properties
+            .iter()
+            .map(|(key, value)| (key.clone(), substitute(value, &params)))
+            .collect();
+        store_properties(&root, id, &properties)?;
+    }
+    Ok((path, id))
+}
+
+fn modify<P: AsRef<Path>>(
+    root: P,
+    mut operator: impl FnMut(&mut BTreeMap<ResourceId, Template>),
+) -> Result<()> {
+    let file = AtomicFile::new(storage_path(root))?;
+    modify_json(&file, |templates: &mut Option<Templates>| {
+        operator(
+            &mut templates
+                .get_or_insert_with(Templates::default)
+                .templates,
+        );
+    })?;
+    Ok(())
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(TEMPLATES_STORAGE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::prop::load_raw_properties;
+    use crate::tags::tags_of;
+    use tempdir::TempDir;
+
+    #[test]
+    fn placeholders_are_substituted() {
+        let params =
+            BTreeMap::from([("title".to_string(), "Trip".to_string())]);
+        assert_eq!(
+            substitute("# {{ title }}\n{{unknown}} {{title}}{{", &params),
+            "# Trip\n{{unknown}} Trip{{"
+        );
+    }
+
+    #[test]
+    fn resources_are_created_from_templates() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("templates")).unwrap();
+        fs::write(root.join("templates/meeting.md"), "# {{title}}\n").unwrap();
+        let template_id = {
+            let index = provide_index(&root).unwrap();
+            let index = index.read().unwrap();
+            index
+                .get_entry(root.join("templates/meeting.md"))
+                .unwrap()
+                .id
+        };
+        let template = Template {
+            file_name: Some("{{title}}.md".into()),
+            folder: Some(RelativePath::parse("meetings").unwrap()),
+            tags: vec!["meeting".into(), "{{project}}".into()],
+            properties: BTreeMap::from([("day".into(), "{{date}}".into())]),
+        };
+        register_template(&root, template_id, &template).unwrap();
+        fs::create_dir(root.join("meetings")).unwrap();
+
+        let params = BTreeMap::from([
+            ("title".to_string(), "Kick-off".to_string()),
+            ("project".to_string(), "ark".to_string()),
+        ]);
+        let (path, id) =
+            create_from_template(&root, template_id, &params).unwrap();
+        assert_eq!(path, root.join("meetings/Kick-off.md"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# Kick-off\n");
+        assert_eq!(
+            tags_of(&root, id).unwrap(),
+            ["ark".to_string(), "meeting".to_string()].into()
+        );
+        let properties: BTreeMap<String, String> =
+            serde_json::from_slice(&load_raw_properties(&root, id).unwrap())
+                .unwrap();
+        assert_eq!(properties["day"].len(), "2024-01-01".len());
+
+        let (second, _) =
+            create_from_template(&root, template_id, &params).unwrap();
+        assert_eq!(second, root.join("meetings/Kick-off (2).md"));
+        assert!(unregister_template(&root, template_id).unwrap());
+        assert!(load_templates(&root).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+//! Previews of plain text, markup and source code
+//!
+//! Only the beginning of the file is read, and the snippet is stored in
+//! the metadata cache, so that list views can show it without reading
+//! the files on the UI thread.
+use std::io::Read;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::resource::ResourceId;
+use crate::storage::metadata;
+use crate::{ArklibError, Result};
+
+/// Maximum amount of lines kept in [`TextMetadata::snippet`]
+pub const SNIPPET_LINES: usize = 20;
+/// Maximum amount of characters kept in [`TextMetadata::snippet`]
+pub const SNIPPET_LENGTH: usize = 2048;
+
+/// Amount of bytes read from the beginning of the file
+const READ_LIMIT: u64 = 64 * 1024;
+
+/// Languages by file extensions, names are the ones used by
+/// common syntax highlighters
+const LANGUAGES: &[(&str, &[&str])] = &[
+    ("markdown", &["md", "markdown", "mdown", "mkd"]),
+    ("org", &["org"]),
+    ("asciidoc", &["adoc", "asciidoc"]),
+    ("rst", &["rst"]),
+    ("text", &["txt", "text", "log"]),
+    ("rust", &["rs"]),
+    ("kotlin", &["kt", "kts"]),
+    ("java", &["java"]),
+    ("python", &["py", "pyw"]),
+    ("javascript", &["js", "mjs", "cjs", "jsx"]),
+    ("typescript", &["ts", "tsx"]),
+    ("c", &["c", "h"]),
+    ("cpp", &["cpp", "cc", "cxx", "hpp", "hh"]),
+    ("csharp", &["cs"]),
+    ("go", &["go"]),
+    ("swift", &["swift"]),
+    ("ruby", &["rb"]),
+    ("php", &["php"]),
+    ("shell", &["sh", "bash", "zsh"]),
+    ("html", &["html", "htm"]),
+    ("css", &["css"]),
+    ("json", &["json"]),
+    ("yaml", &["yaml", "yml"]),
+    ("toml", &["toml"]),
+    ("xml", &["xml"]),
+    ("sql", &["sql"]),
+    ("csv", &["csv", "tsv"]),
+];
+
+/// Interpreters named in shebang lines of scripts without extensions
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "python"),
+    ("bash", "shell"),
+    ("sh", "shell"),
+    ("zsh", "shell"),
+    ("node", "javascript"),
+    ("ruby", "ruby"),
+    ("php", "php"),
+];
+
+/// Metadata extracted from a text file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextMetadata {
+    /// Title from the front matter or the first heading of markup
+    pub title: Option<String>,
+    pub language: Option<String>,
+    /// First lines of the text, without the front matter
+    pub snippet: String,
+    /// Whether the text continues after the snippet
+    pub truncated: bool,
+}
+
+/// Detects the language by the extension or the shebang line
+pub fn detect_language(
+    extension: Option<&str>,
+    first_line: &str,
+) -> Option<&'static str> {
+    if let Some(extension) = extension {
+        let extension = extension.to_lowercase();
+        let language = LANGUAGES
+            .iter()
+            .find(|(_, extensions)| extensions.contains(&extension.as_str()));
+        if let Some((language, _)) = language {
+            return Some(language);
+        }
+    }
+    let command = first_line.strip_prefix("#!")?;
+    // both `#!/usr/bin/python3` and `#!/usr/bin/env python3`
+    let interpreter = command
+        .split_whitespace()
+        .map(|part| part.rsplit('/').next().unwrap_or(part))
+        .find(|part| *part != "env")?;
+    INTERPRETERS
+        .iter()
+        .find(|(name, _)| {
+            interpreter
+                .strip_prefix(name)
+                .is_some_and(|version| {
+                    version
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || c == '.')
+                })
+        })
+        .map(|(_, language)| *language)
+}
+
+/// Reads the beginning of the text and extracts its metadata,
+/// failing if the data looks binary
+pub fn extract<R: Read>(
+    data: R,
+    extension: Option<&str>,
+) -> Result<TextMetadata> {
+    let mut bytes = vec![];
+    data.take(READ_LIMIT + 1)
+        .read_to_end(&mut bytes)?;
+    let complete = bytes.len() as u64 <= READ_LIMIT;
+    bytes.truncate(READ_LIMIT as usize);
+    if bytes.contains(&0) {
+        return Err(ArklibError::Parse);
+    }
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(text) => text.to_string(),
+        // the limit may cut a character in the middle
+        Err(e) if e.error_len().is_none() => {
+            String::from_utf8_lossy(&bytes[..e.valid_up_to()]).to_string()
+        }
+        Err(_) => String::from_utf8_lossy(&bytes).to_string(),
+    };
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+
+    let first_line = text.lines().next().unwrap_or_default();
+    let language = detect_language(extension, first_line);
+    let (front_matter, body) = split_front_matter(text);
+    let title = front_matter
+        .and_then(front_matter_title)
+        .or_else(|| language.and_then(|language| heading(language, body)));
+
+    let mut snippet = String::new();
+    let mut lines = body
+        .lines()
+        .skip_while(|line| line.trim().is_empty());
+    for line in lines.by_ref().take(SNIPPET_LINES) {
+        snippet.push_str(line.trim_end());
+        snippet.push('\n');
+    }
+    let mut truncated = !complete || lines.any(|line| !line.trim().is_empty());
+    let snippet = snippet.trim_end();
+    if snippet.chars().count() > SNIPPET_LENGTH {
+        truncated = true;
+    }
+    Ok(TextMetadata {
+        title,
+        language: language.map(|language| language.to_string()),
+        snippet: snippet.chars().take(SNIPPET_LENGTH).collect(),
+        truncated,
+    })
+}
+
+/// Stores metadata of the text into the metadata cache
+pub fn generate<R: Read, P: AsRef<Path>>(
+    root: P,
+    id: ResourceId,
+    data: R,
+    extension: Option<&str>,
+) -> Result<TextMetadata> {
+    let metadata = extract(data, extension)?;
+    metadata::store(&root, id, &metadata)?;
+    Ok(metadata)
+}
+
+/// Splits YAML front matter delimited by `---` lines off the text
+pub(crate) fn split_front_matter(text: &str) -> (Option<&str>, &str) {
+    let rest = match text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    {
+        Some(rest) => rest,
+        None => return (None, text),
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            let body = &rest[offset + line.len()..];
+            return (Some(&rest[..offset]), body);
+        }
+        offset += line.len();
+    }
+    (None, text)
+}
+
+fn front_matter_title(front_matter: &str) -> Option<String> {
+    front_matter.lines().find_map(|line| {
+        let value = line.strip_prefix("title:")?.trim();
+        let value = value.trim_matches(|c| c == '"' || c == '\'');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Finds the first heading of markup, other languages have no title
+pub(crate) fn heading(language: &str, body: &str) -> Option<String> {
+    let mut lines = body.lines().map(str::trim_end).peekable();
+    while let Some(line) = lines.next() {
+        let title = match language {
+            "markdown" => line
+                .strip_prefix('#')
+                .map(|heading| heading.trim_start_matches('#'))
+                .filter(|heading| heading.starts_with(' '))
+                .or_else(|| {
+                    // setext headings are underlined
+                    let next = lines.peek()?;
+                    let underlined = !next.is_empty()
+                        && (next.chars().all(|c| c == '=')
+                            || next.chars().all(|c| c == '-'));
+                    (underlined && !line.trim().is_empty()).then_some(line)
+                }),
+            "org" => {
+                let lower = line.to_lowercase();
+                match lower.starts_with("#+title:") {
+                    true => Some(&line["#+title:".len()..]),
+                    false => line
+                        .strip_prefix('*')
+                        .map(|heading| heading.trim_start_matches('*'))
+                        .filter(|heading| heading.starts_with(' ')),
+                }
+            }
+            "asciidoc" => line.strip_prefix("= "),
+            "rst" => {
+                let next = lines.peek().copied().unwrap_or_default();
+                let underlined = !next.is_empty()
+                    && next.chars().count() >= line.chars().count()
+                    && next.chars().all(|c| "=-~*#^\"".contains(c));
+                (underlined && !line.trim().is_empty()).then_some(line)
+            }
+            _ => return None,
+        };
+        if let Some(title) = title.map(str::trim) {
+            if !title.is_empty() {
+                return Some(title.to_string());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_titles_and_snippets() {
+        let note = "---\ntitle: \"Groceries\"\ntags: [home]\n---\n\n# List\n\
+                    - milk\n- bread\n";
+        let metadata = extract(note.as_bytes(), Some("md")).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Groceries"));
+        assert_eq!(metadata.language.as_deref(), Some("markdown"));
+        assert_eq!(metadata.snippet, "# List\n- milk\n- bread");
+        assert!(!metadata.truncated);
+
+        let setext = "Plans\n=====\n\nSome text";
+        let metadata = extract(setext.as_bytes(), Some("MD")).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Plans"));
+
+        let org = "#+TITLE: Journal\n* Monday\n";
+        let metadata = extract(org.as_bytes(), Some("org")).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Journal"));
+    }
+
+    #[test]
+    fn code_languages_are_detected() {
+        assert_eq!(detect_language(Some("rs"), ""), Some("rust"));
+        assert_eq!(
+            detect_language(None, "#!/usr/bin/env python3"),
+            Some("python")
+        );
+        assert_eq!(detect_language(None, "#!/bin/bash -e"), Some("shell"));
+        assert_eq!(detect_language(None, "#!/usr/bin/pythonista"), None);
+        assert_eq!(detect_language(Some("bin"), "hello"), None);
+
+        let code = (0..30)
+            .map(|i| format!("let x{} = {};", i, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let metadata = extract(code.as_bytes(), Some("rs")).unwrap();
+        assert_eq!(metadata.title, None);
+        assert_eq!(metadata.snippet.lines().count(), SNIPPET_LINES);
+        assert!(metadata.truncated);
+    }
+
+    #[test]
+    fn binary_data_is_rejected() {
+        assert!(extract(&b"\x89PNG\r\n\x1a\n\0\0"[..], None).is_err());
+
+        // the read limit cuts the last character in the middle
+        let mut text = "a".repeat(READ_LIMIT as usize - 1);
+        text.push('é');
+        let metadata = extract(text.as_bytes(), Some("txt")).unwrap();
+        assert!(metadata.truncated);
+        assert!(!metadata.snippet.contains('\u{fffd}'));
+    }
+}
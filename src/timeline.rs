@@ -0,0 +1,365 @@
+//! Index of resource dates for timeline views
+//!
+//! Photos are dated by their EXIF capture date, other resources by the
+//! modification time of their files. Dates are kept in a single cache file
+//! sorted chronologically, so grouping resources by day, month or year
+//! doesn't need to read metadata of every resource. The index has to be
+//! refreshed with [`update_timeline`] after every update of the resource
+//! index.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+use exif::{In, Tag, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::{modify_json, AtomicFile};
+use crate::index::{IndexUpdate, ResourceIndex};
+use crate::resource::ResourceId;
+use crate::{images, Result, ARK_FOLDER, TIMELINE_STORAGE_FILE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateSource {
+    /// Capture date from EXIF attributes, in the local time of the camera
+    Exif,
+    /// Modification time of the file, in UTC
+    Modified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineEntry {
+    pub id: ResourceId,
+    pub date: NaiveDateTime,
+    pub source: DateSource,
+}
+
+/// Dates of resources sorted chronologically
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timeline {
+    entries: Vec<TimelineEntry>,
+}
+
+impl Timeline {
+    /// Loads the timeline of the root, empty if it was never built
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let path = storage_path(root);
+        if !path.exists() {
+            return Ok(Timeline::default());
+        }
+        match AtomicFile::new(path)?.load()?.open()? {
+            Some(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            None => Ok(Timeline::default()),
+        }
+    }
+
+    pub fn entries(&self) -> &[TimelineEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, id: ResourceId) -> Option<&TimelineEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// Sets the date of the resource, replacing the previous one
+    pub fn insert(&mut self, entry: TimelineEntry) {
+        self.remove(entry.id);
+        let position = self.entries.partition_point(|other| {
+            (other.date, other.id) < (entry.date, entry.id)
+        });
+        self.entries.insert(position, entry);
+    }
+
+    pub fn remove(&mut self, id: ResourceId) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.id != id);
+        self.entries.len() != len
+    }
+
+    /// Entries dated within the range, `to` excluded
+    pub fn range(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> &[TimelineEntry] {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.date < from);
+        let end = self
+            .entries
+            .partition_point(|entry| entry.date < to);
+        &self.entries[start..end.max(start)]
+    }
+
+    pub fn group_by_day(&self) -> BTreeMap<NaiveDate, Vec<ResourceId>> {
+        self.group_by(|date| date.date())
+    }
+
+    /// Groups resources by year and month
+    pub fn group_by_month(&self) -> BTreeMap<(i32, u32), Vec<ResourceId>> {
+        self.group_by(|date| (date.year(), date.month()))
+    }
+
+    pub fn group_by_year(&self) -> BTreeMap<i32, Vec<ResourceId>> {
+        self.group_by(|date| date.year())
+    }
+
+    fn group_by<K: Ord>(
+        &self,
+        key: impl Fn(&NaiveDateTime) -> K,
+    ) -> BTreeMap<K, Vec<ResourceId>> {
+        let mut groups: BTreeMap<K, Vec<ResourceId>> = BTreeMap::new();
+        for entry in &self.entries {
+            groups
+                .entry(key(&entry.date))
+                .or_default()
+                .push(entry.id);
+        }
+        groups
+    }
+}
+
+/// Reads the capture date from EXIF attributes of the photo
+pub fn read_capture_date<R: BufRead + Seek>(
+    data: R,
+) -> Result<Option<NaiveDateTime>> {
+    let exif = match images::read_exif(data)? {
+        Some(exif) => exif,
+        None => return Ok(None),
+    };
+    let date = [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+        .into_iter()
+        .filter_map(|tag| exif.get_field(tag, In::PRIMARY))
+        .find_map(|field| match &field.value {
+            Value::Ascii(values) => {
+                let value = String::from_utf8_lossy(values.first()?);
+                NaiveDateTime::parse_from_str(value.trim(), "%Y:%m:%d %H:%M:%S")
+                    .ok()
+            }
+            _ => None,
+        });
+    Ok(date)
+}
+
+/// Dates the resource by the EXIF capture date of photos, or by
+/// the modification time of the file
+pub fn date_file(path: &Path) -> Result<(NaiveDateTime, DateSource)> {
+    let is_image = path.extension().is_some_and(|extension| {
+        images::is_supported(&extension.to_string_lossy())
+    });
+    if is_image {
+        match read_capture_date(BufReader::new(File::open(path)?)) {
+            Ok(Some(date)) => return Ok((date, DateSource::Exif)),
+            Ok(None) => {}
+            Err(e) => {
+                log::warn!("Couldn't read EXIF of {}: {}", path.display(), e)
+            }
+        }
+    }
+    let modified = std::fs::metadata(path)?.modified()?;
+    Ok((utc(modified), DateSource::Modified))
+}
+
+/// Dates resources added or modified by the update and forgets dates
+/// of deleted resources
+///
+/// Returns the number of dated resources.
+pub fn update_timeline<P: AsRef<Path>>(
+    root: P,
+    update: &IndexUpdate,
+) -> Result<usize> {
+    let added = update.added.iter().map(|(path, id)| (path, *id));
+    let modified = update
+        .modified
+        .iter()
+        .map(|(path, (_, id))| (path, *id));
+    let dated = date_all(added.chain(modified));
+    let outdated: Vec<ResourceId> = update
+        .deleted
+        .iter()
+        .copied()
+        .chain(
+            update
+                .modified
+                .values()
+                .map(|(old_id, _)| *old_id),
+        )
+        .collect();
+    if dated.is_empty() && outdated.is_empty() {
+        return Ok(0);
+    }
+    modify(root, |timeline| {
+        for id in &outdated {
+            timeline.remove(*id);
+        }
+        for entry in &dated {
+            timeline.insert(*entry);
+        }
+    })?;
+    Ok(dated.len())
+}
+
+/// Builds the timeline of all resources of the index from scratch
+pub fn rebuild_timeline(index: &ResourceIndex) -> Result<usize> {
+    let dated = date_all(
+        index
+            .entries()
+            .map(|(path, entry)| (path, entry.id)),
+    );
+    modify(index.root(), |timeline| {
+        *timeline = Timeline::default();
+        for entry in &dated {
+            timeline.insert(*entry);
+        }
+    })?;
+    Ok(dated.len())
+}
+
+fn date_all<P: AsRef<Path>>(
+    resources: impl Iterator<Item = (P, ResourceId)>,
+) -> Vec<TimelineEntry> {
+    resources
+        .filter_map(|(path, id)| match date_file(path.as_ref()) {
+            Ok((date, source)) => Some(entry(id, date, source)),
+            Err(e) => {
+                log::warn!("Couldn't date {}: {}", path.as_ref().display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn entry(
+    id: ResourceId,
+    date: NaiveDateTime,
+    source: DateSource,
+) -> TimelineEntry {
+    TimelineEntry { id, date, source }
+}
+
+fn utc(time: SystemTime) -> NaiveDateTime {
+    DateTime::<Utc>::from(time).naive_utc()
+}
+
+fn modify<P: AsRef<Path>>(
+    root: P,
+    mut operator: impl FnMut(&mut Timeline),
+) -> Result<()> {
+    let file = AtomicFile::new(storage_path(root))?;
+    modify_json(&file, |timeline: &mut Option<Timeline>| {
+        operator(timeline.get_or_insert_with(Timeline::default));
+    })?;
+    Ok(())
+}
+
+fn storage_path<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref()
+        .join(ARK_FOLDER)
+        .join(TIMELINE_STORAGE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+    use std::io::Cursor;
+    use tempdir::TempDir;
+
+    fn id(hash: u32) -> ResourceId {
+        ResourceId { hash, data_size: 1 }
+    }
+
+    fn date(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// JPEG with an APP1 segment holding `DateTime` of IFD0
+    fn dated_jpeg(date: &str) -> Vec<u8> {
+        let mut tiff = vec![b'M', b'M', 0, 42, 0, 0, 0, 8];
+        tiff.extend([0, 1, 0x01, 0x32, 0, 2, 0, 0, 0, 20, 0, 0, 0, 26]);
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(date.as_bytes());
+        tiff.push(0);
+
+        let jpeg = images::encode(&DynamicImage::new_rgb8(8, 8)).unwrap();
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend([0xFF, 0xE1]);
+        bytes.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        bytes.extend(b"Exif\0\0");
+        bytes.extend(tiff);
+        bytes.extend(&jpeg[2..]);
+        bytes
+    }
+
+    #[test]
+    fn resources_are_grouped() {
+        let mut timeline = Timeline::default();
+        for (hash, value) in [
+            (1, "2023-12-31 23:00"),
+            (2, "2024-01-01 10:00"),
+            (3, "2024-01-01 08:00"),
+            (4, "2024-03-15 12:00"),
+        ] {
+            timeline.insert(entry(id(hash), date(value), DateSource::Exif));
+        }
+        timeline.insert(entry(
+            id(1),
+            date("2022-06-01 00:00"),
+            DateSource::Modified,
+        ));
+
+        let days = timeline.group_by_day();
+        assert_eq!(days.len(), 3);
+        assert_eq!(days[&date("2024-01-01 00:00").date()], [id(3), id(2)]);
+        let months = timeline.group_by_month();
+        assert_eq!(
+            months.keys().collect::<Vec<_>>(),
+            [&(2022, 6), &(2024, 1), &(2024, 3)]
+        );
+        let years = timeline.group_by_year();
+        assert_eq!(years[&2024].len(), 3);
+
+        let january =
+            timeline.range(date("2024-01-01 00:00"), date("2024-02-01 00:00"));
+        assert_eq!(january.len(), 2);
+    }
+
+    #[test]
+    fn photos_are_dated_by_exif() {
+        let jpeg = dated_jpeg("2021:07:04 18:30:00");
+        let captured = read_capture_date(Cursor::new(jpeg)).unwrap();
+        assert_eq!(captured, Some(date("2021-07-04 18:30")));
+    }
+
+    #[test]
+    fn timeline_follows_updates() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        std::fs::write(
+            root.join("photo.jpg"),
+            dated_jpeg("2021:07:04 18:30:00"),
+        )
+        .unwrap();
+        std::fs::write(root.join("notes.txt"), "text").unwrap();
+        let mut index = ResourceIndex::build(&root);
+        assert_eq!(rebuild_timeline(&index).unwrap(), 2);
+
+        let timeline = Timeline::load(&root).unwrap();
+        let photo = index
+            .get_entry(root.join("photo.jpg"))
+            .unwrap()
+            .id;
+        assert_eq!(timeline.get(photo).unwrap().source, DateSource::Exif);
+        assert_eq!(timeline.group_by_year().len(), 2);
+
+        std::fs::remove_file(root.join("photo.jpg")).unwrap();
+        let update = index.update_all().unwrap();
+        update_timeline(&root, &update).unwrap();
+        let timeline = Timeline::load(&root).unwrap();
+        assert_eq!(timeline.entries().len(), 1);
+        assert_eq!(timeline.entries()[0].source, DateSource::Modified);
+    }
+}
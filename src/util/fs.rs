@@ -0,0 +1,211 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{ArklibError, Result};
+
+/// Makes a file or folder name from arbitrary text,
+/// valid on all platforms and not hidden
+pub fn safe_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // hidden files are never indexed
+    let name = name.trim().trim_start_matches('.').trim();
+    match name.is_empty() {
+        true => "Untitled".to_string(),
+        false => name.to_string(),
+    }
+}
+
+/// Finds a free path for the file name in the folder, appending
+/// ` (2)`, ` (3)` and so on to the name before its extension
+pub fn unique_path(folder: &Path, file_name: &str) -> PathBuf {
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            (stem, format!(".{}", extension))
+        }
+        _ => (file_name, String::new()),
+    };
+    (1..)
+        .map(|n| match n {
+            1 => folder.join(file_name),
+            n => folder.join(format!("{} ({}){}", stem, n, extension)),
+        })
+        .find(|path| !path.exists())
+        .unwrap_or_else(|| folder.join(file_name))
+}
+
+/// Writes into a temporary file first, so that readers never observe
+/// a partially written file
+pub fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    let parent = path.parent().ok_or_else(|| {
+        ArklibError::Path(format!("{} has no parent", path.display()))
+    })?;
+    fs::create_dir_all(parent)?;
+    let filename: String = std::iter::repeat_with(fastrand::alphanumeric)
+        .take(10)
+        .collect();
+    let tmp = parent.join(format!(".{}", filename));
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_data()?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Converts a path inside of `base` into the portable form
+/// used in manifests and by other devices, with `/` as separator
+///
+/// Names are normalized to NFC, since macOS may store them
+/// in NFD while other platforms use NFC.
+pub fn relative_path(base: &Path, path: &Path) -> Result<String> {
+    let base = simplify_prefix(base);
+    let path = simplify_prefix(path);
+    let relative = path.strip_prefix(&base).map_err(|_| {
+        ArklibError::Path(format!(
+            "{} is outside of {}",
+            path.display(),
+            base.display()
+        ))
+    })?;
+    let parts: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().nfc().collect())
+        .collect();
+    Ok(parts.join("/"))
+}
+
+/// Normalizes a path in the portable form to NFC
+pub fn normalize_relative(path: &str) -> String {
+    path.nfc().collect()
+}
+
+/// Finds the file denoted by a path in the portable form, which is NFC
+/// normalized, even if its name is stored in NFD on a filesystem
+/// distinguishing the forms
+pub fn locate_relative(base: &Path, path: &str) -> Option<PathBuf> {
+    let nfc: String = path.nfc().collect();
+    let nfd: String = path.nfd().collect();
+    [join_relative(base, &nfc), join_relative(base, &nfd)]
+        .into_iter()
+        .flatten()
+        .find(|path| fs::symlink_metadata(path).is_ok())
+}
+
+/// Replaces Windows verbatim prefixes like `\\?\C:\` and
+/// `\\?\UNC\server\share`, produced by canonicalization, with
+/// their regular forms, so that both forms of a path can be compared
+///
+/// Paths without a prefix, including all paths on other platforms,
+/// are returned as is.
+pub fn simplify_prefix(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let prefix = match components.next() {
+        Some(Component::Prefix(prefix)) => prefix,
+        _ => return path.to_path_buf(),
+    };
+    let mut simplified = OsString::new();
+    match prefix.kind() {
+        Prefix::VerbatimDisk(disk) | Prefix::Disk(disk) => {
+            simplified.push(format!("{}:", disk as char))
+        }
+        Prefix::VerbatimUNC(server, share) | Prefix::UNC(server, share) => {
+            simplified.push(r"\\");
+            simplified.push(server);
+            simplified.push(r"\");
+            simplified.push(share);
+        }
+        _ => return path.to_path_buf(),
+    }
+    let mut simplified = PathBuf::from(simplified);
+    simplified.extend(components);
+    simplified
+}
+
+/// Resolves a path in the portable form against `base`, rejecting
+/// paths which are absolute or would escape `base`
+pub fn join_relative(base: &Path, path: &str) -> Result<PathBuf> {
+    let mut joined = base.to_path_buf();
+    for part in path.split('/') {
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) if !part.contains('\\') => {
+                joined.push(part)
+            }
+            _ => {
+                return Err(ArklibError::Path(format!(
+                    "Illegal path: {}",
+                    path
+                )))
+            }
+        }
+    }
+    Ok(joined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping_paths_are_rejected() {
+        let base = Path::new("/root/.ark");
+        assert_eq!(
+            join_relative(base, "user/tags").unwrap(),
+            base.join("user").join("tags")
+        );
+        assert!(join_relative(base, "user/../../etc/passwd").is_err());
+        assert!(join_relative(base, "/etc/passwd").is_err());
+        assert!(join_relative(base, "").is_err());
+        assert!(join_relative(base, "user\\..\\secret").is_err());
+    }
+
+    #[test]
+    fn relative_paths_are_nfc_normalized() {
+        let dir = tempdir::TempDir::new("arklib_test").unwrap();
+        let nfd = "cafe\u{301}.txt";
+        let nfc = "caf\u{e9}.txt";
+        fs::write(dir.path().join(nfd), "coffee").unwrap();
+
+        assert_eq!(
+            relative_path(dir.path(), &dir.path().join(nfd)).unwrap(),
+            nfc
+        );
+        assert_eq!(normalize_relative(nfd), nfc);
+        assert_eq!(
+            locate_relative(dir.path(), nfc).unwrap(),
+            dir.path().join(nfd)
+        );
+        assert!(locate_relative(dir.path(), "tea.txt").is_none());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn verbatim_prefixes_are_simplified() {
+        assert_eq!(
+            simplify_prefix(Path::new(r"\\?\C:\photos\cat.jpg")),
+            Path::new(r"C:\photos\cat.jpg")
+        );
+        assert_eq!(
+            simplify_prefix(Path::new(r"\\?\UNC\nas\share\cat.jpg")),
+            Path::new(r"\\nas\share\cat.jpg")
+        );
+        assert_eq!(
+            relative_path(
+                Path::new(r"C:\photos"),
+                Path::new(r"\\?\C:\photos\2023\cat.jpg")
+            )
+            .unwrap(),
+            "2023/cat.jpg"
+        );
+    }
+}
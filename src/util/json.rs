@@ -5,6 +5,9 @@ use serde_json::Value;
 
 pub fn merge(origin: Value, new_data: Value) -> Value {
     match (origin, new_data) {
+        // absent data never changes anything, including arrays
+        (old, Value::Null) => old,
+        (Value::Null, new) => new,
         (Value::Object(old), Value::Object(new)) => merge_object(old, new),
         (Value::Array(old), Value::Array(new)) => merge_vec(old, new),
         (Value::Array(mut old), new) => {
@@ -32,8 +35,6 @@ pub fn merge(origin: Value, new_data: Value) -> Value {
                 old
             }
         }
-        (old, Value::Null) => old,
-        (Value::Null, new) => new,
         (old, new) => {
             if std::mem::discriminant(&old) == std::mem::discriminant(&new)
                 && old != new
@@ -134,6 +135,7 @@ fn merge_vec(original: Vec<Value>, new_data: Vec<Value>) -> Value {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     use rstest::rstest;
 
     #[rstest]
@@ -154,4 +156,45 @@ mod tests {
         let merged = merge(old, new);
         assert_eq!(merged, expected);
     }
+
+    fn value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            "[a-z]{0,4}".prop_map(Value::from),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                proptest::collection::vec(inner.clone(), 0..4)
+                    .prop_map(Value::Array),
+                proptest::collection::btree_map("[a-c]", inner, 0..4)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn merging_null_keeps_value(value in value()) {
+            prop_assert_eq!(merge(value.clone(), Value::Null), value.clone());
+            prop_assert_eq!(merge(Value::Null, value.clone()), value);
+        }
+
+        #[test]
+        fn merging_objects_keeps_all_keys(
+            old in proptest::collection::btree_map("[a-c]", value(), 0..4),
+            new in proptest::collection::btree_map("[a-c]", value(), 0..4),
+        ) {
+            let merged = merge(
+                Value::Object(old.clone().into_iter().collect()),
+                Value::Object(new.clone().into_iter().collect()),
+            );
+            let merged = merged.as_object().unwrap();
+            for key in old.keys().chain(new.keys()) {
+                prop_assert!(merged.contains_key(key));
+            }
+            prop_assert!(merged.len() <= old.len() + new.len());
+        }
+    }
 }
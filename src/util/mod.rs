@@ -1 +1,4 @@
+pub mod fs;
 pub mod json;
+pub mod runtime;
+pub mod xml;
@@ -0,0 +1,68 @@
+//! Shared runtime of the blocking variants of async functions
+//!
+//! Creating a runtime per call is slow, and blocking on a future from
+//! inside of a runtime panics. All blocking variants run their futures
+//! on this runtime instead, and from inside of another runtime they only
+//! wait for the result.
+use std::future::Future;
+use std::sync::mpsc;
+
+use anyhow::anyhow;
+use once_cell::sync::OnceCell;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::{ArklibError, Result};
+
+static RUNTIME: OnceCell<Runtime> = OnceCell::new();
+
+fn runtime() -> Result<&'static Runtime> {
+    Ok(RUNTIME.get_or_try_init(|| {
+        Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("arklib")
+            .enable_all()
+            .build()
+    })?)
+}
+
+/// Runs the future to completion, blocking the current thread
+///
+/// Safe to call from both plain threads and tasks of any runtime,
+/// though it blocks a worker of the runtime in the latter case.
+pub fn block_on<F>(future: F) -> Result<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let runtime = runtime()?;
+    if Handle::try_current().is_err() {
+        return Ok(runtime.block_on(future));
+    }
+    let (sender, receiver) = mpsc::sync_channel(1);
+    runtime.spawn(async move {
+        let _ = sender.send(future.await);
+    });
+    receiver
+        .recv()
+        .map_err(|_| ArklibError::Other(anyhow!("Blocking task panicked")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn futures_are_run_from_plain_threads() {
+        assert_eq!(block_on(async { 1 + 1 }).unwrap(), 2);
+        assert_eq!(block_on(async { 2 + 2 }).unwrap(), 4);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn futures_are_run_from_async_contexts() {
+        let result = block_on(async {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            42
+        });
+        assert_eq!(result.unwrap(), 42);
+    }
+}
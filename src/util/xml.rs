@@ -0,0 +1,56 @@
+//! Helpers for zipped XML containers (EPUB, OOXML, OpenDocument)
+use std::io::{Read, Seek};
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use zip::ZipArchive;
+
+use crate::Result;
+
+/// Documents larger than this are truncated, so that a crafted archive
+/// can't exhaust memory
+const ENTRY_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// Reads the whole entry of the archive by its name
+///
+/// The size in the header of the entry is not trusted, the entry is read
+/// up to the size or [`ENTRY_LIMIT`], whichever is smaller.
+pub fn read_zip_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<u8>> {
+    let mut entry = archive.by_name(name)?;
+    let limit = entry.size().min(ENTRY_LIMIT);
+    let mut buf = vec![];
+    entry.by_ref().take(limit).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Returns unescaped value of the attribute, ignoring its namespace prefix
+pub fn attribute(element: &BytesStart, name: &[u8]) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key.local_name().as_ref() == name)
+        .and_then(|attr| attr.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+/// Collects text of the first element with the given local name
+pub fn first_text(document: &[u8], name: &[u8]) -> Result<Option<String>> {
+    let mut reader = Reader::from_reader(document);
+    let mut buf = vec![];
+    let mut inside = false;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) if e.local_name().as_ref() == name => inside = true,
+            Event::Text(text) if inside => {
+                return Ok(Some(text.unescape()?.trim().to_string()))
+            }
+            Event::End(_) => inside = false,
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
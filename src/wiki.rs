@@ -0,0 +1,252 @@
+//! Wiki links between markdown notes and other resources
+//!
+//! Notes can mention resources as `[[target]]`, where the target is an
+//! ID, a relative path or a file name with or without extension, like
+//! `[[Trip]]` or `[[photos/sea.jpg]]`. Aliases and headings are allowed,
+//! as in `[[Trip#Day 1|first day]]`. Links are recorded in the relations
+//! storage, so [`crate::relations::backlinks`] lists notes linking to
+//! a resource. Links found in a note are kept in its metadata, so that
+//! links removed from the note are removed from the relations as well.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::index::{IndexUpdate, RelativePath, ResourceIndex};
+use crate::notes::is_note;
+use crate::relations::{modify_relations, Relation};
+use crate::resource::ResourceId;
+use crate::storage::metadata;
+use crate::Result;
+
+const WIKI_LINKS_METADATA_FIELD: &str = "wiki_links";
+
+/// Extracts targets of wiki links, skipping code blocks and code spans
+pub fn parse_wiki_links(content: &str) -> Vec<String> {
+    let mut targets = vec![];
+    let mut fenced = false;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            continue;
+        }
+        if fenced {
+            continue;
+        }
+        // text between backticks is inline code
+        for text in line.split('`').step_by(2) {
+            let mut rest = text;
+            while let Some(start) = rest.find("[[") {
+                rest = &rest[start + 2..];
+                let Some(end) = rest.find("]]") else {
+                    break;
+                };
+                let link = &rest[..end];
+                rest = &rest[end + 2..];
+                let target = link
+                    .split(['|', '#'])
+                    .next()
+                    .unwrap_or_default()
+                    .trim();
+                if !target.is_empty() && !target.contains('[') {
+                    targets.push(target.to_string());
+                }
+            }
+        }
+    }
+    targets
+}
+
+/// Finds the resource the target of a wiki link refers to
+///
+/// Targets are matched against IDs first, then against relative paths
+/// and then against file names, ignoring case. If several files have
+/// the same name, the one with the shortest path wins. Prefixes of IDs
+/// are tried last.
+pub fn resolve_wiki_link(
+    index: &ResourceIndex,
+    target: &str,
+) -> Option<ResourceId> {
+    if let Ok(id) = target.parse::<ResourceId>() {
+        if index.contains(&id) {
+            return Some(id);
+        }
+    }
+    let target = target.to_lowercase();
+    let entries = index.entries_with_prefix(&RelativePath::default());
+    let by_path = entries.iter().find(|(path, _)| {
+        let path = path.as_str().to_lowercase();
+        path == target || strip_extension(&path) == target
+    });
+    let by_name = || {
+        entries
+            .iter()
+            .filter(|(path, _)| {
+                let name = path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                name == target || strip_extension(&name) == target
+            })
+            .min_by_key(|(path, _)| path.as_str().len())
+    };
+    by_path
+        .or_else(by_name)
+        .map(|(_, entry)| entry.id)
+        .or_else(|| index.resolve_prefix(&target).ok())
+}
+
+/// Loads IDs of resources linked from the note when it was last synced
+pub fn load_wiki_links<P: AsRef<Path>>(
+    root: P,
+    note: ResourceId,
+) -> Result<BTreeSet<ResourceId>> {
+    let metadata: Option<serde_json::Value> = metadata::load(root, note)?;
+    match metadata.and_then(|mut metadata| {
+        metadata
+            .get_mut(WIKI_LINKS_METADATA_FIELD)
+            .map(serde_json::Value::take)
+    }) {
+        Some(links) => Ok(serde_json::from_value(links)?),
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+/// Records wiki links of notes added or modified by the update,
+/// removing relations of links which were deleted from modified notes
+///
+/// Links to resources which are not indexed are skipped, they are
+/// resolved when the note is modified again. Returns the number of
+/// processed notes.
+pub fn sync_wiki_links(
+    index: &ResourceIndex,
+    update: &IndexUpdate,
+) -> Result<usize> {
+    let root = index.root();
+    let added = update
+        .added
+        .iter()
+        .map(|(path, id)| (path, None, *id));
+    let modified = update
+        .modified
+        .iter()
+        .map(|(path, (old_id, new_id))| (path, Some(*old_id), *new_id));
+
+    let mut synced = 0;
+    for (path, old_id, id) in added.chain(modified) {
+        if !is_note(path) {
+            continue;
+        }
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                log::warn!("Couldn't read note {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let links: BTreeSet<ResourceId> = parse_wiki_links(&content)
+            .iter()
+            .filter_map(|target| resolve_wiki_link(index, target))
+            .filter(|target| *target != id)
+            .collect();
+        let stale = match old_id {
+            Some(old_id) => load_wiki_links(root, old_id)?
+                .difference(&links)
+                .copied()
+                .collect(),
+            None => BTreeSet::new(),
+        };
+        if !links.is_empty() || !stale.is_empty() {
+            modify_relations(root, |relations| {
+                for to in &stale {
+                    relations.remove(&Relation { from: id, to: *to });
+                    if let Some(old_id) = old_id {
+                        relations.remove(&Relation {
+                            from: old_id,
+                            to: *to,
+                        });
+                    }
+                }
+                for to in &links {
+                    relations.insert(Relation { from: id, to: *to });
+                }
+            })?;
+        }
+        metadata::store_field(root, id, WIKI_LINKS_METADATA_FIELD, &links)?;
+        synced += 1;
+    }
+    log::debug!("Synced wiki links of {} notes", synced);
+    Ok(synced)
+}
+
+fn strip_extension(name: &str) -> &str {
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() && !stem.ends_with('/') => stem,
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relations::backlinks;
+    use tempdir::TempDir;
+
+    #[test]
+    fn wiki_links_are_parsed() {
+        let note = "See [[Trip#Day 1|first day]] and [[ photos/sea.jpg ]].\n\
+                    `[[code]]` [[]] [[unclosed\n\
+                    ```\n[[fenced]]\n```\n![[embedded.png]]";
+        assert_eq!(
+            parse_wiki_links(note),
+            ["Trip", "photos/sea.jpg", "embedded.png"]
+        );
+    }
+
+    #[test]
+    fn wiki_links_become_relations() {
+        crate::app_id::load(std::env::temp_dir()).unwrap();
+        let dir = TempDir::new("arklib_test").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let mut index = ResourceIndex::build(&root);
+        fs::create_dir_all(root.join("photos/old")).unwrap();
+        fs::write(root.join("photos/sea.jpg"), "sea").unwrap();
+        fs::write(root.join("photos/old/sea.jpg"), "old sea").unwrap();
+        fs::write(root.join("Day 2.md"), "# Day 2\n").unwrap();
+        fs::write(root.join("trip.md"), "[[SEA]] and [[day 2]]\n").unwrap();
+        let update = index.update_all().unwrap();
+        assert_eq!(sync_wiki_links(&index, &update).unwrap(), 2);
+
+        let id = |index: &ResourceIndex, name: &str| {
+            index.get_entry(root.join(name)).unwrap().id
+        };
+        let trip = id(&index, "trip.md");
+        assert_eq!(
+            backlinks(&root, id(&index, "photos/sea.jpg")).unwrap(),
+            [trip].into()
+        );
+        assert_eq!(
+            backlinks(&root, id(&index, "Day 2.md")).unwrap(),
+            [trip].into()
+        );
+        assert!(backlinks(&root, id(&index, "photos/old/sea.jpg"))
+            .unwrap()
+            .is_empty());
+
+        fs::write(root.join("trip.md"), "[[photos/old/sea.jpg]]\n").unwrap();
+        let update = index.update_all().unwrap();
+        sync_wiki_links(&index, &update).unwrap();
+        let trip = id(&index, "trip.md");
+        assert!(backlinks(&root, id(&index, "photos/sea.jpg"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            backlinks(&root, id(&index, "photos/old/sea.jpg")).unwrap(),
+            [trip].into()
+        );
+        assert_eq!(
+            load_wiki_links(&root, trip).unwrap(),
+            [id(&index, "photos/old/sea.jpg")].into()
+        );
+    }
+}